@@ -0,0 +1,57 @@
+_flox_restore_json() {
+  _flox_json=$1
+  _flox_json=${_flox_json//\\/\\\\}
+  _flox_json=${_flox_json//\"/\\\"}
+  _flox_json=${_flox_json//$'\n'/\\n}
+  _flox_json=${_flox_json//$'\t'/\\t}
+  _flox_json=${_flox_json//$'\r'/\\r}
+  case "$_flox_json" in
+    *[[:cntrl:]]*) return 1 ;;
+  esac
+  _flox_json="\"$_flox_json\""
+}
+_flox_restore_add() {
+  _flox_restore_before_json="$_flox_restore_before_json,\"$1\":$2"
+  _flox_restore_after_json="$_flox_restore_after_json,\"$1\":$3"
+}
+_flox_restore_before_json=''
+_flox_restore_after_json=''
+for _flox_var in EXPORTED; do
+  case "$_flox_var" in
+    IGNORED) continue ;;
+  esac
+  eval "_flox_after=\"\${$_flox_var}\""
+  _flox_restore_json "$_flox_after" || continue
+  _flox_after_json=$_flox_json
+  case " $_flox_restore_vars " in
+    *" $_flox_var "*)
+      eval "_flox_before=\"\${_flox_restore_before_$_flox_var}\""
+      [ "$_flox_before" = "$_flox_after" ] && continue
+      _flox_restore_json "$_flox_before" || continue
+      _flox_restore_add "$_flox_var" "$_flox_json" "$_flox_after_json"
+      ;;
+    *) _flox_restore_add "$_flox_var" null "$_flox_after_json" ;;
+  esac
+done
+for _flox_var in SAVED; do
+  eval "_flox_after=\"\${$_flox_var+set}\""
+  eval "_flox_before=\"\${_flox_restore_before_$_flox_var}\""
+  unset "_flox_restore_before_$_flox_var"
+  case "$_flox_var" in
+    IGNORED) continue ;;
+  esac
+  if [ -z "$_flox_after" ] && _flox_restore_json "$_flox_before"; then
+    _flox_restore_add "$_flox_var" "$_flox_json" null
+  fi
+done
+_flox_restore_environment=ENVIRONMENT
+_flox_restore_record="{\"environment\":$_flox_restore_environment,\"before\":{${_flox_restore_before_json#,}},\"after\":{${_flox_restore_after_json#,}}}"
+case "${_FLOX_RESTORE_RECORDS:-[]}" in
+  '[]') _FLOX_RESTORE_RECORDS="[$_flox_restore_record]" ;;
+  *) _FLOX_RESTORE_RECORDS="[$_flox_restore_record,${_FLOX_RESTORE_RECORDS#[}" ;;
+esac
+export _FLOX_RESTORE_RECORDS
+unset -f _flox_restore_json _flox_restore_add
+unset _flox_restore_vars _flox_restore_before_json _flox_restore_after_json \
+  _flox_restore_environment _flox_restore_record \
+  _flox_var _flox_json _flox_before _flox_after _flox_after_json
@@ -0,0 +1,5 @@
+_flox_restore_vars=''
+for _flox_var in EXPORTED; do
+  _flox_restore_vars="$_flox_restore_vars $_flox_var"
+  eval "_flox_restore_before_$_flox_var=\"\${$_flox_var}\""
+done
@@ -1,9 +1,13 @@
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::{ChildStdout, Command, ExitStatus, Stdio};
+use std::thread;
 
+use log::debug;
 use thiserror::Error;
 
+use super::container_image::{ContainerImageError, UnpackedImage};
+
 /// Type representing a container builder script,
 /// i.e. the output of `pkgdb buildenv --container`
 /// ([LockedManifest::build_container](crate::models::lockfile::LockedManifest::build_container)).
@@ -11,7 +15,10 @@ use thiserror::Error;
 /// The script is executed with no arguments
 /// and writes a container tarball to stdout.
 ///
-/// [ContainerBuilder::stream_container] can be used to write that tarball to a sink.
+/// [ContainerBuilder::stream_container] can be used to write that tarball to a sink,
+/// [ContainerBuilder::build_image] unpacks it to write it in other formats.
+/// Either fails if the script exits unsuccessfully,
+/// as the tarball is likely truncated in that case.
 pub struct ContainerBuilder {
     path: PathBuf,
 }
@@ -27,17 +34,106 @@ impl ContainerBuilder {
     /// Run the container builder script
     /// and write the container tarball to the given sink
     pub fn stream_container(&self, mut sink: impl Write) -> Result<(), ContainerBuilderError> {
+        self.run(|stdout| {
+            io::copy(stdout, &mut sink).map_err(ContainerBuilderError::StreamContainer)
+        })?;
+        Ok(())
+    }
+
+    /// Run the container builder script
+    /// and unpack the container tarball
+    ///
+    /// `progress` is called with the number of bytes read so far.
+    pub fn build_image(
+        &self,
+        progress: impl FnMut(u64),
+    ) -> Result<UnpackedImage, ContainerBuilderError> {
+        self.run(|stdout| {
+            UnpackedImage::from_docker_archive(ProgressReader {
+                inner: stdout,
+                read: 0,
+                progress,
+            })
+            .map_err(ContainerBuilderError::UnpackImage)
+        })
+    }
+
+    /// Run the container builder script, pass its stdout to `consume`
+    /// and wait for it to exit.
+    ///
+    /// Output that `consume` leaves unread, e.g. padding after the end of a tarball,
+    /// is discarded, so that the script doesn't fail writing it.
+    /// stderr of the script is collected in the background
+    /// and returned as part of [ContainerBuilderError::BuilderFailed].
+    fn run<T>(
+        &self,
+        consume: impl FnOnce(&mut ChildStdout) -> Result<T, ContainerBuilderError>,
+    ) -> Result<T, ContainerBuilderError> {
         let mut container_builder_command = Command::new(&self.path);
-        container_builder_command.stdout(Stdio::piped());
+        container_builder_command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
 
-        let handle = container_builder_command
+        let mut child = container_builder_command
             .spawn()
             .map_err(ContainerBuilderError::CallContainerBuilder)?;
-        let mut stdout = handle.stdout.expect("stdout set to piped");
+        let mut stdout = child.stdout.take().expect("stdout set to piped");
+        let mut stderr = child.stderr.take().expect("stderr set to piped");
 
-        io::copy(&mut stdout, &mut sink).map_err(ContainerBuilderError::StreamContainer)?;
+        let stderr_reader = thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        });
 
-        Ok(())
+        let result = consume(&mut stdout);
+        match result {
+            Ok(_) => {
+                if let Err(e) = io::copy(&mut stdout, &mut io::sink()) {
+                    debug!("could not drain container builder output: {e}");
+                }
+            },
+            // If the builder is still running, the error is ours.
+            // Stop the builder instead of waiting for it to fail writing to stdout.
+            Err(_) => {
+                let _ = child.kill();
+            },
+        }
+        drop(stdout);
+
+        let status = child
+            .wait()
+            .map_err(ContainerBuilderError::CallContainerBuilder)?;
+        let stderr = stderr_reader.join().unwrap_or_default();
+        debug!("container builder exited with {status}, stderr:\n{stderr}");
+
+        // Killed by us, report the error that made us stop it.
+        // Otherwise a failing builder explains a truncated tarball.
+        if result.is_err() && status.code().is_none() {
+            return result;
+        }
+
+        if !status.success() {
+            return Err(ContainerBuilderError::BuilderFailed { status, stderr });
+        }
+
+        result
+    }
+}
+
+/// Reports the number of bytes read so far to a callback
+struct ProgressReader<R, F> {
+    inner: R,
+    read: u64,
+    progress: F,
+}
+
+impl<R: Read, F: FnMut(u64)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        (self.progress)(self.read);
+        Ok(read)
     }
 }
 
@@ -47,6 +143,10 @@ pub enum ContainerBuilderError {
     CallContainerBuilder(#[source] std::io::Error),
     #[error("failed to stream container to sink")]
     StreamContainer(#[source] std::io::Error),
+    #[error(transparent)]
+    UnpackImage(ContainerImageError),
+    #[error("container builder failed ({status}):\n{stderr}")]
+    BuilderFailed { status: ExitStatus, stderr: String },
 }
 
 #[cfg(test)]
@@ -75,17 +175,30 @@ mod tests {
         echo "hello world"
     "#};
 
-    fn create_test_script() -> (TempDir, PathBuf) {
+    /// Writes more than fits into a pipe buffer
+    const LARGE_OUTPUT_TEST_BUILDER: &str = indoc! {r#"
+        #!/usr/bin/env bash
+        head -c 1000000 /dev/zero
+    "#};
+
+    const FAILING_TEST_BUILDER: &str = indoc! {r#"
+        #!/usr/bin/env bash
+        echo "partial output"
+        echo "builder exploded" >&2
+        exit 3
+    "#};
+
+    fn create_test_script(content: &str) -> (TempDir, PathBuf) {
         let tempdir = tempfile::tempdir().unwrap();
         let path = tempdir.path().join("flox-test-container-builder");
-        std::fs::write(&path, TEST_BUILDER).unwrap();
+        std::fs::write(&path, content).unwrap();
         std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
         (tempdir, path)
     }
 
     #[test]
     fn test_writes_output_to_writer() {
-        let (_tempdir, test_script) = create_test_script();
+        let (_tempdir, test_script) = create_test_script(TEST_BUILDER);
         let container_builder = ContainerBuilder::new(test_script);
 
         let mut buf = Vec::new();
@@ -111,7 +224,7 @@ mod tests {
 
     #[test]
     fn test_allows_forwarding_to_file() {
-        let (tempdir, test_script) = create_test_script();
+        let (tempdir, test_script) = create_test_script(TEST_BUILDER);
         let output_path = tempdir.path().join("output");
 
         let container_builder = ContainerBuilder::new(test_script);
@@ -140,4 +253,68 @@ mod tests {
         let output = fs::read_to_string(&output_path).unwrap();
         assert_eq!(output, "hello world\n");
     }
+
+    #[test]
+    fn test_drains_unconsumed_output() {
+        let (_tempdir, test_script) = create_test_script(LARGE_OUTPUT_TEST_BUILDER);
+        let container_builder = ContainerBuilder::new(test_script);
+
+        // looping to ignore "Text file busy" errors
+        // see the comment on `ERR_TEXT_FILE_BUSY` for more information
+        let mut tries = 0;
+        let read = loop {
+            if tries >= 3 {
+                panic!("Test flaked with 'Text file busy' and can be re-run")
+            }
+            let result = container_builder.run(|stdout| {
+                let mut buf = [0; 512];
+                stdout
+                    .read_exact(&mut buf)
+                    .map_err(ContainerBuilderError::StreamContainer)?;
+                Ok(buf.len())
+            });
+            match result {
+                Err(ContainerBuilderError::CallContainerBuilder(e))
+                    if e.raw_os_error() == Some(ERR_TEXT_FILE_BUSY) =>
+                {
+                    dbg!("Text file busy -- ignored");
+                    tries += 1;
+                    continue;
+                },
+                result => break result.unwrap(),
+            }
+        };
+        assert_eq!(read, 512);
+    }
+
+    #[test]
+    fn test_reports_failing_builder() {
+        let (_tempdir, test_script) = create_test_script(FAILING_TEST_BUILDER);
+        let container_builder = ContainerBuilder::new(test_script);
+
+        // looping to ignore "Text file busy" errors
+        // see the comment on `ERR_TEXT_FILE_BUSY` for more information
+        let mut tries = 0;
+        let err = loop {
+            if tries >= 3 {
+                panic!("Test flaked with 'Text file busy' and can be re-run")
+            }
+            match container_builder.build_image(|_| {}) {
+                Err(ContainerBuilderError::CallContainerBuilder(e))
+                    if e.raw_os_error() == Some(ERR_TEXT_FILE_BUSY) =>
+                {
+                    dbg!("Text file busy -- ignored");
+                    tries += 1;
+                    continue;
+                },
+                result => break result.unwrap_err(),
+            }
+        };
+
+        let ContainerBuilderError::BuilderFailed { status, stderr } = err else {
+            panic!("expected the builder to fail, got {err:?}");
+        };
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(stderr, "builder exploded\n");
+    }
 }
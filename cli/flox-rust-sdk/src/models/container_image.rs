@@ -0,0 +1,598 @@
+//! Container images written by `flox containerize`
+//!
+//! The container builder streams a docker archive
+//! as produced by `dockerTools.streamLayeredImage`.
+//! [UnpackedImage] splits it into content addressed blobs,
+//! which are then written as a docker archive
+//! or as an [OCI image layout](https://github.com/opencontainers/image-spec/blob/main/image-layout.md),
+//! either as a directory or as a tarball.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+use thiserror::Error;
+
+pub const OCI_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+pub const OCI_MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const OCI_CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+pub const OCI_LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar";
+
+/// Format in which `flox containerize` writes images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// A tarball as written by `docker save`
+    #[default]
+    DockerArchive,
+    /// An OCI image layout in a tarball
+    OciArchive,
+    /// An OCI image layout in a directory
+    OciDir,
+}
+
+impl ImageFormat {
+    /// Whether the image is written as a single file that can be streamed
+    pub fn is_archive(&self) -> bool {
+        !matches!(self, ImageFormat::OciDir)
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageFormat::DockerArchive => write!(f, "docker-archive"),
+            ImageFormat::OciArchive => write!(f, "oci-archive"),
+            ImageFormat::OciDir => write!(f, "oci-dir"),
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "docker-archive" => Ok(ImageFormat::DockerArchive),
+            "oci-archive" => Ok(ImageFormat::OciArchive),
+            "oci-dir" => Ok(ImageFormat::OciDir),
+            _ => Err(format!(
+                "unknown format '{s}', expected one of 'docker-archive', 'oci-archive' or 'oci-dir'"
+            )),
+        }
+    }
+}
+
+/// Name and tag of an image, e.g. `myenv:latest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageReference {
+    pub name: String,
+    pub tag: String,
+}
+
+impl Display for ImageReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.name, self.tag)
+    }
+}
+
+/// A content addressed file of an image, i.e. its config or a layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blob {
+    /// `sha256:<hex>`
+    pub digest: String,
+    pub size: u64,
+    path: PathBuf,
+}
+
+impl Blob {
+    /// Copy `content` to `path` while hashing it
+    fn write(content: &mut impl Read, path: PathBuf) -> io::Result<Self> {
+        let mut writer = HashingWriter {
+            inner: File::create(&path)?,
+            hasher: Sha256::new(),
+        };
+        let size = io::copy(content, &mut writer)?;
+        writer.flush()?;
+        Ok(Self {
+            digest: format!("sha256:{:x}", writer.hasher.finalize()),
+            size,
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn hex(&self) -> &str {
+        self.digest.trim_start_matches("sha256:")
+    }
+
+    /// An OCI content descriptor of the blob
+    fn descriptor(&self, media_type: &str) -> serde_json::Value {
+        json!({
+            "mediaType": media_type,
+            "digest": self.digest,
+            "size": self.size,
+        })
+    }
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An entry of `manifest.json` in a docker archive
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifestEntry {
+    config: String,
+    layers: Vec<String>,
+}
+
+/// A container image split into its blobs in a temporary directory
+#[derive(Debug)]
+pub struct UnpackedImage {
+    _dir: TempDir,
+    pub config: Blob,
+    pub layers: Vec<Blob>,
+    /// The OCI image manifest
+    pub manifest: Vec<u8>,
+}
+
+impl UnpackedImage {
+    /// Unpack a docker archive, e.g. the output of a
+    /// [ContainerBuilder](super::container_builder::ContainerBuilder)
+    pub fn from_docker_archive(archive: impl Read) -> Result<Self, ContainerImageError> {
+        let dir = tempfile::tempdir().map_err(ContainerImageError::Unpack)?;
+
+        let mut files = HashMap::new();
+        for entry in tar::Archive::new(archive)
+            .entries()
+            .map_err(ContainerImageError::Unpack)?
+        {
+            let mut entry = entry.map_err(ContainerImageError::Unpack)?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .map_err(ContainerImageError::Unpack)?
+                .to_string_lossy()
+                .into_owned();
+            let path = dir.path().join(files.len().to_string());
+            let blob = Blob::write(&mut entry, path).map_err(ContainerImageError::Unpack)?;
+            files.insert(name, blob);
+        }
+
+        let file = |name: &str| {
+            files
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ContainerImageError::MissingFile(name.to_string()))
+        };
+
+        let docker_manifest =
+            fs::read(file("manifest.json")?.path).map_err(ContainerImageError::Unpack)?;
+        let docker_manifest: Vec<DockerManifestEntry> = serde_json::from_slice(&docker_manifest)
+            .map_err(ContainerImageError::ParseDockerManifest)?;
+        let [docker_manifest] = docker_manifest.as_slice() else {
+            return Err(ContainerImageError::ImageCount(docker_manifest.len()));
+        };
+
+        let config = file(&docker_manifest.config)?;
+        let layers = docker_manifest
+            .layers
+            .iter()
+            .map(|layer| file(layer))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+            "config": config.descriptor(OCI_CONFIG_MEDIA_TYPE),
+            "layers": layers
+                .iter()
+                .map(|layer| layer.descriptor(OCI_LAYER_MEDIA_TYPE))
+                .collect::<Vec<_>>(),
+        });
+
+        Ok(Self {
+            _dir: dir,
+            config,
+            layers,
+            manifest: manifest.to_string().into_bytes(),
+        })
+    }
+
+    /// Digest of the OCI image manifest, which identifies the image
+    pub fn digest(&self) -> String {
+        format!("sha256:{:x}", Sha256::digest(&self.manifest))
+    }
+
+    /// Size of the config and layers of the image
+    pub fn size(&self) -> u64 {
+        self.config.size + self.layers.iter().map(|layer| layer.size).sum::<u64>()
+    }
+
+    /// Write the image as a tarball in `format`
+    pub fn write_archive(
+        &self,
+        format: ImageFormat,
+        reference: &ImageReference,
+        sink: impl Write,
+    ) -> Result<(), ContainerImageError> {
+        let mut archive = tar::Builder::new(sink);
+        match format {
+            ImageFormat::DockerArchive => self.write_docker_archive(reference, &mut archive),
+            ImageFormat::OciArchive => self.write_oci_layout(reference, &mut archive),
+            ImageFormat::OciDir => return Err(ContainerImageError::NotAnArchive(format)),
+        }
+        .and_then(|_| archive.finish())
+        .map_err(ContainerImageError::Write)
+    }
+
+    /// Check that an archive written by [UnpackedImage::write_archive]
+    /// contains all blobs of the image, and that none of them are corrupt
+    pub fn verify_archive(
+        &self,
+        format: ImageFormat,
+        archive: impl Read,
+    ) -> Result<(), ContainerImageError> {
+        let mut missing = std::iter::once(&self.config)
+            .chain(&self.layers)
+            .map(|blob| blob.hex().to_string())
+            .collect::<HashSet<_>>();
+
+        for entry in tar::Archive::new(archive)
+            .entries()
+            .map_err(ContainerImageError::Verify)?
+        {
+            let mut entry = entry.map_err(ContainerImageError::Verify)?;
+            let path = entry
+                .path()
+                .map_err(ContainerImageError::Verify)?
+                .to_string_lossy()
+                .into_owned();
+
+            // blobs are named after their digest
+            let hex = match format {
+                ImageFormat::DockerArchive => path
+                    .strip_suffix("/layer.tar")
+                    .or_else(|| path.strip_suffix(".json")),
+                ImageFormat::OciArchive | ImageFormat::OciDir => path.strip_prefix("blobs/sha256/"),
+            };
+            let Some(hex) = hex.filter(|hex| hex.len() == 64) else {
+                continue;
+            };
+
+            let mut hasher = HashingWriter {
+                inner: io::sink(),
+                hasher: Sha256::new(),
+            };
+            io::copy(&mut entry, &mut hasher).map_err(ContainerImageError::Verify)?;
+            if format!("{:x}", hasher.hasher.finalize()) != hex {
+                return Err(ContainerImageError::CorruptBlob(path));
+            }
+            missing.remove(hex);
+        }
+
+        match missing.into_iter().next() {
+            Some(hex) => Err(ContainerImageError::MissingFile(format!("sha256:{hex}"))),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the image as an OCI image layout in `dir`
+    pub fn write_oci_dir(
+        &self,
+        reference: &ImageReference,
+        dir: &Path,
+    ) -> Result<(), ContainerImageError> {
+        self.write_oci_layout(reference, &mut DirWriter(dir))
+            .map_err(ContainerImageError::Write)
+    }
+
+    fn write_docker_archive(
+        &self,
+        reference: &ImageReference,
+        writer: &mut impl ImageWriter,
+    ) -> io::Result<()> {
+        let config = format!("{}.json", self.config.hex());
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| format!("{}/layer.tar", layer.hex()))
+            .collect::<Vec<_>>();
+
+        writer.add_file(&config, &self.config.path)?;
+        for (path, layer) in layers.iter().zip(&self.layers) {
+            writer.add_file(path, &layer.path)?;
+        }
+
+        let manifest = json!([{
+            "Config": config,
+            "RepoTags": [reference.to_string()],
+            "Layers": layers,
+        }]);
+        writer.add_bytes("manifest.json", manifest.to_string().as_bytes())
+    }
+
+    fn write_oci_layout(
+        &self,
+        reference: &ImageReference,
+        writer: &mut impl ImageWriter,
+    ) -> io::Result<()> {
+        writer.add_bytes(
+            "oci-layout",
+            json!({ "imageLayoutVersion": "1.0.0" })
+                .to_string()
+                .as_bytes(),
+        )?;
+
+        for blob in std::iter::once(&self.config).chain(&self.layers) {
+            writer.add_file(&format!("blobs/sha256/{}", blob.hex()), &blob.path)?;
+        }
+        let digest = self.digest();
+        writer.add_bytes(
+            &format!("blobs/sha256/{}", digest.trim_start_matches("sha256:")),
+            &self.manifest,
+        )?;
+
+        let index = json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_INDEX_MEDIA_TYPE,
+            "manifests": [{
+                "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+                "digest": digest,
+                "size": self.manifest.len(),
+                "annotations": {
+                    "org.opencontainers.image.ref.name": reference.tag,
+                    "io.containerd.image.name": reference.to_string(),
+                },
+            }],
+        });
+        writer.add_bytes("index.json", index.to_string().as_bytes())
+    }
+}
+
+/// Destination of the files of an image
+trait ImageWriter {
+    fn add_bytes(&mut self, path: &str, content: &[u8]) -> io::Result<()>;
+    fn add_file(&mut self, path: &str, source: &Path) -> io::Result<()>;
+}
+
+/// Files in a tarball, with fixed metadata so that archives are reproducible
+impl<W: Write> ImageWriter for tar::Builder<W> {
+    fn add_bytes(&mut self, path: &str, content: &[u8]) -> io::Result<()> {
+        let mut header = tar_header(content.len() as u64);
+        self.append_data(&mut header, path, content)
+    }
+
+    fn add_file(&mut self, path: &str, source: &Path) -> io::Result<()> {
+        let file = File::open(source)?;
+        let mut header = tar_header(file.metadata()?.len());
+        self.append_data(&mut header, path, file)
+    }
+}
+
+fn tar_header(size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header
+}
+
+/// Files in a directory
+struct DirWriter<'a>(&'a Path);
+
+impl DirWriter<'_> {
+    fn create(&self, path: &str) -> io::Result<File> {
+        let path = self.0.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(path)
+    }
+}
+
+impl ImageWriter for DirWriter<'_> {
+    fn add_bytes(&mut self, path: &str, content: &[u8]) -> io::Result<()> {
+        self.create(path)?.write_all(content)
+    }
+
+    fn add_file(&mut self, path: &str, source: &Path) -> io::Result<()> {
+        io::copy(&mut File::open(source)?, &mut self.create(path)?).map(|_| ())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ContainerImageError {
+    #[error("failed to unpack container image")]
+    Unpack(#[source] io::Error),
+    #[error("container image is missing '{0}'")]
+    MissingFile(String),
+    #[error("failed to parse manifest of container image")]
+    ParseDockerManifest(#[source] serde_json::Error),
+    #[error("expected a single container image, found {0}")]
+    ImageCount(usize),
+    #[error("'{0}' images can not be written to a file")]
+    NotAnArchive(ImageFormat),
+    #[error("failed to write container image")]
+    Write(#[source] io::Error),
+    #[error("failed to verify written container image")]
+    Verify(#[source] io::Error),
+    #[error("written container image has a corrupt blob '{0}'")]
+    CorruptBlob(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A docker archive with a single layer containing `hello`
+    fn docker_archive() -> Vec<u8> {
+        let mut layer = tar::Builder::new(Vec::new());
+        layer.add_bytes("hello", b"hello world").unwrap();
+        let layer = layer.into_inner().unwrap();
+        let layer_digest = format!("{:x}", Sha256::digest(&layer));
+
+        let config = json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "rootfs": { "type": "layers", "diff_ids": [format!("sha256:{layer_digest}")] },
+        })
+        .to_string();
+
+        let mut archive = tar::Builder::new(Vec::new());
+        archive
+            .add_bytes(&format!("{layer_digest}/layer.tar"), &layer)
+            .unwrap();
+        archive.add_bytes("config.json", config.as_bytes()).unwrap();
+        archive
+            .add_bytes(
+                "manifest.json",
+                json!([{
+                    "Config": "config.json",
+                    "RepoTags": ["flox-env-container:abc"],
+                    "Layers": [format!("{layer_digest}/layer.tar")],
+                }])
+                .to_string()
+                .as_bytes(),
+            )
+            .unwrap();
+        archive.into_inner().unwrap()
+    }
+
+    fn reference() -> ImageReference {
+        ImageReference {
+            name: "myenv".to_string(),
+            tag: "latest".to_string(),
+        }
+    }
+
+    fn read_archive(archive: &[u8]) -> HashMap<String, Vec<u8>> {
+        tar::Archive::new(archive)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (entry.path().unwrap().display().to_string(), content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unpacks_docker_archive() {
+        let image = UnpackedImage::from_docker_archive(docker_archive().as_slice()).unwrap();
+
+        assert_eq!(image.layers.len(), 1);
+        let manifest: serde_json::Value = serde_json::from_slice(&image.manifest).unwrap();
+        assert_eq!(manifest["layers"][0]["digest"], image.layers[0].digest);
+        assert_eq!(manifest["config"]["digest"], image.config.digest);
+        assert_eq!(image.size(), image.config.size + image.layers[0].size);
+    }
+
+    #[test]
+    fn writes_oci_archive() {
+        let image = UnpackedImage::from_docker_archive(docker_archive().as_slice()).unwrap();
+
+        let mut archive = Vec::new();
+        image
+            .write_archive(ImageFormat::OciArchive, &reference(), &mut archive)
+            .unwrap();
+        let files = read_archive(&archive);
+
+        let digest = image.digest();
+        assert_eq!(
+            files[&format!("blobs/sha256/{}", digest.trim_start_matches("sha256:"))],
+            image.manifest
+        );
+        let index: serde_json::Value = serde_json::from_slice(&files["index.json"]).unwrap();
+        assert_eq!(index["manifests"][0]["digest"], digest);
+        assert_eq!(
+            index["manifests"][0]["annotations"]["org.opencontainers.image.ref.name"],
+            "latest"
+        );
+        assert!(files.contains_key(&format!("blobs/sha256/{}", image.layers[0].hex())));
+        assert!(files.contains_key("oci-layout"));
+    }
+
+    #[test]
+    fn writes_docker_archive_with_reference() {
+        let image = UnpackedImage::from_docker_archive(docker_archive().as_slice()).unwrap();
+
+        let mut archive = Vec::new();
+        image
+            .write_archive(ImageFormat::DockerArchive, &reference(), &mut archive)
+            .unwrap();
+        let files = read_archive(&archive);
+
+        let manifest: serde_json::Value = serde_json::from_slice(&files["manifest.json"]).unwrap();
+        assert_eq!(manifest[0]["RepoTags"], json!(["myenv:latest"]));
+        let layer = manifest[0]["Layers"][0].as_str().unwrap();
+        assert_eq!(
+            format!("sha256:{:x}", Sha256::digest(&files[layer])),
+            image.layers[0].digest
+        );
+    }
+
+    #[test]
+    fn verifies_written_archive() {
+        let image = UnpackedImage::from_docker_archive(docker_archive().as_slice()).unwrap();
+
+        for format in [ImageFormat::DockerArchive, ImageFormat::OciArchive] {
+            let mut archive = Vec::new();
+            image
+                .write_archive(format, &reference(), &mut archive)
+                .unwrap();
+            image.verify_archive(format, archive.as_slice()).unwrap();
+
+            // corrupt the content of the layer
+            let offset = archive
+                .windows(b"hello world".len())
+                .position(|window| window == b"hello world")
+                .unwrap();
+            archive[offset] = b'j';
+            assert!(matches!(
+                image.verify_archive(format, archive.as_slice()),
+                Err(ContainerImageError::CorruptBlob(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_archive_without_manifest() {
+        let mut archive = tar::Builder::new(Vec::new());
+        archive.add_bytes("config.json", b"{}").unwrap();
+        let archive = archive.into_inner().unwrap();
+
+        assert!(matches!(
+            UnpackedImage::from_docker_archive(archive.as_slice()),
+            Err(ContainerImageError::MissingFile(file)) if file == "manifest.json"
+        ));
+    }
+}
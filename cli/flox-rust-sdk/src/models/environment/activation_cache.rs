@@ -0,0 +1,203 @@
+//! Cache of activation results
+//!
+//! Determining the activation path of an environment may require
+//! locking and building it with pkgdb.
+//! To avoid that on every activation, we record the store path the
+//! environment's out link pointed to after the last build
+//! and the variables exported by its activations,
+//! keyed on a hash of the files that define the environment.
+//! As long as neither those files nor the out link change,
+//! the out link can be activated as is.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::{FLOX_ENV_CACHE_VAR, FLOX_ENV_PROJECT_VAR, FLOX_ENV_VAR};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct ActivationCache {
+    /// Hash of the files defining the environment
+    key: String,
+    /// Store path the out link pointed to when the entry was written
+    store_path: PathBuf,
+    /// Variables exported by activations of the environment,
+    /// see [exports]
+    exports: BTreeMap<String, String>,
+}
+
+/// Variables describing an environment to its activations
+pub(super) fn exports(
+    activation_path: &Path,
+    cache_path: &Path,
+    project_path: &Path,
+) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (
+            FLOX_ENV_VAR.to_string(),
+            activation_path.to_string_lossy().to_string(),
+        ),
+        (
+            FLOX_ENV_CACHE_VAR.to_string(),
+            cache_path.to_string_lossy().to_string(),
+        ),
+        (
+            FLOX_ENV_PROJECT_VAR.to_string(),
+            project_path.to_string_lossy().to_string(),
+        ),
+    ])
+}
+
+impl ActivationCache {
+    /// Compute a cache key from the contents of `files`
+    ///
+    /// Missing files are hashed as such,
+    /// so creating or removing a file invalidates the key.
+    pub(super) fn key(files: &[PathBuf]) -> String {
+        let mut hasher = blake3::Hasher::new();
+        for file in files {
+            match fs::read(file) {
+                Ok(content) => {
+                    hasher.update(&[1]);
+                    hasher.update(&(content.len() as u64).to_le_bytes());
+                    hasher.update(&content);
+                },
+                Err(_) => {
+                    hasher.update(&[0]);
+                },
+            }
+        }
+        hasher.finalize().to_string()
+    }
+
+    fn cache_file(cache_dir: &Path, system: &str) -> PathBuf {
+        cache_dir.join(format!("activation.{system}.json"))
+    }
+
+    /// The cached exports of `out_link`,
+    /// if it can be activated without rebuilding
+    ///
+    /// That is the case if a cache entry for `key` exists,
+    /// and `out_link` still points to the cached store path.
+    pub(super) fn load(
+        cache_dir: &Path,
+        system: &str,
+        key: &str,
+        out_link: &Path,
+    ) -> Option<BTreeMap<String, String>> {
+        let content = fs::read_to_string(Self::cache_file(cache_dir, system)).ok()?;
+        let Ok(cached) = serde_json::from_str::<Self>(&content) else {
+            debug!("ignoring invalid activation cache");
+            return None;
+        };
+
+        let fresh = cached.key == key
+            && out_link.read_link().ok().as_ref() == Some(&cached.store_path)
+            && cached.store_path.exists();
+        fresh.then_some(cached.exports)
+    }
+
+    /// Record the store path `out_link` currently points to
+    /// and the `exports` of its activations for `key`
+    ///
+    /// Failing to write the cache only means the next activation is slower,
+    /// so errors are logged rather than returned.
+    pub(super) fn store(
+        cache_dir: &Path,
+        system: &str,
+        key: String,
+        out_link: &Path,
+        exports: &BTreeMap<String, String>,
+    ) {
+        let Ok(store_path) = out_link.read_link() else {
+            debug!("not caching activation: out link does not exist");
+            return;
+        };
+
+        let entry = Self {
+            key,
+            store_path,
+            exports: exports.clone(),
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                fs::write(Self::cache_file(cache_dir, system), content).map_err(|e| e.to_string())
+            });
+
+        if let Err(e) = result {
+            debug!("could not write activation cache: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn key_changes_with_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("manifest.toml");
+        let lockfile = dir.path().join("manifest.lock");
+
+        fs::write(&manifest, "version = 1").unwrap();
+        let without_lockfile = ActivationCache::key(&[manifest.clone(), lockfile.clone()]);
+
+        fs::write(&lockfile, "").unwrap();
+        let with_lockfile = ActivationCache::key(&[manifest.clone(), lockfile.clone()]);
+        assert_ne!(without_lockfile, with_lockfile);
+
+        fs::write(&manifest, "version = 1").unwrap();
+        assert_eq!(
+            with_lockfile,
+            ActivationCache::key(&[manifest.clone(), lockfile.clone()])
+        );
+
+        fs::write(&manifest, "version = 2").unwrap();
+        assert_ne!(with_lockfile, ActivationCache::key(&[manifest, lockfile]));
+    }
+
+    #[test]
+    fn fresh_until_out_link_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_path = dir.path().join("store-path");
+        let other_store_path = dir.path().join("other-store-path");
+        fs::create_dir(&store_path).unwrap();
+        fs::create_dir(&other_store_path).unwrap();
+        let out_link = dir.path().join("out-link");
+        let exports = exports(&out_link, dir.path(), dir.path());
+
+        assert_eq!(
+            ActivationCache::load(dir.path(), "system", "key", &out_link),
+            None
+        );
+
+        symlink(&store_path, &out_link).unwrap();
+        ActivationCache::store(dir.path(), "system", "key".to_string(), &out_link, &exports);
+        assert_eq!(
+            ActivationCache::load(dir.path(), "system", "key", &out_link),
+            Some(exports)
+        );
+        assert_eq!(
+            ActivationCache::load(dir.path(), "system", "other-key", &out_link),
+            None
+        );
+        assert_eq!(
+            ActivationCache::load(dir.path(), "other-system", "key", &out_link),
+            None
+        );
+
+        fs::remove_file(&out_link).unwrap();
+        symlink(&other_store_path, &out_link).unwrap();
+        assert_eq!(
+            ActivationCache::load(dir.path(), "system", "key", &out_link),
+            None
+        );
+    }
+}
@@ -4,8 +4,10 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use log::debug;
+use tempfile::TempPath;
 use thiserror::Error;
 
+use super::include::{self, IncludeError};
 use super::{
     copy_dir_recursive,
     CanonicalizeError,
@@ -44,6 +46,11 @@ pub struct CoreEnvironment<State = ReadOnly> {
     ///
     /// Commonly /.../.flox/env/
     env_dir: PathBuf,
+    /// The directory environments included with `dir` are relative to
+    ///
+    /// Only local environments can include directories,
+    /// see [include::compose].
+    include_dir: Option<PathBuf>,
     _state: State,
 }
 
@@ -70,6 +77,45 @@ impl<State> CoreEnvironment<State> {
         fs::read_to_string(self.manifest_path()).map_err(CoreEnvironmentError::OpenManifest)
     }
 
+    /// Merge the environments included by the manifest into it
+    ///
+    /// Returns the path of a temporary file containing the composed manifest,
+    /// or `None` if the manifest does not include other environments.
+    /// Unless `refresh_includes` is set,
+    /// the generations of included FloxHub environments recorded in the
+    /// lockfile are included again.
+    fn compose_manifest(
+        &self,
+        flox: &Flox,
+        refresh_includes: bool,
+    ) -> Result<Option<TempPath>, CoreEnvironmentError> {
+        let pinned = if refresh_includes {
+            Default::default()
+        } else {
+            include::pinned_generations(&self.lockfile_path())
+        };
+        let Some(composed) = include::compose(
+            flox,
+            &self.manifest_content()?,
+            self.include_dir.as_deref(),
+            &pinned,
+        )
+        .map_err(|e| CoreEnvironmentError::Include(Box::new(e)))?
+        else {
+            return Ok(None);
+        };
+
+        // pkgdb tells manifest formats apart by their extension
+        let mut file = tempfile::Builder::new()
+            .suffix(".toml")
+            .tempfile_in(&flox.temp_dir)
+            .map_err(CoreEnvironmentError::WriteComposedManifest)?;
+        file.write_all(composed.as_bytes())
+            .map_err(CoreEnvironmentError::WriteComposedManifest)?;
+        debug!("composed manifest: path={}", file.path().display());
+        Ok(Some(file.into_temp_path()))
+    }
+
     /// Lock the environment.
     ///
     /// This re-writes the lock if it exists.
@@ -82,7 +128,11 @@ impl<State> CoreEnvironment<State> {
     ///
     /// todo: should we always write the lockfile to disk?
     pub fn lock(&mut self, flox: &Flox) -> Result<LockedManifest, CoreEnvironmentError> {
-        let manifest_path = self.manifest_path();
+        let composed = self.compose_manifest(flox, false)?;
+        let manifest_path = match &composed {
+            Some(composed) => composed.to_path_buf(),
+            None => self.manifest_path(),
+        };
         let environment_lockfile_path = self.lockfile_path();
         let existing_lockfile_path = if environment_lockfile_path.exists() {
             debug!(
@@ -242,10 +292,17 @@ impl CoreEnvironment<ReadOnly> {
     pub fn new(env_dir: impl AsRef<Path>) -> Self {
         CoreEnvironment {
             env_dir: env_dir.as_ref().to_path_buf(),
+            include_dir: None,
             _state: ReadOnly {},
         }
     }
 
+    /// Allow the environment to include directories relative to `include_dir`
+    pub fn with_include_dir(mut self, include_dir: impl AsRef<Path>) -> Self {
+        self.include_dir = Some(include_dir.as_ref().to_path_buf());
+        self
+    }
+
     /// Install packages to the environment atomically
     ///
     /// Returns the new manifest content if the environment was modified. Also
@@ -358,11 +415,19 @@ impl CoreEnvironment<ReadOnly> {
     }
 
     /// Update the inputs of an environment atomically.
+    ///
+    /// Included FloxHub environments are updated to their latest generation.
     pub fn update(
         &mut self,
         flox: &Flox,
         inputs: Vec<String>,
     ) -> Result<UpdateResult, CoreEnvironmentError> {
+        let composed = self.compose_manifest(flox, true)?;
+        let manifest_path = match &composed {
+            Some(composed) => composed.to_path_buf(),
+            None => self.manifest_path(),
+        };
+
         // TODO: double check canonicalization
         let UpdateResult {
             new_lockfile,
@@ -370,7 +435,7 @@ impl CoreEnvironment<ReadOnly> {
             ..
         } = LockedManifest::update_manifest(
             flox,
-            Some(self.manifest_path()),
+            Some(manifest_path),
             self.lockfile_path(),
             inputs,
         )
@@ -394,8 +459,13 @@ impl CoreEnvironment<ReadOnly> {
         flox: &Flox,
         groups_or_iids: &[String],
     ) -> Result<UpgradeResult, CoreEnvironmentError> {
+        let composed = self.compose_manifest(flox, false)?;
+        let manifest_path = match &composed {
+            Some(composed) => composed.to_path_buf(),
+            None => self.manifest_path(),
+        };
+
         // TODO double check canonicalization
-        let manifest_path = self.manifest_path();
         let lockfile_path = self.lockfile_path();
         let maybe_lockfile = if lockfile_path.exists() {
             debug!("found existing lockfile: {}", lockfile_path.display());
@@ -447,6 +517,7 @@ impl CoreEnvironment<ReadOnly> {
 
         Ok(CoreEnvironment {
             env_dir: tempdir.as_ref().to_path_buf(),
+            include_dir: self.include_dir.clone(),
             _state: ReadWrite {},
         })
     }
@@ -681,6 +752,12 @@ pub enum CoreEnvironmentError {
     #[error(transparent)]
     BadLockfilePath(CanonicalizeError),
 
+    /// Boxed since included environments can fail in many ways
+    #[error(transparent)]
+    Include(Box<IncludeError>),
+    #[error("could not write composed manifest")]
+    WriteComposedManifest(#[source] std::io::Error),
+
     // todo: refactor upgrade to use `LockedManifest`
     #[error("unexpected output from pkgdb upgrade")]
     ParseUpgradeOutput(#[source] serde_json::Error),
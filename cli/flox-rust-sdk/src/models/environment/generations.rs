@@ -30,6 +30,7 @@ use super::core_environment::CoreEnvironment;
 use super::{copy_dir_recursive, PathPointer, ENV_DIR_NAME};
 use crate::data::Version;
 use crate::models::environment::MANIFEST_FILENAME;
+use crate::models::environment_ref::EnvironmentRef;
 use crate::providers::git::{
     GitCommandError,
     GitCommandOptions,
@@ -319,6 +320,29 @@ impl Generations<ReadWrite> {
         self.register_generation(environment, *max + 1, description, true)
     }
 
+    /// Record that the generations were copied from another environment
+    ///
+    /// The generations themselves are left unchanged.
+    pub fn set_forked_from(&mut self, forked_from: ForkedFrom) -> Result<(), GenerationsError> {
+        let mut metadata = self.metadata()?;
+        let message = format!("Fork environment {}", forked_from.environment);
+        metadata.forked_from = Some(forked_from);
+
+        write_metadata_file(metadata, self.repo.path())?;
+
+        self.repo
+            .add(&[Path::new(GENERATIONS_METADATA_FILE)])
+            .map_err(GenerationsError::StageChanges)?;
+        self.repo
+            .commit(&message)
+            .map_err(GenerationsError::CommitChanges)?;
+        self.repo
+            .push("origin", false)
+            .map_err(GenerationsError::CompleteTransaction)?;
+
+        Ok(())
+    }
+
     /// Switch to a provided generation.
     ///
     /// Fails if the generation does not exist.
@@ -460,6 +484,10 @@ pub struct AllGenerationsMetadata {
     /// Entries in this map must match up 1-to-1 with the generation folders
     /// in the environment branch.
     pub generations: BTreeMap<GenerationId, SingleGenerationMetadata>,
+    /// The environment these generations were copied from,
+    /// if the environment is a fork
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<ForkedFrom>,
     /// Schema version of the metadata file, not yet utilized
     #[serde(default)]
     version: Version<1>,
@@ -497,6 +525,19 @@ impl SingleGenerationMetadata {
     }
 }
 
+/// The environment that a forked environment was copied from
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ForkedFrom {
+    /// The original environment
+    pub environment: EnvironmentRef,
+    /// The revision of the original environment's floxmeta branch that was copied
+    pub rev: String,
+    /// unix timestamp of the time the environment was forked
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub created: DateTime<Utc>,
+}
+
 #[derive(
     Debug,
     Clone,
@@ -517,6 +558,47 @@ pub struct GenerationId(usize);
 
 #[cfg(test)]
 mod tests {
-    // todo: tests for this will be easier with the `init` method implemented
-    // in https://github.com/flox/flox/pull/563
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::flox::tests::flox_instance;
+
+    /// Recording a fork keeps the generations and adds [ForkedFrom] to the metadata
+    #[test]
+    fn set_forked_from_records_source() {
+        let (flox, _temp_dir_handle) = flox_instance();
+
+        let generations = Generations::init(
+            GitCommandOptions::default(),
+            tempfile::tempdir_in(&flox.temp_dir).unwrap().into_path(),
+            tempfile::tempdir_in(&flox.temp_dir).unwrap().into_path(),
+            "branch".to_string(),
+            &PathPointer::new("name".parse().unwrap()),
+        )
+        .unwrap();
+        let mut generations = generations.writable(&flox.temp_dir).unwrap();
+
+        let env_path = tempfile::tempdir_in(&flox.temp_dir).unwrap();
+        fs::write(env_path.path().join(MANIFEST_FILENAME), "").unwrap();
+        generations
+            .add_generation(
+                &mut CoreEnvironment::new(env_path.path()),
+                "first generation".to_string(),
+            )
+            .unwrap();
+
+        let forked_from = ForkedFrom {
+            environment: EnvironmentRef::new("owner", "name").unwrap(),
+            rev: "0123456789abcdef".to_string(),
+            created: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        };
+        generations.set_forked_from(forked_from.clone()).unwrap();
+
+        let metadata = generations.metadata().unwrap();
+        assert_eq!(metadata.forked_from, Some(forked_from));
+        assert_eq!(metadata.current_gen, Some(1.into()));
+        assert_eq!(metadata.generations.keys().collect::<Vec<_>>(), vec![
+            &GenerationId::from(1)
+        ]);
+    }
 }
@@ -0,0 +1,532 @@
+//! Composing environments from other environments
+//!
+//! A manifest may include other environments, e.g.
+//!
+//! ```toml
+//! [include]
+//! environments = [
+//!   { dir = "../toolchain" },
+//!   { remote = "myorg/toolchain" },
+//! ]
+//! ```
+//!
+//! Before a manifest is locked, the `install`, `vars` and `hook` sections
+//! of the environments it includes are merged into it,
+//! and pkgdb locks the composed manifest like any other.
+//! The composed manifest records the generation of every included
+//! FloxHub environment in its `include` section,
+//! so the lockfile pins the exact generation that was included.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use super::generations::{Generations, GenerationsError};
+use super::managed_environment::remote_branch_name;
+use super::{ManagedPointer, DOT_FLOX, ENV_DIR_NAME, LOCKFILE_FILENAME, MANIFEST_FILENAME};
+use crate::flox::Flox;
+use crate::models::environment_ref::EnvironmentRef;
+use crate::models::floxmetav2::{FloxmetaV2, FloxmetaV2Error};
+use crate::providers::git::GitRemoteCommandError;
+
+/// Sections of included manifests that are merged key by key
+const MERGED_SECTIONS: [&str; 2] = ["install", "vars"];
+
+/// Hooks of included manifests that are run before those of the manifest
+const MERGED_HOOKS: [&str; 3] = ["script", "on-activate", "on-deactivate"];
+
+#[derive(Debug, Error)]
+pub enum IncludeError {
+    #[error("could not parse manifest")]
+    ParseManifest(#[source] toml::de::Error),
+    #[error("invalid 'include' section in manifest")]
+    ParseInclude(#[source] toml::de::Error),
+    #[error("included environments must set exactly one of 'dir' or 'remote'")]
+    InvalidDescriptor,
+    #[error("'generation' can only be set for environments included with 'remote'")]
+    GenerationWithoutRemote,
+    #[error("'{0}' can not be included, only local environments can include directories")]
+    DirNotSupported(PathBuf),
+    #[error("could not read included environment in '{0}'")]
+    ReadDir(PathBuf, #[source] std::io::Error),
+    #[error("could not open included environment '{0}'")]
+    OpenRemote(EnvironmentRef, #[source] FloxmetaV2Error),
+    #[error("could not fetch included environment '{0}'")]
+    FetchRemote(EnvironmentRef, #[source] GitRemoteCommandError),
+    #[error("could not read included environment '{0}'")]
+    ReadGeneration(EnvironmentRef, #[source] GenerationsError),
+    #[error("could not parse manifest of included environment '{0}'")]
+    ParseIncluded(String, #[source] toml::de::Error),
+    #[error("included environment '{0}' includes other environments, which is not supported")]
+    Nested(String),
+    #[error(
+        "'{key}' is defined differently by the included environments '{first}' and '{second}'; \
+         define it in the manifest to override both"
+    )]
+    Conflict {
+        key: String,
+        first: String,
+        second: String,
+    },
+    #[error("could not serialize composed manifest")]
+    SerializeManifest(#[source] toml::ser::Error),
+}
+
+/// The `include` section of a manifest
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Include {
+    #[serde(default)]
+    environments: Vec<IncludeDescriptor>,
+}
+
+/// An environment included by a manifest
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct IncludeDescriptor {
+    /// Directory containing a local environment,
+    /// relative to the directory containing the including environment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dir: Option<PathBuf>,
+    /// Environment on FloxHub
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote: Option<EnvironmentRef>,
+    /// Generation of a FloxHub environment to include,
+    /// recorded when the environment is first included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation: Option<usize>,
+}
+
+/// An environment to merge into a manifest
+struct Included {
+    /// How the environment was included, used in error messages
+    name: String,
+    manifest: toml::Table,
+}
+
+/// Merge the environments included by `manifest` into it
+///
+/// Returns `None` if the manifest does not include any environments.
+/// Directories are included relative to `include_dir`,
+/// and can not be included at all without one.
+/// FloxHub environments are included at the generation recorded in `pinned`,
+/// if any, or else at their latest generation.
+pub(super) fn compose(
+    flox: &Flox,
+    manifest: &str,
+    include_dir: Option<&Path>,
+    pinned: &HashMap<EnvironmentRef, usize>,
+) -> Result<Option<String>, IncludeError> {
+    let mut manifest: toml::Table =
+        toml::from_str(manifest).map_err(IncludeError::ParseManifest)?;
+    let include: Include = match manifest.remove("include") {
+        Some(include) => include.try_into().map_err(IncludeError::ParseInclude)?,
+        None => return Ok(None),
+    };
+    if include.environments.is_empty() {
+        return Ok(None);
+    }
+
+    let mut included = Vec::new();
+    let mut locked = Vec::new();
+    for descriptor in include.environments {
+        let (name, content, descriptor) = match descriptor {
+            IncludeDescriptor {
+                dir: Some(dir),
+                remote: None,
+                generation: None,
+            } => {
+                let Some(include_dir) = include_dir else {
+                    return Err(IncludeError::DirNotSupported(dir));
+                };
+                let manifest_path = include_dir
+                    .join(&dir)
+                    .join(DOT_FLOX)
+                    .join(ENV_DIR_NAME)
+                    .join(MANIFEST_FILENAME);
+                let content = fs::read_to_string(manifest_path)
+                    .map_err(|e| IncludeError::ReadDir(dir.clone(), e))?;
+                let name = dir.to_string_lossy().to_string();
+                (name, content, IncludeDescriptor {
+                    dir: Some(dir),
+                    remote: None,
+                    generation: None,
+                })
+            },
+            IncludeDescriptor {
+                dir: None,
+                remote: Some(env_ref),
+                generation,
+            } => {
+                let generation = generation.or_else(|| pinned.get(&env_ref).copied());
+                let (generation, content) = remote_manifest(flox, &env_ref, generation)?;
+                (env_ref.to_string(), content, IncludeDescriptor {
+                    dir: None,
+                    remote: Some(env_ref),
+                    generation: Some(generation),
+                })
+            },
+            IncludeDescriptor {
+                dir: Some(_),
+                remote: None,
+                generation: Some(_),
+            } => return Err(IncludeError::GenerationWithoutRemote),
+            _ => return Err(IncludeError::InvalidDescriptor),
+        };
+
+        let manifest: toml::Table =
+            toml::from_str(&content).map_err(|e| IncludeError::ParseIncluded(name.clone(), e))?;
+        if manifest.contains_key("include") {
+            return Err(IncludeError::Nested(name));
+        }
+        included.push(Included { name, manifest });
+        locked.push(descriptor);
+    }
+
+    merge(&mut manifest, included)?;
+
+    let include = Include {
+        environments: locked,
+    };
+    manifest.insert(
+        "include".to_string(),
+        toml::Value::try_from(include).map_err(IncludeError::SerializeManifest)?,
+    );
+    toml::to_string(&manifest)
+        .map(Some)
+        .map_err(IncludeError::SerializeManifest)
+}
+
+/// The manifests and lockfiles of the directories included by `manifest`,
+/// relative to `include_dir`
+///
+/// Changes to these files change the composed manifest,
+/// whereas FloxHub environments are pinned by the lockfile of the including environment.
+/// Manifests that can't be parsed don't include any files.
+pub(super) fn included_files(manifest: &str, include_dir: &Path) -> Vec<PathBuf> {
+    let Some(include) = toml::from_str::<toml::Table>(manifest)
+        .ok()
+        .and_then(|mut manifest| manifest.remove("include"))
+        .and_then(|include| include.try_into::<Include>().ok())
+    else {
+        return Vec::new();
+    };
+
+    include
+        .environments
+        .into_iter()
+        .filter_map(|descriptor| descriptor.dir)
+        .flat_map(|dir| {
+            let env_dir = include_dir.join(dir).join(DOT_FLOX).join(ENV_DIR_NAME);
+            [
+                env_dir.join(MANIFEST_FILENAME),
+                env_dir.join(LOCKFILE_FILENAME),
+            ]
+        })
+        .collect()
+}
+
+/// The generations of FloxHub environments recorded in a lockfile
+///
+/// Lockfiles that can't be read or don't include any environments
+/// don't pin any generations.
+pub(super) fn pinned_generations(lockfile_path: &Path) -> HashMap<EnvironmentRef, usize> {
+    let Ok(content) = fs::read_to_string(lockfile_path) else {
+        return HashMap::new();
+    };
+    let Some(include) = serde_json::from_str::<Value>(&content)
+        .ok()
+        .and_then(|lockfile| lockfile.pointer("/manifest/include").cloned())
+        .and_then(|include| serde_json::from_value::<Include>(include).ok())
+    else {
+        return HashMap::new();
+    };
+
+    include
+        .environments
+        .into_iter()
+        .filter_map(|descriptor| Some((descriptor.remote?, descriptor.generation?)))
+        .collect()
+}
+
+/// Read the manifest of a generation of a FloxHub environment,
+/// or of its latest generation if `generation` is `None`
+fn remote_manifest(
+    flox: &Flox,
+    env_ref: &EnvironmentRef,
+    generation: Option<usize>,
+) -> Result<(usize, String), IncludeError> {
+    let pointer = ManagedPointer::new(
+        env_ref.owner().clone(),
+        env_ref.name().clone(),
+        &flox.floxhub,
+    );
+    let floxmeta = match FloxmetaV2::open(flox, &pointer) {
+        Ok(floxmeta) => floxmeta,
+        Err(FloxmetaV2Error::NotFound(_)) => FloxmetaV2::clone(flox, &pointer)
+            .map_err(|e| IncludeError::OpenRemote(env_ref.clone(), e))?,
+        Err(e) => Err(IncludeError::OpenRemote(env_ref.clone(), e))?,
+    };
+
+    let branch = remote_branch_name(&pointer);
+    let generations = Generations::new(floxmeta.git.clone(), branch.clone());
+
+    // Generations never change once pushed,
+    // so only fetch if the generation isn't available locally
+    let available = generation.is_some_and(|generation| generations.manifest(generation).is_ok());
+    if !available {
+        floxmeta
+            .git
+            .fetch_ref("dynamicorigin", &format!("+{branch}:{branch}"))
+            .map_err(|e| IncludeError::FetchRemote(env_ref.clone(), e))?;
+    }
+
+    let generation = match generation {
+        Some(generation) => generation,
+        None => *generations
+            .metadata()
+            .map_err(|e| IncludeError::ReadGeneration(env_ref.clone(), e))?
+            .current_gen
+            .ok_or(IncludeError::ReadGeneration(
+                env_ref.clone(),
+                GenerationsError::NoGenerations,
+            ))?,
+    };
+    let manifest = generations
+        .manifest(generation)
+        .map_err(|e| IncludeError::ReadGeneration(env_ref.clone(), e))?;
+
+    Ok((generation, manifest))
+}
+
+/// Merge included manifests into a manifest
+///
+/// Entries of [MERGED_SECTIONS] defined by the manifest itself take precedence,
+/// entries defined differently by two included manifests are a conflict.
+/// Hooks of the included manifests run in the order they were included,
+/// before those of the manifest itself.
+fn merge(manifest: &mut toml::Table, included: Vec<Included>) -> Result<(), IncludeError> {
+    for section in MERGED_SECTIONS {
+        let mut merged: toml::Table = toml::Table::new();
+        let mut sources: HashMap<String, &str> = HashMap::new();
+
+        for Included {
+            name,
+            manifest: included,
+        } in &included
+        {
+            let Some(entries) = included.get(section).and_then(toml::Value::as_table) else {
+                continue;
+            };
+            for (key, value) in entries {
+                match merged.get(key) {
+                    Some(existing) if existing != value => {
+                        // Entries of the manifest itself resolve conflicts
+                        let overridden = manifest
+                            .get(section)
+                            .and_then(toml::Value::as_table)
+                            .is_some_and(|own| own.contains_key(key));
+                        if !overridden {
+                            return Err(IncludeError::Conflict {
+                                key: format!("{section}.{key}"),
+                                first: sources[key].to_string(),
+                                second: name.clone(),
+                            });
+                        }
+                    },
+                    Some(_) => {},
+                    None => {
+                        merged.insert(key.clone(), value.clone());
+                        sources.insert(key.clone(), name);
+                    },
+                }
+            }
+        }
+
+        if merged.is_empty() {
+            continue;
+        }
+        let own = manifest
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if let Some(own) = own.as_table_mut() {
+            for (key, value) in merged {
+                own.entry(key).or_insert(value);
+            }
+        }
+    }
+
+    for hook in MERGED_HOOKS {
+        let mut scripts: Vec<&str> = included
+            .iter()
+            .filter_map(|Included { manifest, .. }| manifest.get("hook")?.get(hook)?.as_str())
+            .collect();
+        if scripts.is_empty() {
+            continue;
+        }
+        let own_hooks = manifest
+            .entry("hook")
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        let Some(own_hooks) = own_hooks.as_table_mut() else {
+            continue;
+        };
+        if let Some(own) = own_hooks.get(hook).and_then(toml::Value::as_str) {
+            scripts.push(own);
+        }
+        let script = scripts.join("\n");
+        own_hooks.insert(hook.to_string(), toml::Value::String(script));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    fn included(name: &str, manifest: &str) -> Included {
+        Included {
+            name: name.to_string(),
+            manifest: toml::from_str(manifest).unwrap(),
+        }
+    }
+
+    #[test]
+    fn merge_prefers_own_entries() {
+        let mut manifest: toml::Table = toml::from_str(indoc! {r#"
+            [install]
+            hello.pkg-path = "hello"
+
+            [vars]
+            GREETING = "hi"
+        "#})
+        .unwrap();
+
+        merge(&mut manifest, vec![included("toolchain", indoc! {r#"
+            [install]
+            hello.pkg-path = "hello-unstable"
+            gcc.pkg-path = "gcc"
+
+            [vars]
+            GREETING = "hello"
+            CC = "gcc"
+        "#})])
+        .unwrap();
+
+        assert_eq!(
+            manifest,
+            toml::from_str(indoc! {r#"
+            [install]
+            hello.pkg-path = "hello"
+            gcc.pkg-path = "gcc"
+
+            [vars]
+            GREETING = "hi"
+            CC = "gcc"
+        "#})
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn merge_rejects_conflicting_includes() {
+        let mut manifest = toml::Table::new();
+        let err = merge(&mut manifest, vec![
+            included("a", r#"vars.CC = "gcc""#),
+            included("b", r#"vars.CC = "clang""#),
+        ])
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            IncludeError::Conflict { key, first, second }
+                if key == "vars.CC" && first == "a" && second == "b"
+        ));
+    }
+
+    #[test]
+    fn merge_resolves_conflicts_with_own_entries() {
+        let mut manifest: toml::Table = toml::from_str(r#"vars.CC = "tcc""#).unwrap();
+        merge(&mut manifest, vec![
+            included("a", r#"vars.CC = "gcc""#),
+            included("b", r#"vars.CC = "clang""#),
+        ])
+        .unwrap();
+
+        assert_eq!(manifest, toml::from_str(r#"vars.CC = "tcc""#).unwrap());
+    }
+
+    #[test]
+    fn merge_runs_included_hooks_first() {
+        let mut manifest: toml::Table = toml::from_str(r#"hook.on-activate = "echo own""#).unwrap();
+        merge(&mut manifest, vec![
+            included("a", r#"hook.on-activate = "echo a""#),
+            included("b", r#"hook.on-deactivate = "echo b""#),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            manifest,
+            toml::from_str(indoc! {r#"
+            hook.on-activate = """
+            echo a
+            echo own"""
+            hook.on-deactivate = "echo b"
+        "#})
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn included_files_of_dirs() {
+        let manifest = indoc! {r#"
+            [include]
+            environments = [
+              { dir = "../toolchain" },
+              { remote = "owner/name" },
+            ]
+        "#};
+
+        let env_dir = Path::new("/project/../toolchain/.flox/env");
+        assert_eq!(included_files(manifest, Path::new("/project")), vec![
+            env_dir.join(MANIFEST_FILENAME),
+            env_dir.join(LOCKFILE_FILENAME)
+        ]);
+        assert_eq!(
+            included_files("version = 1", Path::new("/project")),
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[test]
+    fn pinned_generations_from_lockfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let lockfile_path = dir.path().join("manifest.lock");
+        fs::write(
+            &lockfile_path,
+            serde_json::json!({
+                "manifest": {
+                    "include": {
+                        "environments": [
+                            { "dir": "../toolchain" },
+                            { "remote": "owner/name", "generation": 3 },
+                        ]
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            pinned_generations(&lockfile_path),
+            HashMap::from([(EnvironmentRef::new("owner", "name").unwrap(), 3)])
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::os::unix::prelude::OsStrExt;
 use std::path::{Path, PathBuf};
 use std::{fs, io};
@@ -6,8 +7,15 @@ use log::debug;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use super::activation_cache::{self, ActivationCache};
 use super::core_environment::CoreEnvironment;
-use super::generations::{Generations, GenerationsError};
+use super::generations::{
+    ForkedFrom,
+    GenerationId,
+    Generations,
+    GenerationsError,
+    SingleGenerationMetadata,
+};
 use super::path_environment::PathEnvironment;
 use super::{
     gcroots_dir,
@@ -28,13 +36,21 @@ use crate::data::Version;
 use crate::flox::{EnvironmentRef, Flox};
 use crate::models::container_builder::ContainerBuilder;
 use crate::models::environment_ref::{EnvironmentName, EnvironmentOwner};
-use crate::models::floxmetav2::{floxmeta_git_options, FloxmetaV2, FloxmetaV2Error};
+use crate::models::floxmetav2::{floxmeta_url, pointer_git_options, FloxmetaV2, FloxmetaV2Error};
 use crate::models::lockfile::LockedManifest;
-use crate::models::manifest::PackageToInstall;
+use crate::models::manifest::{
+    diff_packages,
+    merge_manifests,
+    ManifestMerge,
+    PackageDiff,
+    PackageToInstall,
+    TomlEditError,
+};
 use crate::models::pkgdb::UpgradeResult;
 use crate::providers::git::{
     GitCommandBranchHashError,
     GitCommandError,
+    GitCommandProvider,
     GitProvider,
     GitRemoteCommandError,
 };
@@ -133,11 +149,33 @@ pub enum ManagedEnvironmentError {
     #[error("could not read manifest")]
     ReadManifest(#[source] GenerationsError),
 
+    #[error("could not read generations metadata")]
+    ReadGenerationsMetadata(#[source] GenerationsError),
+
     #[error("could not canonicalize environment path")]
     CanonicalizePath(#[source] CanonicalizeError),
 
     #[error("invalid FloxHub base url")]
     InvalidFloxhubBaseUrl(#[source] url::ParseError),
+
+    #[error("could not merge the local and the upstream manifest")]
+    MergeManifests(#[source] TomlEditError),
+
+    #[error("could not compare the local and the upstream manifest")]
+    DiffManifests(#[source] TomlEditError),
+
+    #[error("environment '{0}' already exists")]
+    AlreadyExists(EnvironmentRef),
+    #[error("could not read links directory")]
+    ReadLinksDir(#[source] std::io::Error),
+    #[error("could not rename environment link {0:?}")]
+    RenameEnvironmentLink(PathBuf, #[source] std::io::Error),
+    #[error("failed to delete upstream environment")]
+    DeleteUpstream(#[source] GitRemoteCommandError),
+    #[error("could not copy the generations of the environment")]
+    CopyGenerations(#[source] GitRemoteCommandError),
+    #[error("could not record the forked environment")]
+    RecordFork(#[source] GenerationsError),
 }
 
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
@@ -355,9 +393,25 @@ impl Environment for ManagedEnvironment {
     }
 
     fn activation_path(&mut self, flox: &Flox) -> Result<PathBuf, EnvironmentError2> {
+        self.activation_exports(flox)?;
+        Ok(self.out_link.to_path_buf())
+    }
+
+    fn activation_exports(
+        &mut self,
+        flox: &Flox,
+    ) -> Result<BTreeMap<String, String>, EnvironmentError2> {
         let pointer_lock_path = self.path.join(GENERATION_LOCK_FILENAME);
+        let cache_dir = self.cache_path()?;
 
-        let pointer_lock_modified_at = mtime_of(pointer_lock_path);
+        let key = ActivationCache::key(std::slice::from_ref(&pointer_lock_path));
+        if let Some(exports) = ActivationCache::load(&cache_dir, &flox.system, &key, &self.out_link)
+        {
+            debug!("reusing cached activation: {}", self.out_link.display());
+            return Ok(exports);
+        }
+
+        let pointer_lock_modified_at = mtime_of(&pointer_lock_path);
         let out_link_modified_at = mtime_of(&self.out_link);
 
         debug!(
@@ -369,7 +423,10 @@ impl Environment for ManagedEnvironment {
             self.build(flox)?;
         }
 
-        Ok(self.out_link.to_path_buf())
+        let exports = activation_cache::exports(&self.out_link, &cache_dir, &self.project_path()?);
+        ActivationCache::store(&cache_dir, &flox.system, key, &self.out_link, &exports);
+
+        Ok(exports)
     }
 
     /// Returns .flox/cache
@@ -530,33 +587,42 @@ impl ManagedEnvironment {
         pointer: ManagedPointer,
         dot_flox_path: impl AsRef<Path>,
     ) -> Result<Self, ManagedEnvironmentError> {
-        let floxmeta = match FloxmetaV2::open(flox, &pointer) {
-            Ok(floxmeta) => floxmeta,
+        let floxmeta = Self::open_floxmeta(flox, &pointer)?;
+
+        let dot_flox_path =
+            CanonicalPath::new(dot_flox_path).map_err(ManagedEnvironmentError::CanonicalizePath)?;
+
+        let out_link =
+            gcroots_dir(flox, &pointer.owner).join(branch_name(&pointer, &dot_flox_path));
+
+        Self::open_with(floxmeta, flox, pointer, dot_flox_path, out_link)
+    }
+
+    /// Open the floxmeta clone of the owner of an environment,
+    /// cloning it if it does not exist yet
+    fn open_floxmeta(
+        flox: &Flox,
+        pointer: &ManagedPointer,
+    ) -> Result<FloxmetaV2, ManagedEnvironmentError> {
+        match FloxmetaV2::open(flox, pointer) {
+            Ok(floxmeta) => Ok(floxmeta),
             Err(FloxmetaV2Error::NotFound(_)) => {
                 debug!("cloning floxmeta for {}", pointer.owner);
-                FloxmetaV2::clone(flox, &pointer).map_err(ManagedEnvironmentError::OpenFloxmeta)?
+                FloxmetaV2::clone(flox, pointer).map_err(ManagedEnvironmentError::OpenFloxmeta)
             },
             Err(FloxmetaV2Error::CloneBranch(GitRemoteCommandError::AccessDenied))
             | Err(FloxmetaV2Error::FetchBranch(GitRemoteCommandError::AccessDenied)) => {
-                return Err(ManagedEnvironmentError::AccessDenied)
+                Err(ManagedEnvironmentError::AccessDenied)
             },
             Err(FloxmetaV2Error::CloneBranch(GitRemoteCommandError::RefNotFound(_)))
             | Err(FloxmetaV2Error::FetchBranch(GitRemoteCommandError::RefNotFound(_))) => {
-                return Err(ManagedEnvironmentError::UpstreamNotFound(
-                    pointer.into(),
+                Err(ManagedEnvironmentError::UpstreamNotFound(
+                    pointer.clone().into(),
                     flox.floxhub.base_url().to_string(),
                 ))
             },
-            Err(e) => Err(ManagedEnvironmentError::OpenFloxmeta(e))?,
-        };
-
-        let dot_flox_path =
-            CanonicalPath::new(dot_flox_path).map_err(ManagedEnvironmentError::CanonicalizePath)?;
-
-        let out_link =
-            gcroots_dir(flox, &pointer.owner).join(branch_name(&pointer, &dot_flox_path));
-
-        Self::open_with(floxmeta, flox, pointer, dot_flox_path, out_link)
+            Err(e) => Err(ManagedEnvironmentError::OpenFloxmeta(e)),
+        }
     }
 
     /// Open a managed environment backed by a provided floxmeta clone.
@@ -803,6 +869,16 @@ impl ManagedEnvironment {
         &self.pointer
     }
 
+    /// Return the current generation of the environment,
+    /// or `None` if the environment does not have any generations yet
+    pub fn current_generation(&self) -> Result<Option<GenerationId>, ManagedEnvironmentError> {
+        let metadata = self
+            .generations()
+            .metadata()
+            .map_err(ManagedEnvironmentError::ReadGenerationsMetadata)?;
+        Ok(metadata.current_gen)
+    }
+
     fn generations(&self) -> Generations {
         Generations::new(
             self.floxmeta.git.clone(),
@@ -954,6 +1030,29 @@ pub enum PullResult {
     Updated,
 }
 
+/// The differences between the local and the upstream environment,
+/// i.e. what pushing or pulling the environment would transfer,
+/// see [ManagedEnvironment::preview_sync]
+#[derive(Debug)]
+pub struct SyncPreview {
+    /// Generations only present in the local environment
+    pub local_generations: Vec<(GenerationId, SingleGenerationMetadata)>,
+    /// Generations only present in the upstream environment
+    pub upstream_generations: Vec<(GenerationId, SingleGenerationMetadata)>,
+    /// Packages of the current local generation
+    /// compared to the current upstream generation
+    pub packages: PackageDiff,
+    /// Whether neither history contains the other
+    pub diverged: bool,
+}
+
+impl SyncPreview {
+    /// Whether the local and the upstream environment are the same
+    pub fn is_up_to_date(&self) -> bool {
+        self.local_generations.is_empty() && self.upstream_generations.is_empty()
+    }
+}
+
 impl ManagedEnvironment {
     /// If access to a remote repository requires authentication,
     /// the FloxHub token must be set in the flox instance.
@@ -982,11 +1081,8 @@ impl ManagedEnvironment {
         let temp_floxmeta_path = tempfile::tempdir_in(&flox.temp_dir).unwrap().into_path();
 
         // Caller decides whether to set token
-        let token = flox.floxhub_token.as_ref();
-
-        let git_url = flox.floxhub.git_url();
-
-        let options = floxmeta_git_options(git_url, &pointer.owner, token);
+        let options = pointer_git_options(flox, &pointer)
+            .map_err(|e| ManagedEnvironmentError::OpenFloxmeta(FloxmetaV2Error::FloxhubError(e)))?;
 
         let generations = Generations::init(
             options,
@@ -1010,7 +1106,9 @@ impl ManagedEnvironment {
         temp_floxmeta_git
             .add_remote(
                 "upstream",
-                &format!("{}/{}/floxmeta", &git_url, &pointer.owner),
+                &floxmeta_url(&pointer).map_err(|e| {
+                    ManagedEnvironmentError::OpenFloxmeta(FloxmetaV2Error::FloxhubError(e))
+                })?,
             )
             .unwrap();
 
@@ -1041,6 +1139,114 @@ impl ManagedEnvironment {
         Ok(env)
     }
 
+    /// Fork the environment `source` into a new environment `name` of `owner`
+    /// and open it in `dot_flox_path`
+    ///
+    /// The generation history of `source` is copied to the floxmeta repository of `owner`,
+    /// with the metadata recording where it was forked from, see [ForkedFrom].
+    /// If access to either repository requires authentication,
+    /// the FloxHub token must be set in the flox instance.
+    pub fn fork(
+        flox: &Flox,
+        source: ManagedPointer,
+        owner: EnvironmentOwner,
+        name: EnvironmentName,
+        dot_flox_path: impl AsRef<Path>,
+    ) -> Result<Self, ManagedEnvironmentError> {
+        let source_floxmeta = Self::open_floxmeta(flox, &source)?;
+        let source_branch = remote_branch_name(&source);
+        source_floxmeta
+            .git
+            .fetch_ref(
+                "dynamicorigin",
+                &format!("+{source_branch}:{source_branch}"),
+            )
+            .map_err(|err| match err {
+                GitRemoteCommandError::AccessDenied => ManagedEnvironmentError::AccessDenied,
+                GitRemoteCommandError::RefNotFound(_) => ManagedEnvironmentError::UpstreamNotFound(
+                    source.clone().into(),
+                    flox.floxhub.base_url().to_string(),
+                ),
+                _ => ManagedEnvironmentError::FetchUpdates(err),
+            })?;
+        let rev = source_floxmeta
+            .git
+            .branch_hash(&source_branch)
+            .map_err(ManagedEnvironmentError::GitBranchHash)?;
+
+        let pointer = ManagedPointer::new(owner, name, &flox.floxhub);
+        let branch = remote_branch_name(&pointer);
+
+        let options = pointer_git_options(flox, &pointer)
+            .map_err(|e| ManagedEnvironmentError::OpenFloxmeta(FloxmetaV2Error::FloxhubError(e)))?;
+
+        // Copy the generations into a temporary repository,
+        // so that recording the fork does not touch the clone of the source environment
+        let temp_floxmeta_path = tempfile::tempdir_in(&flox.temp_dir).unwrap().into_path();
+        let temp_floxmeta_git = GitCommandProvider::clone_branch_with(
+            options,
+            source_floxmeta.git.path(),
+            temp_floxmeta_path,
+            &source_branch,
+            true,
+        )
+        .map_err(ManagedEnvironmentError::CopyGenerations)?;
+
+        temp_floxmeta_git
+            .add_remote(
+                "upstream",
+                &floxmeta_url(&pointer).map_err(|e| {
+                    ManagedEnvironmentError::OpenFloxmeta(FloxmetaV2Error::FloxhubError(e))
+                })?,
+            )
+            .unwrap();
+
+        // Don't overwrite another environment.
+        // Fetching fails if the floxmeta repository of the owner does not exist yet,
+        // in which case pushing the fork creates it.
+        match temp_floxmeta_git.fetch_ref("upstream", &format!("refs/heads/{branch}")) {
+            Ok(()) => Err(ManagedEnvironmentError::AlreadyExists(
+                pointer.clone().into(),
+            ))?,
+            Err(GitRemoteCommandError::AccessDenied) => Err(ManagedEnvironmentError::AccessDenied)?,
+            Err(err) => debug!("assuming environment {branch} does not exist yet: {err}"),
+        }
+
+        let mut generations = Generations::new(temp_floxmeta_git.clone(), source_branch.clone())
+            .writable(flox.temp_dir.clone())
+            .map_err(ManagedEnvironmentError::CreateFloxmetaDir)?;
+        generations
+            .set_forked_from(ForkedFrom {
+                environment: source.into(),
+                rev,
+                created: chrono::Utc::now(),
+            })
+            .map_err(ManagedEnvironmentError::RecordFork)?;
+
+        match temp_floxmeta_git.push_ref(
+            "upstream",
+            format!("refs/heads/{source_branch}:refs/heads/{branch}"),
+            false,
+        ) {
+            Err(GitRemoteCommandError::AccessDenied) => Err(ManagedEnvironmentError::AccessDenied)?,
+            Err(GitRemoteCommandError::Diverged) => Err(ManagedEnvironmentError::AlreadyExists(
+                pointer.clone().into(),
+            ))?,
+            Err(e) => Err(ManagedEnvironmentError::Push(e))?,
+            _ => {},
+        }
+
+        let dot_flox_path = dot_flox_path.as_ref();
+        fs::write(
+            dot_flox_path.join(ENVIRONMENT_POINTER_FILENAME),
+            serde_json::to_string_pretty(&pointer)
+                .map_err(ManagedEnvironmentError::SerializePointer)?,
+        )
+        .map_err(ManagedEnvironmentError::WritePointer)?;
+
+        ManagedEnvironment::open(flox, pointer, dot_flox_path)
+    }
+
     pub fn push(&mut self, flox: &Flox, force: bool) -> Result<(), ManagedEnvironmentError> {
         let project_branch = branch_name(&self.pointer, &self.path);
         let sync_branch = remote_branch_name(&self.pointer);
@@ -1118,6 +1324,17 @@ impl ManagedEnvironment {
                 .branch_contains_commit(&project_branch, &sync_branch)
                 .map_err(ManagedEnvironmentError::Git)?;
             if !consistent_history {
+                // local changes that were not pushed yet,
+                // but already contain all upstream changes
+                let ahead = self
+                    .floxmeta
+                    .git
+                    .branch_contains_commit(&sync_branch, &project_branch)
+                    .map_err(ManagedEnvironmentError::Git)?;
+                if ahead {
+                    return Ok(PullResult::UpToDate);
+                }
+
                 Err(ManagedEnvironmentError::Diverged)?;
             }
 
@@ -1144,6 +1361,354 @@ impl ManagedEnvironment {
 
         Ok(PullResult::Updated)
     }
+
+    /// Compare the local environment to the latest upstream version
+    /// without changing either of them
+    ///
+    /// Generations are compared by their metadata,
+    /// so generations that were added on both sides after the histories diverged
+    /// are listed on both sides, even if they share the same [GenerationId].
+    pub fn preview_sync(&self) -> Result<SyncPreview, ManagedEnvironmentError> {
+        let sync_branch = remote_branch_name(&self.pointer);
+        let project_branch = branch_name(&self.pointer, &self.path);
+
+        self.floxmeta
+            .git
+            .fetch_ref("dynamicorigin", &format!("+{sync_branch}:{sync_branch}"))
+            .map_err(ManagedEnvironmentError::FetchUpdates)?;
+
+        let git = &self.floxmeta.git;
+        let local_rev = git
+            .branch_hash(&project_branch)
+            .map_err(ManagedEnvironmentError::GitBranchHash)?;
+        let upstream_rev = git
+            .branch_hash(&sync_branch)
+            .map_err(ManagedEnvironmentError::GitBranchHash)?;
+
+        if local_rev == upstream_rev {
+            return Ok(SyncPreview {
+                local_generations: Vec::new(),
+                upstream_generations: Vec::new(),
+                packages: PackageDiff::default(),
+                diverged: false,
+            });
+        }
+
+        let local = Generations::new(git.clone(), local_rev.clone());
+        let upstream = Generations::new(git.clone(), upstream_rev.clone());
+        let local_metadata = local
+            .metadata()
+            .map_err(ManagedEnvironmentError::ReadGenerationsMetadata)?
+            .generations;
+        let upstream_metadata = upstream
+            .metadata()
+            .map_err(ManagedEnvironmentError::ReadGenerationsMetadata)?
+            .generations;
+
+        // `last_active` changes when switching generations,
+        // so a generation is considered the same on both sides
+        // if it was created at the same time with the same description and manifest
+        let mut shared = Vec::new();
+        for (id, generation) in &local_metadata {
+            let Some(other) = upstream_metadata.get(id) else {
+                continue;
+            };
+            if other.created != generation.created || other.description != generation.description {
+                continue;
+            }
+            let manifest = |generations: &Generations| {
+                generations
+                    .manifest(**id)
+                    .map_err(ManagedEnvironmentError::ReadManifest)
+            };
+            if manifest(&local)? == manifest(&upstream)? {
+                shared.push(id.clone());
+            }
+        }
+        let only_in = |generations: BTreeMap<GenerationId, SingleGenerationMetadata>| {
+            generations
+                .into_iter()
+                .filter(|(id, _)| !shared.contains(id))
+                .collect::<Vec<_>>()
+        };
+
+        let packages = diff_packages(
+            &self.manifest_at(upstream_rev.clone())?,
+            &self.manifest_at(local_rev.clone())?,
+        )
+        .map_err(ManagedEnvironmentError::DiffManifests)?;
+
+        let diverged = !git
+            .branch_contains_commit(&local_rev, &upstream_rev)
+            .map_err(ManagedEnvironmentError::Git)?
+            && !git
+                .branch_contains_commit(&upstream_rev, &local_rev)
+                .map_err(ManagedEnvironmentError::Git)?;
+
+        Ok(SyncPreview {
+            local_generations: only_in(local_metadata),
+            upstream_generations: only_in(upstream_metadata),
+            packages,
+            diverged,
+        })
+    }
+
+    /// Merge the manifests of the local and the upstream environment
+    /// after their histories diverged.
+    ///
+    /// Both manifests are merged relative to the current generation
+    /// of the latest commit in both histories, see [merge_manifests].
+    /// The merge is only computed, use [Self::commit_merge] to apply it.
+    pub fn merge_upstream(&self) -> Result<ManifestMerge, ManagedEnvironmentError> {
+        let sync_branch = remote_branch_name(&self.pointer);
+        let project_branch = branch_name(&self.pointer, &self.path);
+
+        self.floxmeta
+            .git
+            .fetch_ref("dynamicorigin", &format!("+{sync_branch}:{sync_branch}"))
+            .map_err(ManagedEnvironmentError::FetchUpdates)?;
+
+        let merge_base = self
+            .floxmeta
+            .git
+            .merge_base(&project_branch, &sync_branch)
+            .map_err(ManagedEnvironmentError::Git)?;
+
+        // Without a common ancestor, keys are compared as if they were added on both sides
+        let base = match merge_base {
+            Some(merge_base) => self.manifest_at(merge_base)?,
+            None => String::new(),
+        };
+        let local = self.manifest_at(project_branch)?;
+        let upstream = self.manifest_at(sync_branch)?;
+
+        merge_manifests(&base, &local, &upstream).map_err(ManagedEnvironmentError::MergeManifests)
+    }
+
+    /// Reconcile the diverged local and upstream histories
+    /// with a generation of the merged `manifest`,
+    /// e.g. as computed by [Self::merge_upstream].
+    ///
+    /// The generation is added on top of the upstream generations
+    /// and committed as a merge of both histories,
+    /// so that the environment can be pushed afterwards.
+    /// The local branch is only updated once the merged generation was built.
+    pub fn commit_merge(&mut self, flox: &Flox, manifest: String) -> Result<(), EnvironmentError2> {
+        let sync_branch = remote_branch_name(&self.pointer);
+        let project_branch = branch_name(&self.pointer, &self.path);
+        let merge_branch = format!("{project_branch}.merge");
+
+        let git = self.floxmeta.git.clone();
+        let upstream_rev = git
+            .branch_hash(&sync_branch)
+            .map_err(ManagedEnvironmentError::GitBranchHash)?;
+        let local_rev = git
+            .branch_hash(&project_branch)
+            .map_err(ManagedEnvironmentError::GitBranchHash)?;
+
+        git.reset_branch(&merge_branch, &upstream_rev)
+            .map_err(ManagedEnvironmentError::Git)?;
+        let merged = self.add_merge_generation(flox, &merge_branch, manifest);
+        let merge_rev = git.branch_hash(&merge_branch);
+        git.delete_branch(&merge_branch, true)
+            .map_err(ManagedEnvironmentError::DeleteBranch)?;
+
+        let (result, message) = merged?;
+        let merge_rev = merge_rev.map_err(ManagedEnvironmentError::GitBranchHash)?;
+
+        let fast_forward = result == EditResult::Unchanged
+            && git
+                .branch_contains_commit(&local_rev, &sync_branch)
+                .map_err(ManagedEnvironmentError::Git)?;
+
+        let new_rev = if fast_forward {
+            upstream_rev
+        } else {
+            git.commit_tree(
+                &format!("{merge_rev}^{{tree}}"),
+                &[&local_rev, &upstream_rev],
+                &message,
+            )
+            .map_err(ManagedEnvironmentError::Git)?
+        };
+        git.reset_branch(&project_branch, &new_rev)
+            .map_err(ManagedEnvironmentError::Git)?;
+        self.lock_pointer()?;
+
+        match result.store_path() {
+            Some(store_path) => {
+                let generations = self
+                    .generations()
+                    .writable(flox.temp_dir.clone())
+                    .map_err(ManagedEnvironmentError::CreateFloxmetaDir)?;
+                let mut temporary = generations
+                    .get_current_generation()
+                    .map_err(ManagedEnvironmentError::CreateGenerationFiles)?;
+                temporary.link(flox, &self.out_link, &Some(store_path))?;
+            },
+            None => self.build(flox)?,
+        }
+
+        Ok(())
+    }
+
+    /// Add a generation with the merged `manifest` to `branch`
+    ///
+    /// Returns the result of the edit and the commit message for the merge.
+    fn add_merge_generation(
+        &self,
+        flox: &Flox,
+        branch: &str,
+        manifest: String,
+    ) -> Result<(EditResult, String), EnvironmentError2> {
+        let mut generations = Generations::new(self.floxmeta.git.clone(), branch.to_string())
+            .writable(flox.temp_dir.clone())
+            .map_err(ManagedEnvironmentError::CreateFloxmetaDir)?;
+        let mut temporary = generations
+            .get_current_generation()
+            .map_err(ManagedEnvironmentError::CreateGenerationFiles)?;
+
+        let result = temporary.edit(flox, manifest)?;
+        if result == EditResult::Unchanged {
+            return Ok((result, "Merge upstream generations".to_string()));
+        }
+
+        let description = "merged local and upstream changes".to_string();
+        generations
+            .add_generation(&mut temporary, description.clone())
+            .map_err(ManagedEnvironmentError::CommitGeneration)?;
+        let generation = generations
+            .metadata()
+            .map_err(ManagedEnvironmentError::ReadGenerationsMetadata)?
+            .current_gen
+            .unwrap_or_default();
+
+        Ok((
+            result,
+            format!("Create generation {generation}\n\n{description}"),
+        ))
+    }
+
+    /// Rename the environment upstream and in every directory linking to it
+    ///
+    /// The generations of the environment are pushed to a branch of the new name,
+    /// before the branch of the old name is deleted upstream.
+    /// Directories linking to the environment are found via their reverse links,
+    /// see [ManagedEnvironment::ensure_reverse_link].
+    /// Copies of the environment on other machines have to be pulled again.
+    pub fn rename(
+        &mut self,
+        flox: &Flox,
+        name: EnvironmentName,
+    ) -> Result<(), ManagedEnvironmentError> {
+        let old_pointer = self.pointer.clone();
+        let new_pointer = ManagedPointer {
+            name,
+            ..old_pointer.clone()
+        };
+        let old_branch = remote_branch_name(&old_pointer);
+        let new_branch = remote_branch_name(&new_pointer);
+        let git = &self.floxmeta.git;
+
+        // Don't overwrite another environment
+        match git.fetch_ref("dynamicorigin", &format!("+{new_branch}:{new_branch}")) {
+            Err(GitRemoteCommandError::RefNotFound(_)) => {},
+            Ok(()) => Err(ManagedEnvironmentError::AlreadyExists(
+                new_pointer.clone().into(),
+            ))?,
+            Err(GitRemoteCommandError::AccessDenied) => Err(ManagedEnvironmentError::AccessDenied)?,
+            Err(e) => Err(ManagedEnvironmentError::FetchUpdates(e))?,
+        }
+
+        git.fetch_ref("dynamicorigin", &format!("+{old_branch}:{old_branch}"))
+            .map_err(ManagedEnvironmentError::FetchUpdates)?;
+        git.push_ref(
+            "dynamicorigin",
+            format!("refs/heads/{old_branch}:refs/heads/{new_branch}"),
+            false,
+        )
+        .map_err(|err| match err {
+            GitRemoteCommandError::AccessDenied => ManagedEnvironmentError::AccessDenied,
+            _ => ManagedEnvironmentError::Push(err),
+        })?;
+        git.move_branch(&old_branch, &new_branch)
+            .map_err(ManagedEnvironmentError::Git)?;
+
+        let gcroots = gcroots_dir(flox, &old_pointer.owner);
+        let rename_link = |old: &str, new: &str| {
+            let old_link = gcroots.join(old);
+            if fs::symlink_metadata(&old_link).is_err() {
+                return Ok(());
+            }
+            fs::rename(&old_link, gcroots.join(new))
+                .map_err(|e| ManagedEnvironmentError::RenameEnvironmentLink(old_link, e))
+        };
+        // the out link of remote environments
+        rename_link(&old_branch, &new_branch)?;
+
+        let links_dir = reverse_links_dir(flox);
+        let links = match fs::read_dir(&links_dir) {
+            Ok(links) => links.collect::<Result<Vec<_>, _>>(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+        .map_err(ManagedEnvironmentError::ReadLinksDir)?;
+
+        for link in links {
+            // links to deleted or moved projects are skipped
+            let Ok(dot_flox_path) = fs::read_link(link.path()) else {
+                continue;
+            };
+            let Ok(dot_flox_path) = CanonicalPath::new(dot_flox_path) else {
+                continue;
+            };
+            let pointer_path = dot_flox_path.join(ENVIRONMENT_POINTER_FILENAME);
+            let pointer = fs::read_to_string(&pointer_path)
+                .ok()
+                .and_then(|pointer| serde_json::from_str::<ManagedPointer>(&pointer).ok());
+            if pointer.as_ref() != Some(&old_pointer) {
+                continue;
+            }
+
+            debug!("renaming environment linked from {dot_flox_path:?}");
+            fs::write(
+                &pointer_path,
+                serde_json::to_string_pretty(&new_pointer)
+                    .map_err(ManagedEnvironmentError::SerializePointer)?,
+            )
+            .map_err(ManagedEnvironmentError::WritePointer)?;
+
+            let old_project_branch = branch_name(&old_pointer, &dot_flox_path);
+            let new_project_branch = branch_name(&new_pointer, &dot_flox_path);
+            if git
+                .has_branch(&old_project_branch)
+                .map_err(ManagedEnvironmentError::CheckBranchExists)?
+            {
+                git.move_branch(&old_project_branch, &new_project_branch)
+                    .map_err(ManagedEnvironmentError::Git)?;
+            }
+            rename_link(&old_project_branch, &new_project_branch)?;
+        }
+
+        git.push_ref("dynamicorigin", format!(":refs/heads/{old_branch}"), false)
+            .map_err(ManagedEnvironmentError::DeleteUpstream)?;
+
+        self.out_link = gcroots.join(branch_name(&new_pointer, &self.path));
+        self.pointer = new_pointer;
+
+        Ok(())
+    }
+
+    /// Read the manifest of the current generation at a floxmeta revision
+    ///
+    /// Returns an empty manifest if there are no generations at that revision yet.
+    fn manifest_at(&self, rev: String) -> Result<String, ManagedEnvironmentError> {
+        match Generations::new(self.floxmeta.git.clone(), rev).current_gen_manifest() {
+            Ok(manifest) => Ok(manifest),
+            Err(GenerationsError::NoGenerations) => Ok(String::new()),
+            Err(e) => Err(ManagedEnvironmentError::ReadManifest(e)),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1151,14 +1716,25 @@ mod test {
     use std::str::FromStr;
     use std::time::Duration;
 
+    #[cfg(feature = "impure-unit-tests")]
+    use serial_test::serial;
     use url::Url;
 
     use super::*;
     use crate::flox::tests::flox_instance;
-    use crate::models::environment::{DOT_FLOX, ENVIRONMENT_POINTER_FILENAME};
+    use crate::flox::Floxhub;
+    #[cfg(feature = "impure-unit-tests")]
+    use crate::models::environment::{global_manifest_path, init_global_manifest};
+    use crate::models::environment::{
+        PathPointer,
+        DOT_FLOX,
+        ENVIRONMENT_POINTER_FILENAME,
+        MANIFEST_FILENAME,
+    };
     use crate::models::floxmetav2::floxmeta_dir;
+    use crate::models::manifest::contains_conflict_markers;
     use crate::providers::git::tests::commit_file;
-    use crate::providers::git::{GitCommandProvider, GitProvider};
+    use crate::providers::git::{GitCommandOptions, GitProvider};
 
     fn make_test_pointer(remote_path: &Path) -> ManagedPointer {
         ManagedPointer {
@@ -1212,6 +1788,66 @@ mod test {
         FloxmetaV2::open(flox, test_pointer).unwrap()
     }
 
+    /// Create an upstream floxmeta repository for `pointer`
+    /// with a generation for each of the `manifests`
+    fn create_remote_generations(
+        flox: &Flox,
+        remote_base_path: &Path,
+        pointer: &ManagedPointer,
+        manifests: &[&str],
+    ) -> GitCommandProvider {
+        let remote_path = remote_base_path
+            .join(pointer.owner.as_str())
+            .join("floxmeta");
+        let generations = Generations::init(
+            GitCommandOptions::default(),
+            tempfile::tempdir_in(&flox.temp_dir).unwrap().into_path(),
+            remote_path,
+            remote_branch_name(pointer),
+            &PathPointer::new(pointer.name.clone()),
+        )
+        .unwrap();
+        let remote = generations.git().clone();
+        // like on FloxHub, environment branches are not the default branch,
+        // which could not be deleted
+        let status = remote
+            .new_command()
+            .args(["symbolic-ref", "HEAD", "refs/heads/main"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        add_generations(flox, generations, manifests);
+        remote
+    }
+
+    /// Add a generation for each of the `manifests` to `generations`
+    ///
+    /// The generations are not built.
+    fn add_generations(flox: &Flox, generations: Generations, manifests: &[&str]) {
+        let mut generations = generations.writable(&flox.temp_dir).unwrap();
+        for manifest in manifests {
+            let env_path = tempfile::tempdir_in(&flox.temp_dir).unwrap();
+            fs::write(env_path.path().join(MANIFEST_FILENAME), manifest).unwrap();
+            generations
+                .add_generation(
+                    &mut CoreEnvironment::new(env_path.path()),
+                    format!("set manifest to {manifest:?}"),
+                )
+                .unwrap();
+        }
+    }
+
+    /// Open the environment of `pointer` in a new `.flox` directory in `project_path`
+    fn open_in_project(
+        flox: &Flox,
+        pointer: &ManagedPointer,
+        project_path: &Path,
+    ) -> ManagedEnvironment {
+        fs::create_dir_all(project_path).unwrap();
+        let dot_flox_path = create_dot_flox(&project_path.join(DOT_FLOX), pointer, None);
+        ManagedEnvironment::open(flox, pointer.clone(), dot_flox_path).unwrap()
+    }
+
     /// Test that when ensure_locked has input state of:
     /// - no lock
     /// - floxmeta at commit 1
@@ -1852,4 +2488,250 @@ mod test {
         let canonicalized_path = std::fs::canonicalize(&path).unwrap();
         assert_eq!(canonicalized_path, canonicalized_decoded_path);
     }
+
+    const BASE_MANIFEST: &str = "[vars]\nshared = \"base\"\n";
+
+    /// Test that generations added on only one side since the histories diverged
+    /// are previewed on that side, and that changes to different keys merge cleanly
+    #[test]
+    fn test_preview_and_merge_diverged_upstream() {
+        let (flox, _temp_dir_handle) = flox_instance();
+
+        let remote_base_path = flox.temp_dir.join("remote");
+        let test_pointer = make_test_pointer(&remote_base_path);
+        let remote =
+            create_remote_generations(&flox, &remote_base_path, &test_pointer, &[BASE_MANIFEST]);
+        let env = open_in_project(&flox, &test_pointer, &flox.temp_dir.join("project"));
+
+        let local_manifest = "[vars]\nshared = \"base\"\nlocal = \"1\"\n";
+        let upstream_manifest = "[vars]\nshared = \"base\"\nupstream = \"1\"\n";
+        add_generations(&flox, env.generations(), &[local_manifest]);
+        add_generations(
+            &flox,
+            Generations::new(remote.clone(), remote_branch_name(&test_pointer)),
+            &[upstream_manifest],
+        );
+
+        let preview = env.preview_sync().unwrap();
+        assert!(preview.diverged);
+        assert!(!preview.is_up_to_date());
+        let descriptions = |generations: &[(GenerationId, SingleGenerationMetadata)]| {
+            generations
+                .iter()
+                .map(|(id, metadata)| (id.clone(), metadata.description.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(descriptions(&preview.local_generations), vec![(
+            2.into(),
+            format!("set manifest to {local_manifest:?}")
+        )]);
+        assert_eq!(descriptions(&preview.upstream_generations), vec![(
+            2.into(),
+            format!("set manifest to {upstream_manifest:?}")
+        )]);
+
+        let merge = env.merge_upstream().unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(
+            merge.manifest,
+            "[vars]\nshared = \"base\"\nlocal = \"1\"\nupstream = \"1\"\n"
+        );
+    }
+
+    /// Test that a key changed differently on both sides is reported as a conflict
+    /// and marked with conflict markers rather than the internal placeholder
+    #[test]
+    fn test_merge_upstream_conflict() {
+        let (flox, _temp_dir_handle) = flox_instance();
+
+        let remote_base_path = flox.temp_dir.join("remote");
+        let test_pointer = make_test_pointer(&remote_base_path);
+        let remote =
+            create_remote_generations(&flox, &remote_base_path, &test_pointer, &[BASE_MANIFEST]);
+        let env = open_in_project(&flox, &test_pointer, &flox.temp_dir.join("project"));
+
+        add_generations(&flox, env.generations(), &["[vars]\nshared = \"local\"\n"]);
+        add_generations(
+            &flox,
+            Generations::new(remote, remote_branch_name(&test_pointer)),
+            &["[vars]\nshared = \"upstream\"\n"],
+        );
+
+        let merge = env.merge_upstream().unwrap();
+        assert_eq!(merge.conflicts, vec!["vars.shared".to_string()]);
+        assert!(contains_conflict_markers(&merge.manifest));
+        assert!(!merge.manifest.contains("__flox_merge_conflict_"));
+        assert!(merge.manifest.contains("shared = \"local\""));
+        assert!(merge.manifest.contains("shared = \"upstream\""));
+    }
+
+    /// Test that committing a merge of diverged histories
+    /// creates a commit with the local and the upstream history as parents
+    #[test]
+    #[serial]
+    #[cfg(feature = "impure-unit-tests")]
+    fn test_commit_merge_creates_merge_commit() {
+        let (flox, _temp_dir_handle) = flox_instance();
+        init_global_manifest(&global_manifest_path(&flox)).unwrap();
+
+        let remote_base_path = flox.temp_dir.join("remote");
+        let test_pointer = make_test_pointer(&remote_base_path);
+        let remote =
+            create_remote_generations(&flox, &remote_base_path, &test_pointer, &[BASE_MANIFEST]);
+        let mut env = open_in_project(&flox, &test_pointer, &flox.temp_dir.join("project"));
+
+        add_generations(&flox, env.generations(), &[
+            "[vars]\nshared = \"base\"\nlocal = \"1\"\n",
+        ]);
+        add_generations(
+            &flox,
+            Generations::new(remote, remote_branch_name(&test_pointer)),
+            &["[vars]\nshared = \"base\"\nupstream = \"1\"\n"],
+        );
+
+        let merge = env.merge_upstream().unwrap();
+        let project_branch = branch_name(&test_pointer, &env.path);
+        let sync_branch = remote_branch_name(&test_pointer);
+        let git = env.floxmeta.git.clone();
+        let local_rev = git.branch_hash(&project_branch).unwrap();
+        let upstream_rev = git.branch_hash(&sync_branch).unwrap();
+
+        env.commit_merge(&flox, merge.manifest.clone()).unwrap();
+
+        let merge_rev = git.branch_hash(&project_branch).unwrap();
+        assert!(git.contains_commit(&format!("{merge_rev}^2")).unwrap());
+        assert!(!git.contains_commit(&format!("{merge_rev}^3")).unwrap());
+        assert!(git.branch_contains_commit(&local_rev, &merge_rev).unwrap());
+        assert!(git
+            .branch_contains_commit(&upstream_rev, &merge_rev)
+            .unwrap());
+        assert_eq!(env.manifest_content(&flox).unwrap(), merge.manifest);
+        assert!(!env.preview_sync().unwrap().diverged);
+    }
+
+    /// Test that pulling an environment with local generations that were not pushed yet,
+    /// but which already contain all upstream generations, leaves the environment unchanged
+    #[test]
+    fn test_pull_up_to_date_when_ahead() {
+        let (flox, _temp_dir_handle) = flox_instance();
+
+        let remote_base_path = flox.temp_dir.join("remote");
+        let test_pointer = make_test_pointer(&remote_base_path);
+        create_remote_generations(&flox, &remote_base_path, &test_pointer, &[BASE_MANIFEST]);
+        let mut env = open_in_project(&flox, &test_pointer, &flox.temp_dir.join("project"));
+
+        add_generations(&flox, env.generations(), &["[vars]\nshared = \"local\"\n"]);
+        let project_branch = branch_name(&test_pointer, &env.path);
+        let local_rev = env.floxmeta.git.branch_hash(&project_branch).unwrap();
+
+        assert!(matches!(env.pull(false).unwrap(), PullResult::UpToDate));
+        assert_eq!(
+            env.floxmeta.git.branch_hash(&project_branch).unwrap(),
+            local_rev
+        );
+    }
+
+    /// Test that renaming an environment moves its branch upstream and in floxmeta,
+    /// and updates the pointers of all `.flox` directories linking to it
+    #[test]
+    fn test_rename_moves_branches_and_pointers() {
+        let (flox, _temp_dir_handle) = flox_instance();
+
+        let remote_base_path = flox.temp_dir.join("remote");
+        let test_pointer = make_test_pointer(&remote_base_path);
+        let remote =
+            create_remote_generations(&flox, &remote_base_path, &test_pointer, &[BASE_MANIFEST]);
+        let mut env = open_in_project(&flox, &test_pointer, &flox.temp_dir.join("project_1"));
+        let other = open_in_project(&flox, &test_pointer, &flox.temp_dir.join("project_2"));
+
+        let new_name = EnvironmentName::from_str("renamed").unwrap();
+        env.rename(&flox, new_name.clone()).unwrap();
+
+        let new_pointer = ManagedPointer {
+            name: new_name,
+            ..test_pointer.clone()
+        };
+        assert_eq!(env.pointer(), &new_pointer);
+
+        assert!(!remote
+            .has_branch(&remote_branch_name(&test_pointer))
+            .unwrap());
+        assert!(remote
+            .has_branch(&remote_branch_name(&new_pointer))
+            .unwrap());
+
+        let git = &env.floxmeta.git;
+        assert!(!git.has_branch(&remote_branch_name(&test_pointer)).unwrap());
+        assert!(git.has_branch(&remote_branch_name(&new_pointer)).unwrap());
+        for dot_flox_path in [&env.path, &other.path] {
+            let pointer: ManagedPointer = serde_json::from_slice(
+                &fs::read(dot_flox_path.join(ENVIRONMENT_POINTER_FILENAME)).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(pointer, new_pointer);
+            assert!(!git
+                .has_branch(&branch_name(&test_pointer, dot_flox_path))
+                .unwrap());
+            assert!(git
+                .has_branch(&branch_name(&new_pointer, dot_flox_path))
+                .unwrap());
+        }
+    }
+
+    /// Test that forking an environment copies its generations to the new owner
+    /// and records the environment it was forked from
+    #[test]
+    fn test_fork_copies_generations() {
+        let (mut flox, _temp_dir_handle) = flox_instance();
+
+        let remote_base_path = flox.temp_dir.join("remote");
+        flox.floxhub = Floxhub::new(
+            Url::from_str("https://hub.flox.dev").unwrap(),
+            Some(Url::from_directory_path(&remote_base_path).unwrap()),
+        )
+        .unwrap();
+        let source = make_test_pointer(&remote_base_path);
+        let remote = create_remote_generations(&flox, &remote_base_path, &source, &[
+            BASE_MANIFEST,
+            "[vars]\nshared = \"changed\"\n",
+        ]);
+        let source_rev = remote.branch_hash(&remote_branch_name(&source)).unwrap();
+
+        // the floxmeta repository of the new owner already exists upstream
+        let fork_owner = EnvironmentOwner::from_str("fork_owner").unwrap();
+        let fork_remote_path = remote_base_path.join(fork_owner.as_str()).join("floxmeta");
+        fs::create_dir_all(&fork_remote_path).unwrap();
+        GitCommandProvider::init(&fork_remote_path, true).unwrap();
+
+        let dot_flox_path = flox.temp_dir.join(DOT_FLOX);
+        fs::create_dir(&dot_flox_path).unwrap();
+        let fork = ManagedEnvironment::fork(
+            &flox,
+            source.clone(),
+            fork_owner.clone(),
+            EnvironmentName::from_str("fork").unwrap(),
+            &dot_flox_path,
+        )
+        .unwrap();
+
+        assert_eq!(fork.owner(), &fork_owner);
+        let source_generations = Generations::new(remote, remote_branch_name(&source));
+        let source_metadata = source_generations.metadata().unwrap();
+        let fork_metadata = fork.generations().metadata().unwrap();
+        assert_eq!(fork_metadata.current_gen, source_metadata.current_gen);
+        assert_eq!(
+            fork_metadata.generations.keys().collect::<Vec<_>>(),
+            source_metadata.generations.keys().collect::<Vec<_>>()
+        );
+        for generation in [1, 2] {
+            assert_eq!(
+                fork.generations().manifest(generation).unwrap(),
+                source_generations.manifest(generation).unwrap()
+            );
+        }
+
+        let forked_from = fork_metadata.forked_from.unwrap();
+        assert_eq!(forked_from.environment, source.into());
+        assert_eq!(forked_from.rev, source_rev);
+    }
 }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use std::{env, fs, io};
 
@@ -26,12 +26,16 @@ use crate::providers::git::{
 };
 use crate::utils::copy_file_without_permissions;
 
+mod activation_cache;
 mod core_environment;
 pub use core_environment::{CoreEnvironmentError, EditResult};
+mod include;
+pub use include::IncludeError;
 
 pub mod generations;
 pub mod managed_environment;
 pub mod path_environment;
+pub mod prebuild;
 pub mod remote_environment;
 
 pub const CATALOG_JSON: &str = "catalog.json";
@@ -58,6 +62,9 @@ pub const FLOX_ENV_LIB_DIRS_VAR: &str = "FLOX_ENV_LIB_DIRS";
 pub const FLOX_ACTIVE_ENVIRONMENTS_VAR: &str = "_FLOX_ACTIVE_ENVIRONMENTS";
 pub const FLOX_PROMPT_ENVIRONMENTS_VAR: &str = "FLOX_PROMPT_ENVIRONMENTS";
 pub const FLOX_PATH_PATCHED_VAR: &str = "FLOX_PATH_PATCHED";
+pub const FLOX_PURE_VAR: &str = "FLOX_PURE";
+pub const FLOX_ACTIVATIONS_VAR: &str = "_FLOX_ACTIVATIONS";
+pub const FLOX_RESTORE_RECORDS_VAR: &str = "_FLOX_RESTORE_RECORDS";
 pub const FLOX_SYSTEM_PLACEHOLDER: &str = "_FLOX_INIT_SYSTEM";
 pub const FLOX_HOOK_PLACEHOLDER: &str = "_FLOX_INIT_HOOK";
 pub const FLOX_INSTALL_PLACEHOLDER: &str = "_FLOX_INIT_INSTALL";
@@ -178,6 +185,23 @@ pub trait Environment: Send {
     /// without requiring reactivation.
     fn activation_path(&mut self, flox: &Flox) -> Result<PathBuf, EnvironmentError2>;
 
+    /// Return the variables describing the environment to its activations,
+    /// i.e. [FLOX_ENV_VAR], [FLOX_ENV_CACHE_VAR] and [FLOX_ENV_PROJECT_VAR]
+    ///
+    /// Like [Environment::activation_path], this may build the environment.
+    /// Path and managed environments cache the result with the activation path.
+    fn activation_exports(
+        &mut self,
+        flox: &Flox,
+    ) -> Result<BTreeMap<String, String>, EnvironmentError2> {
+        let activation_path = self.activation_path(flox)?;
+        Ok(activation_cache::exports(
+            &activation_path,
+            &self.cache_path()?,
+            &self.project_path()?,
+        ))
+    }
+
     /// Return a path that environment hooks should use to store transient data.
     fn cache_path(&self) -> Result<PathBuf, EnvironmentError2>;
 
@@ -272,12 +296,15 @@ pub struct ManagedPointer {
 
 impl ManagedPointer {
     /// Create a new [ManagedPointer] with the given owner and name.
+    ///
+    /// Environments of owners with a self-hosted git remote
+    /// keep the url of that remote in `floxhub_git_url_override`.
     pub fn new(owner: EnvironmentOwner, name: EnvironmentName, floxhub: &Floxhub) -> Self {
         Self {
+            floxhub_git_url_override: floxhub.git_url_override_for(&owner).cloned(),
             name,
             owner,
             floxhub_url: floxhub.base_url().clone(),
-            floxhub_git_url_override: floxhub.git_url_override().cloned(),
             version: Version::<1>,
         }
     }
@@ -454,6 +481,9 @@ pub enum EnvironmentError2 {
     #[error("failed to create cache directory")]
     CreateCacheDir(#[source] std::io::Error),
 
+    #[error("could not write prebuild state")]
+    WritePrebuildState(#[source] std::io::Error),
+
     #[error("could not create temporary directory")]
     CreateTempDir(#[source] std::io::Error),
 
@@ -13,6 +13,7 @@
 //! `ENVIRONMENT_DIR_NAME` contains the environment definition
 //! and is modified using [CoreEnvironment].
 
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::fs::{self};
 use std::io::Write;
@@ -21,9 +22,11 @@ use std::path::{Path, PathBuf};
 use indoc::{formatdoc, indoc};
 use log::debug;
 
+use super::activation_cache::{self, ActivationCache};
 use super::core_environment::CoreEnvironment;
 use super::{
     copy_dir_recursive,
+    include,
     CanonicalPath,
     CanonicalizeError,
     EditResult,
@@ -155,7 +158,19 @@ impl PathEnvironment {
     /// This method should only be used to create [CoreEnvironment]s for a [PathEnvironment].
     /// To modify the environment, use the [PathEnvironment] methods instead.
     pub(super) fn into_core_environment(self) -> CoreEnvironment {
-        CoreEnvironment::new(self.path.join(ENV_DIR_NAME))
+        self.core_environment()
+    }
+
+    /// A view of the environment's files
+    ///
+    /// Directories included by the manifest are relative to
+    /// the directory containing `.flox`.
+    fn core_environment(&self) -> CoreEnvironment {
+        let env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        match self.path.parent() {
+            Some(parent) => env_view.with_include_dir(parent),
+            None => env_view,
+        }
     }
 
     pub fn rename(&mut self, new_name: EnvironmentName) -> Result<(), EnvironmentError2> {
@@ -186,7 +201,7 @@ impl Environment for PathEnvironment {
     /// - Create a lockfile if one doesn't already exist, updating it with
     ///   any new packages.
     fn build(&mut self, flox: &Flox) -> Result<(), EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         let store_path = env_view.build(flox)?;
         env_view.link(flox, self.out_link(&flox.system)?, &Some(store_path))?;
 
@@ -194,12 +209,12 @@ impl Environment for PathEnvironment {
     }
 
     fn lock(&mut self, flox: &Flox) -> Result<LockedManifest, EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         Ok(env_view.lock(flox)?)
     }
 
     fn build_container(&mut self, flox: &Flox) -> Result<ContainerBuilder, EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         let builder = env_view.build_container(flox)?;
         Ok(builder)
     }
@@ -217,7 +232,7 @@ impl Environment for PathEnvironment {
         packages: &[PackageToInstall],
         flox: &Flox,
     ) -> Result<InstallationAttempt, EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         let result = env_view.install(packages, flox)?;
         env_view.link(flox, self.out_link(&flox.system)?, &result.store_path)?;
 
@@ -234,7 +249,7 @@ impl Environment for PathEnvironment {
         packages: Vec<String>,
         flox: &Flox,
     ) -> Result<UninstallationAttempt, EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         let result = env_view.uninstall(packages, flox)?;
         env_view.link(flox, self.out_link(&flox.system)?, &result.store_path)?;
 
@@ -243,7 +258,7 @@ impl Environment for PathEnvironment {
 
     /// Atomically edit this environment, ensuring that it still builds
     fn edit(&mut self, flox: &Flox, contents: String) -> Result<EditResult, EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         let result = env_view.edit(flox, contents)?;
         if result != EditResult::Unchanged {
             env_view.link(flox, self.out_link(&flox.system)?, &result.store_path())?;
@@ -257,7 +272,7 @@ impl Environment for PathEnvironment {
         flox: &Flox,
         inputs: Vec<String>,
     ) -> Result<UpdateResult, EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         let result = env_view.update(flox, inputs)?;
         env_view.link(flox, self.out_link(&flox.system)?, &result.store_path)?;
 
@@ -270,7 +285,7 @@ impl Environment for PathEnvironment {
         flox: &Flox,
         groups_or_iids: &[String],
     ) -> Result<UpgradeResult, EnvironmentError2> {
-        let mut env_view = CoreEnvironment::new(self.path.join(ENV_DIR_NAME));
+        let mut env_view = self.core_environment();
         let result = env_view.upgrade(flox, groups_or_iids)?;
         env_view.link(flox, self.out_link(&flox.system)?, &result.store_path)?;
 
@@ -301,13 +316,36 @@ impl Environment for PathEnvironment {
     }
 
     fn activation_path(&mut self, flox: &Flox) -> Result<PathBuf, EnvironmentError2> {
+        self.activation_exports(flox)?;
+        self.out_link(&flox.system)
+    }
+
+    fn activation_exports(
+        &mut self,
+        flox: &Flox,
+    ) -> Result<BTreeMap<String, String>, EnvironmentError2> {
         let out_link = self.out_link(&flox.system)?;
+        let cache_dir = self.cache_path()?;
+        let mut key_files = vec![self.manifest_path(flox)?, self.lockfile_path(flox)?];
+        key_files.extend(self.included_files(flox)?);
+
+        let key = ActivationCache::key(&key_files);
+        if let Some(exports) = ActivationCache::load(&cache_dir, &flox.system, &key, &out_link) {
+            debug!("reusing cached activation: {}", out_link.display());
+            return Ok(exports);
+        }
 
         if self.needs_rebuild(flox)? {
             self.build(flox)?;
         }
 
-        Ok(out_link)
+        let exports = activation_cache::exports(&out_link, &cache_dir, &self.project_path()?);
+
+        // building may have (re)locked the environment
+        let key = ActivationCache::key(&key_files);
+        ActivationCache::store(&cache_dir, &flox.system, key, &out_link, &exports);
+
+        Ok(exports)
     }
 
     /// Returns .flox/cache
@@ -511,14 +549,26 @@ impl PathEnvironment {
     /// Similarly, if any adjacent files are modified, the environment will not be rebuilt.
     fn needs_rebuild(&self, flox: &Flox) -> Result<bool, EnvironmentError2> {
         let manifest_modified_at = mtime_of(self.manifest_path(flox)?);
+        let included_modified_at = self.included_files(flox)?.iter().map(mtime_of).max();
         let out_link_modified_at = mtime_of(self.out_link(&flox.system)?);
 
         debug!(
             "manifest_modified_at: {manifest_modified_at:?},
+             included_modified_at: {included_modified_at:?},
              out_link_modified_at: {out_link_modified_at:?}"
         );
 
-        Ok(manifest_modified_at >= out_link_modified_at)
+        Ok(manifest_modified_at >= out_link_modified_at
+            || included_modified_at >= Some(out_link_modified_at))
+    }
+
+    /// The manifests and lockfiles of the directories included by the manifest,
+    /// changes to which require the environment to be rebuilt
+    fn included_files(&self, flox: &Flox) -> Result<Vec<PathBuf>, EnvironmentError2> {
+        let Ok(manifest) = fs::read_to_string(self.manifest_path(flox)?) else {
+            return Ok(Vec::new());
+        };
+        Ok(include::included_files(&manifest, &self.parent_path()?))
     }
 }
 
@@ -527,6 +577,7 @@ mod tests {
 
     use super::*;
     use crate::flox::tests::flox_instance;
+    use crate::models::environment::FLOX_ENV_VAR;
 
     #[test]
     fn create_env() {
@@ -601,4 +652,104 @@ mod tests {
         drop(file);
         assert!(env.needs_rebuild(&flox).unwrap());
     }
+
+    /// Set the modification time of `path` to an hour ago,
+    /// so that files created afterwards are newer even with coarse timestamps
+    fn backdate(path: &Path) {
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+    }
+
+    #[test]
+    fn included_environments_require_rebuild() {
+        let (flox, temp_dir) = flox_instance();
+
+        let environment_temp_dir = tempfile::tempdir_in(&temp_dir).unwrap();
+        let included_env_dir = environment_temp_dir.path().join("included/.flox/env");
+        fs::create_dir_all(&included_env_dir).unwrap();
+        fs::write(included_env_dir.join(MANIFEST_FILENAME), "version = 1").unwrap();
+
+        let mut env = PathEnvironment::init(
+            PathPointer::new("test".parse().unwrap()),
+            environment_temp_dir.path(),
+            temp_dir.path(),
+            &flox.system,
+            &InitCustomization::default(),
+            &flox,
+        )
+        .unwrap();
+        fs::write(
+            env.manifest_path(&flox).unwrap(),
+            "version = 1\n[include]\nenvironments = [{ dir = \"included\" }]\n",
+        )
+        .unwrap();
+        backdate(&env.manifest_path(&flox).unwrap());
+        backdate(&included_env_dir.join(MANIFEST_FILENAME));
+
+        // pretend the environment has been built
+        let store_path = tempfile::tempdir_in(&temp_dir).unwrap();
+        let out_link = env.out_link(&flox.system).unwrap();
+        std::os::unix::fs::symlink(store_path.path(), &out_link).unwrap();
+        assert!(!env.needs_rebuild(&flox).unwrap());
+        env.activation_exports(&flox).unwrap();
+
+        fs::write(included_env_dir.join(MANIFEST_FILENAME), "version = 1\n").unwrap();
+        assert!(env.needs_rebuild(&flox).unwrap());
+
+        let cache_dir = env.cache_path().unwrap();
+        let mut key_files = vec![
+            env.manifest_path(&flox).unwrap(),
+            env.lockfile_path(&flox).unwrap(),
+        ];
+        key_files.extend(env.included_files(&flox).unwrap());
+        let key = ActivationCache::key(&key_files);
+        assert_eq!(
+            ActivationCache::load(&cache_dir, &flox.system, &key, &out_link),
+            None,
+            "changing an included manifest should invalidate the activation cache"
+        );
+    }
+
+    #[test]
+    fn second_activation_skips_rebuild() {
+        let (flox, temp_dir) = flox_instance();
+
+        let environment_temp_dir = tempfile::tempdir_in(&temp_dir).unwrap();
+        let pointer = PathPointer::new("test".parse().unwrap());
+
+        let mut env = PathEnvironment::init(
+            pointer,
+            environment_temp_dir.path(),
+            temp_dir.path(),
+            &flox.system,
+            &InitCustomization::default(),
+            &flox,
+        )
+        .unwrap();
+        backdate(&env.manifest_path(&flox).unwrap());
+
+        // pretend the environment has been built
+        let store_path = tempfile::tempdir_in(&temp_dir).unwrap();
+        let out_link = env.out_link(&flox.system).unwrap();
+        std::os::unix::fs::symlink(store_path.path(), &out_link).unwrap();
+        assert!(!env.needs_rebuild(&flox).unwrap());
+
+        let exports = env.activation_exports(&flox).unwrap();
+        assert_eq!(exports[FLOX_ENV_VAR], out_link.to_string_lossy());
+
+        // rewriting the manifest without changing it would trigger a rebuild
+        // if it wasn't for the cache
+        let manifest = fs::read_to_string(env.manifest_path(&flox).unwrap()).unwrap();
+        fs::write(env.manifest_path(&flox).unwrap(), manifest).unwrap();
+        assert!(env.needs_rebuild(&flox).unwrap());
+
+        assert_eq!(env.activation_exports(&flox).unwrap(), exports);
+        assert_eq!(env.activation_path(&flox).unwrap(), out_link);
+        assert_eq!(out_link.read_link().unwrap(), store_path.path());
+    }
 }
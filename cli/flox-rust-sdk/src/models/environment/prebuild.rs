@@ -0,0 +1,120 @@
+//! Background prebuilds of environments
+//!
+//! `flox watch` locks and builds environments as soon as their manifest or
+//! lockfile changes, e.g. after pulling a branch,
+//! so that the next activation does not have to wait for a build.
+//! The state of the latest prebuild is recorded in the environment's
+//! cache directory, where `flox status` picks it up.
+
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::activation_cache::ActivationCache;
+use super::{Environment, EnvironmentError2};
+use crate::flox::Flox;
+
+/// The outcome of the latest prebuild of an environment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum PrebuildStatus {
+    Building,
+    Built { store_path: PathBuf },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrebuildState {
+    /// Hash of the manifest and lockfile the prebuild started from,
+    /// or, once it finished, the ones it produced
+    pub key: String,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub updated: DateTime<Utc>,
+    #[serde(flatten)]
+    pub status: PrebuildStatus,
+}
+
+impl PrebuildState {
+    fn state_file(cache_dir: &Path, system: &str) -> PathBuf {
+        cache_dir.join(format!("prebuild.{system}.json"))
+    }
+
+    /// Read the state of the latest prebuild of an environment, if any
+    pub fn read(
+        flox: &Flox,
+        environment: &dyn Environment,
+    ) -> Result<Option<Self>, EnvironmentError2> {
+        let state_file = Self::state_file(&environment.cache_path()?, &flox.system);
+        let Ok(content) = fs::read_to_string(state_file) else {
+            return Ok(None);
+        };
+        match serde_json::from_str(&content) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) => {
+                debug!("ignoring invalid prebuild state: {e}");
+                Ok(None)
+            },
+        }
+    }
+
+    /// Record the state of a prebuild of an environment
+    pub fn write(
+        &self,
+        flox: &Flox,
+        environment: &dyn Environment,
+    ) -> Result<(), EnvironmentError2> {
+        let state_file = Self::state_file(&environment.cache_path()?, &flox.system);
+        serde_json::to_vec(self)
+            .map_err(io::Error::from)
+            .and_then(|content| fs::write(state_file, content))
+            .map_err(EnvironmentError2::WritePrebuildState)
+    }
+}
+
+/// Hash of the files defining an environment
+///
+/// A prebuild is due whenever this differs from the [PrebuildState::key]
+/// of the latest prebuild.
+pub fn prebuild_key(
+    flox: &Flox,
+    environment: &dyn Environment,
+) -> Result<String, EnvironmentError2> {
+    Ok(ActivationCache::key(&[
+        environment.manifest_path(flox)?,
+        environment.lockfile_path(flox)?,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_roundtrip() {
+        let state = PrebuildState {
+            key: "key".to_string(),
+            updated: DateTime::from_timestamp(1700000000, 0).unwrap(),
+            status: PrebuildStatus::Failed {
+                error: "could not build".to_string(),
+            },
+        };
+
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "key": "key",
+                "updated": 1700000000,
+                "status": "failed",
+                "error": "could not build",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<PrebuildState>(json).unwrap(),
+            state
+        );
+    }
+}
@@ -5,6 +5,7 @@ use log::debug;
 use tempfile::TempDir;
 use thiserror::Error;
 
+use super::generations::GenerationId;
 use super::managed_environment::{remote_branch_name, ManagedEnvironment, ManagedEnvironmentError};
 use super::{
     gcroots_dir,
@@ -143,6 +144,20 @@ impl RemoteEnvironment {
         self.inner.pointer()
     }
 
+    /// Return the current generation of the environment
+    pub fn current_generation(&self) -> Result<Option<GenerationId>, ManagedEnvironmentError> {
+        self.inner.current_generation()
+    }
+
+    /// Rename the environment upstream, see [ManagedEnvironment::rename]
+    pub fn rename(
+        &mut self,
+        flox: &Flox,
+        name: EnvironmentName,
+    ) -> Result<(), ManagedEnvironmentError> {
+        self.inner.rename(flox, name)
+    }
+
     /// Update the out link to point to the current version of the environment
     ///
     /// The inner out link points to the latest version of the managed environment.
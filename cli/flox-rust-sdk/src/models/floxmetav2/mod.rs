@@ -56,22 +56,13 @@ impl FloxmetaV2 {
         flox: &Flox,
         pointer: &ManagedPointer,
     ) -> Result<Self, FloxmetaV2Error> {
-        let token = flox.floxhub_token.as_ref();
-
-        let floxhub = Floxhub::new(
-            pointer.floxhub_url.to_owned(),
-            pointer.floxhub_git_url_override.clone(),
-        )
-        .map_err(FloxmetaV2Error::FloxhubError)?;
-
-        let git_url = floxhub.git_url();
-
-        let git_options = floxmeta_git_options(git_url, &pointer.owner, token);
+        let git_options =
+            pointer_git_options(flox, pointer).map_err(FloxmetaV2Error::FloxhubError)?;
         let branch = remote_branch_name(pointer);
 
         let git = GitCommandProvider::clone_branch_with(
             git_options,
-            format!("{}/{}/floxmeta", git_url, pointer.owner),
+            floxmeta_url(pointer).map_err(FloxmetaV2Error::FloxhubError)?,
             path,
             branch,
             true,
@@ -110,17 +101,8 @@ impl FloxmetaV2 {
         flox: &Flox,
         pointer: &ManagedPointer,
     ) -> Result<Self, FloxmetaV2Error> {
-        let token = flox.floxhub_token.as_ref();
-
-        let floxhub = Floxhub::new(
-            pointer.floxhub_url.to_owned(),
-            pointer.floxhub_git_url_override.clone(),
-        )
-        .map_err(FloxmetaV2Error::FloxhubError)?;
-
-        let git_url = floxhub.git_url();
-
-        let git_options = floxmeta_git_options(git_url, &pointer.owner, token);
+        let git_options =
+            pointer_git_options(flox, pointer).map_err(FloxmetaV2Error::FloxhubError)?;
 
         if !user_floxmeta_dir.as_ref().exists() {
             Err(FloxmetaV2Error::NotFound(pointer.owner.to_string()))?
@@ -153,17 +135,8 @@ impl FloxmetaV2 {
         flox: &Flox,
         pointer: &ManagedPointer,
     ) -> Result<Self, FloxmetaV2Error> {
-        let token = flox.floxhub_token.as_ref();
-
-        let floxhub = Floxhub::new(
-            pointer.floxhub_url.to_owned(),
-            pointer.floxhub_git_url_override.clone(),
-        )
-        .map_err(FloxmetaV2Error::FloxhubError)?;
-
-        let git_url = floxhub.git_url();
-
-        let git_options = floxmeta_git_options(git_url, &pointer.owner, token);
+        let git_options =
+            pointer_git_options(flox, pointer).map_err(FloxmetaV2Error::FloxhubError)?;
 
         let git = GitCommandProvider::init_with(git_options, user_floxmeta_dir, false).unwrap();
         git.rename_branch(&remote_branch_name(pointer)).unwrap();
@@ -172,6 +145,58 @@ impl FloxmetaV2 {
     }
 }
 
+/// Url of the floxmeta repository that stores the environment of `pointer`
+pub(crate) fn floxmeta_url(pointer: &ManagedPointer) -> Result<String, FloxhubError> {
+    let floxhub = Floxhub::new(
+        pointer.floxhub_url.to_owned(),
+        pointer.floxhub_git_url_override.clone(),
+    )?;
+    Ok(format!("{}/{}/floxmeta", floxhub.git_url(), pointer.owner))
+}
+
+/// Returns the git options for the floxmeta repository
+/// that stores the environment of `pointer`
+///
+/// Environments on a self-hosted git remote use [self_hosted_floxmeta_git_options],
+/// all others [floxmeta_git_options] with the FloxHub token of the flox instance.
+pub(crate) fn pointer_git_options(
+    flox: &Flox,
+    pointer: &ManagedPointer,
+) -> Result<GitCommandOptions, FloxhubError> {
+    let floxhub = Floxhub::new(
+        pointer.floxhub_url.to_owned(),
+        pointer.floxhub_git_url_override.clone(),
+    )?;
+    let git_url = floxhub.git_url();
+
+    if is_self_hosted(flox, pointer) {
+        Ok(self_hosted_floxmeta_git_options(git_url, &pointer.owner))
+    } else {
+        Ok(floxmeta_git_options(
+            git_url,
+            &pointer.owner,
+            flox.floxhub_token.as_ref(),
+        ))
+    }
+}
+
+/// Whether the environment of `pointer` is stored in a self-hosted git remote
+///
+/// That is decided by the pointer rather than the current configuration,
+/// so that the FloxHub token is never sent to any other remote.
+/// Pointers overriding the git url with a url other than the FloxHub git url,
+/// or the git url flox is configured to use for FloxHub, point to a self-hosted remote.
+fn is_self_hosted(flox: &Flox, pointer: &ManagedPointer) -> bool {
+    let Some(git_url) = &pointer.floxhub_git_url_override else {
+        return false;
+    };
+    let floxhub_git_url = Floxhub::new(pointer.floxhub_url.to_owned(), None)
+        .ok()
+        .map(|floxhub| floxhub.git_url().clone());
+
+    Some(git_url) != floxhub_git_url.as_ref() && git_url != flox.floxhub.git_url()
+}
+
 /// Returns the git options for interacting with floxmeta repositories
 ///
 /// * Disable global and system config
@@ -187,30 +212,12 @@ pub fn floxmeta_git_options(
     floxhub_owner: &str,
     floxhub_token: Option<&FloxhubToken>,
 ) -> GitCommandOptions {
-    let mut options = GitCommandOptions::default();
-
-    // set the user config
-    // todo: eventually use the user's name and email once integrated with FloxHub
-    options.add_config_flag("user.name", "Flox User");
-    options.add_config_flag("user.email", "floxuser@example.invalid");
+    let mut options = base_floxmeta_git_options(floxhub_git_url, floxhub_owner);
 
     // unset the global and system config
     options.add_env_var("GIT_CONFIG_GLOBAL", "/dev/null");
     options.add_env_var("GIT_CONFIG_SYSTEM", "/dev/null");
 
-    // provides a "dynamic" remote "dynamicorigin".
-    //
-    // either the FloxHub url from the environment pointer
-    // or the default FloxHub url if the current operation does not operate on a managed environment.
-    //
-    // Local floxmeta repositories may contain environments from different FloxHub hosts.
-    // The dynamic origin allows to fetch from different FloxHub hosts per environment
-    // and reduces the amount of state stored in the local floxmeta repository.
-    options.add_config_flag(
-        "remote.dynamicorigin.url",
-        format!("{floxhub_git_url}/{floxhub_owner}/floxmeta"),
-    );
-
     let token = if let Some(token) = floxhub_token {
         debug!("using configured FloxHub token");
         token.secret()
@@ -233,12 +240,97 @@ pub fn floxmeta_git_options(
     options
 }
 
+/// Returns the git options for floxmeta repositories on a self-hosted git remote
+///
+/// Unlike [floxmeta_git_options], the global and system config are kept,
+/// so that the user's credential helpers, ssh settings and url rewrites
+/// apply to the remote, as they would with plain git.
+/// Commits are still made as the flox user, and never signed.
+pub fn self_hosted_floxmeta_git_options(git_url: &Url, owner: &str) -> GitCommandOptions {
+    let mut options = base_floxmeta_git_options(git_url, owner);
+    options.add_config_flag("commit.gpgsign", "false");
+    options
+}
+
+/// Git options shared by all floxmeta repositories
+///
+/// * Set required user config (name and email)
+/// * Configure a dynamic origin for the floxmeta repository of the owner
+fn base_floxmeta_git_options(git_url: &Url, owner: &str) -> GitCommandOptions {
+    let mut options = GitCommandOptions::default();
+
+    // set the user config
+    // todo: eventually use the user's name and email once integrated with FloxHub
+    options.add_config_flag("user.name", "Flox User");
+    options.add_config_flag("user.email", "floxuser@example.invalid");
+
+    // provides a "dynamic" remote "dynamicorigin".
+    //
+    // either the FloxHub url from the environment pointer
+    // or the default FloxHub url if the current operation does not operate on a managed environment.
+    //
+    // Local floxmeta repositories may contain environments from different FloxHub hosts.
+    // The dynamic origin allows to fetch from different FloxHub hosts per environment
+    // and reduces the amount of state stored in the local floxmeta repository.
+    options.add_config_flag(
+        "remote.dynamicorigin.url",
+        format!("{git_url}/{owner}/floxmeta"),
+    );
+
+    options
+}
+
 pub(super) fn floxmeta_dir(flox: &Flox, owner: &EnvironmentOwner) -> PathBuf {
     flox.data_dir
         .join(FLOXMETA_DIR_NAME)
         .join(owner.to_string())
 }
 
+#[cfg(test)]
+mod pointer_tests {
+    use super::*;
+    use crate::flox::tests::{flox_instance, FAKE_TOKEN};
+
+    fn sends_token(options: &GitCommandOptions) -> bool {
+        options
+            .new_command()
+            .get_envs()
+            .any(|(var, value)| var == "FLOX_FLOXHUB_TOKEN" && value == Some(FAKE_TOKEN.as_ref()))
+    }
+
+    #[test]
+    fn token_is_only_sent_to_floxhub() {
+        let (mut flox, _tempdir) = flox_instance();
+        flox.floxhub_token = Some(FloxhubToken::new(FAKE_TOKEN.to_string()).unwrap());
+
+        let floxhub_pointer = ManagedPointer::new(
+            "owner".parse().unwrap(),
+            "name".parse().unwrap(),
+            &flox.floxhub,
+        );
+        assert!(!is_self_hosted(&flox, &floxhub_pointer));
+        assert!(sends_token(
+            &pointer_git_options(&flox, &floxhub_pointer).unwrap()
+        ));
+
+        // the owner is not configured to be self-hosted,
+        // but the pointer refers to a self-hosted remote
+        let mut self_hosted_pointer = floxhub_pointer.clone();
+        self_hosted_pointer.floxhub_git_url_override =
+            Some(Url::parse("ssh://git@git.example.com/flox").unwrap());
+        assert!(!flox.floxhub.is_self_hosted(&self_hosted_pointer.owner));
+        assert!(is_self_hosted(&flox, &self_hosted_pointer));
+        assert!(!sends_token(
+            &pointer_git_options(&flox, &self_hosted_pointer).unwrap()
+        ));
+
+        // overriding the git url with that of FloxHub is not self-hosting
+        let mut overridden_pointer = floxhub_pointer;
+        overridden_pointer.floxhub_git_url_override = Some(flox.floxhub.git_url().clone());
+        assert!(!is_self_hosted(&flox, &overridden_pointer));
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "impure-unit-tests")]
 mod tests {
@@ -13,6 +13,7 @@ use thiserror::Error;
 
 use super::container_builder::ContainerBuilder;
 use super::environment::{CanonicalizeError, UpdateResult};
+use super::manifest::{ManifestContainerize, ManifestError};
 use super::pkgdb::CallPkgDbError;
 use crate::data::{System, Version};
 use crate::flox::Flox;
@@ -106,6 +107,10 @@ impl LockedManifest {
     ///
     /// The sink can be e.g. a [File](std::fs::File), [Stdout](std::io::Stdout),
     /// or an internal buffer.
+    ///
+    /// The image config is extended with `[containerize.config]`
+    /// of the manifest, and store paths are split into layers
+    /// according to `[containerize]` `layering` and `max-layers`.
     pub fn build_container(&self, pkgdb: &Path) -> Result<ContainerBuilder, LockedManifestError> {
         let mut pkgdb_cmd = Command::new(pkgdb);
         pkgdb_cmd
@@ -113,6 +118,27 @@ impl LockedManifest {
             .arg("--container")
             .arg(&self.to_string());
 
+        let containerize = self.containerize()?;
+        if let Some(config) = &containerize.config {
+            let config = config
+                .to_oci_config()
+                .map_err(LockedManifestError::InvalidContainerize)?;
+            pkgdb_cmd.arg("--container-config").arg(config.to_string());
+        }
+        if let Some(layering) = containerize.layering {
+            pkgdb_cmd
+                .arg("--container-layering")
+                .arg(layering.to_string());
+        }
+        if let Some(max_layers) = containerize
+            .checked_max_layers()
+            .map_err(LockedManifestError::InvalidContainerize)?
+        {
+            pkgdb_cmd
+                .arg("--container-max-layers")
+                .arg(max_layers.to_string());
+        }
+
         debug!(
             "building container builder with command: {}",
             pkgdb_cmd.display()
@@ -126,6 +152,16 @@ impl LockedManifest {
         Ok(ContainerBuilder::new(container_builder_path))
     }
 
+    /// Read the `[containerize]` section of the locked manifest
+    pub fn containerize(&self) -> Result<ManifestContainerize, LockedManifestError> {
+        match self.0.pointer("/manifest/containerize") {
+            Some(containerize) => serde_json::from_value(containerize.clone()).map_err(|e| {
+                LockedManifestError::InvalidContainerize(ManifestError::InvalidContainerize(e))
+            }),
+            None => Ok(ManifestContainerize::default()),
+        }
+    }
+
     /// Wrapper around `pkgdb update`
     ///
     /// lockfile_path does not need to exist
@@ -360,6 +396,8 @@ pub enum LockedManifestError {
     CallContainerBuilder(#[source] std::io::Error),
     #[error("failed to write container builder to sink")]
     WriteContainer(#[source] std::io::Error),
+    #[error(transparent)]
+    InvalidContainerize(ManifestError),
     #[error("failed to parse buildenv output")]
     ParseBuildEnvOutput(#[source] serde_json::Error),
     #[error("failed to update environment")]
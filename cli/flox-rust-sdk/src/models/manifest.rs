@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
 use std::process::Command;
 use std::str::FromStr;
 
 use log::debug;
-use serde::Deserialize;
-use toml_edit::{self, Document, Formatted, InlineTable, Item, Table, Value};
+use serde::{Deserialize, Serialize};
+use toml_edit::{self, ArrayOfTables, Document, Formatted, InlineTable, Item, Table, Value};
 
 use crate::models::pkgdb::PKGDB_BIN;
 
@@ -15,6 +16,20 @@ pub enum ManifestError {
     /// FIXME: This is a temporary error variant until `flox` parses descriptors on its own
     #[error("failed while calling pkgdb")]
     PkgDbCall(#[source] std::io::Error),
+    #[error("invalid services in manifest")]
+    InvalidServices(#[source] toml::de::Error),
+    #[error(
+        "invalid service name '{0}', service names may only contain letters, digits, '-' and '_'"
+    )]
+    InvalidServiceName(String),
+    #[error("invalid 'containerize' section in manifest")]
+    InvalidContainerize(#[source] serde_json::Error),
+    #[error("invalid exposed port '{0}', expected '<port>' or '<port>/<tcp|udp|sctp>'")]
+    InvalidExposedPort(String),
+    #[error(
+        "invalid maximum number of layers '{0}', expected between {MIN_CONTAINER_LAYERS} and {MAX_CONTAINER_LAYERS}"
+    )]
+    InvalidMaxLayers(u32),
 }
 
 /// A subset of the manifest used to check what type of edits users make. We
@@ -30,6 +45,224 @@ pub struct Manifest {
     _toml: toml::Table,
 }
 
+/// A background service declared in the `[services]` section of the manifest
+///
+/// pkgdb only checks that `[services]` is a table,
+/// the services themselves are validated when they are read with [services].
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ManifestService {
+    /// Command running the service in the foreground, evaluated by bash
+    pub command: String,
+    /// Variables set for the service in addition to those of the environment
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    /// Command that succeeds once the service is ready to be used
+    pub is_ready: Option<String>,
+    /// Whether to restart the service when it exits
+    #[serde(default)]
+    pub restart: RestartPolicy,
+}
+
+/// When to restart a service that exited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    /// Whether a service that exited with `success` should be restarted
+    pub fn should_restart(&self, success: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// Read the services declared in a manifest
+///
+/// Service names are used as file names for the state of a service,
+/// so they are restricted to letters, digits, `-` and `_`.
+pub fn services(manifest: &str) -> Result<BTreeMap<String, ManifestService>, ManifestError> {
+    #[derive(Deserialize)]
+    struct Services {
+        #[serde(default)]
+        services: BTreeMap<String, ManifestService>,
+    }
+
+    let Services { services } = toml::from_str(manifest).map_err(ManifestError::InvalidServices)?;
+
+    if let Some(name) = services.keys().find(|name| !is_valid_service_name(name)) {
+        return Err(ManifestError::InvalidServiceName(name.clone()));
+    }
+
+    Ok(services)
+}
+
+fn is_valid_service_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The `[containerize]` section of the manifest
+///
+/// pkgdb only checks that `[containerize]` is a table,
+/// it is validated when the lockfile is read by
+/// [LockedManifest::containerize](crate::models::lockfile::LockedManifest::containerize).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ManifestContainerize {
+    /// Config of the image built by `flox containerize`
+    pub config: Option<ContainerConfig>,
+    /// Order in which store paths get a layer of their own
+    pub layering: Option<ContainerLayering>,
+    /// Maximum number of layers of the image,
+    /// the remaining store paths share the last layer
+    pub max_layers: Option<u32>,
+}
+
+/// The image needs one layer for its customisation layer
+/// and at least one for the store paths.
+pub const MIN_CONTAINER_LAYERS: u32 = 2;
+/// Docker and podman refuse to run images with more than 125 layers
+pub const MAX_CONTAINER_LAYERS: u32 = 125;
+
+impl ManifestContainerize {
+    /// Check that `max-layers` is within what container runtimes support
+    pub fn checked_max_layers(&self) -> Result<Option<u32>, ManifestError> {
+        match self.max_layers {
+            Some(layers) if !(MIN_CONTAINER_LAYERS..=MAX_CONTAINER_LAYERS).contains(&layers) => {
+                Err(ManifestError::InvalidMaxLayers(layers))
+            },
+            layers => Ok(layers),
+        }
+    }
+}
+
+/// Order in which the store paths of an image get a layer of their own,
+/// until the maximum number of layers is reached.
+///
+/// Layers that did not change need not be pushed or pulled again,
+/// so the order decides how much of an image is reused after a change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerLayering {
+    /// Store paths referenced by the most other store paths first
+    #[default]
+    Popularity,
+    /// Store paths closest to the environment first,
+    /// so that each package gets a layer of its own
+    /// and only deep dependencies share the last layer
+    Depth,
+}
+
+impl Display for ContainerLayering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerLayering::Popularity => write!(f, "popularity"),
+            ContainerLayering::Depth => write!(f, "depth"),
+        }
+    }
+}
+
+/// Image config set in `[containerize.config]`,
+/// a subset of the [OCI image config](https://github.com/opencontainers/image-spec/blob/main/config.md#properties)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ContainerConfig {
+    /// User (and group) to run the container as, e.g. `app` or `1000:1000`
+    pub user: Option<String>,
+    /// Ports to expose, e.g. `8080` or `53/udp`
+    pub exposed_ports: Option<BTreeSet<String>>,
+    /// Command and arguments run in the activated environment,
+    /// instead of an interactive shell
+    pub cmd: Option<Vec<String>>,
+    /// Executable run in the activated environment, with `cmd` as arguments
+    pub entrypoint: Option<Vec<String>>,
+    /// Variables set in the image in addition to those of the environment
+    pub env: Option<BTreeMap<String, String>>,
+    /// Directories to mount volumes at
+    pub volumes: Option<BTreeSet<String>>,
+    /// Working directory of the container
+    pub working_dir: Option<String>,
+    /// Labels of the image
+    pub labels: Option<BTreeMap<String, String>>,
+    /// Signal sent to stop the container, e.g. `SIGINT`
+    pub stop_signal: Option<String>,
+}
+
+impl ContainerConfig {
+    /// Convert to the format of the OCI image config
+    pub fn to_oci_config(&self) -> Result<serde_json::Value, ManifestError> {
+        let mut config = serde_json::Map::new();
+
+        // OCI uses objects with empty values as sets
+        let set = |items: &BTreeSet<String>| {
+            serde_json::Value::Object(
+                items
+                    .iter()
+                    .map(|item| (item.clone(), serde_json::json!({})))
+                    .collect(),
+            )
+        };
+
+        if let Some(user) = &self.user {
+            config.insert("User".to_string(), user.clone().into());
+        }
+        if let Some(exposed_ports) = &self.exposed_ports {
+            let exposed_ports = exposed_ports
+                .iter()
+                .map(|port| Self::normalize_port(port))
+                .collect::<Result<_, _>>()?;
+            config.insert("ExposedPorts".to_string(), set(&exposed_ports));
+        }
+        if let Some(cmd) = &self.cmd {
+            config.insert("Cmd".to_string(), cmd.clone().into());
+        }
+        if let Some(entrypoint) = &self.entrypoint {
+            config.insert("Entrypoint".to_string(), entrypoint.clone().into());
+        }
+        if let Some(env) = &self.env {
+            let env = env
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>();
+            config.insert("Env".to_string(), env.into());
+        }
+        if let Some(volumes) = &self.volumes {
+            config.insert("Volumes".to_string(), set(volumes));
+        }
+        if let Some(working_dir) = &self.working_dir {
+            config.insert("WorkingDir".to_string(), working_dir.clone().into());
+        }
+        if let Some(labels) = &self.labels {
+            config.insert("Labels".to_string(), serde_json::json!(labels));
+        }
+        if let Some(stop_signal) = &self.stop_signal {
+            config.insert("StopSignal".to_string(), stop_signal.clone().into());
+        }
+
+        Ok(serde_json::Value::Object(config))
+    }
+
+    /// Ports default to TCP, as with `EXPOSE` in a Dockerfile
+    fn normalize_port(port: &str) -> Result<String, ManifestError> {
+        let (number, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+        if number.parse::<u16>().is_err() || !["tcp", "udp", "sctp"].contains(&protocol) {
+            return Err(ManifestError::InvalidExposedPort(port.to_string()));
+        }
+        Ok(format!("{number}/{protocol}"))
+    }
+}
+
 /// An error encountered while installing packages.
 #[derive(Debug, thiserror::Error)]
 pub enum TomlEditError {
@@ -239,6 +472,292 @@ pub fn add_system(toml: &str, system: &str) -> Result<Document, TomlEditError> {
     Ok(doc)
 }
 
+/// Marks the position of a conflicting key in a merged manifest,
+/// until it is replaced with conflict markers
+const MERGE_CONFLICT_PLACEHOLDER: &str = "__flox_merge_conflict_";
+
+/// The result of a three-way merge of manifests, see [merge_manifests]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestMerge {
+    /// The merged manifest
+    ///
+    /// Keys that were changed differently on both sides
+    /// are surrounded by git style conflict markers,
+    /// which have to be resolved before the manifest can be used.
+    pub manifest: String,
+    /// Dotted paths of the conflicting keys, e.g. `install.hello`
+    pub conflicts: Vec<String>,
+}
+
+/// A key that was changed differently in the local and the remote manifest
+struct MergeConflict {
+    key: String,
+    local: Option<Item>,
+    remote: Option<Item>,
+}
+
+/// Three-way merge a `local` and a `remote` manifest
+/// that were both derived from the `base` manifest.
+///
+/// Changes made on only one side are applied to the result.
+/// `[install]`, `[vars]` and `[options]` are merged key-by-key,
+/// any other top-level key merges cleanly only if it was changed on one side.
+/// The formatting of `local` is preserved where possible.
+pub fn merge_manifests(
+    base: &str,
+    local: &str,
+    remote: &str,
+) -> Result<ManifestMerge, TomlEditError> {
+    let parse = |toml: &str| {
+        toml.parse::<Document>()
+            .map_err(TomlEditError::ParseManifest)
+    };
+    let base = parse(base)?;
+    let mut merged = parse(local)?;
+    let remote = parse(remote)?;
+
+    let mut conflicts = Vec::new();
+    merge_tables(
+        "",
+        Some(base.as_table()),
+        merged.as_table_mut(),
+        Some(remote.as_table()),
+        &mut conflicts,
+    );
+
+    let mut manifest = merged.to_string();
+    for (n, conflict) in conflicts.iter().enumerate() {
+        manifest = mark_conflict(&manifest, n, conflict);
+    }
+
+    Ok(ManifestMerge {
+        manifest,
+        conflicts: conflicts.into_iter().map(|conflict| conflict.key).collect(),
+    })
+}
+
+/// Whether a manifest still contains conflict markers
+/// written by [merge_manifests]
+pub fn contains_conflict_markers(manifest: &str) -> bool {
+    manifest.lines().any(|line| {
+        line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> ") || line == "======="
+    })
+}
+
+/// Merge the changes between `base` and `remote` into `local`
+///
+/// Conflicting keys are replaced with a placeholder
+/// and recorded in `conflicts`.
+fn merge_tables(
+    path: &str,
+    base: Option<&Table>,
+    local: &mut Table,
+    remote: Option<&Table>,
+    conflicts: &mut Vec<MergeConflict>,
+) {
+    let mut keys: Vec<String> = Vec::new();
+    for table in [Some(&*local), remote, base].into_iter().flatten() {
+        for (key, _) in table.iter() {
+            if !keys.iter().any(|known| known == key) {
+                keys.push(key.to_string());
+            }
+        }
+    }
+
+    for key in keys {
+        let key_path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+
+        let base_item = base.and_then(|table| table.get(&key));
+        let local_item = local.get(&key).cloned();
+        let remote_item = remote.and_then(|table| table.get(&key));
+
+        let base_value = item_value(base_item);
+        let local_value = item_value(local_item.as_ref());
+        let remote_value = item_value(remote_item);
+
+        // unchanged upstream, or the same change on both sides
+        if remote_value == base_value || remote_value == local_value {
+            continue;
+        }
+
+        // changed upstream only
+        if local_value == base_value {
+            match remote.and_then(|table| table.get_key_value(&key)) {
+                Some((key, item)) => local.insert_formatted(key, detached(item)),
+                None => local.remove(&key),
+            };
+            continue;
+        }
+
+        let merge_by_key = matches!(key_path.as_str(), "install" | "vars" | "options")
+            || key_path.starts_with("options.");
+        if merge_by_key {
+            if let (Some(Item::Table(local_table)), Some(Item::Table(remote_table))) =
+                (local.get_mut(&key), remote_item)
+            {
+                merge_tables(
+                    &key_path,
+                    base_item.and_then(Item::as_table),
+                    local_table,
+                    Some(remote_table),
+                    conflicts,
+                );
+                continue;
+            }
+        }
+
+        local.insert(
+            &key,
+            toml_edit::value(format!("{MERGE_CONFLICT_PLACEHOLDER}{}", conflicts.len())),
+        );
+        local.set_implicit(false);
+        conflicts.push(MergeConflict {
+            key: key_path,
+            local: local_item,
+            remote: remote_item.cloned(),
+        });
+    }
+}
+
+/// The value of an item independent of its formatting,
+/// or `None` if the item is missing
+fn item_value(item: Option<&Item>) -> Option<toml::Value> {
+    let item = item.filter(|item| !item.is_none())?;
+    let mut document = Document::new();
+    document.insert("value", item.clone());
+    let mut table: toml::Table = toml::from_str(&document.to_string()).ok()?;
+    table.remove("value")
+}
+
+/// Copy an item from another document
+///
+/// Tables lose their position in the other document,
+/// so that they are rendered next to their parent table.
+fn detached(item: &Item) -> Item {
+    let detached_table = |table: &Table| {
+        let mut detached_table = Table::new();
+        for (key, item) in table.iter() {
+            let (key, _) = table.get_key_value(key).unwrap();
+            detached_table.insert_formatted(key, detached(item));
+        }
+        detached_table.set_implicit(table.is_implicit());
+        detached_table.set_dotted(table.is_dotted());
+        *detached_table.decor_mut() = table.decor().clone();
+        detached_table
+    };
+
+    match item {
+        Item::Table(table) => Item::Table(detached_table(table)),
+        Item::ArrayOfTables(array) => {
+            let mut detached_array = ArrayOfTables::new();
+            for table in array.iter() {
+                detached_array.push(detached_table(table));
+            }
+            Item::ArrayOfTables(detached_array)
+        },
+        _ => item.clone(),
+    }
+}
+
+/// Replace the placeholder of the `n`th conflict with conflict markers
+/// surrounding the local and the remote version of the key
+fn mark_conflict(manifest: &str, n: usize, conflict: &MergeConflict) -> String {
+    let placeholder = format!("\"{MERGE_CONFLICT_PLACEHOLDER}{n}\"");
+
+    let inline = |key: &str, item: &Option<Item>| match item {
+        Some(item) => {
+            let value = match item.clone().into_value() {
+                Ok(mut value) => {
+                    value.decor_mut().clear();
+                    value.to_string()
+                },
+                Err(item) => item.to_string(),
+            };
+            format!("{key} = {value}\n")
+        },
+        None => String::new(),
+    };
+
+    let mut marked = String::new();
+    for line in manifest.split_inclusive('\n') {
+        let key = line
+            .trim_end()
+            .strip_suffix(&placeholder)
+            .and_then(|rest| rest.trim_end().strip_suffix('='))
+            .map(str::trim_end);
+
+        match key {
+            Some(key) => {
+                marked.push_str("<<<<<<< local\n");
+                marked.push_str(&inline(key, &conflict.local));
+                marked.push_str("=======\n");
+                marked.push_str(&inline(key, &conflict.remote));
+                marked.push_str(">>>>>>> remote\n");
+            },
+            None => marked.push_str(line),
+        }
+    }
+    marked
+}
+
+/// Packages that differ between two manifests, see [diff_packages]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackageDiff {
+    /// Install ids of packages only installed by the new manifest
+    pub added: Vec<String>,
+    /// Install ids of packages only installed by the old manifest
+    pub removed: Vec<String>,
+    /// Install ids of packages with a different descriptor in the new manifest
+    pub changed: Vec<String>,
+}
+
+impl PackageDiff {
+    /// Whether both manifests install the same packages
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare the `[install]` tables of an `old` and a `new` manifest
+///
+/// Descriptors are compared independent of their formatting.
+pub fn diff_packages(old: &str, new: &str) -> Result<PackageDiff, TomlEditError> {
+    let installed = |toml: &str| {
+        let document = toml
+            .parse::<Document>()
+            .map_err(TomlEditError::ParseManifest)?;
+        let packages = match document.get("install") {
+            None => BTreeMap::new(),
+            Some(Item::Table(install)) => install
+                .iter()
+                .map(|(id, item)| (id.to_string(), item_value(Some(item))))
+                .collect(),
+            Some(install) => Err(TomlEditError::MalformedInstallTable(
+                install.type_name().into(),
+            ))?,
+        };
+        Ok::<_, TomlEditError>(packages)
+    };
+    let old = installed(old)?;
+    let new = installed(new)?;
+
+    let mut diff = PackageDiff::default();
+    for (id, descriptor) in &new {
+        match old.get(id) {
+            None => diff.added.push(id.clone()),
+            Some(old_descriptor) if old_descriptor != descriptor => diff.changed.push(id.clone()),
+            Some(_) => {},
+        }
+    }
+    diff.removed = old.into_keys().filter(|id| !new.contains_key(id)).collect();
+
+    Ok(diff)
+}
+
 /// A parsed descriptor from `pkgdb parse descriptor --manifest`
 ///
 /// FIXME: this is currently a hack using a tool in `pkgdb` only meant for debugging.
@@ -325,6 +844,8 @@ pub fn temporary_parse_descriptor(descriptor: &str) -> Result<PackageToInstall,
 
 #[cfg(test)]
 mod test {
+    use indoc::indoc;
+
     use super::*;
 
     const DUMMY_MANIFEST: &str = r#"
@@ -488,4 +1009,239 @@ ripgrep = {}
             input: Some("nixpkgs".to_string())
         });
     }
+
+    #[test]
+    fn parses_services() {
+        let manifest = indoc! {r#"
+            [install]
+            hello = {}
+
+            [services.postgres]
+            command = "postgres -D $PGDATA"
+            vars = { PGPORT = "5432" }
+            is-ready = "pg_isready"
+            restart = "on-failure"
+
+            [services.redis]
+            command = "redis-server"
+        "#};
+
+        let services = services(manifest).unwrap();
+        assert_eq!(services.len(), 2);
+        assert_eq!(services["postgres"], ManifestService {
+            command: "postgres -D $PGDATA".to_string(),
+            vars: BTreeMap::from([("PGPORT".to_string(), "5432".to_string())]),
+            is_ready: Some("pg_isready".to_string()),
+            restart: RestartPolicy::OnFailure,
+        });
+        assert_eq!(services["redis"].restart, RestartPolicy::Never);
+    }
+
+    #[test]
+    fn rejects_invalid_services() {
+        assert!(services("[services.redis]\nrestart = \"always\"\n").is_err());
+        assert!(services("[services.redis]\ncommand = \"redis-server\"\nport = 1\n").is_err());
+        assert!(services(DUMMY_MANIFEST).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_service_names() {
+        for name in ["../../x", "a/b", "", ".", "redis server"] {
+            let manifest = format!("[services.\"{name}\"]\ncommand = \"true\"\n");
+            assert!(
+                matches!(services(&manifest), Err(ManifestError::InvalidServiceName(n)) if n == name),
+                "{name:?} should be rejected"
+            );
+        }
+        assert!(services("[services.redis_2-a]\ncommand = \"true\"\n").is_ok());
+    }
+
+    #[test]
+    fn converts_container_config_to_oci() {
+        let containerize: ManifestContainerize = toml::from_str(indoc! {r#"
+            [config]
+            user = "app"
+            exposed-ports = ["8080", "53/udp"]
+            cmd = ["serve"]
+            env = { MODE = "production" }
+            working-dir = "/srv"
+            labels = { "org.opencontainers.image.title" = "app" }
+        "#})
+        .unwrap();
+
+        assert_eq!(
+            containerize.config.unwrap().to_oci_config().unwrap(),
+            serde_json::json!({
+                "User": "app",
+                "ExposedPorts": { "53/udp": {}, "8080/tcp": {} },
+                "Cmd": ["serve"],
+                "Env": ["MODE=production"],
+                "WorkingDir": "/srv",
+                "Labels": { "org.opencontainers.image.title": "app" },
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_container_config() {
+        assert!(toml::from_str::<ManifestContainerize>("[config]\nports = [\"80\"]\n").is_err());
+
+        let config = ContainerConfig {
+            exposed_ports: Some(BTreeSet::from(["http".to_string()])),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.to_oci_config(),
+            Err(ManifestError::InvalidExposedPort(port)) if port == "http"
+        ));
+    }
+
+    #[test]
+    fn parses_container_layering() {
+        let containerize: ManifestContainerize =
+            toml::from_str("layering = \"depth\"\nmax-layers = 120\n").unwrap();
+        assert_eq!(containerize.layering, Some(ContainerLayering::Depth));
+        assert_eq!(containerize.checked_max_layers().unwrap(), Some(120));
+
+        assert!(toml::from_str::<ManifestContainerize>("layering = \"size\"\n").is_err());
+
+        let containerize = ManifestContainerize {
+            max_layers: Some(200),
+            ..Default::default()
+        };
+        assert!(matches!(
+            containerize.checked_max_layers(),
+            Err(ManifestError::InvalidMaxLayers(200))
+        ));
+    }
+
+    const MERGE_BASE: &str = indoc! {r#"
+        [install]
+        hello.pkg-path = "hello"
+
+        [vars]
+        foo = "bar"
+
+        [options]
+        systems = ["x86_64-linux"]
+    "#};
+
+    #[test]
+    fn merges_changes_key_by_key() {
+        let local = indoc! {r#"
+            [install]
+            hello.pkg-path = "hello"
+            ripgrep.pkg-path = "ripgrep"
+
+            [vars]
+            foo = "baz"
+
+            [options]
+            systems = ["x86_64-linux"]
+        "#};
+        let remote = indoc! {r#"
+            [install]
+            hello.pkg-path = "hello"
+            curl.pkg-path = "curl"
+
+            [vars]
+            foo = "bar"
+            answer = "42"
+
+            [options]
+            systems = ["x86_64-linux", "aarch64-darwin"]
+
+            [hook]
+            on-activate = "echo hello"
+        "#};
+
+        let merge = merge_manifests(MERGE_BASE, local, remote).unwrap();
+        assert!(merge.conflicts.is_empty());
+
+        let merged: toml::Value = toml::from_str(&merge.manifest).unwrap();
+        let expected: toml::Value = toml::from_str(indoc! {r#"
+            [install]
+            hello.pkg-path = "hello"
+            ripgrep.pkg-path = "ripgrep"
+            curl.pkg-path = "curl"
+
+            [vars]
+            foo = "baz"
+            answer = "42"
+
+            [options]
+            systems = ["x86_64-linux", "aarch64-darwin"]
+
+            [hook]
+            on-activate = "echo hello"
+        "#})
+        .unwrap();
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn merges_removals() {
+        let local = MERGE_BASE.replace("[vars]\nfoo = \"bar\"\n", "[vars]\n");
+        let remote = MERGE_BASE.replace("hello.pkg-path = \"hello\"\n", "");
+
+        let merge = merge_manifests(MERGE_BASE, &local, &remote).unwrap();
+        assert!(merge.conflicts.is_empty());
+
+        let merged: toml::Value = toml::from_str(&merge.manifest).unwrap();
+        assert_eq!(merged["install"].as_table().unwrap().len(), 0);
+        assert_eq!(merged["vars"].as_table().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        let local = MERGE_BASE.replace(
+            "hello.pkg-path = \"hello\"",
+            "hello = { pkg-path = \"hello\", version = \"2.10\" }",
+        );
+        let remote = MERGE_BASE
+            .replace(
+                "hello.pkg-path = \"hello\"",
+                "hello = { pkg-path = \"hello\", version = \"2.12\" }",
+            )
+            .replace("foo = \"bar\"", "foo = \"qux\"");
+
+        let merge = merge_manifests(MERGE_BASE, &local, &remote).unwrap();
+        assert_eq!(merge.conflicts, vec!["install.hello".to_string()]);
+        assert!(contains_conflict_markers(&merge.manifest));
+        assert_eq!(merge.manifest, indoc! {r#"
+            [install]
+            <<<<<<< local
+            hello = { pkg-path = "hello", version = "2.10" }
+            =======
+            hello = { pkg-path = "hello", version = "2.12" }
+            >>>>>>> remote
+
+            [vars]
+            foo = "qux"
+
+            [options]
+            systems = ["x86_64-linux"]
+        "#});
+    }
+
+    #[test]
+    fn diffs_installed_packages() {
+        let new = indoc! {r#"
+            [install]
+            hello = { pkg-path = "hello", version = "2.12" }
+            vim.pkg-path = "vim"
+        "#};
+
+        assert_eq!(diff_packages(MERGE_BASE, new).unwrap(), PackageDiff {
+            added: vec!["vim".to_string()],
+            removed: vec![],
+            changed: vec!["hello".to_string()],
+        });
+        assert_eq!(diff_packages(new, MERGE_BASE).unwrap(), PackageDiff {
+            added: vec![],
+            removed: vec!["vim".to_string()],
+            changed: vec!["hello".to_string()],
+        });
+        assert!(diff_packages(MERGE_BASE, MERGE_BASE).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,195 @@
+//! Protocol and client of `floxd`, the per-user environment lifecycle daemon
+//!
+//! `floxd` listens on a unix socket in the flox cache directory.
+//! `flox activate` registers the processes that have an environment activated,
+//! and once the last of these processes for an environment exits,
+//! `floxd` runs the cleanup commands registered for the environment,
+//! e.g. to run deactivation hooks or to stop services.
+//!
+//! Requests and responses are exchanged as single lines of JSON.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::flox::Flox;
+
+/// Name of the socket `floxd` listens on, in [Flox::cache_dir]
+pub const FLOXD_SOCKET_NAME: &str = "floxd.sock";
+
+/// Path of the socket `floxd` listens on
+pub fn socket_path(flox: &Flox) -> PathBuf {
+    flox.cache_dir.join(FLOXD_SOCKET_NAME)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+    /// Record that a process has an environment activated
+    Register(Registration),
+    /// Forget that a process has an environment activated,
+    /// e.g. after `flox deactivate`
+    Unregister { pid: i32, environment: String },
+    /// List the tracked environments
+    Activations,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "kebab-case")]
+pub enum Response {
+    Ok,
+    Activations {
+        activations: Vec<TrackedEnvironment>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// An activation of an environment in a process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Registration {
+    /// Process that has the environment activated
+    pub pid: i32,
+    /// Identifies the environment across activations
+    pub environment: String,
+    /// Human readable name of the environment
+    pub name: String,
+    /// The activated environment, i.e. `$FLOX_ENV`
+    pub flox_env: PathBuf,
+    /// Commands to run after the last activation of the environment exited
+    pub cleanup: Vec<CleanupCommand>,
+}
+
+/// A command `floxd` runs to clean up after an environment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CleanupCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Working directory of the command
+    pub dir: Option<PathBuf>,
+    /// Variables set for the command in addition to those of `floxd`
+    pub env: BTreeMap<String, String>,
+}
+
+/// An environment that is active in at least one process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackedEnvironment {
+    pub environment: String,
+    pub name: String,
+    pub flox_env: PathBuf,
+    pub pids: Vec<i32>,
+}
+
+#[derive(Debug, Error)]
+pub enum FloxdError {
+    #[error("could not connect to floxd")]
+    Connect(#[source] io::Error),
+    #[error("could not communicate with floxd")]
+    Io(#[source] io::Error),
+    #[error("invalid message from floxd")]
+    Json(#[source] serde_json::Error),
+    #[error("floxd closed the connection")]
+    Closed,
+    #[error("floxd: {0}")]
+    Daemon(String),
+}
+
+/// A connection to a running `floxd`
+#[derive(Debug)]
+pub struct FloxdClient {
+    stream: BufReader<UnixStream>,
+}
+
+impl FloxdClient {
+    /// How long to wait for `floxd` to answer a request
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Connect to `floxd` listening on `socket`
+    pub fn connect(socket: impl AsRef<Path>) -> Result<Self, FloxdError> {
+        let stream = UnixStream::connect(socket).map_err(FloxdError::Connect)?;
+        stream
+            .set_read_timeout(Some(Self::TIMEOUT))
+            .map_err(FloxdError::Connect)?;
+        Ok(Self {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    /// Send a request and wait for the response
+    ///
+    /// [Response::Error] is returned as [FloxdError::Daemon].
+    pub fn request(&mut self, request: &Request) -> Result<Response, FloxdError> {
+        write_message(self.stream.get_mut(), request).map_err(FloxdError::Io)?;
+
+        match read_message(&mut self.stream)? {
+            Some(Response::Error { message }) => Err(FloxdError::Daemon(message)),
+            Some(response) => Ok(response),
+            None => Err(FloxdError::Closed),
+        }
+    }
+}
+
+/// Write a message as a single line of JSON
+pub fn write_message(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+/// Read a message written by [write_message]
+///
+/// Returns [None] if the connection was closed.
+pub fn read_message<T: for<'de> Deserialize<'de>>(
+    reader: &mut impl BufRead,
+) -> Result<Option<T>, FloxdError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).map_err(FloxdError::Io)? == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(FloxdError::Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn messages_roundtrip() {
+        let request = Request::Register(Registration {
+            pid: 1234,
+            environment: "/project/.flox".to_string(),
+            name: "project".to_string(),
+            flox_env: PathBuf::from("/project/.flox/run/x86_64-linux.project"),
+            cleanup: vec![CleanupCommand {
+                program: PathBuf::from("/bin/bash"),
+                args: vec!["on-deactivate.sh".to_string()],
+                dir: Some(PathBuf::from("/project")),
+                env: BTreeMap::from([("FLOX_ENV".to_string(), "/env".to_string())]),
+            }],
+        });
+
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &request).unwrap();
+        write_message(&mut buffer, &Request::Activations).unwrap();
+        assert_eq!(buffer.iter().filter(|b| **b == b'\n').count(), 2);
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_message::<Request>(&mut reader).unwrap(), Some(request));
+        assert_eq!(
+            read_message::<Request>(&mut reader).unwrap(),
+            Some(Request::Activations)
+        );
+        assert_eq!(read_message::<Request>(&mut reader).unwrap(), None);
+    }
+}
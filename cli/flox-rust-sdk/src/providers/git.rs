@@ -359,6 +359,41 @@ impl GitCommandProvider {
         }
     }
 
+    /// Return the best common ancestor of two revisions,
+    /// or `None` if their histories are unrelated
+    pub fn merge_base(&self, a: &str, b: &str) -> Result<Option<String>, GitCommandError> {
+        let result =
+            GitCommandProvider::run_command(self.new_command().arg("merge-base").arg(a).arg(b));
+        match result {
+            Ok(hash) => Ok(Some(hash.to_string_lossy().trim().to_string())),
+            Err(GitCommandError::BadExit(1, stdout, stderr))
+                if stdout.is_empty() && stderr.is_empty() =>
+            {
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a commit of an existing tree with the given parents
+    /// and return its hash
+    ///
+    /// This does not update any branch.
+    pub fn commit_tree(
+        &self,
+        tree: &str,
+        parents: &[&str],
+        message: &str,
+    ) -> Result<String, GitCommandError> {
+        let mut command = self.new_command();
+        command.arg("commit-tree").arg(tree).arg("-m").arg(message);
+        for parent in parents {
+            command.arg("-p").arg(parent);
+        }
+        let hash = GitCommandProvider::run_command(&mut command)?;
+        Ok(hash.to_string_lossy().trim().to_string())
+    }
+
     /// Create branch at a specified revision
     pub fn create_branch(&self, name: &str, rev: &str) -> Result<(), GitCommandError> {
         GitCommandProvider::run_command(self.new_command().arg("branch").arg(name).arg(rev))?;
@@ -377,6 +412,22 @@ impl GitCommandProvider {
         Ok(())
     }
 
+    /// Rename a branch, replacing an existing branch named `new_name`
+    ///
+    /// Unlike [GitProvider::rename_branch], this renames any branch,
+    /// not just the checked out one.
+    pub fn move_branch(&self, name: &str, new_name: &str) -> Result<(), GitCommandError> {
+        GitCommandProvider::run_command(
+            self.new_command()
+                .arg("branch")
+                .arg("--move")
+                .arg("--force")
+                .arg(name)
+                .arg(new_name),
+        )?;
+        Ok(())
+    }
+
     /// Return the hash of a branch or error if it does not exist
     pub fn branch_hash(&self, name: &str) -> Result<String, GitCommandBranchHashError> {
         let result = GitCommandProvider::run_command(
@@ -1193,6 +1244,58 @@ pub mod tests {
         assert_eq!(repo.branch_hash("test").unwrap(), hash)
     }
 
+    #[test]
+    fn test_merge_base() {
+        let (repo, _tempdir_handle) = init_temp_repo(false);
+        repo.checkout("branch_1", true).unwrap();
+        commit_file(&repo, "dummy");
+        let base = repo.branch_hash("branch_1").unwrap();
+        commit_file(&repo, "dummy_1");
+
+        repo.create_branch("branch_2", &base).unwrap();
+        repo.checkout("branch_2", false).unwrap();
+        commit_file(&repo, "dummy_2");
+
+        assert_eq!(repo.merge_base("branch_1", "branch_2").unwrap(), Some(base));
+
+        repo.checkout("branch_3", true).unwrap();
+        commit_file(&repo, "dummy_3");
+        assert_eq!(repo.merge_base("branch_1", "branch_3").unwrap(), None);
+    }
+
+    #[test]
+    fn test_commit_tree() {
+        let (repo, _tempdir_handle) = init_temp_repo(false);
+        repo.checkout("branch_1", true).unwrap();
+        commit_file(&repo, "dummy");
+        let parent_1 = repo.branch_hash("branch_1").unwrap();
+        repo.checkout("branch_2", true).unwrap();
+        commit_file(&repo, "dummy_2");
+        let parent_2 = repo.branch_hash("branch_2").unwrap();
+
+        let merge = repo
+            .commit_tree("branch_2^{tree}", &[&parent_1, &parent_2], "merge")
+            .unwrap();
+        repo.reset_branch("merged", &merge).unwrap();
+
+        assert!(repo.branch_contains_commit(&parent_1, "merged").unwrap());
+        assert!(repo.branch_contains_commit(&parent_2, "merged").unwrap());
+    }
+
+    #[test]
+    fn test_move_branch() {
+        let (repo, _tempdir_handle) = init_temp_repo(false);
+        repo.checkout("branch_1", true).unwrap();
+        commit_file(&repo, "dummy");
+        let hash = repo.branch_hash("branch_1").unwrap();
+        repo.create_branch("branch_2", &hash).unwrap();
+
+        repo.move_branch("branch_2", "branch_3").unwrap();
+
+        assert!(!repo.has_branch("branch_2").unwrap());
+        assert_eq!(repo.branch_hash("branch_3").unwrap(), hash);
+    }
+
     // test that clone_branch only clones the specified branch
     #[test]
     fn test_clone_branch() {
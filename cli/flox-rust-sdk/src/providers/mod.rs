@@ -1 +1,3 @@
+pub mod floxd;
 pub mod git;
+pub mod oci_registry;
@@ -0,0 +1,792 @@
+//! Client pushing container images to registries
+//!
+//! Implements the push side of the
+//! [OCI distribution API](https://github.com/opencontainers/distribution-spec/blob/main/spec.md).
+//! Blobs the registry already has are not uploaded again.
+//! Registries are authenticated with basic auth or with bearer tokens,
+//! as requested by the registry.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use base64::Engine;
+use log::debug;
+use reqwest::blocking::{Body, Client, RequestBuilder, Response};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::models::container_image::{Blob, UnpackedImage, OCI_MANIFEST_MEDIA_TYPE};
+
+const DOCKER_HUB: &str = "docker.io";
+const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+
+/// Reference of an image in a registry,
+/// e.g. `registry.example.com/team/env:tag`
+///
+/// As with `docker push`, references without a registry refer to Docker Hub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteImageReference {
+    /// Host, and optionally port, of the registry
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+}
+
+impl FromStr for RemoteImageReference {
+    type Err = RegistryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RegistryError::InvalidReference(s.to_string());
+
+        let (rest, tag) = match s.rsplit_once(':') {
+            Some((rest, tag)) if !tag.contains('/') => (rest, Some(tag.to_string())),
+            _ => (s, None),
+        };
+        let (registry, repository) = match rest.split_once('/') {
+            Some((host, repository))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), repository.to_string())
+            },
+            Some(_) => (DOCKER_HUB.to_string(), rest.to_string()),
+            None => (DOCKER_HUB.to_string(), format!("library/{rest}")),
+        };
+
+        let valid_repository = repository.split('/').all(|component| {
+            !component.is_empty()
+                && component
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+        });
+        if !valid_repository || tag.as_deref() == Some("") {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            registry,
+            repository,
+            tag,
+        })
+    }
+}
+
+impl Display for RemoteImageReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        Ok(())
+    }
+}
+
+impl RemoteImageReference {
+    /// Base URL of the registry API
+    ///
+    /// Like docker, registries on localhost are accessed without TLS.
+    fn base_url(&self) -> Result<Url, RegistryError> {
+        let host = match self.registry.as_str() {
+            DOCKER_HUB => DOCKER_HUB_REGISTRY,
+            registry => registry,
+        };
+        let hostname = host.split(':').next().unwrap_or(host);
+        let scheme = if hostname == "localhost" || hostname == "127.0.0.1" {
+            "http"
+        } else {
+            "https"
+        };
+        Url::parse(&format!("{scheme}://{host}/"))
+            .map_err(|_| RegistryError::InvalidReference(self.to_string()))
+    }
+}
+
+/// Username and password for a registry
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RegistryCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// The parts of `~/.docker/config.json` relevant to authentication
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+    creds_store: Option<String>,
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DockerAuth {
+    auth: Option<String>,
+}
+
+impl RegistryCredentials {
+    /// Look up credentials for `registry` in the docker config
+    /// at `$DOCKER_CONFIG/config.json` or `~/.docker/config.json`,
+    /// including credentials stored with credential helpers
+    pub fn from_docker_config(registry: &str) -> Option<Self> {
+        let config_dir = std::env::var_os("DOCKER_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".docker")))?;
+        Self::from_docker_config_file(&config_dir.join("config.json"), registry)
+    }
+
+    fn from_docker_config_file(path: &Path, registry: &str) -> Option<Self> {
+        let config: DockerConfig = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| debug!("ignoring invalid docker config {}: {e}", path.display()))
+                .ok()?,
+            Err(_) => return None,
+        };
+
+        // Docker Hub credentials are stored under its legacy index URL
+        let matches = |key: &str| {
+            let host = key
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .split('/')
+                .next()
+                .unwrap_or_default();
+            host == registry
+                || (registry == DOCKER_HUB
+                    && ["index.docker.io", DOCKER_HUB_REGISTRY].contains(&host))
+        };
+
+        let helper = config
+            .cred_helpers
+            .iter()
+            .find(|(key, _)| matches(key))
+            .map(|(_, helper)| helper)
+            .or(config.creds_store.as_ref());
+        if let Some(credentials) = helper.and_then(|helper| Self::from_helper(helper, registry)) {
+            return Some(credentials);
+        }
+
+        config
+            .auths
+            .iter()
+            .filter(|(key, _)| matches(key))
+            .find_map(|(_, auth)| Self::decode(auth.auth.as_ref()?))
+    }
+
+    /// Decode `<username>:<password>` in base64, as stored in the docker config
+    fn decode(auth: &str) -> Option<Self> {
+        let auth = base64::engine::general_purpose::STANDARD
+            .decode(auth)
+            .ok()?;
+        let (username, password) = std::str::from_utf8(&auth).ok()?.split_once(':')?;
+        Some(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    /// Ask a docker credential helper, i.e. `docker-credential-<helper> get`
+    fn from_helper(helper: &str, registry: &str) -> Option<Self> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct HelperCredentials {
+            username: String,
+            secret: String,
+        }
+
+        let server = if registry == DOCKER_HUB {
+            "https://index.docker.io/v1/"
+        } else {
+            registry
+        };
+        let mut child = Command::new(format!("docker-credential-{helper}"))
+            .arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| debug!("could not run credential helper '{helper}': {e}"))
+            .ok()?;
+        child.stdin.take()?.write_all(server.as_bytes()).ok()?;
+        let output = child.wait_with_output().ok()?;
+        if !output.status.success() {
+            debug!("credential helper '{helper}' has no credentials for {registry}");
+            return None;
+        }
+        let credentials: HelperCredentials = serde_json::from_slice(&output.stdout).ok()?;
+        Some(Self {
+            username: credentials.username,
+            password: credentials.secret,
+        })
+    }
+}
+
+/// Outcome of [push_image]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushedImage {
+    /// Digest of the image manifest
+    pub digest: String,
+    /// Number of blobs that were uploaded
+    pub uploaded: usize,
+    /// Number of blobs the registry already had
+    pub skipped: usize,
+}
+
+/// Push an image to the tag of `reference`
+pub fn push_image(
+    image: &UnpackedImage,
+    reference: &RemoteImageReference,
+    credentials: Option<RegistryCredentials>,
+) -> Result<PushedImage, RegistryError> {
+    let tag = reference.tag.as_deref().unwrap_or("latest");
+    let mut client = RegistryClient {
+        client: Client::builder()
+            // uploading large layers may take a while
+            .timeout(None)
+            .build()
+            .map_err(RegistryError::Request)?,
+        base_url: reference.base_url()?,
+        registry: reference.registry.clone(),
+        repository: reference.repository.clone(),
+        credentials,
+        authorization: None,
+    };
+
+    let mut uploaded = 0;
+    let mut skipped = 0;
+    for blob in std::iter::once(&image.config).chain(&image.layers) {
+        if client.has_blob(blob)? {
+            debug!("registry already has blob {}", blob.digest);
+            skipped += 1;
+        } else {
+            client.upload_blob(blob)?;
+            uploaded += 1;
+        }
+    }
+
+    client.put_manifest(tag, &image.manifest)?;
+
+    Ok(PushedImage {
+        digest: image.digest(),
+        uploaded,
+        skipped,
+    })
+}
+
+struct RegistryClient {
+    client: Client,
+    base_url: Url,
+    registry: String,
+    repository: String,
+    credentials: Option<RegistryCredentials>,
+    /// Value of the `Authorization` header, once the registry asked for it
+    authorization: Option<String>,
+}
+
+impl RegistryClient {
+    fn url(&self, path: &str) -> Result<Url, RegistryError> {
+        self.base_url
+            .join(&format!("v2/{}/{path}", self.repository))
+            .map_err(|_| RegistryError::InvalidReference(self.repository.clone()))
+    }
+
+    /// Send a request, authenticating and retrying it once
+    /// if the registry responds with `401 Unauthorized`
+    ///
+    /// This also happens if an authorization was already set,
+    /// as tokens handed out by the registry may expire during a long push.
+    fn send(
+        &mut self,
+        request: impl Fn(&Client) -> Result<RequestBuilder, RegistryError>,
+    ) -> Result<Response, RegistryError> {
+        let response = self.authorize(request(&self.client)?).send()?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        if self.authorization.is_some() {
+            debug!("registry rejected authorization, authenticating again");
+        }
+
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|challenge| challenge.to_str().ok())
+            .ok_or_else(|| RegistryError::Unauthorized(self.registry.clone()))?
+            .to_string();
+        self.authorization = Some(self.authenticate(&challenge)?);
+        Ok(self.authorize(request(&self.client)?).send()?)
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.authorization {
+            Some(authorization) => request.header(AUTHORIZATION, authorization),
+            None => request,
+        }
+    }
+
+    /// Answer a `WWW-Authenticate` challenge of the registry
+    fn authenticate(&self, challenge: &str) -> Result<String, RegistryError> {
+        let unauthorized = || RegistryError::Unauthorized(self.registry.clone());
+
+        let (scheme, params) = challenge.split_once(' ').unwrap_or((challenge, ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            let credentials = self.credentials.as_ref().ok_or_else(unauthorized)?;
+            let encoded = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", credentials.username, credentials.password));
+            return Ok(format!("Basic {encoded}"));
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return Err(unauthorized());
+        }
+
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+
+        let params = parse_challenge_params(params);
+        let realm = params.get("realm").ok_or_else(unauthorized)?;
+        let mut token_url = Url::parse(realm).map_err(|_| unauthorized())?;
+        if let Some(service) = params.get("service") {
+            token_url.query_pairs_mut().append_pair("service", service);
+        }
+        let scope = format!("repository:{}:pull,push", self.repository);
+        token_url.query_pairs_mut().append_pair("scope", &scope);
+
+        let mut request = self.client.get(token_url);
+        if let Some(credentials) = &self.credentials {
+            request = request.basic_auth(&credentials.username, Some(&credentials.password));
+        }
+        let response = request.send()?;
+        if !response.status().is_success() {
+            return Err(unauthorized());
+        }
+        let token: TokenResponse = response.json()?;
+        let token = token
+            .token
+            .or(token.access_token)
+            .ok_or_else(unauthorized)?;
+        Ok(format!("Bearer {token}"))
+    }
+
+    fn has_blob(&mut self, blob: &Blob) -> Result<bool, RegistryError> {
+        let url = self.url(&format!("blobs/{}", blob.digest))?;
+        let response = self.send(|client| Ok(client.head(url.clone())))?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(RegistryError::unexpected("check for blob", response)),
+        }
+    }
+
+    /// Upload a blob in a single request
+    fn upload_blob(&mut self, blob: &Blob) -> Result<(), RegistryError> {
+        let url = self.url("blobs/uploads/")?;
+        let response = self.send(|client| Ok(client.post(url.clone())))?;
+        if response.status() != StatusCode::ACCEPTED {
+            return Err(RegistryError::unexpected("start upload", response));
+        }
+        let Some(location) = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(str::to_string)
+        else {
+            return Err(RegistryError::unexpected("start upload", response));
+        };
+        let mut upload_url = self
+            .base_url
+            .join(&location)
+            .map_err(|_| RegistryError::InvalidLocation(location))?;
+        upload_url
+            .query_pairs_mut()
+            .append_pair("digest", &blob.digest);
+
+        debug!("uploading blob {} ({} bytes)", blob.digest, blob.size);
+        let response = self.send(|client| {
+            let file = File::open(blob.path()).map_err(RegistryError::ReadBlob)?;
+            Ok(client
+                .put(upload_url.clone())
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .body(Body::sized(file, blob.size)))
+        })?;
+        if response.status() != StatusCode::CREATED {
+            return Err(RegistryError::unexpected("upload blob", response));
+        }
+        Ok(())
+    }
+
+    fn put_manifest(&mut self, tag: &str, manifest: &[u8]) -> Result<(), RegistryError> {
+        let url = self.url(&format!("manifests/{tag}"))?;
+        let response = self.send(|client| {
+            Ok(client
+                .put(url.clone())
+                .header(CONTENT_TYPE, OCI_MANIFEST_MEDIA_TYPE)
+                .body(manifest.to_vec()))
+        })?;
+        if !response.status().is_success() {
+            return Err(RegistryError::unexpected("upload manifest", response));
+        }
+        Ok(())
+    }
+}
+
+/// Parse `key="value",key2="value2"` parameters of a `WWW-Authenticate` header,
+/// where quoted values may contain commas
+fn parse_challenge_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        parsed.insert(key, value.to_string());
+        rest = remainder;
+    }
+    parsed
+}
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("invalid image reference '{0}', expected '[<registry>/]<repository>[:<tag>]'")]
+    InvalidReference(String),
+    #[error("could not reach registry")]
+    Request(#[from] reqwest::Error),
+    #[error("not authorized to push to {0}")]
+    Unauthorized(String),
+    #[error("registry returned an invalid upload location '{0}'")]
+    InvalidLocation(String),
+    #[error("could not read image blob")]
+    ReadBlob(#[source] std::io::Error),
+    #[error("failed to {action}: registry responded with {status}: {body}")]
+    UnexpectedResponse {
+        action: &'static str,
+        status: StatusCode,
+        body: String,
+    },
+}
+
+impl RegistryError {
+    fn unexpected(action: &'static str, response: Response) -> Self {
+        let status = response.status();
+        let body = response.text().unwrap_or_default().trim().to_string();
+        RegistryError::UnexpectedResponse {
+            action,
+            status,
+            body,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Bearer tokens handed out by a [TestRegistry],
+    /// each of which expires after [Tokens::USES] requests
+    #[derive(Default)]
+    struct Tokens {
+        issued: usize,
+        uses: usize,
+    }
+
+    impl Tokens {
+        const USES: usize = 2;
+    }
+
+    /// A minimal stand-in for a registry like `registry:2`,
+    /// recording the requests it receives
+    struct TestRegistry {
+        address: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestRegistry {
+        fn start() -> Self {
+            Self::start_with(None)
+        }
+
+        /// Start a registry that requires short-lived bearer tokens
+        fn start_with_expiring_tokens() -> Self {
+            Self::start_with(Some(Arc::new(Mutex::new(Tokens::default()))))
+        }
+
+        fn start_with(tokens: Option<Arc<Mutex<Tokens>>>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let blobs = Arc::new(Mutex::new(HashSet::new()));
+
+            let recorded = requests.clone();
+            let realm = format!("http://{address}/token");
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = stream.unwrap();
+                    let requests = recorded.clone();
+                    let blobs = blobs.clone();
+                    let tokens = tokens.clone();
+                    let realm = realm.clone();
+                    std::thread::spawn(move || {
+                        Self::serve(stream, requests, blobs, tokens, &realm)
+                    });
+                }
+            });
+
+            Self { address, requests }
+        }
+
+        fn serve(
+            stream: std::net::TcpStream,
+            requests: Arc<Mutex<Vec<String>>>,
+            blobs: Arc<Mutex<HashSet<String>>>,
+            tokens: Option<Arc<Mutex<Tokens>>>,
+            realm: &str,
+        ) {
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            loop {
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                    return;
+                }
+                let mut content_length = 0;
+                let mut authorization = String::new();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                        if name.eq_ignore_ascii_case("authorization") {
+                            authorization = value.trim().to_string();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut parts = request_line.split_whitespace();
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+                requests.lock().unwrap().push(format!("{method} {path}"));
+
+                if let Some(tokens) = &tokens {
+                    let mut tokens = tokens.lock().unwrap();
+                    if path.starts_with("/token") {
+                        tokens.issued += 1;
+                        tokens.uses = 0;
+                        let body = format!(r#"{{"token":"token-{}"}}"#, tokens.issued);
+                        write!(
+                            writer,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                            body.len()
+                        )
+                        .unwrap();
+                        continue;
+                    }
+                    let valid = format!("Bearer token-{}", tokens.issued);
+                    if authorization != valid || tokens.uses >= Tokens::USES {
+                        write!(
+                            writer,
+                            "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Bearer realm=\"{realm}\",service=\"test\"\r\nContent-Length: 0\r\n\r\n"
+                        )
+                        .unwrap();
+                        continue;
+                    }
+                    tokens.uses += 1;
+                }
+
+                let (status, location) = match method {
+                    "HEAD" => {
+                        let digest = path.rsplit('/').next().unwrap();
+                        match blobs.lock().unwrap().contains(digest) {
+                            true => ("200 OK", None),
+                            false => ("404 Not Found", None),
+                        }
+                    },
+                    "POST" => ("202 Accepted", Some("/upload/1?state=x")),
+                    "PUT" if path.starts_with("/upload/") => {
+                        let digest = Url::parse(&format!("http://localhost{path}"))
+                            .unwrap()
+                            .query_pairs()
+                            .find(|(key, _)| key == "digest")
+                            .unwrap()
+                            .1
+                            .to_string();
+                        blobs.lock().unwrap().insert(digest);
+                        ("201 Created", None)
+                    },
+                    "PUT" => ("201 Created", None),
+                    _ => ("405 Method Not Allowed", None),
+                };
+                let location = location
+                    .map(|location| format!("Location: {location}\r\n"))
+                    .unwrap_or_default();
+                write!(
+                    writer,
+                    "HTTP/1.1 {status}\r\n{location}Content-Length: 0\r\n\r\n"
+                )
+                .unwrap();
+            }
+        }
+
+        fn take_requests(&self) -> Vec<String> {
+            std::mem::take(&mut self.requests.lock().unwrap())
+        }
+    }
+
+    fn test_image() -> UnpackedImage {
+        let layer = b"layer".to_vec();
+        let config = b"{}".to_vec();
+        let manifest = serde_json::json!([{
+            "Config": "config.json",
+            "Layers": ["layer/layer.tar"],
+        }])
+        .to_string();
+
+        let mut archive = tar::Builder::new(Vec::new());
+        for (path, content) in [
+            ("layer/layer.tar", layer),
+            ("config.json", config),
+            ("manifest.json", manifest.into_bytes()),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            archive
+                .append_data(&mut header, path, content.as_slice())
+                .unwrap();
+        }
+        UnpackedImage::from_docker_archive(archive.into_inner().unwrap().as_slice()).unwrap()
+    }
+
+    #[test]
+    fn parses_references() {
+        let parse = |s: &str| RemoteImageReference::from_str(s).unwrap();
+        assert_eq!(
+            parse("registry.example.com/team/env:v1"),
+            RemoteImageReference {
+                registry: "registry.example.com".to_string(),
+                repository: "team/env".to_string(),
+                tag: Some("v1".to_string()),
+            }
+        );
+        assert_eq!(parse("localhost:5000/env").registry, "localhost:5000");
+        assert_eq!(parse("localhost:5000/env").tag, None);
+        assert_eq!(parse("team/env").registry, DOCKER_HUB);
+        assert_eq!(parse("env:v1").repository, "library/env");
+
+        assert!(RemoteImageReference::from_str("registry.example.com/Team/env").is_err());
+        assert!(RemoteImageReference::from_str("registry.example.com/env:").is_err());
+    }
+
+    #[test]
+    fn parses_bearer_challenge() {
+        let params = parse_challenge_params(
+            r#"realm="https://auth.example.com/token",service="registry.example.com",scope="repository:team/env:pull,push""#,
+        );
+        assert_eq!(params["realm"], "https://auth.example.com/token");
+        assert_eq!(params["service"], "registry.example.com");
+        assert_eq!(params["scope"], "repository:team/env:pull,push");
+    }
+
+    #[test]
+    fn reads_credentials_from_docker_config() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("config.json");
+        std::fs::write(
+            &path,
+            serde_json::json!({
+                "auths": {
+                    "https://index.docker.io/v1/": { "auth": "aHViOnNlY3JldA==" },
+                    "registry.example.com": { "auth": "dXNlcjpwYXNz" },
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let credentials = |registry| RegistryCredentials::from_docker_config_file(&path, registry);
+        assert_eq!(
+            credentials("registry.example.com"),
+            Some(RegistryCredentials {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            })
+        );
+        assert_eq!(credentials(DOCKER_HUB).unwrap().username, "hub");
+        assert_eq!(credentials("other.example.com"), None);
+    }
+
+    #[test]
+    fn pushes_image_and_skips_existing_blobs() {
+        let registry = TestRegistry::start();
+        let image = test_image();
+        let reference = RemoteImageReference::from_str(&format!(
+            "localhost:{}/team/env:v1",
+            registry.address.rsplit(':').next().unwrap()
+        ))
+        .unwrap();
+
+        let pushed = push_image(&image, &reference, None).unwrap();
+        assert_eq!(pushed.uploaded, 2);
+        assert_eq!(pushed.skipped, 0);
+        assert_eq!(pushed.digest, image.digest());
+        let requests = registry.take_requests();
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|r| r.starts_with("PUT /upload/"))
+                .count(),
+            2
+        );
+        assert_eq!(requests.last().unwrap(), "PUT /v2/team/env/manifests/v1");
+
+        let pushed = push_image(&image, &reference, None).unwrap();
+        assert_eq!(pushed.uploaded, 0);
+        assert_eq!(pushed.skipped, 2);
+        assert!(!registry
+            .take_requests()
+            .iter()
+            .any(|r| r.starts_with("PUT /upload/")));
+    }
+
+    #[test]
+    fn authenticates_again_when_token_expires() {
+        let registry = TestRegistry::start_with_expiring_tokens();
+        let image = test_image();
+        let reference = RemoteImageReference::from_str(&format!(
+            "localhost:{}/team/env:v1",
+            registry.address.rsplit(':').next().unwrap()
+        ))
+        .unwrap();
+
+        let pushed = push_image(&image, &reference, None).unwrap();
+        assert_eq!(pushed.uploaded, 2);
+        let token_requests = registry
+            .take_requests()
+            .iter()
+            .filter(|r| r.starts_with("GET /token"))
+            .count();
+        assert!(token_requests > 1, "requested {token_requests} token(s)");
+    }
+}
@@ -1,28 +1,35 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
 use std::fs::{self, File};
 use std::io::{stdin, stdout, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::str::FromStr;
-use std::{env, vec};
+use std::{env, mem, vec};
 
 use anyhow::{anyhow, bail, Context, Result};
 use bpaf::Bpaf;
 use crossterm::tty::IsTty;
 use flox_rust_sdk::flox::{EnvironmentName, EnvironmentOwner, EnvironmentRef, Flox};
+use flox_rust_sdk::models::container_image::{ImageFormat, ImageReference, UnpackedImage};
+use flox_rust_sdk::models::environment::generations::SingleGenerationMetadata;
 use flox_rust_sdk::models::environment::managed_environment::{
     ManagedEnvironment,
     ManagedEnvironmentError,
     PullResult,
+    SyncPreview,
+    GENERATION_LOCK_FILENAME,
 };
 use flox_rust_sdk::models::environment::path_environment::{self};
+use flox_rust_sdk::models::environment::prebuild::{PrebuildState, PrebuildStatus};
 use flox_rust_sdk::models::environment::{
     CanonicalPath,
     CoreEnvironmentError,
+    DotFlox,
     EditResult,
     Environment,
     EnvironmentError2,
@@ -32,6 +39,7 @@ use flox_rust_sdk::models::environment::{
     UpdateResult,
     DOT_FLOX,
     ENVIRONMENT_POINTER_FILENAME,
+    FLOX_ACTIVATIONS_VAR,
     FLOX_ACTIVE_ENVIRONMENTS_VAR,
     FLOX_ENV_CACHE_VAR,
     FLOX_ENV_DIRS_VAR,
@@ -40,6 +48,8 @@ use flox_rust_sdk::models::environment::{
     FLOX_ENV_VAR,
     FLOX_PATH_PATCHED_VAR,
     FLOX_PROMPT_ENVIRONMENTS_VAR,
+    FLOX_PURE_VAR,
+    FLOX_RESTORE_RECORDS_VAR,
 };
 use flox_rust_sdk::models::lockfile::{
     Input,
@@ -51,25 +61,36 @@ use flox_rust_sdk::models::lockfile::{
 };
 use flox_rust_sdk::models::manifest::{self, PackageToInstall};
 use flox_rust_sdk::models::pkgdb::{self, error_codes, CallPkgDbError, PkgDbError, ScrapeError};
+use flox_rust_sdk::providers::floxd::{CleanupCommand, Registration, Request, Response};
+use flox_rust_sdk::providers::oci_registry::{
+    push_image,
+    RegistryCredentials,
+    RemoteImageReference,
+};
 use indexmap::IndexSet;
 use indoc::{formatdoc, indoc};
 use itertools::Itertools;
 use log::debug;
+use serde::{Deserialize, Serialize};
 use toml_edit::Document;
 use url::Url;
 
+use super::prompt::{PromptEnvironment, DEFAULT_PROMPT_FORMAT, FLOX_SET_PROMPT_VAR};
+use super::services::start_services;
 use super::{environment_select, EnvironmentSelect};
 use crate::commands::{
     activated_environments,
     ensure_environment_trust,
     ensure_floxhub_token,
+    ensure_floxhub_token_for,
     environment_description,
+    ActiveEnvironments,
     ConcreteEnvironment,
     EnvironmentSelectError,
     UninitializedEnvironment,
 };
 use crate::config::Config;
-use crate::utils::dialog::{Confirm, Dialog, Select, Spinner};
+use crate::utils::dialog::{Confirm, Dialog, ProgressSpinner, Select, Spinner};
 use crate::utils::didyoumean::{DidYouMean, InstallSuggestion};
 use crate::utils::errors::{
     apply_doc_link_for_unsupported_packages,
@@ -77,7 +98,7 @@ use crate::utils::errors::{
     format_core_error,
     format_locked_manifest_error,
 };
-use crate::utils::message;
+use crate::utils::{floxd, message};
 use crate::{subcommand_metric, utils};
 
 // Edit declarative environment configuration
@@ -113,8 +134,8 @@ impl Edit {
             .detect_concrete_environment(&flox, "edit")?;
 
         // Ensure the user is logged in for the following remote operations
-        if let ConcreteEnvironment::Remote(_) = detected_environment {
-            ensure_floxhub_token(&mut flox).await?;
+        if let ConcreteEnvironment::Remote(ref environment) = detected_environment {
+            ensure_floxhub_token_for(&mut flox, environment.owner()).await?;
         };
 
         match self.action {
@@ -130,8 +151,7 @@ impl Edit {
                     environment.rename(name.clone())?;
                     message::updated(format!("renamed environment {old_name} to {name}"));
                 } else {
-                    // todo: handle remote environments in the future
-                    bail!("Cannot rename environments on FloxHub");
+                    Self::rename_upstream(&mut flox, detected_environment, name).await?;
                 }
             },
         }
@@ -139,6 +159,52 @@ impl Edit {
         Ok(())
     }
 
+    /// Rename a managed or remote environment upstream,
+    /// updating all directories on this machine that link to it
+    async fn rename_upstream(
+        flox: &mut Flox,
+        detected_environment: ConcreteEnvironment,
+        name: EnvironmentName,
+    ) -> Result<()> {
+        let env_ref = match &detected_environment {
+            ConcreteEnvironment::Managed(environment) => {
+                EnvironmentRef::from(environment.pointer().clone())
+            },
+            ConcreteEnvironment::Remote(environment) => environment.env_ref(),
+            ConcreteEnvironment::Path(_) => unreachable!("path environments are renamed locally"),
+        };
+        let owner = env_ref.owner();
+        if &name == env_ref.name() {
+            bail!("environment already named {owner}/{name}");
+        }
+
+        // Ensure the user is logged in for the following remote operations
+        ensure_floxhub_token_for(flox, owner).await?;
+        let remote = remote_name(flox, owner);
+
+        Dialog {
+            message: &format!("Renaming {env_ref} to {owner}/{name} on {remote}..."),
+            help_message: None,
+            typed: Spinner::new(|| match detected_environment {
+                ConcreteEnvironment::Managed(mut environment) => {
+                    environment.rename(flox, name.clone())
+                },
+                ConcreteEnvironment::Remote(mut environment) => {
+                    environment.rename(flox, name.clone())
+                },
+                ConcreteEnvironment::Path(_) => unreachable!(),
+            }),
+        }
+        .spin()?;
+
+        message::updated(formatdoc! {"
+            Renamed environment {env_ref} to {owner}/{name} on {remote}
+
+            Copies of {env_ref} on other machines have to be pulled again with 'flox pull {owner}/{name}'.
+        "});
+        Ok(())
+    }
+
     async fn edit_manifest(
         flox: &Flox,
         detected_environment: ConcreteEnvironment,
@@ -362,10 +428,12 @@ impl Delete {
 /// in the current directory. Calling 'flox activate' in your home directory will
 /// activate a default environment. Environments in other directories and remote
 /// environments are activated with the '-d' and '-r' flags respectively.
+/// Repeat these flags to activate multiple environments at once,
+/// environments listed first take precedence.
 #[derive(Bpaf, Clone)]
 pub struct Activate {
-    #[bpaf(external(environment_select), fallback(Default::default()))]
-    environment: EnvironmentSelect,
+    #[bpaf(external(environment_select), many)]
+    environments: Vec<EnvironmentSelect>,
 
     /// Trust a remote environment temporarily for this activation
     #[bpaf(long, short)]
@@ -375,15 +443,81 @@ pub struct Activate {
     #[bpaf(long("print-script"), short, hide)]
     print_script: bool,
 
+    /// Start with a minimal environment rather than inheriting the current one
+    #[bpaf(long)]
+    pure: bool,
+
+    /// Print the variables of the activated environment instead of spawning a subshell
+    #[bpaf(long("print-env"))]
+    print_env: bool,
+
+    /// Print the variables of the activated environment as JSON (requires '--print-env')
+    #[bpaf(long)]
+    json: bool,
+
+    /// Start the services of the environment in the background
+    #[bpaf(long("start-services"))]
+    start_services: bool,
+
     /// Command to run interactively in the context of the environment
     #[bpaf(positional("cmd"), strict, many)]
     run_args: Vec<String>,
 }
 
+/// Variables passed through from the calling environment to pure activations
+///
+/// - `HOME` and `USER` identify the user running the activation
+/// - `TERM` describes the terminal of interactive activations
+/// - `LANG` and `LANGUAGE`, together with all `LC_*` variables,
+///   select the user's locale
+/// - `LD_FLOXLIB` is set by the flox wrapper and used by the activation scripts
+///
+/// `PATH` is passed through as well,
+/// but replaced by the activation scripts (see [Activate::set_base_env]).
+/// Everything else, e.g. `SHELL`, `DISPLAY` or `SSH_AUTH_SOCK`, is dropped.
+const PURE_ENV_VARS: [&str; 6] = ["HOME", "USER", "TERM", "LANG", "LANGUAGE", "LD_FLOXLIB"];
+
+/// Path to the bash used to compute activation environments
+/// for shells that can't source the activation scripts
+const BASH_BIN: &str = env!("BASH_BIN");
+
+/// Variables maintained by bash itself,
+/// which are not taken from the environment computed by [Activate::bash_activation_env]
+const BASH_INTERNAL_VARS: [&str; 4] = ["_", "SHLVL", "PWD", "OLDPWD"];
+
+/// Prompt setup for interactive nushell activations
+///
+/// Prefixes the user's `PROMPT_COMMAND` with the active environments,
+/// like `set-prompt.{bash,zsh}.sh` do for `PS1`.
+const NU_SET_PROMPT: &str = indoc! {r#"
+    let _flox_prompt_command = if "PROMPT_COMMAND" in $env { $env.PROMPT_COMMAND } else { {|| $env.PWD } }
+    $env.PROMPT_COMMAND = {||
+        let prompt = if ($_flox_prompt_command | describe) == "closure" { do $_flox_prompt_command } else { $_flox_prompt_command }
+        let flox = if ($env.NO_COLOR? | default "0") == "0" {
+            $"(ansi attr_bold)(ansi --escape $'38;5;($env.FLOX_PROMPT_COLOR_1)m')flox (ansi --escape $'38;5;($env.FLOX_PROMPT_COLOR_2)m')[($env.FLOX_PROMPT_ENVIRONMENTS)](ansi reset)"
+        } else {
+            $"flox [($env.FLOX_PROMPT_ENVIRONMENTS)]"
+        }
+        $"($flox) ($prompt)"
+    }
+"#};
+
 #[derive(Debug)]
 enum ShellType {
     Bash(PathBuf),
     Zsh(PathBuf),
+    Nu(PathBuf),
+}
+
+/// The process flox was invoked from
+///
+/// Read from `FLOX_PARENT_PID` (set by flox on startup)
+/// and defaults to the actual parent process.
+fn parent_pid() -> Result<i32> {
+    match env::var("FLOX_PARENT_PID") {
+        Ok(ppid) => ppid.parse().context("Invalid FLOX_PARENT_PID"),
+        Err(_) => Ok(nix::unistd::getppid().as_raw()),
+    }
 }
 
 impl TryFrom<&Path> for ShellType {
@@ -393,6 +527,7 @@ impl TryFrom<&Path> for ShellType {
         match value.file_name() {
             Some(name) if name == "bash" => Ok(ShellType::Bash(value.to_owned())),
             Some(name) if name == "zsh" => Ok(ShellType::Zsh(value.to_owned())),
+            Some(name) if name == "nu" => Ok(ShellType::Nu(value.to_owned())),
             _ => Err(anyhow!("Unsupported shell {value:?}")),
         }
     }
@@ -403,80 +538,366 @@ impl Display for ShellType {
         match self {
             ShellType::Bash(_) => write!(f, "bash"),
             ShellType::Zsh(_) => write!(f, "zsh"),
+            ShellType::Nu(_) => write!(f, "nu"),
         }
     }
 }
 
 impl ShellType {
-    /// Detect the current shell from the SHELL environment variable
+    /// Detect the current shell
     ///
-    /// TODO:
     /// We want to print an activation script in the format appropriate for the shell that's actually running,
-    /// not whatever `SHELL` might be, as `SHELL` might not always be set correctly.
-    /// We should detect the type of our parent shell from flox' parent process,
-    /// using an approach like [1], rather than blindly trusting `SHELL`.
+    /// not whatever `SHELL` might be, as `SHELL` might not always be set correctly,
+    /// e.g. when running `zsh` from a `bash` login shell.
     ///
-    /// [1]: <https://github.com/flox/flox/blob/668a80a40ba19d50f8ca304ff351f4b27a886e21/flox-bash/lib/utils.sh#L1432>
+    /// Hence, the shell is determined by (in order of precedence):
+    ///
+    /// 1. `FLOX_SHELL` if set
+    /// 2. the executable of flox' parent process if that is a supported shell
+    /// 3. `SHELL`
     fn detect() -> Result<Self> {
-        let shell =
-            env::var("FLOX_SHELL").unwrap_or(env::var("SHELL").context("SHELL must be set")?);
+        if let Ok(shell) = env::var("FLOX_SHELL") {
+            return Self::try_from(Path::new(&shell));
+        }
+
+        match Self::detect_parent_shell() {
+            Ok(Some(shell)) => {
+                debug!("detected shell from parent process: {shell:?}");
+                return Ok(shell);
+            },
+            Ok(None) => debug!("parent process is not a supported shell, falling back to SHELL"),
+            Err(e) => debug!("could not detect parent shell, falling back to SHELL: {e}"),
+        }
+
+        let shell = env::var("SHELL").context("SHELL must be set")?;
         let shell = Path::new(&shell);
         let shell = Self::try_from(shell)?;
         Ok(shell)
     }
 
+    /// Detect the type of our parent shell from flox' parent process
+    ///
+    /// The parent process is read from `FLOX_PARENT_PID`
+    /// (set by flox on startup) and defaults to the actual parent process.
+    fn detect_parent_shell() -> Result<Option<Self>> {
+        let (exe, name) = Self::process_exe_and_name(parent_pid()?)?;
+        Ok(Self::from_exe_and_name(exe, &name))
+    }
+
+    /// Select a shell from the executable path and the name of a process
+    ///
+    /// The executable may not be called like the shell it provides,
+    /// e.g. `/usr/bin/zsh5`, in which case the shell is determined by the process name.
+    /// Login shells may have their name prefixed with `-`, e.g. `-zsh`.
+    fn from_exe_and_name(exe: PathBuf, name: &str) -> Option<Self> {
+        if let Ok(shell) = Self::try_from(exe.as_path()) {
+            return Some(shell);
+        }
+
+        let name = name.trim_start_matches('-');
+        let name = Path::new(name).file_name()?;
+        match Self::try_from(Path::new(name)).ok()? {
+            ShellType::Bash(_) => Some(ShellType::Bash(exe)),
+            ShellType::Zsh(_) => Some(ShellType::Zsh(exe)),
+            ShellType::Nu(_) => Some(ShellType::Nu(exe)),
+        }
+    }
+
+    /// Read the executable path and name of a process from `/proc`
+    #[cfg(target_os = "linux")]
+    fn process_exe_and_name(pid: i32) -> Result<(PathBuf, String)> {
+        let proc_dir = Path::new("/proc").join(pid.to_string());
+        let exe = fs::read_link(proc_dir.join("exe"))
+            .with_context(|| format!("Could not read executable of process {pid}"))?;
+        let name = fs::read_to_string(proc_dir.join("comm"))
+            .with_context(|| format!("Could not read name of process {pid}"))?;
+        Ok((exe, name.trim().to_string()))
+    }
+
+    /// Read the executable path and name of a process using `ps`
+    ///
+    /// `ps` reports the command as invoked,
+    /// which may be a name without a path, e.g. `-zsh`.
+    /// In that case, the shell is looked up in `PATH` when it is spawned.
+    #[cfg(not(target_os = "linux"))]
+    fn process_exe_and_name(pid: i32) -> Result<(PathBuf, String)> {
+        let output = Command::new("/bin/ps")
+            .args(["-o", "comm=", "-p", &pid.to_string()])
+            .output()
+            .context("Could not run ps")?;
+        if !output.status.success() {
+            bail!("Could not find process {pid}");
+        }
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let exe = PathBuf::from(name.trim_start_matches('-'));
+        Ok((exe, name))
+    }
+
     fn exe_path(&self) -> &Path {
         match self {
             ShellType::Bash(path) => path,
             ShellType::Zsh(path) => path,
+            ShellType::Nu(path) => path,
         }
     }
 }
 
+/// An environment prepared for activation, see [Activate::prepare_layer]
+///
+/// Multiple environments are activated as layers in the same shell,
+/// the exports of each layer build on those of the layer below it.
+struct ActivationLayer {
+    concrete_environment: ConcreteEnvironment,
+    now_active: UninitializedEnvironment,
+    activation_path: PathBuf,
+    exports: HashMap<&'static str, String>,
+}
+
 impl Activate {
-    pub async fn handle(self, mut config: Config, flox: Flox) -> Result<()> {
+    pub async fn handle(self, config: Config, flox: Flox) -> Result<()> {
         subcommand_metric!("activate");
 
-        let mut concrete_environment = self.environment.to_concrete_environment(&flox)?;
+        self.activate(config, flox, false).await
+    }
+
+    /// Activate the environments
+    ///
+    /// Multiple environments are activated from the last to the first,
+    /// so that the first environment takes precedence.
+    /// Rather than nesting shells, the activation scripts of all environments
+    /// are sourced in the same shell, each after setting the variables
+    /// of the environments activated so far, see [Self::prepare_layer].
+    ///
+    /// With `direnv` set, a script to be evaluated by direnv is printed,
+    /// see [Direnv].
+    async fn activate(mut self, mut config: Config, flox: Flox, direnv: bool) -> Result<()> {
+        if self.json && !self.print_env {
+            bail!("'--json' can only be used with '--print-env'.");
+        }
+        if self.print_env && !self.run_args.is_empty() {
+            bail!("'--print-env' can not be used with a command.");
+        }
+        if self.start_services && self.print_env {
+            bail!("'--start-services' can not be used with '--print-env'.");
+        }
+
+        let mut environments = mem::take(&mut self.environments);
+        if environments.is_empty() {
+            environments.push(EnvironmentSelect::default());
+        }
+        if environments.len() > 1 {
+            if self.pure {
+                bail!("'--pure' can only be used with a single environment.");
+            }
+            let mut seen = HashSet::new();
+            for environment in &environments {
+                if !seen.insert(Self::layer_args(environment)?) {
+                    bail!("The same environment can not be activated twice.");
+                }
+            }
+        }
+
+        let in_place = !self.print_env
+            && (direnv || self.print_script || (!stdout().is_tty() && self.run_args.is_empty()));
+        if self.pure && in_place {
+            bail!("'--pure' can only be used to start a new shell or to run a command.");
+        }
+
+        let mut layers: Vec<ActivationLayer> = Vec::new();
+        for environment in environments.into_iter().rev() {
+            let layer = self
+                .prepare_layer(
+                    &mut config,
+                    &flox,
+                    direnv,
+                    in_place,
+                    environment,
+                    layers.last(),
+                )
+                .await?;
+            layers.extend(layer);
+        }
+
+        // direnv evaluates `.envrc` files with bash
+        let shell = if direnv {
+            ShellType::Bash(PathBuf::from(BASH_BIN))
+        } else {
+            ShellType::detect()?
+        };
+
+        // All environments are already active
+        if layers.is_empty() {
+            let flox_env_install_prefixes = IndexSet::from_iter(env::split_paths(
+                &env::var(FLOX_ENV_DIRS_VAR).unwrap_or_default(),
+            ));
+            let fixed_up_original_path_joined =
+                Self::fixup_path(&flox_env_install_prefixes).transpose()?;
+            return Self::reactivate_in_place(&shell, fixed_up_original_path_joined);
+        }
+
+        if self.start_services {
+            for i in 0..layers.len() {
+                let activated_env =
+                    Self::bash_activation_env(&layers[i].exports, &layers[..=i], self.pure)?;
+                let environment = layers[i].concrete_environment.dyn_environment_ref_mut();
+                start_services(&flox, environment, &[], activated_env)?;
+            }
+        }
+
+        // Activations printing a script or the environment don't outlive this process,
+        // otherwise this process is replaced by the activation, or sourced by its parent.
+        if !direnv && !self.print_env {
+            let pid = if in_place {
+                parent_pid()?
+            } else {
+                std::process::id() as i32
+            };
+            for layer in &mut layers {
+                Self::register_activation(
+                    &flox,
+                    pid,
+                    &layer.now_active,
+                    layer.concrete_environment.dyn_environment_ref_mut(),
+                    &layer.activation_path,
+                    &layer.exports,
+                );
+            }
+        }
+
+        // when output is not a tty, and no command is provided
+        // we just print an activation script to stdout
+        //
+        // That script can then be `eval`ed in the current shell,
+        // e.g. in a .bashrc or .zshrc file:
+        //
+        //    eval "$(flox activate)"
+        //
+        // or, in nushell, applied as a record of changed and removed variables:
+        //
+        //    let activation = (flox activate | from json)
+        //    hide-env --ignore-errors ...$activation.hide
+        //    load-env $activation.load
+        if direnv {
+            Direnv::activate(&layers);
+
+            return Ok(());
+        }
+
+        // The variables of the topmost layer are those of all environments combined
+        let exports = layers.last().unwrap().exports.clone();
+
+        if self.print_env {
+            let activated_env = Self::bash_activation_env(&exports, &layers, self.pure)?;
+            println!("{}", Self::render_env(activated_env, self.json)?);
+
+            return Ok(());
+        }
+
+        if in_place {
+            Self::activate_in_place(&shell, &layers)?;
+
+            return Ok(());
+        }
+
+        let activate_error = if !self.run_args.is_empty() {
+            Self::activate_non_interactive(self.run_args, shell, exports, &layers, self.pure)
+        } else {
+            Self::activate_interactive(&flox, shell, exports, &layers, self.pure)
+        };
+        // If we get here, exec failed!
+        Err(activate_error)
+    }
 
-        // TODO could move this to a pretty print method on the Environment trait?
-        let prompt_name = match concrete_environment {
-            // Note that the same environment could show up twice without any
-            // indication of which comes from which path
-            ConcreteEnvironment::Path(ref path) => path.name().to_string(),
-            ConcreteEnvironment::Managed(ref managed) => {
-                format!("{}/{}", managed.owner(), managed.name())
+    /// Arguments selecting an environment for `flox activate`
+    ///
+    /// Used to detect environments that are listed twice,
+    /// directories are made absolute for that purpose.
+    fn layer_args(environment: &EnvironmentSelect) -> Result<Vec<String>> {
+        let args = match environment {
+            EnvironmentSelect::Dir(path) => {
+                let path = fs::canonicalize(path)
+                    .map_err(|_| EnvironmentError2::DotFloxNotFound(path.clone()))?;
+                vec!["--dir".to_string(), path.to_string_lossy().to_string()]
             },
-            ConcreteEnvironment::Remote(ref remote) => {
-                format!("{}/{}", remote.owner(), remote.name())
+            EnvironmentSelect::Remote(env_ref) => {
+                vec!["--remote".to_string(), env_ref.to_string()]
+            },
+            EnvironmentSelect::Unspecified => {
+                let current_dir = env::current_dir().context("could not get current directory")?;
+                vec![
+                    "--dir".to_string(),
+                    current_dir.to_string_lossy().to_string(),
+                ]
             },
         };
+        Ok(args)
+    }
+
+    /// Prepare the activation of a single environment
+    ///
+    /// The variables listing the active environments,
+    /// such as `FLOX_ENV_DIRS` and `FLOX_PROMPT_ENVIRONMENTS`,
+    /// extend those of the layer `below`, or those of the current shell
+    /// for the first layer.
+    ///
+    /// Returns [None] if the environment is already active
+    /// and is activated in place.
+    async fn prepare_layer(
+        &self,
+        config: &mut Config,
+        flox: &Flox,
+        direnv: bool,
+        in_place: bool,
+        environment: EnvironmentSelect,
+        below: Option<&ActivationLayer>,
+    ) -> Result<Option<ActivationLayer>> {
+        let mut concrete_environment = environment.to_concrete_environment(flox)?;
+
+        // Note that the same environment could show up twice without any
+        // indication of which comes from which path
+        let prompt_format = config
+            .flox
+            .prompt_format
+            .clone()
+            .unwrap_or(DEFAULT_PROMPT_FORMAT.to_string());
+        let prompt_name =
+            PromptEnvironment::from_concrete_environment(&concrete_environment, &prompt_format)
+                .render(&prompt_format);
 
         if let ConcreteEnvironment::Remote(ref env) = concrete_environment {
+            if self.start_services {
+                bail!("Services are not supported for remote environments.");
+            }
             if !self.trust {
-                ensure_environment_trust(&mut config, &flox, env).await?;
+                ensure_environment_trust(config, flox, env).await?;
             }
         }
 
         let now_active =
             UninitializedEnvironment::from_concrete_environment(&concrete_environment)?;
 
+        // Declare watch files first, so that direnv reloads once the environment is fixed
+        // in case the activation fails.
+        if direnv {
+            println!("{}", Direnv::watch_files(flox, &concrete_environment)?);
+        }
+
+        let generation = current_generation(&concrete_environment);
         let environment = concrete_environment.dyn_environment_ref_mut();
 
-        let in_place = self.print_script || (!stdout().is_tty() && self.run_args.is_empty());
         // Don't spin in bashrcs and similar contexts
-        let activation_path_result = if in_place {
-            environment.activation_path(&flox)
+        let activation_exports_result = if in_place || self.print_env {
+            environment.activation_exports(flox)
         } else {
             Dialog {
                 message: &format!("Getting ready to use environment {now_active}..."),
                 help_message: None,
-                typed: Spinner::new(|| environment.activation_path(&flox)),
+                typed: Spinner::new(|| environment.activation_exports(flox)),
             }
             .spin()
         };
 
-        let activation_path = match activation_path_result {
+        let environment_exports = match activation_exports_result {
             Err(EnvironmentError2::Core(CoreEnvironmentError::LockedManifest(
                 LockedManifestError::BuildEnv(CallPkgDbError::PkgDbError(PkgDbError {
                     exit_code: error_codes::LOCKFILE_INCOMPATIBLE_SYSTEM,
@@ -500,20 +921,34 @@ impl Activate {
             },
             other => other?,
         };
+        let activation_path = PathBuf::from(&environment_exports[FLOX_ENV_VAR]);
+
+        // Variables of the layer below, or of the current shell.
+        // Pure activations don't inherit any of the environments
+        // that are active in the current shell.
+        let inherited = |var: &str| match below {
+            Some(below) => below.exports.get(var).cloned(),
+            None if self.pure => None,
+            None => env::var(var).ok(),
+        };
 
         // We don't have access to the current PS1 (it's not exported), so we
         // can't modify it. Instead set FLOX_PROMPT_ENVIRONMENTS and let the
         // activation script set PS1 based on that.
-        let flox_prompt_environments = env::var(FLOX_PROMPT_ENVIRONMENTS_VAR)
-            .map_or(prompt_name.clone(), |prompt_environments| {
-                format!("{prompt_name} {prompt_environments}")
-            });
+        let flox_prompt_environments = match inherited(FLOX_PROMPT_ENVIRONMENTS_VAR) {
+            Some(prompt_environments) => format!("{prompt_name} {prompt_environments}"),
+            None => prompt_name.clone(),
+        };
 
-        let mut flox_active_environments = activated_environments();
+        let mut flox_active_environments = match below {
+            Some(below) => below.exports[FLOX_ACTIVE_ENVIRONMENTS_VAR].parse()?,
+            None if self.pure => ActiveEnvironments::default(),
+            None => activated_environments(),
+        };
 
         // install prefixes of all active environments
         let flox_env_install_prefixes = IndexSet::from_iter(env::split_paths(
-            &env::var(FLOX_ENV_DIRS_VAR).unwrap_or_default(),
+            &inherited(FLOX_ENV_DIRS_VAR).unwrap_or_default(),
         ));
 
         // on macos: patch the existing PATH
@@ -523,23 +958,44 @@ impl Activate {
         // NOTE: this does _not_ include any additions to the PATH
         // due to the newly activated environment.
         // Amending the path is strictly implemented by the activation scripts!
-        let fixed_up_original_path_joined =
-            Self::fixup_path(&flox_env_install_prefixes).transpose()?;
+        //
+        // The PATH of pure activations is replaced entirely, so there is nothing to patch.
+        // The PATH of layers above the first one is already patched by the layer below.
+        let fixed_up_original_path_joined = if self.pure || below.is_some() {
+            None
+        } else {
+            Self::fixup_path(&flox_env_install_prefixes).transpose()?
+        };
 
         // Detect if the current environment is already active
-        if flox_active_environments.is_active(&now_active) {
+        //
+        // The environment printed by `--print-env` is computed regardless,
+        // based on the current environment.
+        if flox_active_environments.is_active(&now_active) && !self.print_env {
             if !in_place {
                 // Error if interactive and already active
                 bail!("Environment '{now_active}' is already active.");
             }
             debug!("Environment is already active: environment={now_active}. Ignoring activation (may patch PATH)");
-            Self::reactivate_in_place(fixed_up_original_path_joined)?;
-            return Ok(());
+            return Ok(None);
         }
 
         // Add to _FLOX_ACTIVE_ENVIRONMENTS so we can detect what environments are active.
         flox_active_environments.set_last_active(now_active.clone());
 
+        // Record details of this activation in _FLOX_ACTIVATIONS for `flox status`
+        let mut flox_activations = match inherited(FLOX_ACTIVATIONS_VAR) {
+            Some(activations) => activations.parse().unwrap_or_default(),
+            None => Activations::default(),
+        };
+        flox_activations.set_last_active(Activation::new(
+            flox,
+            now_active.clone(),
+            environment,
+            generation,
+            &activation_path,
+        ));
+
         // Prepend the new environment to the list of active environments
         let flox_env_install_prefixes = {
             let mut set = IndexSet::from([activation_path.clone()]);
@@ -563,20 +1019,19 @@ impl Activate {
             (flox_env_dirs, flox_env_lib_dirs)
         };
 
-        let shell = ShellType::detect()?;
-
         let prompt_color_1 = env::var("FLOX_PROMPT_COLOR_1")
             .unwrap_or(utils::colors::INDIGO_400.to_ansi256().to_string());
         let prompt_color_2 = env::var("FLOX_PROMPT_COLOR_2")
             .unwrap_or(utils::colors::INDIGO_300.to_ansi256().to_string());
 
         let mut exports = HashMap::from([
-            (FLOX_ENV_VAR, activation_path.to_string_lossy().to_string()),
+            (FLOX_ENV_VAR, environment_exports[FLOX_ENV_VAR].clone()),
             (FLOX_PROMPT_ENVIRONMENTS_VAR, flox_prompt_environments),
             (
                 FLOX_ACTIVE_ENVIRONMENTS_VAR,
                 flox_active_environments.to_string(),
             ),
+            (FLOX_ACTIVATIONS_VAR, flox_activations.to_string()),
             (
                 FLOX_ENV_DIRS_VAR,
                 flox_env_dirs_joined.to_string_lossy().to_string(),
@@ -587,11 +1042,11 @@ impl Activate {
             ),
             (
                 FLOX_ENV_CACHE_VAR,
-                environment.cache_path()?.to_string_lossy().to_string(),
+                environment_exports[FLOX_ENV_CACHE_VAR].clone(),
             ),
             (
                 FLOX_ENV_PROJECT_VAR,
-                environment.project_path()?.to_string_lossy().to_string(),
+                environment_exports[FLOX_ENV_PROJECT_VAR].clone(),
             ),
             ("FLOX_PROMPT_COLOR_1", prompt_color_1),
             ("FLOX_PROMPT_COLOR_2", prompt_color_2),
@@ -599,6 +1054,16 @@ impl Activate {
 
         exports.extend(Self::default_subprocess_env_vars());
 
+        if self.pure {
+            exports.insert(FLOX_PURE_VAR, "1".to_string());
+        }
+
+        // The activation scripts leave the prompt alone,
+        // but the environment is still listed in FLOX_PROMPT_ENVIRONMENTS
+        if config.flox.set_prompt == Some(false) {
+            exports.insert(FLOX_SET_PROMPT_VAR, "false".to_string());
+        }
+
         if let Some(fixed_up_original_path_joined) = fixed_up_original_path_joined {
             exports.insert(
                 FLOX_PATH_PATCHED_VAR,
@@ -606,26 +1071,102 @@ impl Activate {
             );
         }
 
-        // when output is not a tty, and no command is provided
-        // we just print an activation script to stdout
-        //
-        // That script can then be `eval`ed in the current shell,
-        // e.g. in a .bashrc or .zshrc file:
-        //
-        //    eval "$(flox activate)"
-        if in_place {
-            Self::activate_in_place(&shell, &exports, &activation_path);
+        Ok(Some(ActivationLayer {
+            concrete_environment,
+            now_active,
+            activation_path,
+            exports,
+        }))
+    }
 
-            return Ok(());
-        }
+    /// Register the activation with floxd,
+    /// which cleans up after the environment once its last activation exited
+    ///
+    /// floxd is only started for activations that need to be cleaned up,
+    /// other activations are reported to an already running floxd only,
+    /// so that activating doesn't wait for floxd to start.
+    /// Failing to register the activation does not affect the activation itself.
+    fn register_activation(
+        flox: &Flox,
+        pid: i32,
+        now_active: &UninitializedEnvironment,
+        environment: &dyn Environment,
+        activation_path: &Path,
+        exports: &HashMap<&str, String>,
+    ) {
+        let register = || -> Result<()> {
+            let cleanup =
+                Self::cleanup_commands(flox, now_active, environment, activation_path, exports)?;
+            let mut client = if cleanup.is_empty() {
+                let Some(client) = floxd::connect(flox) else {
+                    return Ok(());
+                };
+                client
+            } else {
+                floxd::connect_or_start(flox)?
+            };
 
-        let activate_error = if !self.run_args.is_empty() {
-            Self::activate_non_interactive(self.run_args, shell, exports, activation_path)
-        } else {
-            Self::activate_interactive(shell, exports, activation_path, now_active)
+            let registration = Registration {
+                pid,
+                environment: serde_json::to_string(now_active)?,
+                name: now_active.to_string(),
+                flox_env: activation_path.to_path_buf(),
+                cleanup,
+            };
+            client.request(&Request::Register(registration))?;
+            Ok(())
         };
-        // If we get here, exec failed!
-        Err(activate_error)
+
+        if let Err(e) = register() {
+            debug!("could not register activation with floxd: {e:#}");
+        }
+    }
+
+    /// Commands floxd runs after the last activation of an environment exited
+    ///
+    /// - `hook.on-deactivate`, if the environment defines one
+    /// - stopping the environment's services, if it declares any
+    fn cleanup_commands(
+        flox: &Flox,
+        now_active: &UninitializedEnvironment,
+        environment: &dyn Environment,
+        activation_path: &Path,
+        exports: &HashMap<&str, String>,
+    ) -> Result<Vec<CleanupCommand>> {
+        let mut cleanup = Vec::new();
+
+        let on_deactivate = activation_path.join("activate").join("on-deactivate.sh");
+        if on_deactivate.exists() {
+            let env = [FLOX_ENV_VAR, FLOX_ENV_CACHE_VAR, FLOX_ENV_PROJECT_VAR]
+                .into_iter()
+                .filter_map(|var| Some((var.to_string(), exports.get(var)?.clone())))
+                .collect();
+            cleanup.push(CleanupCommand {
+                program: PathBuf::from(BASH_BIN),
+                args: vec![on_deactivate.to_string_lossy().to_string()],
+                dir: Some(environment.project_path()?),
+                env,
+            });
+        }
+
+        // Services of remote environments are not supported
+        if let UninitializedEnvironment::DotFlox(DotFlox { path, .. }) = now_active {
+            if !manifest::services(&environment.manifest_content(flox)?)?.is_empty() {
+                cleanup.push(CleanupCommand {
+                    program: env::current_exe().context("Could not locate flox")?,
+                    args: vec![
+                        "services".to_string(),
+                        "stop".to_string(),
+                        "--dir".to_string(),
+                        path.to_string_lossy().to_string(),
+                    ],
+                    dir: None,
+                    env: BTreeMap::new(),
+                });
+            }
+        }
+
+        Ok(cleanup)
     }
 
     /// Used for `flox activate -- run_args`
@@ -633,25 +1174,52 @@ impl Activate {
         run_args: Vec<String>,
         shell: ShellType,
         exports: HashMap<&str, String>,
-        activation_path: PathBuf,
+        layers: &[ActivationLayer],
+        pure: bool,
     ) -> anyhow::Error {
         let mut command = Command::new(shell.exe_path());
 
+        // nushell can't source the activation scripts,
+        // so run the command in the environment computed by bash instead
+        if let ShellType::Nu(_) = shell {
+            let activated_env = match Self::bash_activation_env(&exports, layers, pure) {
+                Ok(activated_env) => activated_env,
+                Err(e) => return e,
+            };
+
+            command
+                .env_clear()
+                .envs(activated_env)
+                .arg("-c")
+                .arg(Self::quote_run_args(&shell, &run_args));
+
+            debug!("running activation command: {:?}", command);
+
+            // exec should never return
+            return command.exec().into();
+        }
+
+        Self::set_base_env(&mut command, pure);
         command.envs(exports);
 
+        // zsh sources ~/.zshenv even when running a command
+        if pure && matches!(shell, ShellType::Zsh(_)) {
+            command.arg("--no-rcs");
+        }
+
         let script = formatdoc! {r#"
                 # to avoid infinite recursion sourcing bashrc
                 export FLOX_SOURCED_FROM_SHELL_RC=1
 
                 # TODO: this script sets prompt, which isn't necessary
-                source {activation_path}/activate/{shell}
+                {source_layers}
 
                 unset FLOX_SOURCED_FROM_SHELL_RC
 
                 {quoted_args}
         "#,
-            activation_path=shell_escape::escape(activation_path.to_string_lossy()),
-            quoted_args = Self::quote_run_args(&run_args)
+            source_layers = Self::source_layers_script(&shell, layers),
+            quoted_args = Self::quote_run_args(&shell, &run_args)
         };
 
         command.arg("-c");
@@ -666,21 +1234,28 @@ impl Activate {
     /// Activate the environment interactively by spawning a new shell
     /// and running the respective activation scripts.
     ///
+    /// Multiple environments are activated by an rc file sourcing
+    /// the activation scripts of all of them, see [Self::rc_file].
+    ///
     /// This function should never return as it replaces the current process
     fn activate_interactive(
+        flox: &Flox,
         shell: ShellType,
         exports: HashMap<&str, String>,
-        activation_path: PathBuf,
-        now_active: UninitializedEnvironment,
+        layers: &[ActivationLayer],
+        pure: bool,
     ) -> anyhow::Error {
         let mut command = Command::new(shell.exe_path());
-        command.envs(exports);
+        Self::set_base_env(&mut command, pure);
+        command.envs(&exports);
 
         match shell {
             ShellType::Bash(_) => {
-                command
-                    .arg("--rcfile")
-                    .arg(activation_path.join("activate").join("bash"));
+                let rc_file = match Self::rc_file(flox, &shell, layers) {
+                    Ok(rc_file) => rc_file,
+                    Err(e) => return e,
+                };
+                command.arg("--rcfile").arg(rc_file);
             },
             ShellType::Zsh(_) => {
                 // From man zsh:
@@ -703,24 +1278,49 @@ impl Activate {
                 // since macOS sets
                 // HISTFILE=${ZDOTDIR:-$HOME}/.zsh_history
                 // in /etc/zshrc.
+                let rc_file = match Self::rc_file(flox, &shell, layers) {
+                    Ok(rc_file) => rc_file,
+                    Err(e) => return e,
+                };
                 if let Ok(zdotdir) = env::var("ZDOTDIR") {
                     command.env("FLOX_ORIG_ZDOTDIR", zdotdir);
                 }
                 command
                     .env("ZDOTDIR", env!("FLOX_ZDOTDIR"))
-                    .env(
-                        "FLOX_ZSH_INIT_SCRIPT",
-                        activation_path.join("activate").join("zsh"),
-                    )
+                    .env("FLOX_ZSH_INIT_SCRIPT", rc_file)
                     .arg("--no-globalrcs");
             },
+            ShellType::Nu(_) => {
+                // nushell can't source the activation scripts,
+                // so start it with the environment computed by bash instead
+                // and only install the flox prompt from nushell itself.
+                let activated_env = match Self::bash_activation_env(&exports, layers, pure) {
+                    Ok(activated_env) => activated_env,
+                    Err(e) => return e,
+                };
+
+                command.env_clear().envs(activated_env);
+                if exports.get(FLOX_SET_PROMPT_VAR).map(String::as_str) != Some("false") {
+                    command.arg("--execute").arg(NU_SET_PROMPT);
+                }
+            },
         };
 
         debug!("running activation command: {:?}", command);
 
-        let message = formatdoc! {"
+        let now_active = layers
+            .iter()
+            .rev()
+            .map(|layer| &layer.now_active)
+            .join(", ");
+        let message = match layers {
+            [_] => formatdoc! {"
                 You are now using the environment {now_active}.
-                To stop using this environment, type 'exit'\n"};
+                To stop using this environment, type 'exit'\n"},
+            _ => formatdoc! {"
+                You are now using the environments {now_active}.
+                To stop using these environments, type 'exit'\n"},
+        };
         message::updated(message);
 
         // exec should never return
@@ -819,73 +1419,342 @@ impl Activate {
     ///
     ///     eval "$(flox activate)" -> eval "export PATH=<flox_env_dirs>:$PATH"
     ///
+    /// For nushell the equivalent records are printed instead,
+    /// i.e. `{"hide": [], "load": {}}`
+    /// or `{"hide": [], "load": {"PATH": [<flox_env_dirs>, ...]}}`.
+    ///
     /// See [Self::fixup_path] for more details.
-    fn reactivate_in_place(fixed_up_path_joined: Option<OsString>) -> Result<(), anyhow::Error> {
+    fn reactivate_in_place(
+        shell: &ShellType,
+        fixed_up_path_joined: Option<OsString>,
+    ) -> Result<(), anyhow::Error> {
         if let Some(fixed_up_path_joined) = fixed_up_path_joined {
             debug!(
                 "Patching PATH to {}",
                 fixed_up_path_joined.to_string_lossy()
             );
-            println!(
-                "export PATH={}",
-                shell_escape::escape(fixed_up_path_joined.to_string_lossy())
-            );
+            if let ShellType::Nu(_) = shell {
+                let env = HashMap::from([(
+                    "PATH".to_string(),
+                    fixed_up_path_joined.to_string_lossy().to_string(),
+                )]);
+                println!("{}", Self::render_nu_env(env, HashMap::new())?);
+            } else {
+                println!(
+                    "export PATH={}",
+                    shell_escape::escape(fixed_up_path_joined.to_string_lossy())
+                );
+            }
         } else {
             debug!("No path patching needed");
+            if let ShellType::Nu(_) = shell {
+                println!("{}", Self::render_nu_env(HashMap::new(), HashMap::new())?);
+            }
         };
         Ok(())
     }
 
     /// Used for `eval "$(flox activate)"`
-    fn activate_in_place(
-        shell: &ShellType,
-        exports: &HashMap<&str, String>,
-        activation_path: &Path,
-    ) {
-        let exports_rendered = exports
-            .iter()
-            .map(|(key, value)| (key, shell_escape::escape(Cow::Borrowed(value))))
-            .map(|(key, value)| format!("export {key}={value}",))
-            .join("\n");
+    /// or, in nushell, applied with `hide-env` and `load-env`, see [Self::render_nu_env]
+    ///
+    /// For bash and zsh, the activation script records the variables
+    /// each layer changed for `flox deactivate`, see [RestoreRecord::snapshot_script].
+    fn activate_in_place(shell: &ShellType, layers: &[ActivationLayer]) -> Result<()> {
+        if let ShellType::Nu(_) = shell {
+            let exports = &layers.last().unwrap().exports;
+            let activated_env = Self::bash_activation_env(exports, layers, false)?;
+            println!(
+                "{}",
+                Self::render_nu_env(activated_env, env::vars().collect())?
+            );
+            return Ok(());
+        }
 
-        let script = formatdoc! {"
-                # Common flox environment variables
-                {exports_rendered}
+        for layer in layers {
+            let script = formatdoc! {r#"
+                    # save the variables prior to the activation for 'flox deactivate'
+                    {snapshot}
 
-                # to avoid infinite recursion sourcing bashrc
-                export FLOX_SOURCED_FROM_SHELL_RC=1
+                    # to avoid infinite recursion sourcing bashrc
+                    export FLOX_SOURCED_FROM_SHELL_RC=1
 
-                source {activation_path}/activate/{shell}
+                    {source_layer}
 
-                unset FLOX_SOURCED_FROM_SHELL_RC
-            ",
-        activation_path=shell_escape::escape(activation_path.to_string_lossy()),
-        };
+                    unset FLOX_SOURCED_FROM_SHELL_RC
 
-        println!("{script}");
+                    # record the changes made by this activation for 'flox deactivate'
+                    {record}
+                "#,
+            snapshot=RestoreRecord::snapshot_script(shell),
+            source_layer=Self::source_layers_script(shell, std::slice::from_ref(layer)),
+            record=RestoreRecord::record_script(shell, &layer.now_active)?,
+            };
+
+            println!("{script}");
+        }
+        Ok(())
     }
 
-    /// Quote run args so that words don't get split,
-    /// but don't escape all characters.
+    /// Script sourcing the activation scripts of `layers` for `shell`, bottom to top
     ///
-    /// To do this we escape `"`,
-    /// but we don't escape anything else.
-    /// We want `$` for example to be expanded by the shell.
-    fn quote_run_args(run_args: &[String]) -> String {
-        run_args
+    /// The exports of each layer are set before sourcing its activation script,
+    /// so that its hooks run in the context of the environments activated so far.
+    fn source_layers_script(shell: &ShellType, layers: &[ActivationLayer]) -> String {
+        layers
             .iter()
-            .map(|arg| format!(r#""{}""#, arg.replace('"', r#"\""#)))
-            .join(" ")
+            .map(|layer| {
+                let exports_rendered = layer
+                    .exports
+                    .iter()
+                    .sorted()
+                    .map(|(key, value)| (key, shell_escape::escape(Cow::Borrowed(value))))
+                    .map(|(key, value)| format!("export {key}={value}",))
+                    .join("\n");
+                formatdoc! {r#"
+                        # Common flox environment variables
+                        {exports_rendered}
+
+                        source {activation_path}/activate/{shell}"#,
+                    activation_path=shell_escape::escape(layer.activation_path.to_string_lossy()),
+                }
+            })
+            .join("\n\n")
     }
 
-    /// Explicitly set environment for nix calls
-    ///
-    /// Nixpkgs itself is broken in that the packages it creates depends
-    /// upon a variety of environment variables at runtime.  On NixOS
-    /// these are convenient to set on a system-wide basis but that
-    /// essentially masks the problem, and it's not uncommon to see Nix
-    /// packages trip over the absence of environment variables when
-    /// invoked on other Linux distributions.
+    /// The rc file of an interactive bash, or the init script of an interactive zsh
+    ///
+    /// This is the activation script of a single environment.
+    /// For multiple environments, a script sourcing the activation scripts
+    /// of all of them is written to the flox temp directory,
+    /// which removes itself once it is sourced.
+    fn rc_file(flox: &Flox, shell: &ShellType, layers: &[ActivationLayer]) -> Result<PathBuf> {
+        if let [layer] = layers {
+            return Ok(layer
+                .activation_path
+                .join("activate")
+                .join(shell.to_string()));
+        }
+
+        let rc_file = tempfile::Builder::new()
+            .prefix("activate-")
+            .tempfile_in(&flox.temp_dir)
+            .context("Could not create activation script")?;
+        let (_, path) = rc_file.keep().context("Could not keep activation script")?;
+
+        // bash sources ~/.bashrc from the first activation script only
+        let (first, above) = layers.split_first().context("No environment to activate")?;
+        let script = formatdoc! {r#"
+                rm -f {path}
+
+                {source_first}
+
+                # to avoid sourcing bashrc again
+                export FLOX_SOURCED_FROM_SHELL_RC=1
+
+                {source_above}
+
+                unset FLOX_SOURCED_FROM_SHELL_RC
+            "#,
+            path = shell_escape::escape(path.to_string_lossy()),
+            source_first = Self::source_layers_script(shell, std::slice::from_ref(first)),
+            source_above = Self::source_layers_script(shell, above),
+        };
+        fs::write(&path, script).context("Could not write activation script")?;
+        Ok(path)
+    }
+
+    /// Set up the environment that activations start from
+    ///
+    /// Pure activations start from a minimal environment
+    /// that only contains [PURE_ENV_VARS] and locale variables of the current environment.
+    /// `PATH` is kept for the activation scripts to use,
+    /// which replace it with the environment's paths if [FLOX_PURE_VAR] is set.
+    /// Otherwise, the current environment is inherited,
+    /// except for [FLOX_PURE_VAR] which would skip sourcing the user's shell rc files.
+    fn set_base_env(command: &mut Command, pure: bool) {
+        if !pure {
+            command.env_remove(FLOX_PURE_VAR);
+            return;
+        }
+
+        command.env_clear().envs(env::vars().filter(|(key, _)| {
+            key == "PATH" || PURE_ENV_VARS.contains(&key.as_str()) || key.starts_with("LC_")
+        }));
+    }
+
+    /// Compute the environment resulting from sourcing the bash activation script
+    ///
+    /// Shells that can't source the activation scripts (i.e. nushell)
+    /// are started with, or load, this environment instead.
+    /// Output of hooks is redirected to stderr,
+    /// so that stdout only contains the NUL separated environment.
+    fn bash_activation_env(
+        exports: &HashMap<&str, String>,
+        layers: &[ActivationLayer],
+        pure: bool,
+    ) -> Result<HashMap<String, String>> {
+        let script = formatdoc! {r#"
+                # to avoid infinite recursion sourcing bashrc
+                export FLOX_SOURCED_FROM_SHELL_RC=1
+
+                {{
+                {source_layers}
+                }} 1>&2
+
+                unset FLOX_SOURCED_FROM_SHELL_RC
+
+                for _flox_var in $(compgen -e); do
+                    printf '%s=%s\0' "$_flox_var" "${{!_flox_var}}"
+                done
+            "#,
+            source_layers=Self::source_layers_script(&ShellType::Bash(PathBuf::from(BASH_BIN)), layers),
+        };
+
+        let mut command = Command::new(BASH_BIN);
+        Self::set_base_env(&mut command, pure);
+        command
+            .envs(exports)
+            .args(["--noprofile", "--norc", "-c"])
+            .arg(script)
+            .stderr(Stdio::inherit());
+
+        debug!("computing activation environment: {:?}", command);
+
+        let output = command
+            .output()
+            .context("Could not run the activation script")?;
+        if !output.status.success() {
+            bail!("Activation script failed: {}", output.status);
+        }
+
+        let mut activated_env = output
+            .stdout
+            .split(|byte| *byte == 0)
+            .filter_map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                let (key, value) = entry.split_once('=')?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .filter(|(key, _)| !BASH_INTERNAL_VARS.contains(&key.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        // keep variables managed by bash itself as they are in the calling process
+        for var in BASH_INTERNAL_VARS {
+            if let Ok(value) = env::var(var) {
+                activated_env.insert(var.to_string(), value);
+            }
+        }
+
+        Ok(activated_env)
+    }
+
+    /// Render the changes from `current_env` to `activated_env` as a JSON record
+    /// that nushell can apply to the current environment.
+    ///
+    /// Variables that were added or changed are listed in `load`,
+    /// to be loaded by nushell's `load-env`.
+    /// Variables that the activation unset are listed in `hide`,
+    /// to be removed by nushell's `hide-env`.
+    /// Bash does not list variables with names that aren't valid shell identifiers,
+    /// so those are never hidden.
+    ///
+    /// `PATH` is rendered as a list, as that is nushell's native representation.
+    fn render_nu_env(
+        activated_env: HashMap<String, String>,
+        current_env: HashMap<String, String>,
+    ) -> Result<String> {
+        let is_identifier = |key: &str| {
+            key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        let hidden = current_env
+            .keys()
+            .filter(|key| is_identifier(key) && !activated_env.contains_key(*key))
+            .sorted()
+            .collect::<Vec<_>>();
+
+        let changed = activated_env
+            .iter()
+            .filter(|(key, value)| current_env.get(*key) != Some(value))
+            .map(|(key, value)| {
+                let value = if key == "PATH" {
+                    serde_json::Value::from_iter(
+                        env::split_paths(value).map(|path| path.to_string_lossy().to_string()),
+                    )
+                } else {
+                    serde_json::Value::String(value.clone())
+                };
+                (key.clone(), value)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        Ok(serde_json::to_string(&serde_json::json!({
+            "hide": hidden,
+            "load": changed,
+        }))?)
+    }
+
+    /// Render the variables of an activated environment for `--print-env`
+    ///
+    /// Variables are sorted by name and either rendered as a JSON object,
+    /// or as `NAME=value` lines with values quoted for POSIX shells.
+    fn render_env(activated_env: HashMap<String, String>, json: bool) -> Result<String> {
+        let activated_env = BTreeMap::from_iter(activated_env);
+
+        if json {
+            return Ok(serde_json::to_string_pretty(&activated_env)?);
+        }
+
+        Ok(activated_env
+            .iter()
+            .map(|(key, value)| format!("{key}={}", shell_escape::escape(Cow::Borrowed(value))))
+            .join("\n"))
+    }
+
+    /// Quote run args so that words don't get split,
+    /// but don't escape all characters.
+    ///
+    /// For POSIX shells we escape `"`,
+    /// but we don't escape anything else.
+    /// We want `$` for example to be expanded by the shell.
+    ///
+    /// Nushell does not expand variables in double quoted strings
+    /// and treats quoted command names as external commands.
+    /// Hence, words that don't need quoting, such as command names and
+    /// variable references like `$env.HOME`, are left unquoted.
+    /// Other words are quoted with backslashes and `"` escaped.
+    fn quote_run_args(shell: &ShellType, run_args: &[String]) -> String {
+        match shell {
+            ShellType::Nu(_) => run_args
+                .iter()
+                .map(|arg| {
+                    let word = arg.strip_prefix('$').unwrap_or(arg);
+                    let is_bare = !word.is_empty()
+                        && word
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+                    if is_bare {
+                        arg.to_string()
+                    } else {
+                        format!(r#""{}""#, arg.replace('\\', r"\\").replace('"', r#"\""#))
+                    }
+                })
+                .join(" "),
+            ShellType::Bash(_) | ShellType::Zsh(_) => run_args
+                .iter()
+                .map(|arg| format!(r#""{}""#, arg.replace('"', r#"\""#)))
+                .join(" "),
+        }
+    }
+
+    /// Explicitly set environment for nix calls
+    ///
+    /// Nixpkgs itself is broken in that the packages it creates depends
+    /// upon a variety of environment variables at runtime.  On NixOS
+    /// these are convenient to set on a system-wide basis but that
+    /// essentially masks the problem, and it's not uncommon to see Nix
+    /// packages trip over the absence of environment variables when
+    /// invoked on other Linux distributions.
     ///
     /// For flox specifically, set Nix-provided defaults for certain
     /// environment variables that we know to be required on the various
@@ -928,24 +1797,771 @@ impl Activate {
     }
 }
 
-#[cfg(test)]
-mod activate_tests {
-    use super::*;
+// Print a script for direnv that activates an environment
+#[derive(Bpaf, Clone)]
+pub struct Direnv {
+    #[bpaf(external(environment_select), fallback(Default::default()))]
+    environment: EnvironmentSelect,
 
-    const PATH: &str =
-        "/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin:/flox/env/bin:/nix/store/some/bin";
+    /// Trust a remote environment temporarily for this activation
+    #[bpaf(long, short)]
+    trust: bool,
+}
 
-    #[test]
-    fn test_fixup_path() {
-        let flox_env_dirs = IndexSet::from(["/flox/env"].map(PathBuf::from));
-        let fixed_up_path = Activate::fixup_path_with(PATH, &flox_env_dirs);
-        let joined = env::join_paths(fixed_up_path).unwrap();
+impl Direnv {
+    pub async fn handle(self, config: Config, flox: Flox) -> Result<()> {
+        subcommand_metric!("direnv");
+
+        let activate = Activate {
+            environments: vec![self.environment],
+            trust: self.trust,
+            print_script: false,
+            pure: false,
+            print_env: false,
+            json: false,
+            start_services: false,
+            run_args: vec![],
+        };
+        activate.activate(config, flox, true).await
+    }
 
-        assert_eq!(
-            joined.to_string_lossy(),
-            "/flox/env/bin:/nix/store/some/bin:/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin",
-            "PATH was not reordered correctly"
-        );
+    /// Declare the files that direnv should watch to reload the environment
+    ///
+    /// These are the files that change when the environment is modified,
+    /// i.e. the manifest and lockfile of path environments,
+    /// and the generation lock of managed environments.
+    /// Remote environments don't have any local files to watch.
+    fn watch_files(flox: &Flox, environment: &ConcreteEnvironment) -> Result<String> {
+        let watched = match environment {
+            ConcreteEnvironment::Path(path_env) => vec![
+                path_env.manifest_path(flox)?,
+                path_env.lockfile_path(flox)?,
+                path_env.path.join(ENVIRONMENT_POINTER_FILENAME),
+            ],
+            ConcreteEnvironment::Managed(managed_env) => vec![
+                managed_env.path.join(ENVIRONMENT_POINTER_FILENAME),
+                managed_env.path.join(GENERATION_LOCK_FILENAME),
+            ],
+            ConcreteEnvironment::Remote(_) => vec![],
+        };
+
+        Ok(watched
+            .iter()
+            .map(|path| {
+                format!(
+                    "watch_file {}",
+                    shell_escape::escape(path.to_string_lossy())
+                )
+            })
+            .join("\n"))
+    }
+
+    /// Print a script that sources the bash activation scripts of `layers`,
+    /// see [Activate::source_layers_script]
+    ///
+    /// direnv computes the changes made to the environment by this script itself,
+    /// and undoes them when leaving the directory.
+    fn activate(layers: &[ActivationLayer]) {
+        let script = formatdoc! {r#"
+                # to avoid sourcing bashrc
+                export FLOX_SOURCED_FROM_SHELL_RC=1
+
+                {source_layers}
+
+                unset FLOX_SOURCED_FROM_SHELL_RC
+            "#,
+            source_layers=Activate::source_layers_script(&ShellType::Bash(PathBuf::from(BASH_BIN)), layers),
+        };
+
+        println!("{script}");
+    }
+}
+
+/// Details of an activation that can't be recovered from the environment later,
+/// recorded by `flox activate` in [FLOX_ACTIVATIONS_VAR] for `flox status`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Activation {
+    environment: UninitializedEnvironment,
+    /// Store path the environment was activated from
+    store_path: Option<PathBuf>,
+    /// Hash of the environment's lockfile at the time of activation
+    lockfile_hash: Option<String>,
+    /// Generation of managed and remote environments at the time of activation
+    #[serde(default)]
+    generation: Option<usize>,
+}
+
+impl Activation {
+    fn new(
+        flox: &Flox,
+        environment: UninitializedEnvironment,
+        activated: &dyn Environment,
+        generation: Option<usize>,
+        activation_path: &Path,
+    ) -> Self {
+        Self {
+            environment,
+            store_path: fs::canonicalize(activation_path).ok(),
+            lockfile_hash: lockfile_hash(flox, activated),
+            generation,
+        }
+    }
+}
+
+/// Current generation of managed and remote environments
+fn current_generation(environment: &ConcreteEnvironment) -> Option<usize> {
+    match environment {
+        ConcreteEnvironment::Path(_) => None,
+        ConcreteEnvironment::Managed(managed) => managed.current_generation().ok().flatten(),
+        ConcreteEnvironment::Remote(remote) => remote.current_generation().ok().flatten(),
+    }
+    .map(|generation| *generation)
+}
+
+/// Hash of the lockfile of an environment, if it has one
+fn lockfile_hash(flox: &Flox, environment: &dyn Environment) -> Option<String> {
+    let lockfile = fs::read(environment.lockfile_path(flox).ok()?).ok()?;
+    Some(blake3::hash(&lockfile).to_string())
+}
+
+/// Activations of the currently active environments,
+/// most recently activated first like [ActiveEnvironments]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct Activations(VecDeque<Activation>);
+
+impl Activations {
+    /// Read [Activations] from [FLOX_ACTIVATIONS_VAR]
+    ///
+    /// Activations recorded in an incompatible format are ignored,
+    /// as they are only used for informational purposes.
+    fn from_env() -> Self {
+        env::var(FLOX_ACTIVATIONS_VAR)
+            .ok()
+            .and_then(|activations| activations.parse().ok())
+            .unwrap_or_default()
+    }
+
+    fn set_last_active(&mut self, activation: Activation) {
+        self.0.push_front(activation);
+    }
+
+    fn get(&self, environment: &UninitializedEnvironment) -> Option<&Activation> {
+        self.0
+            .iter()
+            .find(|activation| &activation.environment == environment)
+    }
+
+    fn remove(&mut self, environment: &UninitializedEnvironment) {
+        self.0
+            .retain(|activation| &activation.environment != environment);
+    }
+}
+
+impl FromStr for Activations {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl Display for Activations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data = serde_json::to_string(&self).map_err(|_| std::fmt::Error)?;
+        f.write_str(&data)
+    }
+}
+
+/// Variables that are not recorded as changes of an in-place activation
+///
+/// These are either maintained by the shell or set by flox itself
+/// and thus differ between the flox processes recording the activation.
+const RESTORE_IGNORED_VARS: [&str; 7] = [
+    "_",
+    "SHLVL",
+    "PWD",
+    "OLDPWD",
+    "FLOX_PARENT_PID",
+    "_FLOX_PKGDB_VERBOSITY",
+    FLOX_RESTORE_RECORDS_VAR,
+];
+
+/// Filter variables that should not be recorded, see [RESTORE_IGNORED_VARS]
+fn recordable_env(vars: impl IntoIterator<Item = (String, String)>) -> HashMap<String, String> {
+    vars.into_iter()
+        .filter(|(var, _)| !RESTORE_IGNORED_VARS.contains(&var.as_str()))
+        .collect()
+}
+
+/// Changes made to the environment of a shell by an in-place activation
+///
+/// Only variables that were changed by the activation are recorded,
+/// with `None` representing an unset variable.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct RestoreRecord {
+    environment: UninitializedEnvironment,
+    before: BTreeMap<String, Option<String>>,
+    after: BTreeMap<String, Option<String>>,
+}
+
+/// Save the exported variables of a shell in unexported shell variables
+///
+/// `EXPORTED` is replaced by a shell specific list of exported variables.
+const RESTORE_SNAPSHOT_SCRIPT: &str =
+    include_str!(concat!(env!("FLOX_RESTORE_RECORD_SCRIPTS"), "/snapshot.sh"));
+
+/// Compare the exported variables of a shell with those saved by
+/// [RESTORE_SNAPSHOT_SCRIPT] and push a [RestoreRecord] of the changes
+/// onto [FLOX_RESTORE_RECORDS_VAR]
+///
+/// `EXPORTED` is replaced by a shell specific list of exported variables,
+/// `SAVED` by a shell specific expansion of the saved variable names,
+/// `IGNORED` by a pattern matching [RESTORE_IGNORED_VARS],
+/// `ENVIRONMENT` by the activated environment serialized to JSON.
+/// Values are escaped for JSON with shell builtins only,
+/// variables containing control characters JSON can't represent are not recorded.
+const RESTORE_RECORD_SCRIPT: &str =
+    include_str!(concat!(env!("FLOX_RESTORE_RECORD_SCRIPTS"), "/record.sh"));
+
+impl RestoreRecord {
+    /// Shell code listing the exported variables of `shell`
+    /// and expanding the names saved by [RESTORE_SNAPSHOT_SCRIPT]
+    fn shell_lists(shell: &ShellType) -> (&'static str, &'static str) {
+        match shell {
+            ShellType::Zsh(_) => ("${(k)parameters[(R)*export*]}", "${=_flox_restore_vars}"),
+            ShellType::Bash(_) | ShellType::Nu(_) => ("$(compgen -e)", "$_flox_restore_vars"),
+        }
+    }
+
+    /// Shell code run before an in-place activation,
+    /// saving the exported variables in unexported shell variables
+    ///
+    /// The saved values never leave the shell,
+    /// [Self::record_script] removes them after recording the changes of the activation.
+    fn snapshot_script(shell: &ShellType) -> String {
+        let (exported, _) = Self::shell_lists(shell);
+        RESTORE_SNAPSHOT_SCRIPT.replace("EXPORTED", exported)
+    }
+
+    /// Shell code run after an in-place activation of `environment`,
+    /// recording the variables it changed in [FLOX_RESTORE_RECORDS_VAR]
+    fn record_script(shell: &ShellType, environment: &UninitializedEnvironment) -> Result<String> {
+        let (exported, saved) = Self::shell_lists(shell);
+        let environment = serde_json::to_string(environment)?;
+        Ok(RESTORE_RECORD_SCRIPT
+            .replace("EXPORTED", exported)
+            .replace("SAVED", saved)
+            .replace("IGNORED", &RESTORE_IGNORED_VARS.join("|"))
+            .replace("ENVIRONMENT", &shell_escape::escape(environment.into())))
+    }
+
+    /// Compute the value a variable should be restored to,
+    /// given its `current` value.
+    ///
+    /// * If the variable is unchanged since the activation,
+    ///   its value prior to the activation is restored.
+    /// * If the variable has been changed since,
+    ///   e.g. by activating another environment,
+    ///   only the entries added by this activation are removed from it.
+    ///   This applies to lists such as `PATH`, `FLOX_ENV_DIRS`,
+    ///   `_FLOX_ACTIVE_ENVIRONMENTS` and `_FLOX_ACTIVATIONS`.
+    /// * Otherwise, the current value is kept.
+    fn restored_value(&self, var: &str, current: Option<&str>) -> Option<String> {
+        let Some(after) = self.after.get(var) else {
+            return current.map(String::from);
+        };
+        let before = self.before.get(var).cloned().flatten();
+
+        if after.as_deref() == current {
+            return before;
+        }
+
+        let current = current?;
+
+        if var == FLOX_ACTIVE_ENVIRONMENTS_VAR {
+            let Ok(mut active) = current.parse::<ActiveEnvironments>() else {
+                return Some(current.to_string());
+            };
+            active.remove(&self.environment);
+            return Some(active.to_string());
+        }
+
+        if var == FLOX_ACTIVATIONS_VAR {
+            let Ok(mut activations) = current.parse::<Activations>() else {
+                return Some(current.to_string());
+            };
+            activations.remove(&self.environment);
+            return Some(activations.to_string());
+        }
+
+        let remaining = if var == FLOX_PROMPT_ENVIRONMENTS_VAR {
+            // Entries rendered with `prompt_format` may contain spaces,
+            // so rather than splitting the list, remove the entry
+            // the activation prepended to the list as a whole.
+            let after = after.as_deref().unwrap_or_default();
+            let entry = before
+                .as_deref()
+                .and_then(|before| after.strip_suffix(&format!(" {before}")))
+                .unwrap_or(after);
+            Self::remove_prompt_entry(current, entry)
+        } else {
+            let before_entries = before
+                .as_deref()
+                .map(|before| before.split(':').collect::<Vec<_>>())
+                .unwrap_or_default();
+            let added = after
+                .as_deref()
+                .unwrap_or_default()
+                .split(':')
+                .filter(|entry| !entry.is_empty() && !before_entries.contains(entry))
+                .collect::<Vec<_>>();
+
+            current
+                .split(':')
+                .filter(|entry| !added.contains(entry))
+                .join(":")
+        };
+
+        if remaining.is_empty() && before.is_none() {
+            None
+        } else {
+            Some(remaining)
+        }
+    }
+
+    /// Remove the first occurrence of `entry` from a space separated list of prompt entries,
+    /// matching the entry as a whole
+    fn remove_prompt_entry(prompt_environments: &str, entry: &str) -> String {
+        let padded = format!(" {prompt_environments} ");
+        let Some(start) = padded.find(&format!(" {entry} ")) else {
+            return prompt_environments.to_string();
+        };
+        let remaining = format!("{}{}", &padded[..start], &padded[start + entry.len() + 1..]);
+        remaining
+            .strip_prefix(' ')
+            .and_then(|remaining| remaining.strip_suffix(' '))
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Compute the changes required to undo this activation
+    /// in an environment with the `current` values
+    fn restore(&self, current: &HashMap<String, String>) -> BTreeMap<String, Option<String>> {
+        self.after
+            .keys()
+            .filter_map(|var| {
+                let current = current.get(var).map(String::as_str);
+                let restored = self.restored_value(var, current);
+                (restored.as_deref() != current).then(|| (var.clone(), restored))
+            })
+            .collect()
+    }
+
+    /// Update a record of an activation that happened after this one,
+    /// such that deactivating it later won't restore values of this activation.
+    ///
+    /// The values after the later activation are updated as well,
+    /// so that the entries it added to lists can still be told apart.
+    fn rebase(&self, later: &mut RestoreRecord) {
+        for (var, value) in later.before.iter_mut().chain(later.after.iter_mut()) {
+            *value = self.restored_value(var, value.as_deref());
+        }
+    }
+}
+
+/// A stack of [RestoreRecord]s of in-place activations in the current shell
+///
+/// Serialized to JSON into [FLOX_RESTORE_RECORDS_VAR],
+/// with the most recent activation first.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct RestoreRecords(VecDeque<RestoreRecord>);
+
+impl RestoreRecords {
+    fn from_env() -> Result<Self> {
+        match env::var(FLOX_RESTORE_RECORDS_VAR) {
+            Ok(records) if !records.is_empty() => Ok(serde_json::from_str(&records)
+                .with_context(|| format!("Could not parse {FLOX_RESTORE_RECORDS_VAR}"))?),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Render a shell command setting or unsetting [FLOX_RESTORE_RECORDS_VAR]
+    fn to_shell(&self) -> Result<String> {
+        if self.0.is_empty() {
+            return Ok(format!("unset {FLOX_RESTORE_RECORDS_VAR}"));
+        }
+        Ok(format!(
+            "export {FLOX_RESTORE_RECORDS_VAR}={}",
+            shell_escape::escape(serde_json::to_string(&self)?.into())
+        ))
+    }
+}
+
+// Deactivate an environment activated in the current shell
+//
+// Only environments activated in place, i.e. with `eval "$(flox activate)"`,
+// can be deactivated.
+// Environments activated in a subshell are left by exiting the subshell.
+#[derive(Bpaf, Clone)]
+pub struct Deactivate {
+    #[bpaf(external(environment_select), fallback(Default::default()))]
+    environment: EnvironmentSelect,
+}
+
+impl Deactivate {
+    pub async fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("deactivate");
+
+        if let ShellType::Nu(_) = ShellType::detect()? {
+            bail!("'flox deactivate' is not supported for nushell yet.");
+        }
+
+        let mut records = RestoreRecords::from_env()?;
+
+        let environment = match self.environment {
+            EnvironmentSelect::Unspecified => match records.0.front() {
+                Some(record) => record.environment.clone(),
+                None => match activated_environments().last_active() {
+                    Some(active) => bail!(Self::subshell_message(&active)),
+                    None => bail!("No environment is active."),
+                },
+            },
+            EnvironmentSelect::Remote(ref env_ref) => {
+                UninitializedEnvironment::Remote(ManagedPointer::new(
+                    env_ref.owner().clone(),
+                    env_ref.name().clone(),
+                    &flox.floxhub,
+                ))
+            },
+            EnvironmentSelect::Dir(_) => UninitializedEnvironment::from_concrete_environment(
+                &self.environment.to_concrete_environment(&flox)?,
+            )?,
+        };
+
+        let Some(index) = records
+            .0
+            .iter()
+            .position(|record| record.environment == environment)
+        else {
+            if activated_environments().is_active(&environment) {
+                bail!(Self::subshell_message(&environment));
+            }
+            bail!("Environment '{environment}' is not active.");
+        };
+
+        if stdout().is_tty() {
+            bail!(formatdoc! {"
+                'flox deactivate' prints a script that needs to be evaluated by your shell.
+
+                Use 'eval \"$(flox deactivate)\"' to deactivate '{environment}'.
+            "});
+        }
+
+        let record = records.0.remove(index).expect("index is valid");
+        for later in records.0.iter_mut().take(index) {
+            record.rebase(later);
+        }
+
+        let current = recordable_env(env::vars());
+        let changes = record.restore(&current);
+
+        println!("{}", Self::render_script(&changes, &records)?);
+
+        // The shell evaluating the script no longer has the environment activated
+        if let Some(mut floxd) = floxd::connect(&flox) {
+            let request = Request::Unregister {
+                pid: parent_pid()?,
+                environment: serde_json::to_string(&environment)?,
+            };
+            if let Err(e) = floxd.request(&request) {
+                debug!("could not unregister activation with floxd: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn subshell_message(environment: &UninitializedEnvironment) -> String {
+        formatdoc! {"
+            Environment '{environment}' was activated in a subshell.
+
+            Type 'exit' to leave it.
+        "}
+    }
+
+    /// Render a script applying `changes` and updating the prompt
+    fn render_script(
+        changes: &BTreeMap<String, Option<String>>,
+        records: &RestoreRecords,
+    ) -> Result<String> {
+        let mut script = Vec::new();
+
+        // The prompt has been set by the activation scripts using the current
+        // value of FLOX_PROMPT_ENVIRONMENTS, so update it before that changes.
+        if let Some(prompt_environments) = changes.get(FLOX_PROMPT_ENVIRONMENTS_VAR) {
+            let set_prompt = match prompt_environments {
+                Some(prompt_environments) if !prompt_environments.is_empty() => formatdoc! {r#"
+                    _flox_prompt_environments={prompt_environments}
+                    PS1="${{PS1/"[$FLOX_PROMPT_ENVIRONMENTS]"/"[$_flox_prompt_environments]"}}"
+                    unset _flox_prompt_environments"#,
+                    prompt_environments = shell_escape::escape(prompt_environments.into()),
+                },
+                _ => r#"PS1="$FLOX_SAVE_PS1""#.to_string(),
+            };
+            script.push(formatdoc! {r#"
+                if [ -n "${{FLOX_SAVE_PS1:-}}" ] && [ -n "${{PS1:-}}" ]; then
+                {set_prompt}
+                fi"#,
+            });
+        }
+
+        for (var, value) in changes {
+            match value {
+                Some(value) => script.push(format!(
+                    "export {var}={}",
+                    shell_escape::escape(value.into())
+                )),
+                None => script.push(format!("unset {var}")),
+            }
+        }
+
+        script.push(records.to_shell()?);
+
+        Ok(script.join("\n"))
+    }
+}
+
+// Show the environments that are active in the current shell
+#[derive(Bpaf, Clone)]
+pub struct Status {
+    /// Print the active environments as a JSON array
+    #[bpaf(long)]
+    json: bool,
+
+    /// Show the environments active in any process, as tracked by floxd
+    #[bpaf(long)]
+    all: bool,
+}
+
+/// Status of an active environment as reported by `flox status`
+#[derive(Debug, Serialize, PartialEq)]
+struct ActiveEnvironmentStatus {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: String,
+    owner: Option<String>,
+    /// Path of the directory containing the `.flox` directory
+    path: Option<PathBuf>,
+    /// Generation of managed and remote environments at the time of activation
+    generation: Option<usize>,
+    /// Store path the environment was activated from
+    store_path: Option<PathBuf>,
+    /// Whether the lockfile changed since the environment was activated,
+    /// `None` if that is unknown
+    lockfile_changed: Option<bool>,
+    /// The latest prebuild by `flox watch`, if any
+    prebuild: Option<PrebuildState>,
+}
+
+impl ActiveEnvironmentStatus {
+    fn new(
+        flox: &Flox,
+        environment: UninitializedEnvironment,
+        activation: Option<&Activation>,
+    ) -> Self {
+        let (kind, name, owner, path) = match &environment {
+            UninitializedEnvironment::DotFlox(DotFlox {
+                path,
+                pointer: EnvironmentPointer::Path(pointer),
+            }) => ("path", pointer.name.to_string(), None, Some(path.clone())),
+            UninitializedEnvironment::DotFlox(DotFlox {
+                path,
+                pointer: EnvironmentPointer::Managed(pointer),
+            }) => (
+                "managed",
+                pointer.name.to_string(),
+                Some(pointer.owner.to_string()),
+                Some(path.clone()),
+            ),
+            UninitializedEnvironment::Remote(pointer) => (
+                "remote",
+                pointer.name.to_string(),
+                Some(pointer.owner.to_string()),
+                None,
+            ),
+        };
+
+        let mut status = Self {
+            kind,
+            name,
+            owner,
+            path,
+            generation: None,
+            store_path: activation.and_then(|activation| activation.store_path.clone()),
+            lockfile_changed: None,
+            prebuild: None,
+        };
+
+        let concrete_environment = match environment.into_concrete_environment(flox) {
+            Ok(concrete_environment) => concrete_environment,
+            Err(e) => {
+                debug!("could not open active environment: {e}");
+                return status;
+            },
+        };
+
+        // Environments activated before the generation was recorded
+        // fall back to the current generation.
+        status.generation = match activation {
+            Some(Activation {
+                generation: Some(generation),
+                ..
+            }) => Some(*generation),
+            _ => current_generation(&concrete_environment),
+        };
+
+        let environment = concrete_environment.into_dyn_environment();
+        status.lockfile_changed = activation.map(|activation| {
+            activation.lockfile_hash != lockfile_hash(flox, environment.as_ref())
+        });
+        status.prebuild = PrebuildState::read(flox, environment.as_ref()).unwrap_or_else(|e| {
+            debug!("could not read prebuild state: {e}");
+            None
+        });
+
+        status
+    }
+}
+
+impl Display for ActiveEnvironmentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match &self.owner {
+            Some(owner) => format!("{owner}/{}", self.name),
+            None => self.name.clone(),
+        };
+        let location = match &self.path {
+            Some(path) => path.to_string_lossy().to_string(),
+            None => "FloxHub".to_string(),
+        };
+        let lockfile = match self.lockfile_changed {
+            Some(true) => "changed since activation",
+            Some(false) => "unchanged since activation",
+            None => "unknown",
+        };
+
+        let message = formatdoc! {"
+            {name}
+              Type:       {kind}
+              Location:   {location}
+              Generation: {generation}
+              Store path: {store_path}
+              Lockfile:   {lockfile}",
+            kind = self.kind,
+            generation = self
+                .generation
+                .map_or("N/A".to_string(), |generation| generation.to_string()),
+            store_path = self
+                .store_path
+                .as_ref()
+                .map_or("unknown".into(), |store_path| store_path.to_string_lossy()),
+        };
+        f.write_str(&message)?;
+
+        if let Some(prebuild) = &self.prebuild {
+            let updated = prebuild
+                .updated
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S");
+            match &prebuild.status {
+                PrebuildStatus::Building => write!(f, "\n  Prebuild:   building since {updated}")?,
+                PrebuildStatus::Built { .. } => write!(f, "\n  Prebuild:   built at {updated}")?,
+                PrebuildStatus::Failed { error } => {
+                    write!(f, "\n  Prebuild:   failed at {updated}")?;
+                    for line in error.lines() {
+                        write!(f, "\n    {line}")?;
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Status {
+    pub async fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("status");
+
+        if self.all {
+            return Self::all_processes(&flox, self.json);
+        }
+
+        let activations = Activations::from_env();
+        let statuses = activated_environments()
+            .into_iter()
+            .map(|environment| {
+                let activation = activations.get(&environment);
+                ActiveEnvironmentStatus::new(&flox, environment, activation)
+            })
+            .collect::<Vec<_>>();
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&statuses)?);
+            return Ok(());
+        }
+
+        if statuses.is_empty() {
+            message::plain("No environment is active.");
+            return Ok(());
+        }
+
+        message::plain("Active environments, most recently activated first:");
+        for status in statuses {
+            println!();
+            println!("{status}");
+        }
+
+        Ok(())
+    }
+
+    /// Show the environments floxd tracks as active in any process
+    fn all_processes(flox: &Flox, json: bool) -> Result<()> {
+        let activations = match floxd::connect(flox) {
+            Some(mut floxd) => match floxd.request(&Request::Activations)? {
+                Response::Activations { activations } => activations,
+                response => bail!("Unexpected response from floxd: {response:?}"),
+            },
+            // floxd exits a while after the last activation exited
+            None => vec![],
+        };
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&activations)?);
+            return Ok(());
+        }
+
+        if activations.is_empty() {
+            message::plain("No environment is active.");
+            return Ok(());
+        }
+
+        message::plain("Environments active in any process:");
+        for activation in activations {
+            let message = formatdoc! {"
+                {name}
+                  Processes:  {pids}
+                  Store path: {flox_env}",
+                name = activation.name,
+                pids = activation.pids.iter().join(", "),
+                flox_env = fs::canonicalize(&activation.flox_env)
+                    .unwrap_or(activation.flox_env)
+                    .to_string_lossy(),
+            };
+            println!();
+            println!("{message}");
+        }
+
+        Ok(())
     }
 }
 
@@ -1169,8 +2785,8 @@ impl Install {
         let description = environment_description(&concrete_environment)?;
 
         // Ensure the user is logged in for the following remote operations
-        if let ConcreteEnvironment::Remote(_) = concrete_environment {
-            ensure_floxhub_token(&mut flox).await?;
+        if let ConcreteEnvironment::Remote(ref environment) = concrete_environment {
+            ensure_floxhub_token_for(&mut flox, environment.owner()).await?;
         };
 
         let mut environment = concrete_environment.into_dyn_environment();
@@ -1325,8 +2941,8 @@ impl Uninstall {
         };
 
         // Ensure the user is logged in for the following remote operations
-        if let ConcreteEnvironment::Remote(_) = concrete_environment {
-            ensure_floxhub_token(&mut flox).await?;
+        if let ConcreteEnvironment::Remote(ref environment) = concrete_environment {
+            ensure_floxhub_token_for(&mut flox, environment.owner()).await?;
         };
 
         let description = environment_description(&concrete_environment)?;
@@ -1403,6 +3019,241 @@ impl History {
     }
 }
 
+/// Url of the remote that stores the environments of `owner`,
+/// either FloxHub or a self-hosted git remote set in `floxmeta_remotes`
+fn remote_url<'a>(flox: &'a Flox, owner: &EnvironmentOwner) -> &'a Url {
+    if flox.floxhub.is_self_hosted(owner) {
+        flox.floxhub.git_url_for(owner)
+    } else {
+        flox.floxhub.base_url()
+    }
+}
+
+/// Like [remote_url] but refers to FloxHub by name
+fn remote_name(flox: &Flox, owner: &EnvironmentOwner) -> String {
+    if flox.floxhub.is_self_hosted(owner) {
+        remote_url(flox, owner).to_string()
+    } else {
+        "FloxHub".to_string()
+    }
+}
+
+/// Compare a managed environment in `dir` to its upstream version
+fn preview_sync(
+    flox: &Flox,
+    pointer: &ManagedPointer,
+    dir: &Path,
+    remote: &str,
+) -> Result<SyncPreview> {
+    let env = ManagedEnvironment::open(flox, pointer.clone(), dir.join(DOT_FLOX))?;
+    let preview = Dialog {
+        message: &format!("Comparing with {remote}..."),
+        help_message: None,
+        typed: Spinner::new(|| env.preview_sync()),
+    }
+    .spin()?;
+    Ok(preview)
+}
+
+/// Whether a preview describes pushing or pulling an environment
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SyncDirection {
+    Push,
+    Pull,
+}
+
+/// Describe what pushing or pulling a managed environment would change,
+/// as computed by [ManagedEnvironment::preview_sync]
+fn sync_preview_message(
+    preview: &SyncPreview,
+    pointer: &ManagedPointer,
+    remote: &str,
+    direction: SyncDirection,
+    force: bool,
+) -> String {
+    let owner = &pointer.owner;
+    let name = &pointer.name;
+
+    if preview.is_up_to_date() {
+        return format!("{owner}/{name} is up to date with {remote}.");
+    }
+
+    let generations = |generations: &[(_, SingleGenerationMetadata)]| {
+        generations
+            .iter()
+            .map(|(id, generation)| {
+                let created = generation
+                    .created
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S");
+                format!("  * {id} ({created}): {}", generation.description)
+            })
+            .join("\n")
+    };
+
+    let mut sections = Vec::new();
+    if !preview.local_generations.is_empty() {
+        sections.push(format!(
+            "Local generations not in {remote}:\n{}",
+            generations(&preview.local_generations)
+        ));
+    }
+    if !preview.upstream_generations.is_empty() {
+        sections.push(format!(
+            "Generations in {remote} not in the local environment:\n{}",
+            generations(&preview.upstream_generations)
+        ));
+    }
+
+    let packages = &preview.packages;
+    if !packages.is_empty() {
+        let mut lines = Vec::new();
+        if !packages.added.is_empty() {
+            lines.push(format!(
+                "  Only installed locally: {}",
+                packages.added.join(", ")
+            ));
+        }
+        if !packages.removed.is_empty() {
+            lines.push(format!(
+                "  Only installed in {remote}: {}",
+                packages.removed.join(", ")
+            ));
+        }
+        if !packages.changed.is_empty() {
+            lines.push(format!(
+                "  Installed differently: {}",
+                packages.changed.join(", ")
+            ));
+        }
+        sections.push(format!("Packages:\n{}", lines.join("\n")));
+    }
+
+    let outcome = match direction {
+        SyncDirection::Push if force && !preview.upstream_generations.is_empty() => {
+            format!(
+                "Pushing with '--force' would discard the generations in {remote} listed above."
+            )
+        },
+        SyncDirection::Push if preview.local_generations.is_empty() => formatdoc! {"
+            Nothing to push, {remote} has newer generations.
+            Use 'flox pull' to update the local environment."},
+        SyncDirection::Push if preview.diverged => formatdoc! {"
+            The environment has diverged from {remote},
+            pushing would merge local and upstream changes first."},
+        SyncDirection::Push => {
+            format!("Pushing would add the local generations listed above to {remote}.")
+        },
+        SyncDirection::Pull if force && !preview.local_generations.is_empty() => {
+            "Pulling with '--force' would discard the local generations listed above.".to_string()
+        },
+        SyncDirection::Pull if preview.upstream_generations.is_empty() => formatdoc! {"
+            Nothing to pull, the local environment has newer generations.
+            Use 'flox push' to share them."},
+        SyncDirection::Pull if preview.diverged => formatdoc! {"
+            The environment has diverged from {remote},
+            pulling would merge local and upstream changes."},
+        SyncDirection::Pull => format!(
+            "Pulling would add the generations in {remote} listed above to the local environment."
+        ),
+    };
+    sections.push(outcome);
+
+    sections.join("\n\n")
+}
+
+/// Reconcile diverged local and upstream changes of a managed environment
+/// with a merge generation
+///
+/// Conflicting changes are marked in the merged manifest,
+/// which the user resolves in their editor.
+async fn merge_diverged(flox: &Flox, env: &mut ManagedEnvironment) -> Result<()> {
+    let merge = Dialog {
+        message: "Merging local and upstream changes...",
+        help_message: None,
+        typed: Spinner::new(|| env.merge_upstream()),
+    }
+    .spin()?;
+
+    if merge.conflicts.is_empty() {
+        Dialog {
+            message: "Building merged environment...",
+            help_message: None,
+            typed: Spinner::new(|| env.commit_merge(flox, merge.manifest)),
+        }
+        .spin()
+        .map_err(apply_doc_link_for_unsupported_packages)?;
+        return Ok(());
+    }
+
+    let conflicts = merge
+        .conflicts
+        .iter()
+        .map(|key| format!("  * {key}"))
+        .join("\n");
+
+    if !Dialog::can_prompt() {
+        bail!(formatdoc! {"
+            Local and upstream changes to the environment conflict in:
+            {conflicts}
+
+            Run this command interactively to resolve the conflicts in your editor,
+            or use '--force' to discard either the local or the upstream changes.
+        "});
+    }
+
+    message::warning(formatdoc! {"
+        Local and upstream changes to the environment conflict in:
+        {conflicts}
+
+        Resolve the conflicts marked in the manifest to complete the merge.
+    "});
+
+    let editor = Edit::determine_editor()?;
+    let tmp_manifest = tempfile::Builder::new()
+        .prefix("manifest.")
+        .suffix(".toml")
+        .tempfile_in(&flox.temp_dir)?;
+    std::fs::write(&tmp_manifest, &merge.manifest)?;
+    let should_continue = Dialog {
+        message: "Continue resolving conflicts?",
+        help_message: Default::default(),
+        typed: Confirm {
+            default: Some(true),
+        },
+    };
+
+    // Let the user keep editing the file until the conflicts are resolved
+    // and the merged environment builds, or the user decides to stop.
+    loop {
+        let resolved = Edit::edited_manifest_contents(&tmp_manifest, &editor)?;
+
+        if manifest::contains_conflict_markers(&resolved) {
+            message::error("The manifest still contains conflict markers.");
+        } else {
+            let result = Dialog {
+                message: "Building merged environment...",
+                help_message: None,
+                typed: Spinner::new(|| env.commit_merge(flox, resolved)),
+            }
+            .spin()
+            .map_err(apply_doc_link_for_unsupported_packages);
+
+            match result {
+                Err(EnvironmentError2::Core(CoreEnvironmentError::LockedManifest(e))) => {
+                    message::error(format_locked_manifest_error(&e));
+                },
+                Err(e) => bail!(e),
+                Ok(()) => return Ok(()),
+            }
+        }
+
+        if !should_continue.clone().prompt().await? {
+            bail!("Merge cancelled, the environment was not changed");
+        }
+    }
+}
+
 // Send environment to FloxHub
 #[derive(Bpaf, Clone)]
 pub struct Push {
@@ -1417,37 +3268,53 @@ pub struct Push {
     /// Forceably overwrite the remote copy of the environment
     #[bpaf(long, short)]
     force: bool,
+
+    /// Show the generations and packages that would be pushed
+    /// without pushing them
+    #[bpaf(long)]
+    dry_run: bool,
 }
 
 impl Push {
     pub async fn handle(self, mut flox: Flox) -> Result<()> {
         subcommand_metric!("push");
 
-        // Ensure the user is logged in for the following remote operations
-        ensure_floxhub_token(&mut flox).await?;
-
         let dir = self.dir.unwrap_or_else(|| std::env::current_dir().unwrap());
 
         match EnvironmentPointer::open(&dir)? {
-            EnvironmentPointer::Managed(managed_pointer) => {
-                let message = Self::push_existing_message(&managed_pointer, self.force);
-
-                Dialog {
-                    message: "Pushing updates to FloxHub...",
-                    help_message: None,
-                    typed: Spinner::new(|| {
-                        Self::push_managed_env(&flox, managed_pointer, dir, self.force)
-                    }),
+            EnvironmentPointer::Managed(managed_pointer) => {
+                // Ensure the user is logged in for the following remote operations
+                ensure_floxhub_token_for(&mut flox, &managed_pointer.owner).await?;
+
+                let remote = remote_name(&flox, &managed_pointer.owner);
+
+                if self.dry_run {
+                    let preview = preview_sync(&flox, &managed_pointer, &dir, &remote)?;
+                    message::plain(sync_preview_message(
+                        &preview,
+                        &managed_pointer,
+                        &remote,
+                        SyncDirection::Push,
+                        self.force,
+                    ));
+                    return Ok(());
                 }
-                .spin()?;
+
+                let message = Self::push_existing_message(&managed_pointer, &remote, self.force);
+
+                Self::push_managed_env(&flox, managed_pointer, dir, &remote, self.force).await?;
 
                 message::updated(message);
             },
 
             EnvironmentPointer::Path(path_pointer) => {
                 let owner = if let Some(owner) = self.owner {
+                    // Ensure the user is logged in for the following remote operations
+                    ensure_floxhub_token_for(&mut flox, &owner).await?;
                     owner
                 } else {
+                    // The owner defaults to the logged in FloxHub user
+                    ensure_floxhub_token(&mut flox).await?;
                     EnvironmentOwner::from_str(
                         flox.floxhub_token
                             .as_ref()
@@ -1456,8 +3323,18 @@ impl Push {
                     )?
                 };
 
+                let remote = remote_name(&flox, &owner);
+
+                if self.dry_run {
+                    message::plain(format!(
+                        "Pushing would create {owner}/{name} in {remote}.",
+                        name = path_pointer.name
+                    ));
+                    return Ok(());
+                }
+
                 let env = Dialog {
-                    message: "Pushing environment to FloxHub...",
+                    message: &format!("Pushing environment to {remote}..."),
                     help_message: None,
                     typed: Spinner::new(|| {
                         Self::push_make_managed(&flox, path_pointer, &dir, owner, self.force)
@@ -1465,21 +3342,51 @@ impl Push {
                 }
                 .spin()?;
 
-                message::updated(Self::push_new_message(env.pointer(), self.force));
+                message::updated(Self::push_new_message(env.pointer(), &remote, self.force));
             },
         }
         Ok(())
     }
 
-    fn push_managed_env(
+    /// Push updates of a managed environment,
+    /// merging upstream changes if the environment has diverged
+    async fn push_managed_env(
         flox: &Flox,
         managed_pointer: ManagedPointer,
         dir: PathBuf,
+        remote: &str,
         force: bool,
     ) -> Result<()> {
-        let mut env = ManagedEnvironment::open(flox, managed_pointer.clone(), dir.join(DOT_FLOX))?;
-        env.push(flox, force)
-            .map_err(|err| Self::convert_error(err, managed_pointer, false))?;
+        let (mut env, pushed) = Dialog {
+            message: &format!("Pushing updates to {remote}..."),
+            help_message: None,
+            typed: Spinner::new(|| -> Result<_> {
+                let mut env =
+                    ManagedEnvironment::open(flox, managed_pointer.clone(), dir.join(DOT_FLOX))?;
+                let pushed = env.push(flox, force);
+                Ok((env, pushed))
+            }),
+        }
+        .spin()?;
+
+        match pushed {
+            // with `--force` upstream changes are overwritten instead
+            Err(ManagedEnvironmentError::Diverged) => {
+                message::plain(format!(
+                    "The environment has diverged from {remote}, merging local and upstream changes."
+                ));
+                merge_diverged(flox, &mut env).await?;
+
+                Dialog {
+                    message: &format!("Pushing merged environment to {remote}..."),
+                    help_message: None,
+                    typed: Spinner::new(|| env.push(flox, false)),
+                }
+                .spin()
+                .map_err(|err| Self::convert_error(err, managed_pointer, false))?;
+            },
+            pushed => pushed.map_err(|err| Self::convert_error(err, managed_pointer, false))?,
+        }
 
         Ok(())
     }
@@ -1546,14 +3453,14 @@ impl Push {
     /// construct a message for an updated environment
     ///
     /// todo: add FloxHub base url when it's available
-    fn push_existing_message(env: &ManagedPointer, force: bool) -> String {
+    fn push_existing_message(env: &ManagedPointer, remote: &str, force: bool) -> String {
         let owner = &env.owner;
         let name = &env.name;
 
         let suffix = if force { " (forced)" } else { "" };
 
         formatdoc! {"
-            Updates to {name} successfully pushed to FloxHub{suffix}
+            Updates to {name} successfully pushed to {remote}{suffix}
 
             Use 'flox pull {owner}/{name}' to get this environment in any other location.
         "}
@@ -1562,14 +3469,14 @@ impl Push {
     /// construct a message for a newly created environment
     ///
     /// todo: add FloxHub base url when it's available
-    fn push_new_message(env: &ManagedPointer, force: bool) -> String {
+    fn push_new_message(env: &ManagedPointer, remote: &str, force: bool) -> String {
         let owner = &env.owner;
         let name = &env.name;
 
         let suffix = if force { " (forced)" } else { "" };
 
         formatdoc! {"
-            {name} successfully pushed to FloxHub{suffix}
+            {name} successfully pushed to {remote}{suffix}
 
             Use 'flox pull {owner}/{name}' to get this environment in any other location.
         "}
@@ -1611,6 +3518,11 @@ pub struct Pull {
     #[bpaf(long, short)]
     force: bool,
 
+    /// Show the generations and packages that would be pulled
+    /// into an existing environment without pulling them
+    #[bpaf(long)]
+    dry_run: bool,
+
     #[bpaf(external(pull_select), fallback(Default::default()))]
     pull_select: PullSelect,
 }
@@ -1621,8 +3533,17 @@ impl Pull {
 
         match self.pull_select {
             PullSelect::New { remote } | PullSelect::NewAbbreviated { remote } => {
-                let (start, complete) =
-                    Self::pull_new_messages(self.dir.as_deref(), &remote, flox.floxhub.base_url());
+                if self.dry_run {
+                    bail!(
+                        "'--dry-run' can only be used to pull updates into an existing environment"
+                    );
+                }
+
+                let (start, complete) = Self::pull_new_messages(
+                    self.dir.as_deref(),
+                    &remote,
+                    remote_url(&flox, remote.owner()),
+                );
 
                 let dir = self.dir.unwrap_or_else(|| std::env::current_dir().unwrap());
 
@@ -1645,27 +3566,62 @@ impl Pull {
                     }
                 };
 
+                if self.dry_run {
+                    let remote = remote_name(&flox, &pointer.owner);
+                    let preview = preview_sync(&flox, &pointer, &dir, &remote)?;
+                    message::plain(sync_preview_message(
+                        &preview,
+                        &pointer,
+                        &remote,
+                        SyncDirection::Pull,
+                        self.force,
+                    ));
+                    return Ok(());
+                }
+
                 let start_message = format!(
                     "⬇️  Remote: pulling and building {owner}/{name} from {floxhub_host}",
                     owner = pointer.owner,
                     name = pointer.name,
-                    floxhub_host = flox.floxhub.base_url()
+                    floxhub_host = remote_url(&flox, &pointer.owner)
                 );
 
-                let result = Dialog {
+                let (mut env, pulled) = Dialog {
                     message: &start_message,
                     help_message: None,
-                    typed: Spinner::new(|| {
-                        Self::pull_existing_environment(
-                            &flox,
-                            dir.join(DOT_FLOX),
-                            pointer.clone(),
-                            self.force,
-                        )
+                    typed: Spinner::new(|| -> Result<_> {
+                        let mut env =
+                            ManagedEnvironment::open(&flox, pointer.clone(), dir.join(DOT_FLOX))?;
+                        let pulled = Self::pull_existing_environment(&flox, &mut env, self.force);
+                        Ok((env, pulled))
                     }),
                 }
                 .spin()?;
 
+                let result = match pulled {
+                    // with `--force` local changes are discarded instead
+                    Err(EnvironmentError2::ManagedEnvironment(
+                        ManagedEnvironmentError::Diverged,
+                    )) => {
+                        message::plain(format!(
+                            "The environment has diverged from {floxhub_host}, merging local and upstream changes.",
+                            floxhub_host = remote_url(&flox, &pointer.owner)
+                        ));
+                        merge_diverged(&flox, &mut env).await?;
+
+                        message::updated(formatdoc! {"
+                            Merged upstream changes to {owner}/{name} from {floxhub_host}
+
+                            Use 'flox push' to share the merged environment.
+                            ",
+                            owner = pointer.owner, name = pointer.name,
+                            floxhub_host = remote_url(&flox, &pointer.owner),
+                        });
+                        return Ok(());
+                    },
+                    pulled => pulled?,
+                };
+
                 match result {
                     PullResult::Updated => {
                         message::updated(formatdoc! {"
@@ -1674,7 +3630,7 @@ impl Pull {
                             You can activate this environment with 'flox activate'
                             ",
                             owner = pointer.owner, name = pointer.name,
-                            floxhub_host = flox.floxhub.base_url(),
+                            floxhub_host = remote_url(&flox, &pointer.owner),
                             suffix = if self.force { " (forced)" } else { "" }
                         });
                     },
@@ -1692,15 +3648,13 @@ impl Pull {
 
     /// Update an existing environment with the latest version from FloxHub
     ///
-    /// Opens the environment and calls [ManagedEnvironment::pull] on it,
+    /// Calls [ManagedEnvironment::pull] on the environment,
     /// which will update the lockfile.
     fn pull_existing_environment(
         flox: &Flox,
-        dot_flox_path: PathBuf,
-        pointer: ManagedPointer,
+        env: &mut ManagedEnvironment,
         force: bool,
     ) -> Result<PullResult, EnvironmentError2> {
-        let mut env = ManagedEnvironment::open(flox, pointer, dot_flox_path)?;
         let state = env.pull(force)?;
         // only build if the environment was updated
         if let PullResult::Updated = state {
@@ -1981,6 +3935,100 @@ impl Pull {
     }
 }
 
+// Fork an environment of another owner into your own namespace
+#[derive(Bpaf, Clone)]
+pub struct Fork {
+    /// Directory in which to create the forked environment (default: current directory)
+    #[bpaf(long, short, argument("path"))]
+    dir: Option<PathBuf>,
+
+    /// Name of the forked environment (default: name of the forked environment)
+    #[bpaf(long("as"), argument("name"))]
+    name: Option<EnvironmentName>,
+
+    /// ID of the environment to fork
+    #[bpaf(positional("owner>/<name"))]
+    remote: EnvironmentRef,
+}
+
+impl Fork {
+    pub async fn handle(self, mut flox: Flox) -> Result<()> {
+        subcommand_metric!("fork");
+
+        // The fork is owned by the logged in FloxHub user
+        ensure_floxhub_token(&mut flox).await?;
+        let owner = EnvironmentOwner::from_str(
+            flox.floxhub_token
+                .as_ref()
+                .context("Need to be loggedin")?
+                .handle(),
+        )?;
+        let name = self.name.unwrap_or_else(|| self.remote.name().clone());
+
+        if &owner == self.remote.owner() && &name == self.remote.name() {
+            bail!(formatdoc! {"
+                Cannot fork {remote} into itself.
+
+                Use 'flox fork {remote} --as <name>' to choose a different name.
+            ", remote = self.remote});
+        }
+
+        let dir = self.dir.unwrap_or_else(|| std::env::current_dir().unwrap());
+        let dot_flox_path = dir.join(DOT_FLOX);
+        if dot_flox_path.exists() {
+            bail!("Cannot fork an environment into an existing one")
+        }
+
+        debug!(
+            "Resolved user intent: fork {} as {owner}/{name} into {dir:?}",
+            self.remote
+        );
+
+        let source = ManagedPointer::new(
+            self.remote.owner().clone(),
+            self.remote.name().clone(),
+            &flox.floxhub,
+        );
+        let remote = remote_name(&flox, &owner);
+
+        fs::create_dir_all(&dot_flox_path).context("Could not create .flox/ directory")?;
+        let result = Dialog {
+            message: &format!("Forking {} to {owner}/{name}...", self.remote),
+            help_message: None,
+            typed: Spinner::new(|| {
+                ManagedEnvironment::fork(&flox, source, owner.clone(), name.clone(), &dot_flox_path)
+            }),
+        }
+        .spin();
+
+        let mut env = match result {
+            Ok(env) => env,
+            Err(err) => {
+                fs::remove_dir_all(&dot_flox_path)
+                    .context("Could not clean up .flox/ directory")?;
+                Err(Pull::handle_error(&flox, err))?
+            },
+        };
+
+        if let Err(err) = env.build(&flox) {
+            message::warning(formatdoc! {"
+                {err:#}
+
+                Could not build the forked environment, build errors need to be resolved manually.",
+                err = anyhow!(err)
+            });
+        }
+
+        message::created(formatdoc! {"
+            Forked {source} to {owner}/{name} on {remote}
+
+            You can activate this environment with 'flox activate'
+        ", source = self.remote});
+
+        Ok(())
+    }
+}
+
 // Rollback to the previous generation of an environment
 #[derive(Bpaf, Clone)]
 pub struct Rollback {
@@ -2265,40 +4313,70 @@ pub struct Containerize {
     /// Path to write the container to (pass '-' to write to stdout)
     #[bpaf(short, long, argument("path"))]
     output: Option<PathBuf>,
+
+    /// Format of the image: 'docker-archive' (default), 'oci-archive' or 'oci-dir'
+    #[bpaf(long, argument("format"))]
+    format: Option<ImageFormat>,
+
+    /// Name of the image (default: the name of the environment)
+    #[bpaf(long, argument("name"))]
+    name: Option<String>,
+
+    /// Tag of the image (default: 'latest')
+    #[bpaf(long, argument("tag"))]
+    tag: Option<String>,
+
+    /// Push the image to a registry, e.g. 'registry.example.com/team/env:tag'
+    #[bpaf(long, argument("reference"))]
+    push: Option<RemoteImageReference>,
 }
 impl Containerize {
-    pub async fn handle(self, flox: Flox) -> Result<()> {
+    pub async fn handle(self, config: Config, flox: Flox) -> Result<()> {
         subcommand_metric!("containerize");
 
+        if self.push.is_some() && self.name.is_some() {
+            bail!(
+                "'--name' can not be used with '--push', the name is part of the pushed reference."
+            );
+        }
+        // The image is only written to a file when pushing if asked to
+        let write_output = self.push.is_none() || self.output.is_some();
+
         let mut env = self
             .environment
             .detect_concrete_environment(&flox, "upgrade")?
             .into_dyn_environment();
 
+        let format = self.format.unwrap_or_default();
+        // A tag in the pushed reference takes precedence over '--tag'
+        let tag = self
+            .push
+            .as_ref()
+            .and_then(|push| push.tag.clone())
+            .or(self.tag)
+            .unwrap_or("latest".to_string());
+        let push = self.push.map(|push| RemoteImageReference {
+            tag: Some(tag.clone()),
+            ..push
+        });
+        let reference = ImageReference {
+            name: match &push {
+                Some(push) => format!("{}/{}", push.registry, push.repository),
+                None => self.name.unwrap_or_else(|| env.name().to_string()),
+            },
+            tag,
+        };
+
         let output_path = match self.output {
             Some(output) => output,
             None => std::env::current_dir()
                 .context("Could not get current directory")?
-                .join(format!("{}-container.tar.gz", env.name())),
+                .join(Self::default_output_name(env.name().as_ref(), format)),
         };
-
-        let (output, output_name): (Box<dyn Write + Send>, String) =
-            if output_path == Path::new("-") {
-                debug!("output=stdout");
-
-                (Box::new(std::io::stdout()), "stdout".to_string())
-            } else {
-                debug!("output={}", output_path.display());
-
-                let file = fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .truncate(true)
-                    .open(&output_path)
-                    .context("Could not open output file")?;
-
-                (Box::new(file), output_path.display().to_string())
-            };
+        let to_stdout = output_path == Path::new("-");
+        if to_stdout && !format.is_archive() {
+            bail!("Images in the '{format}' format can not be written to stdout.");
+        }
 
         let builder = Dialog {
             message: &format!("Building container for environment {}...", env.name()),
@@ -2307,28 +4385,494 @@ impl Containerize {
         }
         .spin()?;
 
-        Dialog {
-            message: &format!("Writing container to '{output_name}'"),
+        let image = Dialog {
+            message: "Assembling container image...",
             help_message: None,
-            typed: Spinner::new(|| builder.stream_container(output)),
+            typed: ProgressSpinner::new(|progress: &dyn Fn(String)| {
+                builder.build_image(|read| progress(format_size(read)))
+            }),
         }
         .spin()?;
 
-        message::created(format!("Container written to '{output_name}'"));
+        if write_output {
+            write_container_image(&image, format, &reference, &output_path)?;
+        }
+
+        if let Some(push) = push {
+            let credentials = config
+                .flox
+                .container_registries
+                .get(&push.registry)
+                .cloned()
+                .or_else(|| RegistryCredentials::from_docker_config(&push.registry));
+
+            let pushed = Dialog {
+                message: &format!("Pushing container to '{push}'..."),
+                help_message: None,
+                typed: Spinner::new(|| push_image(&image, &push, credentials)),
+            }
+            .spin()?;
+
+            message::updated(format!("Container pushed to '{push}'"));
+            message::plain(formatdoc! {"
+                Digest: {digest}
+                Uploaded {uploaded} of {total} blobs, the registry already had the others.",
+                digest = pushed.digest,
+                uploaded = pushed.uploaded,
+                total = pushed.uploaded + pushed.skipped,
+            });
+        }
+
         Ok(())
     }
+
+    fn default_output_name(environment_name: &str, format: ImageFormat) -> String {
+        match format {
+            ImageFormat::DockerArchive => format!("{environment_name}-container.tar.gz"),
+            ImageFormat::OciArchive => format!("{environment_name}-container.oci.tar"),
+            ImageFormat::OciDir => format!("{environment_name}-container"),
+        }
+    }
+}
+
+/// Write a container image to `output_path`, or to stdout if it is `-`
+fn write_container_image(
+    image: &UnpackedImage,
+    format: ImageFormat,
+    reference: &ImageReference,
+    output_path: &Path,
+) -> Result<()> {
+    let to_stdout = output_path == Path::new("-");
+    let output_name = if to_stdout {
+        "stdout".to_string()
+    } else {
+        output_path.display().to_string()
+    };
+
+    Dialog {
+        message: &format!("Writing container to '{output_name}'"),
+        help_message: None,
+        typed: Spinner::new(|| -> Result<()> {
+            if to_stdout {
+                debug!("output=stdout");
+                image.write_archive(format, reference, std::io::stdout().lock())?;
+            } else if format.is_archive() {
+                debug!("output={}", output_path.display());
+                write_container_archive(image, format, reference, output_path)?;
+            } else {
+                debug!("output={}", output_path.display());
+                let created = !output_path.exists();
+                if let Err(err) = image.write_oci_dir(reference, output_path) {
+                    // don't leave a partial image behind
+                    if created {
+                        let _ = fs::remove_dir_all(output_path);
+                    }
+                    return Err(err.into());
+                }
+            }
+            Ok(())
+        }),
+    }
+    .spin()?;
+
+    message::created(format!("Container written to '{output_name}'"));
+    message::plain(formatdoc! {"
+        Image:  {reference} ({format})
+        Digest: {digest}
+        Size:   {size}",
+        digest = image.digest(),
+        size = format_size(image.size()),
+    });
+    Ok(())
+}
+
+/// Write a container archive to a temporary file next to `output_path`,
+/// and move it there once it is verified.
+///
+/// A failed write thus leaves no partial archive behind,
+/// and keeps an existing file at `output_path`.
+fn write_container_archive(
+    image: &UnpackedImage,
+    format: ImageFormat,
+    reference: &ImageReference,
+    output_path: &Path,
+) -> Result<()> {
+    let output_dir = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut file =
+        tempfile::NamedTempFile::new_in(output_dir).context("Could not create output file")?;
+
+    let mut writer = std::io::BufWriter::new(file.as_file_mut());
+    image.write_archive(format, reference, &mut writer)?;
+    writer.flush().context("Could not write output file")?;
+    drop(writer);
+
+    let written = file.reopen().context("Could not read output file")?;
+    image.verify_archive(format, std::io::BufReader::new(written))?;
+
+    // temporary files are only readable by their owner
+    fs::set_permissions(file.path(), fs::Permissions::from_mode(0o644))
+        .context("Could not set permissions of output file")?;
+    file.persist(output_path)
+        .context("Could not move output file into place")?;
+    Ok(())
+}
+
+/// Format a number of bytes with a binary unit, e.g. `1.5 GiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
 }
 
 #[cfg(test)]
 mod tests {
+    use flox_rust_sdk::flox::Floxhub;
 
     use super::*;
 
     #[test]
     fn test_quote_run_args() {
         assert_eq!(
-            Activate::quote_run_args(&["a b".to_string(), '"'.to_string()]),
+            Activate::quote_run_args(&ShellType::Bash("bash".into()), &[
+                "a b".to_string(),
+                '"'.to_string()
+            ]),
             r#""a b" "\"""#
         )
     }
+
+    #[test]
+    fn test_quote_run_args_nu() {
+        assert_eq!(
+            Activate::quote_run_args(&ShellType::Nu("nu".into()), &[
+                "print".to_string(),
+                "a b".to_string(),
+                '"'.to_string(),
+                r"C:\".to_string(),
+                "$env.HOME".to_string(),
+                "$".to_string(),
+                "".to_string(),
+            ]),
+            r#"print "a b" "\"" "C:\\" $env.HOME "$" """#
+        )
+    }
+
+    #[test]
+    fn test_shell_from_exe_and_name() {
+        let shell = ShellType::from_exe_and_name("/bin/bash".into(), "bash");
+        assert!(matches!(shell, Some(ShellType::Bash(path)) if path == Path::new("/bin/bash")));
+
+        // the process name is used if the executable is not named like the shell
+        let shell = ShellType::from_exe_and_name("/usr/bin/zsh5".into(), "-zsh");
+        assert!(matches!(shell, Some(ShellType::Zsh(path)) if path == Path::new("/usr/bin/zsh5")));
+
+        let shell = ShellType::from_exe_and_name("/usr/bin/make".into(), "make");
+        assert!(shell.is_none());
+    }
+
+    #[test]
+    fn test_render_env() {
+        let activated_env = HashMap::from([
+            ("PATH".to_string(), "/flox/bin:/usr/bin".to_string()),
+            ("FOO".to_string(), "foo bar".to_string()),
+        ]);
+
+        assert_eq!(
+            Activate::render_env(activated_env.clone(), false).unwrap(),
+            "FOO='foo bar'\nPATH='/flox/bin:/usr/bin'"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&Activate::render_env(activated_env, true).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"FOO": "foo bar", "PATH": "/flox/bin:/usr/bin"})
+        );
+    }
+
+    #[test]
+    fn test_render_nu_env() {
+        let activated_env = HashMap::from([
+            ("PATH".to_string(), "/flox/env/bin:/usr/bin".to_string()),
+            ("FOO".to_string(), "bar".to_string()),
+            ("UNCHANGED".to_string(), "value".to_string()),
+        ]);
+        let current_env = HashMap::from([("UNCHANGED".to_string(), "value".to_string())]);
+
+        assert_eq!(
+            Activate::render_nu_env(activated_env, current_env).unwrap(),
+            r#"{"hide":[],"load":{"FOO":"bar","PATH":["/flox/env/bin","/usr/bin"]}}"#
+        )
+    }
+
+    #[test]
+    fn test_render_nu_env_hides_unset_variables() {
+        let activated_env = HashMap::from([("FOO".to_string(), "bar".to_string())]);
+        let current_env = HashMap::from([
+            ("FOO".to_string(), "bar".to_string()),
+            ("UNSET_2".to_string(), "value".to_string()),
+            ("UNSET_1".to_string(), "value".to_string()),
+            ("NOT-AN-IDENTIFIER".to_string(), "value".to_string()),
+        ]);
+
+        assert_eq!(
+            Activate::render_nu_env(activated_env, current_env).unwrap(),
+            r#"{"hide":["UNSET_1","UNSET_2"],"load":{}}"#
+        )
+    }
+
+    const PATH: &str =
+        "/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin:/flox/env/bin:/nix/store/some/bin";
+
+    #[test]
+    fn test_fixup_path() {
+        let flox_env_dirs = IndexSet::from(["/flox/env"].map(PathBuf::from));
+        let fixed_up_path = Activate::fixup_path_with(PATH, &flox_env_dirs);
+        let joined = env::join_paths(fixed_up_path).unwrap();
+
+        assert_eq!(
+            joined.to_string_lossy(),
+            "/flox/env/bin:/nix/store/some/bin:/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin",
+            "PATH was not reordered correctly"
+        );
+    }
+
+    fn record(before: &[(&str, Option<&str>)], after: &[(&str, Option<&str>)]) -> RestoreRecord {
+        let to_map = |vars: &[(&str, Option<&str>)]| {
+            vars.iter()
+                .map(|(var, value)| (var.to_string(), value.map(String::from)))
+                .collect()
+        };
+        RestoreRecord {
+            environment: UninitializedEnvironment::Remote(ManagedPointer::new(
+                "owner".parse().unwrap(),
+                "name".parse().unwrap(),
+                &Floxhub::new(Url::parse("https://hub.flox.dev").unwrap(), None).unwrap(),
+            )),
+            before: to_map(before),
+            after: to_map(after),
+        }
+    }
+
+    #[test]
+    fn test_record_script_only_records_changes() {
+        let shell = ShellType::Bash(PathBuf::from(BASH_BIN));
+        let environment = record(&[], &[]).environment;
+        let script = formatdoc! {r#"
+            {snapshot}
+            export PATH="/flox/bin:$PATH"
+            export FOO='bar "baz"'
+            unset REMOVED
+            {record}
+            echo "$_FLOX_RESTORE_RECORDS"
+            "#,
+            snapshot = RestoreRecord::snapshot_script(&shell),
+            record = RestoreRecord::record_script(&shell, &environment).unwrap(),
+        };
+
+        let output = Command::new(BASH_BIN)
+            .args(["--norc", "--noprofile", "-c", &script])
+            .env_clear()
+            .env("PATH", "/usr/bin")
+            .env("HOME", "/home/user")
+            .env("REMOVED", "value")
+            .env(FLOX_RESTORE_RECORDS_VAR, "[]")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let records: RestoreRecords =
+            serde_json::from_slice(&output.stdout).expect("records should be valid JSON");
+        assert_eq!(Vec::from(records.0), vec![self::record(
+            &[
+                ("FOO", None),
+                ("PATH", Some("/usr/bin")),
+                ("REMOVED", Some("value"))
+            ],
+            &[
+                ("FOO", Some(r#"bar "baz""#)),
+                ("PATH", Some("/flox/bin:/usr/bin")),
+                ("REMOVED", None)
+            ],
+        )]);
+    }
+
+    #[test]
+    fn test_restore_unchanged_variables() {
+        let record = record(&[("FOO", None), ("PATH", Some("/usr/bin"))], &[
+            ("FOO", Some("bar")),
+            ("PATH", Some("/flox/bin:/usr/bin")),
+        ]);
+
+        let changes = record.restore(&HashMap::from([
+            ("FOO".to_string(), "bar".to_string()),
+            ("PATH".to_string(), "/flox/bin:/usr/bin".to_string()),
+        ]));
+
+        assert_eq!(
+            changes,
+            BTreeMap::from([
+                ("FOO".to_string(), None),
+                ("PATH".to_string(), Some("/usr/bin".to_string()))
+            ])
+        );
+    }
+
+    #[test]
+    fn test_restore_removes_added_entries_from_changed_lists() {
+        let record = record(
+            &[
+                ("PATH", Some("/usr/bin")),
+                ("FLOX_PROMPT_ENVIRONMENTS", None),
+            ],
+            &[
+                ("PATH", Some("/flox/bin:/usr/bin")),
+                ("FLOX_PROMPT_ENVIRONMENTS", Some("env1")),
+            ],
+        );
+
+        // another environment has been activated since
+        let changes = record.restore(&HashMap::from([
+            (
+                "PATH".to_string(),
+                "/other/bin:/flox/bin:/usr/bin".to_string(),
+            ),
+            (
+                "FLOX_PROMPT_ENVIRONMENTS".to_string(),
+                "env2 env1".to_string(),
+            ),
+        ]));
+
+        assert_eq!(
+            changes,
+            BTreeMap::from([
+                (
+                    "FLOX_PROMPT_ENVIRONMENTS".to_string(),
+                    Some("env2".to_string())
+                ),
+                ("PATH".to_string(), Some("/other/bin:/usr/bin".to_string()))
+            ])
+        );
+    }
+
+    #[test]
+    fn test_restore_removes_prompt_entry_containing_spaces() {
+        let lower = record(&[("FLOX_PROMPT_ENVIRONMENTS", Some("base env"))], &[(
+            "FLOX_PROMPT_ENVIRONMENTS",
+            Some("env1 by owner base env"),
+        )]);
+        let mut upper = record(
+            &[("FLOX_PROMPT_ENVIRONMENTS", Some("env1 by owner base env"))],
+            &[(
+                "FLOX_PROMPT_ENVIRONMENTS",
+                Some("env2 by owner env1 by owner base env"),
+            )],
+        );
+
+        // the upper environment is still active
+        let changes = lower.restore(&HashMap::from([(
+            "FLOX_PROMPT_ENVIRONMENTS".to_string(),
+            "env2 by owner env1 by owner base env".to_string(),
+        )]));
+        assert_eq!(
+            changes,
+            BTreeMap::from([(
+                "FLOX_PROMPT_ENVIRONMENTS".to_string(),
+                Some("env2 by owner base env".to_string())
+            )])
+        );
+
+        // the upper environment is deactivated after the lower one
+        lower.rebase(&mut upper);
+        let changes = upper.restore(&HashMap::from([(
+            "FLOX_PROMPT_ENVIRONMENTS".to_string(),
+            "env2 by owner base env".to_string(),
+        )]));
+        assert_eq!(
+            changes,
+            BTreeMap::from([(
+                "FLOX_PROMPT_ENVIRONMENTS".to_string(),
+                Some("base env".to_string())
+            )])
+        );
+
+        assert_eq!(
+            RestoreRecord::remove_prompt_entry("env1 by owner", "env1 by owner"),
+            ""
+        );
+        assert_eq!(
+            RestoreRecord::remove_prompt_entry("env10 env1", "env1"),
+            "env10"
+        );
+    }
+
+    #[test]
+    fn test_restore_removes_activation() {
+        let environment = record(&[], &[]).environment;
+        let activation = |environment: UninitializedEnvironment| Activation {
+            environment,
+            store_path: None,
+            lockfile_hash: None,
+            generation: None,
+        };
+        let other = UninitializedEnvironment::DotFlox(DotFlox {
+            path: PathBuf::from("/project"),
+            pointer: EnvironmentPointer::Path(PathPointer::new("other".parse().unwrap())),
+        });
+
+        let mut after = Activations::default();
+        after.set_last_active(activation(environment));
+        let record = record(&[(FLOX_ACTIVATIONS_VAR, None)], &[(
+            FLOX_ACTIVATIONS_VAR,
+            Some(&after.to_string()),
+        )]);
+
+        // another environment has been activated since
+        let mut current = after.clone();
+        current.set_last_active(activation(other.clone()));
+        let changes = record.restore(&HashMap::from([(
+            FLOX_ACTIVATIONS_VAR.to_string(),
+            current.to_string(),
+        )]));
+
+        let mut expected = Activations::default();
+        expected.set_last_active(activation(other));
+        assert_eq!(
+            changes,
+            BTreeMap::from([(FLOX_ACTIVATIONS_VAR.to_string(), Some(expected.to_string()))])
+        );
+    }
+
+    #[test]
+    fn test_rebase_later_records() {
+        let first = record(&[("PATH", Some("/usr/bin"))], &[(
+            "PATH",
+            Some("/env1/bin:/usr/bin"),
+        )]);
+        let mut second = record(&[("PATH", Some("/env1/bin:/usr/bin"))], &[(
+            "PATH",
+            Some("/env2/bin:/env1/bin:/usr/bin"),
+        )]);
+
+        first.rebase(&mut second);
+
+        assert_eq!(
+            second.before,
+            BTreeMap::from([("PATH".to_string(), Some("/usr/bin".to_string()))])
+        );
+    }
 }
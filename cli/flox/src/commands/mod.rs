@@ -2,7 +2,11 @@ mod auth;
 mod environment;
 mod general;
 mod init;
+mod prompt;
 mod search;
+mod serve;
+mod services;
+mod watch;
 
 use std::collections::VecDeque;
 use std::fmt::Display;
@@ -13,6 +17,7 @@ use std::{env, fmt, fs, mem};
 use anyhow::{bail, Context, Result};
 use bpaf::{Args, Bpaf, ParseFailure, Parser};
 use flox_rust_sdk::flox::{
+    EnvironmentOwner,
     EnvironmentRef,
     Flox,
     Floxhub,
@@ -78,7 +83,7 @@ static FLOX_WELCOME_MESSAGE: Lazy<String> = Lazy::new(|| {
 });
 
 const ADDITIONAL_COMMANDS: &str = indoc! {"
-    update, upgrade, config, auth
+    update, upgrade, config, auth, direnv, services, serve, watch, prompt
 "};
 
 fn vec_len<T>(x: Vec<T>) -> usize {
@@ -241,7 +246,8 @@ impl FloxArgs {
                 .clone()
                 .unwrap_or_else(|| DEFAULT_FLOXHUB_URL.clone()),
             git_url_override,
-        )?;
+        )?
+        .with_owner_git_urls(config.flox.floxmeta_remotes.clone());
 
         let floxhub_token = config
             .flox
@@ -372,6 +378,12 @@ enum LocalDevelopmentCommands {
         footer("Run 'man flox-activate' for more details.")
     )]
     Activate(#[bpaf(external(environment::activate))] environment::Activate),
+    /// Leave an environment activated in the current shell
+    #[bpaf(command, footer("Run 'man flox-deactivate' for more details."))]
+    Deactivate(#[bpaf(external(environment::deactivate))] environment::Deactivate),
+    /// Show the environments that are active in the current shell
+    #[bpaf(command, footer("Run 'man flox-status' for more details."))]
+    Status(#[bpaf(external(environment::status))] environment::Status),
     /// Search for system or library packages to install
     #[bpaf(command, footer("Run 'man flox-search' for more details."))]
     Search(#[bpaf(external(search::search))] search::Search),
@@ -413,6 +425,8 @@ impl LocalDevelopmentCommands {
         match self {
             LocalDevelopmentCommands::Init(args) => args.handle(flox).await?,
             LocalDevelopmentCommands::Activate(args) => args.handle(config, flox).await?,
+            LocalDevelopmentCommands::Deactivate(args) => args.handle(flox).await?,
+            LocalDevelopmentCommands::Status(args) => args.handle(flox).await?,
             LocalDevelopmentCommands::Edit(args) => args.handle(flox).await?,
             LocalDevelopmentCommands::Install(args) => args.handle(flox).await?,
             LocalDevelopmentCommands::Uninstall(args) => args.handle(flox).await?,
@@ -434,6 +448,9 @@ enum SharingCommands {
     /// Pull an environment from FloxHub
     #[bpaf(command, footer("Run 'man flox-pull' for more details."))]
     Pull(#[bpaf(external(environment::pull))] environment::Pull),
+    /// Fork an environment of another owner into your own namespace
+    #[bpaf(command, footer("Run 'man flox-fork' for more details."))]
+    Fork(#[bpaf(external(environment::fork))] environment::Fork),
     /// Containerize an environment
     #[bpaf(
         command,
@@ -444,11 +461,12 @@ enum SharingCommands {
     Containerize(#[bpaf(external(environment::containerize))] environment::Containerize),
 }
 impl SharingCommands {
-    async fn handle(self, _config: Config, flox: Flox) -> Result<()> {
+    async fn handle(self, config: Config, flox: Flox) -> Result<()> {
         match self {
             SharingCommands::Push(args) => args.handle(flox).await?,
             SharingCommands::Pull(args) => args.handle(flox).await?,
-            SharingCommands::Containerize(args) => args.handle(flox).await?,
+            SharingCommands::Fork(args) => args.handle(flox).await?,
+            SharingCommands::Containerize(args) => args.handle(config, flox).await?,
         }
         Ok(())
     }
@@ -481,6 +499,21 @@ enum AdditionalCommands {
     /// View and set configuration options
     #[bpaf(command, hide, footer("Run 'man flox-config' for more details."))]
     Config(#[bpaf(external(general::config_args))] general::ConfigArgs),
+    /// Print a script for direnv that activates an environment
+    #[bpaf(command, hide, footer("Run 'man flox-direnv' for more details."))]
+    Direnv(#[bpaf(external(environment::direnv))] environment::Direnv),
+    /// Run the services of an environment in the background
+    #[bpaf(command, hide, footer("Run 'man flox-services' for more details."))]
+    Services(#[bpaf(external(services::services))] services::Services),
+    /// Serve a JSON-RPC API for environment operations over a unix socket
+    #[bpaf(command, hide, footer("Run 'man flox-serve' for more details."))]
+    Serve(#[bpaf(external(serve::serve))] serve::Serve),
+    /// Lock and build environments in the background whenever they change
+    #[bpaf(command, hide, footer("Run 'man flox-watch' for more details."))]
+    Watch(#[bpaf(external(watch::watch))] watch::Watch),
+    /// Print the active environments, e.g. for custom shell prompts
+    #[bpaf(command, hide, footer("Run 'man flox-prompt' for more details."))]
+    Prompt(#[bpaf(external(prompt::prompt))] prompt::Prompt),
     /// Delete builds of non-current versions of an environment
     #[bpaf(command("wipe-history"), hide)]
     WipeHistory(#[bpaf(external(environment::wipe_history))] environment::WipeHistory),
@@ -502,6 +535,11 @@ impl AdditionalCommands {
             AdditionalCommands::Update(args) => args.handle(flox).await?,
             AdditionalCommands::Upgrade(args) => args.handle(flox).await?,
             AdditionalCommands::Config(args) => args.handle(config, flox).await?,
+            AdditionalCommands::Direnv(args) => args.handle(config, flox).await?,
+            AdditionalCommands::Services(args) => args.handle(flox).await?,
+            AdditionalCommands::Serve(args) => args.handle(flox).await?,
+            AdditionalCommands::Watch(args) => args.handle(flox).await?,
+            AdditionalCommands::Prompt(args) => args.handle(config, flox).await?,
             AdditionalCommands::WipeHistory(args) => args.handle(flox).await?,
             AdditionalCommands::History(args) => args.handle(flox).await?,
         }
@@ -955,6 +993,11 @@ impl ActiveEnvironments {
     pub fn is_active(&self, env: &UninitializedEnvironment) -> bool {
         self.0.contains(env)
     }
+
+    /// Remove an environment from the list of active environments
+    pub fn remove(&mut self, env: &UninitializedEnvironment) {
+        self.0.retain(|active| active != env);
+    }
 }
 
 impl Display for ActiveEnvironments {
@@ -1175,6 +1218,21 @@ pub(super) async fn ensure_floxhub_token(flox: &mut Flox) -> Result<()> {
     Ok(())
 }
 
+/// Ensure a floxhub_token is present,
+/// unless the environments of `owner` are stored in a self-hosted git remote
+///
+/// Self-hosted remotes are accessed with the user's git credentials instead.
+pub(super) async fn ensure_floxhub_token_for(
+    flox: &mut Flox,
+    owner: &EnvironmentOwner,
+) -> Result<()> {
+    if flox.floxhub.is_self_hosted(owner) {
+        log::debug!("environments of {owner} are self-hosted; not requiring a floxhub token");
+        return Ok(());
+    }
+    ensure_floxhub_token(flox).await
+}
+
 pub fn environment_description(environment: &ConcreteEnvironment) -> Result<String> {
     Ok(UninitializedEnvironment::from_concrete_environment(environment)?.to_string())
 }
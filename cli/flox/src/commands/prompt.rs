@@ -0,0 +1,223 @@
+use anyhow::Result;
+use bpaf::Bpaf;
+use flox_rust_sdk::flox::Flox;
+use flox_rust_sdk::models::environment::managed_environment::ManagedEnvironment;
+use flox_rust_sdk::models::environment::{Environment, EnvironmentPointer};
+use itertools::Itertools;
+use log::debug;
+
+use super::{activated_environments, ConcreteEnvironment, UninitializedEnvironment};
+use crate::config::Config;
+
+/// Format of environments in the shell prompt,
+/// unless set with the `prompt_format` config option
+pub const DEFAULT_PROMPT_FORMAT: &str = "({owner}/){name}";
+
+/// Set to `false` in activated environments to leave the shell prompt alone,
+/// doubles as an override of the `set_prompt` config option
+pub const FLOX_SET_PROMPT_VAR: &str = "FLOX_SET_PROMPT";
+
+/// Print the active environments, e.g. for custom shell prompts
+#[derive(Bpaf, Clone)]
+pub struct Prompt {
+    /// Format of each environment (default: the 'prompt_format' config option)
+    #[bpaf(long, argument("format"))]
+    format: Option<String>,
+}
+
+impl Prompt {
+    pub async fn handle(self, config: Config, flox: Flox) -> Result<()> {
+        // No metric is recorded, since this typically runs for every prompt
+
+        let format = self
+            .format
+            .or(config.flox.prompt_format)
+            .unwrap_or(DEFAULT_PROMPT_FORMAT.to_string());
+
+        let environments = activated_environments()
+            .into_iter()
+            .map(|environment| {
+                PromptEnvironment::from_uninitialized_environment(&flox, environment, &format)
+                    .render(&format)
+            })
+            .join(" ");
+
+        if !environments.is_empty() {
+            println!("{environments}");
+        }
+        Ok(())
+    }
+}
+
+/// An environment as shown in the shell prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptEnvironment {
+    name: String,
+    /// Owner of environments from FloxHub
+    owner: Option<String>,
+    /// Current generation of environments pulled from FloxHub into a directory
+    generation: Option<String>,
+}
+
+impl PromptEnvironment {
+    /// Collect the placeholders of `format` for an environment
+    pub fn from_concrete_environment(environment: &ConcreteEnvironment, format: &str) -> Self {
+        let with_generation = format.contains("{generation}");
+        match environment {
+            ConcreteEnvironment::Path(path) => Self {
+                name: path.name().to_string(),
+                owner: None,
+                generation: None,
+            },
+            ConcreteEnvironment::Managed(managed) => Self {
+                name: managed.name().to_string(),
+                owner: Some(managed.owner().to_string()),
+                generation: with_generation.then(|| Self::generation(managed)).flatten(),
+            },
+            ConcreteEnvironment::Remote(remote) => Self {
+                name: remote.name().to_string(),
+                owner: Some(remote.owner().to_string()),
+                generation: None,
+            },
+        }
+    }
+
+    /// Collect the placeholders of `format` for an active environment
+    ///
+    /// Environments are only opened if `format` shows their generation.
+    fn from_uninitialized_environment(
+        flox: &Flox,
+        environment: UninitializedEnvironment,
+        format: &str,
+    ) -> Self {
+        match environment.pointer() {
+            EnvironmentPointer::Managed(pointer) if format.contains("{generation}") => {
+                let name = pointer.name.to_string();
+                let owner = pointer.owner.to_string();
+                match environment.into_concrete_environment(flox) {
+                    Ok(concrete) => Self::from_concrete_environment(&concrete, format),
+                    Err(e) => {
+                        debug!("could not open environment {name} for the prompt: {e}");
+                        Self {
+                            name,
+                            owner: Some(owner),
+                            generation: None,
+                        }
+                    },
+                }
+            },
+            EnvironmentPointer::Managed(pointer) => Self {
+                name: pointer.name.to_string(),
+                owner: Some(pointer.owner.to_string()),
+                generation: None,
+            },
+            EnvironmentPointer::Path(pointer) => Self {
+                name: pointer.name.to_string(),
+                owner: None,
+                generation: None,
+            },
+        }
+    }
+
+    fn generation(environment: &ManagedEnvironment) -> Option<String> {
+        match environment.current_generation() {
+            Ok(generation) => generation.map(|generation| generation.to_string()),
+            Err(e) => {
+                debug!("could not read generation for the prompt: {e}");
+                None
+            },
+        }
+    }
+
+    /// Render the environment with a prompt format
+    ///
+    /// `{name}`, `{owner}` and `{generation}` are replaced with their values.
+    /// Text in parentheses is only shown if all placeholders in it have a value,
+    /// e.g. `({owner}/){name}` shows the owner only for environments that have one.
+    pub fn render(&self, format: &str) -> String {
+        let mut rendered = String::new();
+        // Text of the current group and whether all of its placeholders have a value
+        let mut group: Option<(String, bool)> = None;
+
+        let mut rest = format;
+        while let Some(c) = rest.chars().next() {
+            // A literal character or the value of a placeholder
+            let placeholder = |name: &str| rest[1..].strip_prefix(name)?.strip_prefix('}');
+            let (piece, len) = match c {
+                '(' if group.is_none() => {
+                    group = Some((String::new(), true));
+                    rest = &rest[1..];
+                    continue;
+                },
+                ')' if group.is_some() => {
+                    if let Some((text, true)) = group.take() {
+                        rendered.push_str(&text);
+                    }
+                    rest = &rest[1..];
+                    continue;
+                },
+                '{' if placeholder("name").is_some() => (Some(self.name.as_str()), "{name}".len()),
+                '{' if placeholder("owner").is_some() => (self.owner.as_deref(), "{owner}".len()),
+                '{' if placeholder("generation").is_some() => {
+                    (self.generation.as_deref(), "{generation}".len())
+                },
+                c => (Some(&rest[..c.len_utf8()]), c.len_utf8()),
+            };
+            rest = &rest[len..];
+
+            match (&mut group, piece) {
+                (Some((text, _)), Some(piece)) => text.push_str(piece),
+                (Some((_, complete)), None) => *complete = false,
+                (None, piece) => rendered.push_str(piece.unwrap_or_default()),
+            }
+        }
+
+        // An unclosed group extends to the end of the format
+        if let Some((text, true)) = group {
+            rendered.push_str(&text);
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment(owner: Option<&str>, generation: Option<&str>) -> PromptEnvironment {
+        PromptEnvironment {
+            name: "env".to_string(),
+            owner: owner.map(String::from),
+            generation: generation.map(String::from),
+        }
+    }
+
+    #[test]
+    fn render_default_format() {
+        assert_eq!(environment(None, None).render(DEFAULT_PROMPT_FORMAT), "env");
+        assert_eq!(
+            environment(Some("owner"), Some("1")).render(DEFAULT_PROMPT_FORMAT),
+            "owner/env"
+        );
+    }
+
+    #[test]
+    fn render_skips_groups_without_values() {
+        let format = "{name}( by {owner})(@{generation})";
+        assert_eq!(environment(None, None).render(format), "env");
+        assert_eq!(
+            environment(Some("owner"), None).render(format),
+            "env by owner"
+        );
+        assert_eq!(
+            environment(Some("owner"), Some("2")).render(format),
+            "env by owner@2"
+        );
+    }
+
+    #[test]
+    fn render_keeps_unknown_placeholders() {
+        assert_eq!(environment(None, None).render("{nom} {name}"), "{nom} env");
+        assert_eq!(environment(None, None).render("{name"), "{name");
+    }
+}
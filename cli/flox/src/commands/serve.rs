@@ -0,0 +1,545 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::{fs, thread};
+
+use anyhow::{bail, Context, Result};
+use bpaf::Bpaf;
+use flox_rust_sdk::flox::Flox;
+use flox_rust_sdk::models::environment::{
+    CanonicalPath,
+    EditResult,
+    Environment,
+    EnvironmentError2,
+};
+use flox_rust_sdk::models::lockfile::{LockedManifest, TypedLockedManifest};
+use flox_rust_sdk::models::manifest::PackageToInstall;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::services::activated_env;
+use super::{ConcreteEnvironment, EnvironmentSelect};
+use crate::subcommand_metric;
+use crate::utils::errors::format_error;
+use crate::utils::message;
+
+/// Name of the default socket `flox serve` listens on, in the flox cache directory
+const SOCKET_NAME: &str = "api.sock";
+
+const JSONRPC_VERSION: &str = "2.0";
+
+/// Error codes defined by JSON-RPC 2.0
+mod error_codes {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// An environment operation failed
+    pub const OPERATION_FAILED: i64 = -32000;
+}
+
+/// Serve a JSON-RPC API for environment operations over a unix socket
+#[derive(Bpaf, Clone, Debug)]
+pub struct Serve {
+    /// Socket to listen on
+    /// (default: 'api.sock' in the flox cache directory)
+    #[bpaf(long, argument("path"))]
+    socket: Option<PathBuf>,
+}
+
+impl Serve {
+    pub async fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("serve");
+
+        let socket = self
+            .socket
+            .unwrap_or_else(|| flox.cache_dir.join(SOCKET_NAME));
+        let listener = bind(&socket)?;
+        message::plain(format!("Listening on {}", socket.display()));
+
+        let flox = Arc::new(flox);
+        // Operations modify environments on disk, so only one runs at a time
+        let operations = Arc::new(Mutex::new(()));
+
+        // Accepting connections blocks, so keep it off the async runtime
+        tokio::task::spawn_blocking(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("could not accept connection: {e}");
+                        continue;
+                    },
+                };
+                let flox = Arc::clone(&flox);
+                let operations = Arc::clone(&operations);
+                thread::spawn(move || {
+                    if let Err(e) = serve_connection(stream, &flox, &operations) {
+                        debug!("connection failed: {e:#}");
+                    }
+                });
+            }
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Bind the socket, replacing a stale socket left behind by a previous server
+fn bind(socket: &Path) -> Result<UnixListener> {
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent).context("Could not create socket directory")?;
+    }
+
+    match UnixListener::bind(socket) {
+        Ok(listener) => Ok(listener),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            if UnixStream::connect(socket).is_ok() {
+                bail!("Another server is listening on {}", socket.display());
+            }
+            fs::remove_file(socket).context("Could not remove stale socket")?;
+            UnixListener::bind(socket).context("Could not bind socket")
+        },
+        Err(e) => Err(e).context("Could not bind socket"),
+    }
+}
+
+/// A JSON-RPC request or notification
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    /// Requests without an id are notifications and aren't answered
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// The methods served, with their parameters
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "kebab-case")]
+enum Method {
+    /// List the packages installed for the current system
+    List(EnvironmentParams),
+    /// Get the contents of the manifest
+    Manifest(EnvironmentParams),
+    Install(PackagesParams),
+    Uninstall(PackagesParams),
+    /// Replace the contents of the manifest
+    Edit(EditParams),
+    /// Lock the environment and get the lockfile
+    Lock(EnvironmentParams),
+    /// Build the environment and get its store path
+    Build(EnvironmentParams),
+    /// Get the variables set by activating the environment
+    ActivationEnv(EnvironmentParams),
+}
+
+impl Method {
+    const NAMES: [&'static str; 8] = [
+        "list",
+        "manifest",
+        "install",
+        "uninstall",
+        "edit",
+        "lock",
+        "build",
+        "activation-env",
+    ];
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentParams {
+    /// Directory containing the environment's `.flox` directory
+    dir: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PackagesParams {
+    dir: PathBuf,
+    packages: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EditParams {
+    dir: PathBuf,
+    contents: String,
+}
+
+/// Answer the requests sent over a connection until it is closed
+///
+/// Requests on the same connection are answered in order.
+fn serve_connection(stream: UnixStream, flox: &Flox, operations: &Mutex<()>) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = RpcError::new(error_codes::PARSE_ERROR, e.to_string());
+                write_line(&mut writer, &error_response(Value::Null, error))?;
+                continue;
+            },
+        };
+
+        let request = match serde_json::from_value::<RpcRequest>(request) {
+            Ok(request) if request.jsonrpc == JSONRPC_VERSION => request,
+            Ok(_) => {
+                let error = RpcError::new(error_codes::INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+                write_line(&mut writer, &error_response(Value::Null, error))?;
+                continue;
+            },
+            Err(e) => {
+                let error = RpcError::new(error_codes::INVALID_REQUEST, e.to_string());
+                write_line(&mut writer, &error_response(Value::Null, error))?;
+                continue;
+            },
+        };
+
+        debug!("serving {}", request.method);
+        let id = request.id.clone();
+        let mut notify_error = None;
+        let result = {
+            // The lock only serializes operations and guards no data,
+            // so it remains usable after an operation of another connection panicked
+            let _operation = operations.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut progress = |message: &str| {
+                let notification = json!({
+                    "jsonrpc": JSONRPC_VERSION,
+                    "method": "progress",
+                    "params": { "id": id, "message": message },
+                });
+                if let Err(e) = write_line(&mut writer, &notification) {
+                    notify_error.get_or_insert(e);
+                }
+            };
+            call(flox, request.method, request.params, &mut progress)
+        };
+        if let Some(e) = notify_error {
+            return Err(e);
+        }
+
+        // Notifications aren't answered
+        let Some(id) = request.id else {
+            continue;
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": JSONRPC_VERSION, "id": id, "result": result }),
+            Err(error) => error_response(id, error),
+        };
+        write_line(&mut writer, &response)?;
+    }
+
+    Ok(())
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": JSONRPC_VERSION, "id": id, "error": error })
+}
+
+fn write_line(writer: &mut impl Write, message: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parse the parameters of a method and run it
+fn call(
+    flox: &Flox,
+    method: String,
+    params: Value,
+    progress: &mut dyn FnMut(&str),
+) -> Result<Value, RpcError> {
+    let method = parse(method, params)?;
+
+    run(flox, method, progress).map_err(|e| {
+        let message = match e.downcast_ref::<EnvironmentError2>() {
+            Some(e) => format_error(e),
+            None => e
+                .chain()
+                .skip(1)
+                .fold(e.to_string(), |acc, cause| format!("{acc}: {cause}")),
+        };
+        RpcError::new(error_codes::OPERATION_FAILED, message.trim())
+    })
+}
+
+fn parse(method: String, params: Value) -> Result<Method, RpcError> {
+    if !Method::NAMES.contains(&method.as_str()) {
+        return Err(RpcError::new(
+            error_codes::METHOD_NOT_FOUND,
+            format!("unknown method '{method}'"),
+        ));
+    }
+
+    serde_json::from_value(json!({ "method": method, "params": params }))
+        .map_err(|e| RpcError::new(error_codes::INVALID_PARAMS, e.to_string()))
+}
+
+fn run(flox: &Flox, method: Method, progress: &mut dyn FnMut(&str)) -> Result<Value> {
+    let result = match method {
+        Method::List(EnvironmentParams { dir }) => {
+            let mut environment = open(flox, dir)?;
+            let lockfile_path = environment.lockfile_path(flox)?;
+            let lockfile = if lockfile_path.exists() {
+                LockedManifest::read_from_file(&CanonicalPath::new(lockfile_path)?)?
+            } else {
+                progress("Locking environment");
+                environment.lock(flox)?
+            };
+            let lockfile: TypedLockedManifest = lockfile.try_into()?;
+
+            let packages = lockfile
+                .list_packages(&flox.system)
+                .into_iter()
+                .map(|package| {
+                    json!({
+                        "id": package.name,
+                        "path": package.rel_path,
+                        "version": package.info.version,
+                        "priority": package.priority,
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({ "packages": packages })
+        },
+        Method::Manifest(EnvironmentParams { dir }) => {
+            let environment = open(flox, dir)?;
+            json!({ "contents": environment.manifest_content(flox)? })
+        },
+        Method::Install(PackagesParams { dir, packages }) => {
+            let mut environment = open(flox, dir)?;
+            let packages = packages
+                .iter()
+                .map(|package| PackageToInstall::from_str(package))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            progress("Installing packages");
+            let attempt = environment.install(&packages, flox)?;
+            let (already_installed, installed): (BTreeMap<_, _>, BTreeMap<_, _>) = attempt
+                .already_installed
+                .into_iter()
+                .partition(|(_, already_installed)| *already_installed);
+            json!({
+                "installed": installed.into_keys().collect::<Vec<_>>(),
+                "already-installed": already_installed.into_keys().collect::<Vec<_>>(),
+                "store-path": attempt.store_path,
+            })
+        },
+        Method::Uninstall(PackagesParams { dir, packages }) => {
+            let mut environment = open(flox, dir)?;
+
+            progress("Uninstalling packages");
+            let attempt = environment.uninstall(packages, flox)?;
+            json!({ "store-path": attempt.store_path })
+        },
+        Method::Edit(EditParams { dir, contents }) => {
+            let mut environment = open(flox, dir)?;
+
+            progress("Building environment to validate edit");
+            let (result, store_path) = match environment.edit(flox, contents)? {
+                EditResult::Unchanged => ("unchanged", None),
+                EditResult::Success { store_path } => ("success", store_path),
+                EditResult::ReActivateRequired { store_path } => {
+                    ("reactivate-required", store_path)
+                },
+            };
+            json!({ "result": result, "store-path": store_path })
+        },
+        Method::Lock(EnvironmentParams { dir }) => {
+            let mut environment = open(flox, dir)?;
+
+            progress("Locking environment");
+            serde_json::to_value(environment.lock(flox)?)?
+        },
+        Method::Build(EnvironmentParams { dir }) => {
+            let mut environment = open(flox, dir)?;
+
+            progress("Building environment");
+            let activation_path = environment.activation_path(flox)?;
+            let store_path = fs::canonicalize(&activation_path).unwrap_or(activation_path);
+            json!({ "store-path": store_path })
+        },
+        Method::ActivationEnv(EnvironmentParams { dir }) => {
+            let environment = EnvironmentSelect::Dir(dir).to_concrete_environment(flox)?;
+
+            progress("Activating environment");
+            json!({ "env": activated_env(&environment)? })
+        },
+    };
+    Ok(result)
+}
+
+/// Open a path or managed environment
+fn open(flox: &Flox, dir: PathBuf) -> Result<Box<dyn Environment>> {
+    let environment = EnvironmentSelect::Dir(dir).to_concrete_environment(flox)?;
+    Ok(match environment {
+        ConcreteEnvironment::Path(environment) => Box::new(environment),
+        ConcreteEnvironment::Managed(environment) => Box::new(environment),
+        ConcreteEnvironment::Remote(_) => unreachable!("environments are opened by path"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Shutdown;
+
+    use flox_rust_sdk::flox::test_flox_instance;
+    use flox_rust_sdk::models::environment::path_environment::{
+        InitCustomization,
+        PathEnvironment,
+    };
+    use flox_rust_sdk::models::environment::PathPointer;
+    use flox_rust_sdk::models::environment_ref::EnvironmentName;
+
+    use super::*;
+
+    /// Send `requests` over a socket pair served by [serve_connection]
+    /// and collect everything written back until the server is done
+    fn exchange(flox: &Flox, requests: &[String]) -> Vec<Value> {
+        let (client, server) = UnixStream::pair().unwrap();
+        let operations = Mutex::new(());
+
+        thread::scope(|scope| {
+            let served = scope.spawn(|| serve_connection(server, flox, &operations));
+
+            let mut writer = &client;
+            for request in requests {
+                writeln!(writer, "{request}").unwrap();
+            }
+            client.shutdown(Shutdown::Write).unwrap();
+
+            let responses = BufReader::new(&client)
+                .lines()
+                .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+                .collect();
+            served.join().unwrap().unwrap();
+            responses
+        })
+    }
+
+    fn init_environment(flox: &Flox) -> PathBuf {
+        let dir = flox.temp_dir.join("project");
+        fs::create_dir_all(&dir).unwrap();
+        PathEnvironment::init(
+            PathPointer::new(EnvironmentName::from_str("test").unwrap()),
+            &dir,
+            &flox.temp_dir,
+            &flox.system,
+            &InitCustomization::default(),
+            flox,
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parses_methods() {
+        let error = parse("delete".to_string(), json!({})).unwrap_err();
+        assert_eq!(error.code, error_codes::METHOD_NOT_FOUND);
+
+        let error = parse("install".to_string(), json!({ "dir": "/project" })).unwrap_err();
+        assert_eq!(error.code, error_codes::INVALID_PARAMS);
+
+        let method = parse("activation-env".to_string(), json!({ "dir": "/project" })).unwrap();
+        assert_eq!(
+            method,
+            Method::ActivationEnv(EnvironmentParams {
+                dir: PathBuf::from("/project")
+            })
+        );
+    }
+
+    #[test]
+    fn test_answers_requests_in_order() {
+        let (flox, _temp_dir_handle) = test_flox_instance();
+        let dir = init_environment(&flox);
+
+        let responses = exchange(&flox, &[
+            "not json".to_string(),
+            json!({ "jsonrpc": "1.0", "id": 1, "method": "list" }).to_string(),
+            json!({ "jsonrpc": "2.0", "method": "delete" }).to_string(),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "delete" }).to_string(),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "manifest", "params": { "dir": dir } })
+                .to_string(),
+        ]);
+
+        // The notification for "delete" isn't answered
+        assert_eq!(responses.len(), 4, "{responses:#?}");
+        assert_eq!(responses[0]["id"], Value::Null);
+        assert_eq!(responses[0]["error"]["code"], error_codes::PARSE_ERROR);
+        assert_eq!(responses[1]["id"], Value::Null);
+        assert_eq!(responses[1]["error"]["code"], error_codes::INVALID_REQUEST);
+        assert_eq!(responses[2]["id"], 2);
+        assert_eq!(responses[2]["error"]["code"], error_codes::METHOD_NOT_FOUND);
+        assert_eq!(responses[3]["id"], 3);
+        assert_eq!(
+            responses[3]["result"]["contents"],
+            fs::read_to_string(dir.join(".flox/env/manifest.toml")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_reports_progress_before_failure() {
+        let (flox, _temp_dir_handle) = test_flox_instance();
+        let dir = init_environment(&flox);
+
+        let responses = exchange(&flox, &[json!({
+            "jsonrpc": "2.0",
+            "id": "uninstall",
+            "method": "uninstall",
+            "params": { "dir": dir, "packages": ["hello"] },
+        })
+        .to_string()]);
+
+        assert_eq!(responses.len(), 2, "{responses:#?}");
+        assert_eq!(
+            responses[0],
+            json!({
+                "jsonrpc": "2.0",
+                "method": "progress",
+                "params": { "id": "uninstall", "message": "Uninstalling packages" },
+            })
+        );
+        assert_eq!(responses[1]["id"], "uninstall");
+        assert_eq!(responses[1]["error"]["code"], error_codes::OPERATION_FAILED);
+        assert!(
+            responses[1]["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("hello"),
+            "{responses:#?}"
+        );
+    }
+}
@@ -0,0 +1,821 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{stdout, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+use anyhow::{bail, Context, Result};
+use bpaf::Bpaf;
+use flox_rust_sdk::flox::Flox;
+use flox_rust_sdk::models::environment::Environment;
+use flox_rust_sdk::models::manifest::{self, ManifestService, RestartPolicy};
+use itertools::Itertools;
+use log::debug;
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::{setsid, Pid};
+use serde::{Deserialize, Serialize};
+
+use super::{environment_select, EnvironmentSelect};
+use crate::commands::ConcreteEnvironment;
+use crate::subcommand_metric;
+use crate::utils::message;
+
+/// Path to the bash used to run services and their readiness checks
+const BASH_BIN: &str = env!("BASH_BIN");
+
+/// How long `flox services start` waits for a service to become ready
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `flox services stop` waits for a service to exit before killing it
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to poll services while waiting for them
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Delay before a service is restarted
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Manage the services of an environment
+#[derive(Bpaf, Clone, Debug)]
+pub enum Services {
+    /// Start services in the background
+    #[bpaf(command)]
+    Start(#[bpaf(external(start))] Start),
+
+    /// Stop running services
+    #[bpaf(command)]
+    Stop(#[bpaf(external(stop))] Stop),
+
+    /// Show the status of services
+    #[bpaf(command)]
+    Status(#[bpaf(external(status))] Status),
+
+    /// Print the output of a service
+    #[bpaf(command)]
+    Logs(#[bpaf(external(logs))] Logs),
+
+    /// Run a service and restart it according to its restart policy
+    #[bpaf(command, hide)]
+    Supervise(#[bpaf(external(supervise))] Supervise),
+}
+
+impl Services {
+    pub async fn handle(self, flox: Flox) -> Result<()> {
+        match self {
+            Services::Start(args) => args.handle(flox),
+            Services::Stop(args) => args.handle(flox),
+            Services::Status(args) => args.handle(flox),
+            Services::Logs(args) => args.handle(flox),
+            Services::Supervise(args) => args.handle(),
+        }
+    }
+}
+
+#[derive(Bpaf, Clone, Debug)]
+pub struct Start {
+    #[bpaf(external(environment_select), fallback(Default::default()))]
+    environment: EnvironmentSelect,
+
+    /// Services to start (default: all services)
+    #[bpaf(positional("name"), many)]
+    names: Vec<String>,
+}
+
+impl Start {
+    fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("services::start");
+
+        let environment = self
+            .environment
+            .detect_concrete_environment(&flox, "start services of")?;
+        let activated_env = activated_env(&environment)?;
+
+        start_services(
+            &flox,
+            &*environment.into_dyn_environment(),
+            &self.names,
+            activated_env,
+        )
+    }
+}
+
+#[derive(Bpaf, Clone, Debug)]
+pub struct Stop {
+    #[bpaf(external(environment_select), fallback(Default::default()))]
+    environment: EnvironmentSelect,
+
+    /// Services to stop (default: all services)
+    #[bpaf(positional("name"), many)]
+    names: Vec<String>,
+}
+
+impl Stop {
+    fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("services::stop");
+
+        let environment = self
+            .environment
+            .detect_concrete_environment(&flox, "stop services of")?;
+        let state = ServicesState::open(&environment)?;
+        let services = selected_services(&flox, &environment, &self.names)?;
+
+        for name in services.keys() {
+            match state.pid(name) {
+                Some(pid) => {
+                    state.stop(name, pid)?;
+                    message::deleted(format!("Service '{name}' stopped"));
+                },
+                None if !self.names.is_empty() => {
+                    message::warning(format!("Service '{name}' is not running"))
+                },
+                None => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Bpaf, Clone, Debug)]
+pub struct Status {
+    #[bpaf(external(environment_select), fallback(Default::default()))]
+    environment: EnvironmentSelect,
+
+    /// Print the status of services as JSON
+    #[bpaf(long)]
+    json: bool,
+
+    /// Services to show (default: all services)
+    #[bpaf(positional("name"), many)]
+    names: Vec<String>,
+}
+
+/// The status of a service as shown by `flox services status`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ServiceStatus {
+    name: String,
+    status: ServiceState,
+    pid: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum ServiceState {
+    /// The service is not running
+    Stopped,
+    /// The service is running and has no readiness check
+    Running,
+    /// The service is running but its readiness check fails
+    Starting,
+    /// The service is running and its readiness check succeeds
+    Ready,
+}
+
+impl std::fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceState::Stopped => write!(f, "stopped"),
+            ServiceState::Running => write!(f, "running"),
+            ServiceState::Starting => write!(f, "starting"),
+            ServiceState::Ready => write!(f, "ready"),
+        }
+    }
+}
+
+impl Status {
+    fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("services::status");
+
+        let environment = self
+            .environment
+            .detect_concrete_environment(&flox, "show services of")?;
+        let state = ServicesState::open(&environment)?;
+        let services = selected_services(&flox, &environment, &self.names)?;
+
+        let statuses = services
+            .keys()
+            .map(|name| {
+                let pid = state.pid(name);
+                let status = match pid {
+                    None => ServiceState::Stopped,
+                    Some(_) => match state.read_spec(name) {
+                        Ok(spec) if spec.is_ready.is_some() => {
+                            if spec.is_ready()? {
+                                ServiceState::Ready
+                            } else {
+                                ServiceState::Starting
+                            }
+                        },
+                        _ => ServiceState::Running,
+                    },
+                };
+                Ok(ServiceStatus {
+                    name: name.clone(),
+                    status,
+                    pid: pid.map(Pid::as_raw),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&statuses)?);
+            return Ok(());
+        }
+
+        if statuses.is_empty() {
+            message::plain("This environment does not declare any services.");
+            return Ok(());
+        }
+
+        println!("{}", render_statuses(&statuses));
+        Ok(())
+    }
+}
+
+/// Render service statuses as a table
+fn render_statuses(statuses: &[ServiceStatus]) -> String {
+    let width = statuses
+        .iter()
+        .map(|status| status.name.len())
+        .max()
+        .unwrap_or_default()
+        .max("NAME".len());
+
+    let header = format!("{:width$}  {:8}  PID", "NAME", "STATUS");
+    let rows = statuses.iter().map(|status| {
+        let pid = status.pid.map(|pid| pid.to_string()).unwrap_or_default();
+        format!(
+            "{:width$}  {:8}  {pid}",
+            status.name,
+            status.status.to_string()
+        )
+        .trim_end()
+        .to_string()
+    });
+
+    std::iter::once(header).chain(rows).join("\n")
+}
+
+#[derive(Bpaf, Clone, Debug)]
+pub struct Logs {
+    #[bpaf(external(environment_select), fallback(Default::default()))]
+    environment: EnvironmentSelect,
+
+    /// Keep printing output as the service produces it
+    #[bpaf(long, short)]
+    follow: bool,
+
+    /// Service to print the output of
+    #[bpaf(positional("name"))]
+    name: String,
+}
+
+impl Logs {
+    fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("services::logs");
+
+        let environment = self
+            .environment
+            .detect_concrete_environment(&flox, "show services of")?;
+        let state = ServicesState::open(&environment)?;
+        selected_services(&flox, &environment, std::slice::from_ref(&self.name))?;
+
+        let log_path = state.log_path(&self.name);
+        let mut log = match File::open(&log_path) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                bail!("Service '{}' has not been started yet.", self.name)
+            },
+            Err(e) => Err(e).context("Could not open service log")?,
+        };
+
+        let mut out = stdout().lock();
+        loop {
+            let mut buffer = Vec::new();
+            log.read_to_end(&mut buffer)
+                .context("Could not read service log")?;
+            out.write_all(&buffer)?;
+            out.flush()?;
+
+            if !self.follow {
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL);
+
+            // The log is truncated when the service is started again
+            let position = log.stream_position()?;
+            if fs::metadata(&log_path).map(|m| m.len()).unwrap_or_default() < position {
+                log.seek(SeekFrom::Start(0))?;
+            }
+        }
+    }
+}
+
+#[derive(Bpaf, Clone, Debug)]
+pub struct Supervise {
+    /// Specification of the service written by `flox services start`
+    #[bpaf(positional("spec"))]
+    spec: PathBuf,
+}
+
+impl Supervise {
+    /// Run the service until it exits and should not be restarted
+    ///
+    /// The supervisor is spawned by [start_services] with its output redirected to the service log.
+    /// It starts a new session, so that `flox services stop` can terminate
+    /// the supervisor along with all processes of the service
+    /// by signalling the process group.
+    ///
+    /// The pid file next to the specification is locked by [ServicesState::spawn]
+    /// and passed to the supervisor as its stdin,
+    /// so that the lock is held until the supervisor exits, see [ServicesState::pid].
+    /// The service itself runs with stdin closed, and doesn't hold the lock.
+    fn handle(self) -> Result<()> {
+        // Fails if we already lead a process group, which serves just as well
+        match setsid() {
+            Ok(_) | Err(Errno::EPERM) => {},
+            Err(e) => Err(e).context("Could not start a new session")?,
+        }
+
+        let spec: ServiceSpec = serde_json::from_str(
+            &fs::read_to_string(&self.spec).context("Could not read service specification")?,
+        )
+        .context("Invalid service specification")?;
+
+        loop {
+            let status = spec
+                .command(&spec.command)
+                .status()
+                .context("Could not start service")?;
+
+            let restart = spec.restart.should_restart(status.success());
+            let exited = match status.code() {
+                Some(code) => format!("exited with code {code}"),
+                None => "was terminated by a signal".to_string(),
+            };
+            eprintln!(
+                "flox: service {exited}{}",
+                if restart { ", restarting" } else { "" }
+            );
+
+            if !restart {
+                return Ok(());
+            }
+            thread::sleep(RESTART_DELAY);
+        }
+    }
+}
+
+/// Everything the supervisor of a service needs to run it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ServiceSpec {
+    command: String,
+    is_ready: Option<String>,
+    restart: RestartPolicy,
+    /// The activated environment with the variables of the service applied
+    env: BTreeMap<String, String>,
+}
+
+impl ServiceSpec {
+    fn new(service: &ManifestService, activated_env: &BTreeMap<String, String>) -> Self {
+        let mut env = activated_env.clone();
+        env.extend(service.vars.clone());
+
+        Self {
+            command: service.command.clone(),
+            is_ready: service.is_ready.clone(),
+            restart: service.restart,
+            env,
+        }
+    }
+
+    /// Prepare a command running `script` in the environment of the service
+    fn command(&self, script: &str) -> Command {
+        let mut command = Command::new(BASH_BIN);
+        command
+            .env_clear()
+            .envs(&self.env)
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::null());
+        command
+    }
+
+    /// Run the readiness check of the service
+    ///
+    /// Services without a readiness check are considered ready once they're running.
+    fn is_ready(&self) -> Result<bool> {
+        let Some(ref is_ready) = self.is_ready else {
+            return Ok(true);
+        };
+
+        let status = self
+            .command(is_ready)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("Could not run readiness check")?;
+        Ok(status.success())
+    }
+}
+
+/// The runtime state of the services of an environment
+///
+/// For each started service, `$FLOX_ENV_CACHE/services` contains
+///
+/// - `<name>.json`: the [ServiceSpec] the service was started with
+/// - `<name>.pid`: the process id of the supervisor running the service,
+///   locked by the supervisor while it runs
+/// - `<name>.log`: the output of the service
+struct ServicesState {
+    dir: PathBuf,
+}
+
+impl ServicesState {
+    fn open(environment: &ConcreteEnvironment) -> Result<Self> {
+        let environment = match environment {
+            ConcreteEnvironment::Path(environment) => environment as &dyn Environment,
+            ConcreteEnvironment::Managed(environment) => environment,
+            ConcreteEnvironment::Remote(_) => {
+                bail!("Services are not supported for remote environments.")
+            },
+        };
+        Self::from_environment(environment)
+    }
+
+    fn from_environment(environment: &dyn Environment) -> Result<Self> {
+        let dir = environment.cache_path()?.join("services");
+        fs::create_dir_all(&dir).context("Could not create services directory")?;
+        Ok(Self { dir })
+    }
+
+    fn spec_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.json"))
+    }
+
+    fn pid_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.pid"))
+    }
+
+    fn log_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.log"))
+    }
+
+    fn read_spec(&self, name: &str) -> Result<ServiceSpec> {
+        let spec = fs::read_to_string(self.spec_path(name))?;
+        Ok(serde_json::from_str(&spec)?)
+    }
+
+    /// The process id of the supervisor of a service, if it is running
+    ///
+    /// A pid file that is not locked was left behind by a supervisor that exited,
+    /// e.g. before a reboot, and its pid may since have been reused by an unrelated process.
+    /// Such stale pid files are removed.
+    fn pid(&self, name: &str) -> Option<Pid> {
+        let path = self.pid_path(name);
+        let mut file = File::open(&path).ok()?;
+
+        match flock(file.as_raw_fd(), FlockArg::LockSharedNonblock) {
+            Err(Errno::EWOULDBLOCK) => {
+                let mut pid = String::new();
+                file.read_to_string(&mut pid).ok()?;
+                Some(Pid::from_raw(pid.trim().parse().ok()?))
+            },
+            Ok(()) => {
+                debug!("removing stale pid file of service '{name}'");
+                let _ = fs::remove_file(&path);
+                None
+            },
+            Err(e) => {
+                debug!("could not check pid file of service '{name}': {e}");
+                None
+            },
+        }
+    }
+
+    /// Spawn a supervisor running the service in the background,
+    /// unless the service is already running
+    ///
+    /// The pid file is locked before the specification is written and the log is truncated,
+    /// so that concurrent starts of the same service don't interfere with each other.
+    /// If the lock is held by another supervisor, `None` is returned.
+    /// Otherwise, the locked pid file is handed to the supervisor, see [Supervise::handle].
+    fn spawn(&self, name: &str, spec: &ServiceSpec) -> Result<Option<Pid>> {
+        let Some(mut pid_file) = lock_pid_file(&self.pid_path(name))? else {
+            return Ok(None);
+        };
+
+        let spec_path = self.spec_path(name);
+        fs::write(&spec_path, serde_json::to_string(spec)?)
+            .context("Could not write service specification")?;
+
+        let log = File::create(self.log_path(name)).context("Could not create service log")?;
+
+        let child = Command::new(env::current_exe().context("Could not locate flox")?)
+            .args(["services", "supervise"])
+            .arg(&spec_path)
+            .stdin(pid_file.try_clone()?)
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .context("Could not start service supervisor")?;
+
+        let pid = Pid::from_raw(child.id() as i32);
+        pid_file.set_len(0).context("Could not write pid file")?;
+        write!(pid_file, "{pid}").context("Could not write pid file")?;
+
+        Ok(Some(pid))
+    }
+
+    /// Terminate the supervisor of a service and all of its processes
+    fn stop(&self, name: &str, pid: Pid) -> Result<()> {
+        signal_service(pid, Signal::SIGTERM)?;
+
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while self.pid(name).is_some() {
+            if Instant::now() > deadline {
+                debug!("service '{name}' did not stop in time, killing it");
+                signal_service(pid, Signal::SIGKILL)?;
+                break;
+            }
+            thread::sleep(POLL_INTERVAL / 5);
+        }
+
+        let _ = fs::remove_file(self.pid_path(name));
+        Ok(())
+    }
+}
+
+/// Lock the pid file of a supervisor,
+/// or return `None` if it is locked by a running supervisor
+///
+/// The lock is released once the returned file and all its duplicates are closed,
+/// which happens at the latest when the supervisor exits.
+fn lock_pid_file(path: &Path) -> Result<Option<File>> {
+    loop {
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .context("Could not open pid file")?;
+
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => {},
+            Err(Errno::EWOULDBLOCK) => return Ok(None),
+            Err(e) => Err(e).context("Could not lock pid file")?,
+        }
+
+        // The pid file may have been removed as stale before it was locked,
+        // see [ServicesState::pid], in which case the lock has to be taken again.
+        let locked = file.metadata().context("Could not read pid file")?.ino();
+        if fs::metadata(path).is_ok_and(|metadata| metadata.ino() == locked) {
+            return Ok(Some(file));
+        }
+    }
+}
+
+/// Check whether a process is still running
+///
+/// Exited children of this process are reaped first,
+/// so that supervisors started by this process don't appear to be running.
+fn is_alive(pid: Pid) -> bool {
+    let _ = nix::sys::wait::waitpid(pid, Some(nix::sys::wait::WaitPidFlag::WNOHANG));
+    kill(pid, None).is_ok()
+}
+
+/// Send `signal` to the process group of a supervisor,
+/// falling back to the supervisor alone if it did not start a session yet
+fn signal_service(pid: Pid, signal: Signal) -> Result<()> {
+    match killpg(pid, signal) {
+        Ok(()) => Ok(()),
+        Err(Errno::ESRCH) => match kill(pid, signal) {
+            Ok(()) | Err(Errno::ESRCH) => Ok(()),
+            Err(e) => Err(e).context("Could not stop service"),
+        },
+        Err(e) => Err(e).context("Could not stop service"),
+    }
+}
+
+/// Read the services declared by an environment,
+/// restricted to `names` if any are given
+fn selected_services(
+    flox: &Flox,
+    environment: &ConcreteEnvironment,
+    names: &[String],
+) -> Result<BTreeMap<String, ManifestService>> {
+    let environment = match environment {
+        ConcreteEnvironment::Path(environment) => environment as &dyn Environment,
+        ConcreteEnvironment::Managed(environment) => environment,
+        ConcreteEnvironment::Remote(environment) => environment,
+    };
+    select_services(flox, environment, names)
+}
+
+fn select_services(
+    flox: &Flox,
+    environment: &dyn Environment,
+    names: &[String],
+) -> Result<BTreeMap<String, ManifestService>> {
+    let mut services = manifest::services(&environment.manifest_content(flox)?)?;
+
+    if names.is_empty() {
+        return Ok(services);
+    }
+
+    names
+        .iter()
+        .map(|name| match services.remove_entry(name) {
+            Some(service) => Ok(service),
+            None => bail!("Service '{name}' is not declared in the manifest."),
+        })
+        .collect()
+}
+
+/// Compute the environment services run in,
+/// using `flox activate --print-env`
+pub(super) fn activated_env(environment: &ConcreteEnvironment) -> Result<BTreeMap<String, String>> {
+    let dir = match environment {
+        ConcreteEnvironment::Path(environment) => environment.parent_path()?,
+        ConcreteEnvironment::Managed(environment) => environment.parent_path()?,
+        ConcreteEnvironment::Remote(_) => {
+            bail!("Services are not supported for remote environments.")
+        },
+    };
+
+    let output = Command::new(env::current_exe().context("Could not locate flox")?)
+        .arg("activate")
+        .arg("--dir")
+        .arg(&dir)
+        .args(["--print-env", "--json"])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Could not activate environment")?;
+
+    if !output.status.success() {
+        bail!("Could not activate environment.");
+    }
+
+    serde_json::from_slice(&output.stdout).context("Could not parse activated environment")
+}
+
+/// Start the services of an environment that are not yet running
+///
+/// Services run in `activated_env`, with their own variables applied.
+/// Services with a readiness check are waited for until they are ready.
+pub(super) fn start_services(
+    flox: &Flox,
+    environment: &dyn Environment,
+    names: &[String],
+    activated_env: impl IntoIterator<Item = (String, String)>,
+) -> Result<()> {
+    let activated_env = BTreeMap::from_iter(activated_env);
+    let state = ServicesState::from_environment(environment)?;
+    let services = select_services(flox, environment, names)?;
+
+    if services.is_empty() {
+        message::warning("This environment does not declare any services.");
+        return Ok(());
+    }
+
+    let mut started = Vec::new();
+    for (name, service) in &services {
+        let spec = ServiceSpec::new(service, &activated_env);
+        let Some(pid) = state.spawn(name, &spec)? else {
+            message::plain(format!("Service '{name}' is already running"));
+            continue;
+        };
+        debug!("started service '{name}' with pid {pid}");
+        started.push((name, spec, pid));
+    }
+
+    for (name, spec, pid) in started {
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            if !is_alive(pid) {
+                bail!(
+                    "Service '{name}' exited. Run 'flox services logs {name}' to see its output."
+                );
+            }
+            if spec.is_ready()? {
+                message::created(format!("Service '{name}' started"));
+                break;
+            }
+            if Instant::now() > deadline {
+                message::warning(format!(
+                    "Service '{name}' started but is not ready yet. Run 'flox services status' to check on it."
+                ));
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_service_spec_applies_vars() {
+        let service = ManifestService {
+            command: "redis-server".to_string(),
+            vars: BTreeMap::from([("PORT".to_string(), "6380".to_string())]),
+            is_ready: None,
+            restart: RestartPolicy::Always,
+        };
+        let activated_env = BTreeMap::from([
+            ("PORT".to_string(), "6379".to_string()),
+            ("PATH".to_string(), "/flox/bin".to_string()),
+        ]);
+
+        let spec = ServiceSpec::new(&service, &activated_env);
+        assert_eq!(
+            spec.env,
+            BTreeMap::from([
+                ("PORT".to_string(), "6380".to_string()),
+                ("PATH".to_string(), "/flox/bin".to_string()),
+            ])
+        );
+        assert!(spec.is_ready().unwrap());
+    }
+
+    #[test]
+    fn test_render_statuses() {
+        let statuses = [
+            ServiceStatus {
+                name: "postgres".to_string(),
+                status: ServiceState::Ready,
+                pid: Some(1234),
+            },
+            ServiceStatus {
+                name: "redis".to_string(),
+                status: ServiceState::Stopped,
+                pid: None,
+            },
+        ];
+
+        assert_eq!(render_statuses(&statuses), indoc::indoc! {"
+                NAME      STATUS    PID
+                postgres  ready     1234
+                redis     stopped"});
+    }
+
+    /// Only pid files locked by a running supervisor refer to a service,
+    /// pid files left behind by a supervisor that exited are removed.
+    #[test]
+    fn test_pid_requires_locked_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = ServicesState {
+            dir: dir.path().to_path_buf(),
+        };
+
+        fs::write(state.pid_path("stale"), std::process::id().to_string()).unwrap();
+        assert_eq!(state.pid("stale"), None);
+        assert!(!state.pid_path("stale").exists());
+
+        let mut pid_file = lock_pid_file(&state.pid_path("running")).unwrap().unwrap();
+        write!(pid_file, "{}", std::process::id()).unwrap();
+        assert_eq!(
+            state.pid("running"),
+            Some(Pid::from_raw(std::process::id() as i32))
+        );
+        assert!(lock_pid_file(&state.pid_path("running")).unwrap().is_none());
+    }
+
+    /// A service is only spawned by whoever locks its pid file,
+    /// without touching the specification or log of a running service.
+    #[test]
+    fn test_spawn_skips_locked_service() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = ServicesState {
+            dir: dir.path().to_path_buf(),
+        };
+        let spec = ServiceSpec {
+            command: "true".to_string(),
+            is_ready: None,
+            restart: RestartPolicy::Never,
+            env: BTreeMap::new(),
+        };
+
+        fs::write(state.log_path("running"), "output").unwrap();
+        let _pid_file = lock_pid_file(&state.pid_path("running")).unwrap().unwrap();
+
+        assert_eq!(state.spawn("running", &spec).unwrap(), None);
+        assert!(!state.spec_path("running").exists());
+        assert_eq!(
+            fs::read_to_string(state.log_path("running")).unwrap(),
+            "output"
+        );
+    }
+}
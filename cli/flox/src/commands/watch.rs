@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use bpaf::Bpaf;
+use chrono::Utc;
+use flox_rust_sdk::flox::Flox;
+use flox_rust_sdk::models::environment::prebuild::{prebuild_key, PrebuildState, PrebuildStatus};
+use flox_rust_sdk::models::environment::{DotFlox, Environment, EnvironmentError2};
+use flox_rust_sdk::providers::floxd::{Request, Response};
+use log::debug;
+
+use super::{EnvironmentSelect, UninitializedEnvironment};
+use crate::subcommand_metric;
+use crate::utils::errors::format_error;
+use crate::utils::{floxd, message};
+
+/// How often to check watched environments for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Lock and build environments in the background whenever they change
+#[derive(Bpaf, Clone, Debug)]
+pub struct Watch {
+    /// Path containing a .flox/ directory to watch, may be repeated
+    /// (default: environments active in any process)
+    #[bpaf(long("dir"), short('d'), argument("path"), many)]
+    dirs: Vec<PathBuf>,
+}
+
+impl Watch {
+    pub async fn handle(self, flox: Flox) -> Result<()> {
+        subcommand_metric!("watch");
+
+        // Fail early on directories that don't contain an environment
+        for dir in &self.dirs {
+            EnvironmentSelect::Dir(dir.clone()).to_concrete_environment(&flox)?;
+        }
+
+        if self.dirs.is_empty() {
+            message::plain("Watching environments active in any process for changes...");
+        } else {
+            message::plain("Watching environments for changes...");
+        }
+
+        loop {
+            for dir in self.watched_dirs(&flox) {
+                if let Err(e) = Self::prebuild_if_changed(&flox, &dir) {
+                    message::warning(format!(
+                        "Could not prebuild environment in {}: {}",
+                        dir.display(),
+                        format_error(&e).trim()
+                    ));
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// The directories given on the command line,
+    /// or the local environments floxd tracks as active
+    ///
+    /// floxd is started if it isn't running,
+    /// since activations only start it if they need to be cleaned up.
+    fn watched_dirs(&self, flox: &Flox) -> Vec<PathBuf> {
+        if !self.dirs.is_empty() {
+            return self.dirs.clone();
+        }
+
+        let mut client = match floxd::connect_or_start(flox) {
+            Ok(client) => client,
+            Err(e) => {
+                debug!("could not connect to floxd: {e:#}");
+                return vec![];
+            },
+        };
+        let activations = match client.request(&Request::Activations) {
+            Ok(Response::Activations { activations }) => activations,
+            Ok(response) => {
+                debug!("unexpected response from floxd: {response:?}");
+                return vec![];
+            },
+            Err(e) => {
+                debug!("could not query floxd: {e}");
+                return vec![];
+            },
+        };
+
+        activations
+            .into_iter()
+            .filter_map(|activation| {
+                match serde_json::from_str::<UninitializedEnvironment>(&activation.environment) {
+                    Ok(UninitializedEnvironment::DotFlox(DotFlox { path, .. })) => Some(path),
+                    // Remote environments aren't edited locally
+                    Ok(UninitializedEnvironment::Remote(_)) => None,
+                    Err(e) => {
+                        debug!("could not parse environment tracked by floxd: {e}");
+                        None
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Lock and build the environment in `dir`,
+    /// unless it has been prebuilt since it last changed
+    ///
+    /// Failing to lock or build the environment is recorded in its
+    /// [PrebuildState] rather than returned.
+    fn prebuild_if_changed(flox: &Flox, dir: &Path) -> Result<(), EnvironmentError2> {
+        // Environments may be deleted while they're watched
+        let mut environment =
+            match EnvironmentSelect::Dir(dir.to_path_buf()).to_concrete_environment(flox) {
+                Ok(environment) => environment.into_dyn_environment(),
+                Err(e) => {
+                    debug!("could not open environment in {}: {e}", dir.display());
+                    return Ok(());
+                },
+            };
+
+        let key = prebuild_key(flox, environment.as_ref())?;
+        let previous = PrebuildState::read(flox, environment.as_ref())?;
+        // A prebuild that is still marked as building was interrupted
+        if previous.is_some_and(|previous| {
+            previous.key == key && previous.status != PrebuildStatus::Building
+        }) {
+            return Ok(());
+        }
+
+        message::plain(format!("Building environment in {}...", dir.display()));
+        PrebuildState {
+            key,
+            updated: Utc::now(),
+            status: PrebuildStatus::Building,
+        }
+        .write(flox, environment.as_ref())?;
+
+        let status = match Self::build(flox, environment.as_mut()) {
+            Ok(store_path) => {
+                message::updated(format!("Environment in {} is ready", dir.display()));
+                PrebuildStatus::Built { store_path }
+            },
+            Err(e) => {
+                let error = format_error(&e).trim().to_string();
+                message::warning(format!(
+                    "Building environment in {} failed: {error}",
+                    dir.display()
+                ));
+                PrebuildStatus::Failed { error }
+            },
+        };
+
+        // Locking may have rewritten the lockfile
+        PrebuildState {
+            key: prebuild_key(flox, environment.as_ref())?,
+            updated: Utc::now(),
+            status,
+        }
+        .write(flox, environment.as_ref())
+    }
+
+    /// Lock and build an environment
+    ///
+    /// Building through [Environment::activation_path] records the build
+    /// in the activation cache, so the next activation can use it right away.
+    fn build(flox: &Flox, environment: &mut dyn Environment) -> Result<PathBuf, EnvironmentError2> {
+        environment.lock(flox)?;
+        let activation_path = environment.activation_path(flox)?;
+        Ok(activation_path.canonicalize().unwrap_or(activation_path))
+    }
+}
@@ -6,7 +6,8 @@ use std::{env, fs};
 
 use anyhow::{Context, Result};
 use config::{Config as HierarchicalConfig, Environment};
-use flox_rust_sdk::flox::EnvironmentRef;
+use flox_rust_sdk::flox::{EnvironmentOwner, EnvironmentRef};
+use flox_rust_sdk::providers::oci_registry::RegistryCredentials;
 use itertools::{Either, Itertools};
 use log::{debug, trace};
 use once_cell::sync::OnceCell;
@@ -72,6 +73,24 @@ pub struct FloxConfig {
 
     /// The URL of the FloxHub instance to use
     pub floxhub_url: Option<Url>,
+
+    /// Plain git remotes that store the environments of an owner instead of FloxHub,
+    /// by owner
+    #[serde(default)]
+    pub floxmeta_remotes: HashMap<EnvironmentOwner, Url>,
+
+    /// Whether `flox activate` adds the active environments to the shell prompt
+    /// (default: true)
+    pub set_prompt: Option<bool>,
+
+    /// Format of each active environment in the shell prompt
+    /// (default: `({owner}/){name}`)
+    pub prompt_format: Option<String>,
+
+    /// Credentials for container registries that `flox containerize` pushes to,
+    /// by host of the registry
+    #[serde(default)]
+    pub container_registries: HashMap<String, RegistryCredentials>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -24,6 +24,15 @@ impl<F: FnOnce() -> T + Send, T: Send> Spinner<F> {
     }
 }
 
+/// A spinner for a closure that reports its progress,
+/// which is shown after the message of the [Dialog]
+pub struct ProgressSpinner<F>(F);
+impl<F: FnOnce(&dyn Fn(String)) -> T, T> ProgressSpinner<F> {
+    pub fn new(f: F) -> Self {
+        Self(f)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Checkpoint;
 
@@ -202,6 +211,24 @@ impl<'a, F: FnOnce() -> T + Send, T: Send> Dialog<'a, Spinner<F>> {
     }
 }
 
+impl<'a, F: FnOnce(&dyn Fn(String)) -> T, T> Dialog<'a, ProgressSpinner<F>> {
+    pub fn spin(self) -> T {
+        let spinner = indicatif::ProgressBar::new_spinner();
+        spinner.set_style(ProgressStyle::with_template("{spinner} {wide_msg} {prefix:>}").unwrap());
+        spinner.set_message(self.message.to_string());
+        if let Some(help_message) = self.help_message {
+            spinner.set_prefix(help_message.to_string())
+        }
+        spinner.enable_steady_tick(Duration::from_millis(100));
+
+        let message = self.message;
+        let res = (self.typed.0)(&|progress| spinner.set_message(format!("{message} {progress}")));
+
+        spinner.finish_and_clear();
+        res
+    }
+}
+
 impl Dialog<'_, ()> {
     /// True if stderr and stdin are ttys
     pub fn can_prompt() -> bool {
@@ -306,6 +306,12 @@ pub fn format_core_error(err: &CoreEnvironmentError) -> String {
         CoreEnvironmentError::ContainerizeUnsupportedSystem(system) => formatdoc! {"
             'containerize' is currently only supported on linux (found {system}).
         "},
+
+        CoreEnvironmentError::Include(include_error) => formatdoc! {"
+            Failed to include environments: {}
+        ", display_chain(include_error.as_ref())},
+        // within the transaction, user should not see this
+        CoreEnvironmentError::WriteComposedManifest(_) => display_chain(err),
     }
 }
 
@@ -457,6 +463,11 @@ pub fn format_managed_error(err: &ManagedEnvironmentError) -> String {
 
             {err}
         ",err = display_chain(e) },
+        ManagedEnvironmentError::ReadGenerationsMetadata(e) => formatdoc! {"
+            Could not read the generations of the managed environment.
+
+            {err}
+        ",err = display_chain(e) },
         ManagedEnvironmentError::CanonicalizePath(canonicalize_err) => formatdoc! {"
             Invalid path to environment: {canonicalize_err}
 
@@ -465,6 +476,31 @@ pub fn format_managed_error(err: &ManagedEnvironmentError) -> String {
         ManagedEnvironmentError::Build(core_environment_error) => {
             format_core_error(core_environment_error)
         },
+        ManagedEnvironmentError::MergeManifests(e) => formatdoc! {"
+            Could not merge the local and the upstream manifest.
+
+            {err}
+        ", err = display_chain(e) },
+        ManagedEnvironmentError::DiffManifests(e) => formatdoc! {"
+            Could not compare the local and the upstream manifest.
+
+            {err}
+        ", err = display_chain(e) },
+        ManagedEnvironmentError::AlreadyExists(env_ref) => formatdoc! {"
+            An environment named '{env_ref}' already exists.
+
+            Please choose a different name.
+        "},
+        ManagedEnvironmentError::ReadLinksDir(_) => display_chain(err),
+        ManagedEnvironmentError::RenameEnvironmentLink(_, _) => display_chain(err),
+        ManagedEnvironmentError::DeleteUpstream(_) => formatdoc! {"
+            {err}
+
+            The environment is available under its new name,
+            but its old name could not be removed upstream.
+        ", err = display_chain(err) },
+        ManagedEnvironmentError::CopyGenerations(_) => display_chain(err),
+        ManagedEnvironmentError::RecordFork(_) => display_chain(err),
     }
 }
 
@@ -539,6 +575,12 @@ pub fn format_locked_manifest_error(err: &LockedManifestError) -> String {
             the destination file.
         "},
 
+        LockedManifestError::InvalidContainerize(_) => formatdoc! {"
+            {err}
+
+            Please check the '[containerize]' section of the manifest.
+        ", err = display_chain(err)},
+
         // this is a BUG
         LockedManifestError::ParseBuildEnvOutput(_) => display_chain(err),
         // this is likely a BUG, since we ensure that the lockfile exists in all cases
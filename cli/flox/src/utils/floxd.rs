@@ -0,0 +1,80 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use std::{env, thread};
+
+use anyhow::{Context, Result};
+use flox_rust_sdk::flox::Flox;
+use flox_rust_sdk::providers::floxd::{self, FloxdClient};
+use log::debug;
+
+/// How long to wait for a freshly started `floxd` to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connect to a running `floxd`
+///
+/// Returns [None] if `floxd` is not running.
+pub fn connect(flox: &Flox) -> Option<FloxdClient> {
+    match FloxdClient::connect(floxd::socket_path(flox)) {
+        Ok(client) => Some(client),
+        Err(e) => {
+            debug!("floxd is not running: {e}");
+            None
+        },
+    }
+}
+
+/// Connect to `floxd`, starting it if it is not running yet
+pub fn connect_or_start(flox: &Flox) -> Result<FloxdClient> {
+    if let Some(client) = connect(flox) {
+        return Ok(client);
+    }
+
+    let socket = floxd::socket_path(flox);
+    fs::create_dir_all(&flox.cache_dir).context("Could not create cache directory")?;
+    let log = File::options()
+        .create(true)
+        .append(true)
+        .open(flox.cache_dir.join("floxd.log"))
+        .context("Could not open floxd log")?;
+
+    debug!("starting floxd listening on {}", socket.display());
+    Command::new(floxd_bin()?)
+        .arg("--socket")
+        .arg(&socket)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .spawn()
+        .context("Could not start floxd")?;
+
+    let step = Duration::from_millis(50);
+    let mut waited = Duration::ZERO;
+    loop {
+        match FloxdClient::connect(&socket) {
+            Ok(client) => return Ok(client),
+            Err(e) if waited >= STARTUP_TIMEOUT => {
+                return Err(e).context("floxd did not start in time")
+            },
+            Err(_) => {
+                thread::sleep(step);
+                waited += step;
+            },
+        }
+    }
+}
+
+/// Locate the `floxd` executable
+///
+/// `$FLOXD_BIN` takes precedence over `floxd` installed next to `flox`.
+fn floxd_bin() -> Result<PathBuf> {
+    if let Ok(floxd) = env::var("FLOXD_BIN") {
+        return Ok(PathBuf::from(floxd));
+    }
+
+    let flox = env::current_exe().context("Could not locate flox")?;
+    let floxd = flox.with_file_name("floxd");
+    fs::metadata(&floxd).with_context(|| format!("Could not find floxd at {floxd:?}"))?;
+    Ok(floxd)
+}
@@ -8,6 +8,7 @@ mod completion;
 pub mod dialog;
 pub mod didyoumean;
 pub mod errors;
+pub mod floxd;
 pub mod init;
 pub mod message;
 pub mod metrics;
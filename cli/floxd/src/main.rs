@@ -1,3 +1,372 @@
-fn main() {
-    println!("TODO: Write A Daemon");
+//! `floxd` tracks which environments are active in which processes
+//! and cleans up after environments once they're no longer active anywhere.
+//!
+//! It is started on demand by `flox activate`, which registers activations
+//! over a unix socket (see [flox_rust_sdk::providers::floxd]).
+//! Processes are polled for whether they're still running,
+//! and once the last process that activated an environment exited,
+//! the cleanup commands registered for the environment are run.
+//! Without any active environments, `floxd` exits after a while.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufReader, ErrorKind};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+use anyhow::{bail, Context, Result};
+use bpaf::Bpaf;
+use flox_rust_sdk::providers::floxd::{
+    read_message,
+    write_message,
+    CleanupCommand,
+    FloxdError,
+    Registration,
+    Request,
+    Response,
+    TrackedEnvironment,
+};
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::unistd::{close, setsid, Pid};
+
+/// How often to check whether activated processes are still running
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to keep running without any active environments
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Track active flox environments and clean up after them
+#[derive(Bpaf, Debug)]
+#[bpaf(options, version)]
+struct Args {
+    /// Socket to listen on
+    #[bpaf(long, argument("path"))]
+    socket: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = args().run();
+
+    // Don't receive signals sent to the terminal of the activation that started us.
+    // Fails if we already lead a process group, which serves just as well.
+    let _ = setsid();
+
+    // Don't hold on to file descriptors inherited from the activation that started us,
+    // e.g. pipes its caller waits on to be closed.
+    for fd in 3..1024 {
+        let _ = close(fd);
+    }
+
+    let Some(listener) = bind(&args.socket)? else {
+        log("already running");
+        return Ok(());
+    };
+    log(format!("listening on {}", args.socket.display()));
+
+    let state = Arc::new(Mutex::new(State::default()));
+
+    {
+        let state = Arc::clone(&state);
+        let socket = args.socket.clone();
+        thread::spawn(move || watch(state, socket));
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, state) {
+                        log(format!("connection failed: {e:#}"));
+                    }
+                });
+            },
+            Err(e) => log(format!("could not accept connection: {e}")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Bind the socket, replacing stale sockets of daemons that exited
+///
+/// Returns [None] if another daemon is listening on the socket already.
+fn bind(socket: &Path) -> Result<Option<UnixListener>> {
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent).context("could not create socket directory")?;
+    }
+
+    match UnixListener::bind(socket) {
+        Ok(listener) => Ok(Some(listener)),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            if UnixStream::connect(socket).is_ok() {
+                return Ok(None);
+            }
+            fs::remove_file(socket).context("could not remove stale socket")?;
+            Ok(Some(
+                UnixListener::bind(socket).context("could not bind socket")?,
+            ))
+        },
+        Err(e) => Err(e).context("could not bind socket"),
+    }
+}
+
+/// Answer the requests sent over a connection until it is closed
+fn serve(stream: UnixStream, state: Arc<Mutex<State>>) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let request = match read_message::<Request>(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(FloxdError::Json(e)) => {
+                let response = Response::Error {
+                    message: format!("invalid request: {e}"),
+                };
+                write_message(&mut writer, &response)?;
+                continue;
+            },
+            Err(e) => Err(e)?,
+        };
+
+        let response = match request {
+            Request::Register(registration) => {
+                log(format!(
+                    "{} activated in process {}",
+                    registration.name, registration.pid
+                ));
+                state.lock().unwrap().register(registration);
+                Response::Ok
+            },
+            Request::Unregister { pid, environment } => {
+                let cleanup = state.lock().unwrap().unregister(pid, &environment);
+                if let Some((name, cleanup)) = cleanup {
+                    run_cleanup(name, cleanup);
+                }
+                Response::Ok
+            },
+            Request::Activations => Response::Activations {
+                activations: state.lock().unwrap().activations(),
+            },
+        };
+        write_message(&mut writer, &response)?;
+    }
+}
+
+/// Clean up after environments whose processes exited,
+/// and exit once there haven't been any active environments for [IDLE_TIMEOUT]
+fn watch(state: Arc<Mutex<State>>, socket: PathBuf) {
+    let mut idle_since = Instant::now();
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let mut state = state.lock().unwrap();
+        for (name, cleanup) in state.reap(is_alive) {
+            run_cleanup(name, cleanup);
+        }
+
+        if !state.environments.is_empty() {
+            idle_since = Instant::now();
+        } else if idle_since.elapsed() > IDLE_TIMEOUT {
+            log("no active environments, exiting");
+            let _ = fs::remove_file(&socket);
+            std::process::exit(0);
+        }
+    }
+}
+
+/// Check whether a process is still running
+fn is_alive(pid: i32) -> bool {
+    match kill(Pid::from_raw(pid), None) {
+        Ok(()) => true,
+        // The process exists but belongs to someone else
+        Err(Errno::EPERM) => true,
+        Err(_) => false,
+    }
+}
+
+/// Run the cleanup commands of an environment in the background
+fn run_cleanup(name: String, cleanup: Vec<CleanupCommand>) {
+    log(format!("{name} is no longer active, cleaning up"));
+    thread::spawn(move || {
+        for command in cleanup {
+            if let Err(e) = run_cleanup_command(&command) {
+                log(format!("cleanup of {name} failed: {e:#}"));
+            }
+        }
+    });
+}
+
+fn run_cleanup_command(cleanup: &CleanupCommand) -> Result<()> {
+    let mut command = Command::new(&cleanup.program);
+    command
+        .args(&cleanup.args)
+        .envs(&cleanup.env)
+        .stdin(Stdio::null());
+    if let Some(ref dir) = cleanup.dir {
+        command.current_dir(dir);
+    }
+
+    let status = command
+        .status()
+        .with_context(|| format!("could not run {}", cleanup.program.display()))?;
+    if !status.success() {
+        bail!("{} failed with {status}", cleanup.program.display());
+    }
+    Ok(())
+}
+
+fn log(message: impl AsRef<str>) {
+    eprintln!("floxd[{}]: {}", std::process::id(), message.as_ref());
+}
+
+/// The environments that are active in at least one process
+#[derive(Debug, Default)]
+struct State {
+    environments: BTreeMap<String, Tracked>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Tracked {
+    name: String,
+    flox_env: PathBuf,
+    pids: BTreeSet<i32>,
+    cleanup: Vec<CleanupCommand>,
+}
+
+impl State {
+    /// Record an activation
+    ///
+    /// The latest registration of an environment determines its cleanup commands.
+    fn register(&mut self, registration: Registration) {
+        let Registration {
+            pid,
+            environment,
+            name,
+            flox_env,
+            cleanup,
+        } = registration;
+
+        let tracked = self
+            .environments
+            .entry(environment)
+            .or_insert_with(|| Tracked {
+                name: String::new(),
+                flox_env: PathBuf::new(),
+                pids: BTreeSet::new(),
+                cleanup: Vec::new(),
+            });
+        tracked.name = name;
+        tracked.flox_env = flox_env;
+        tracked.cleanup = cleanup;
+        tracked.pids.insert(pid);
+    }
+
+    /// Forget an activation
+    ///
+    /// Returns the name and cleanup commands of the environment
+    /// if this was its last activation.
+    fn unregister(&mut self, pid: i32, environment: &str) -> Option<(String, Vec<CleanupCommand>)> {
+        let tracked = self.environments.get_mut(environment)?;
+        tracked.pids.remove(&pid);
+        if !tracked.pids.is_empty() {
+            return None;
+        }
+        let tracked = self.environments.remove(environment)?;
+        Some((tracked.name, tracked.cleanup))
+    }
+
+    /// Forget activations of processes that exited
+    ///
+    /// Returns the names and cleanup commands of the environments
+    /// that are no longer active in any process.
+    fn reap(&mut self, is_alive: impl Fn(i32) -> bool) -> Vec<(String, Vec<CleanupCommand>)> {
+        for tracked in self.environments.values_mut() {
+            tracked.pids.retain(|pid| is_alive(*pid));
+        }
+
+        let inactive = self
+            .environments
+            .iter()
+            .filter(|(_, tracked)| tracked.pids.is_empty())
+            .map(|(environment, _)| environment.clone())
+            .collect::<Vec<_>>();
+
+        inactive
+            .into_iter()
+            .filter_map(|environment| self.environments.remove(&environment))
+            .map(|tracked| (tracked.name, tracked.cleanup))
+            .collect()
+    }
+
+    fn activations(&self) -> Vec<TrackedEnvironment> {
+        self.environments
+            .iter()
+            .map(|(environment, tracked)| TrackedEnvironment {
+                environment: environment.clone(),
+                name: tracked.name.clone(),
+                flox_env: tracked.flox_env.clone(),
+                pids: tracked.pids.iter().copied().collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(pid: i32, environment: &str) -> Registration {
+        Registration {
+            pid,
+            environment: environment.to_string(),
+            name: environment.to_string(),
+            flox_env: PathBuf::from("/flox_env"),
+            cleanup: vec![CleanupCommand {
+                program: PathBuf::from("true"),
+                args: vec![],
+                dir: None,
+                env: BTreeMap::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn cleans_up_after_last_activation() {
+        let mut state = State::default();
+        state.register(registration(1, "a"));
+        state.register(registration(2, "a"));
+        state.register(registration(3, "b"));
+
+        assert!(state.reap(|_| true).is_empty());
+
+        let cleaned_up = state.reap(|pid| pid == 2);
+        assert_eq!(cleaned_up.len(), 1);
+        assert_eq!(cleaned_up[0].0, "b");
+
+        let activations = state.activations();
+        assert_eq!(activations.len(), 1);
+        assert_eq!(activations[0].environment, "a");
+        assert_eq!(activations[0].pids, vec![2]);
+
+        assert_eq!(state.reap(|_| false).len(), 1);
+        assert!(state.activations().is_empty());
+    }
+
+    #[test]
+    fn unregister_cleans_up_after_last_activation() {
+        let mut state = State::default();
+        state.register(registration(1, "a"));
+        state.register(registration(2, "a"));
+
+        assert!(state.unregister(1, "a").is_none());
+        assert!(state.unregister(1, "b").is_none());
+        assert_eq!(state.unregister(2, "a").unwrap().0, "a");
+        assert!(state.activations().is_empty());
+    }
 }
@@ -135,7 +135,7 @@ env_is_activated() {
   SHELL=bash NO_COLOR=1 run -0 expect "$TESTS_DIR/activate/hook.exp" "$PROJECT_DIR"
   assert_output --partial "Welcome to your flox environment!"
 
-  SHELL=bash USER="$REAL_USER" NO_COLOR=1 run $FLOX_BIN activate --dir "$PROJECT_DIR" -- :
+  FLOX_SHELL=bash USER="$REAL_USER" NO_COLOR=1 run $FLOX_BIN activate --dir "$PROJECT_DIR" -- :
   assert_success
   assert_output --partial "Welcome to your flox environment!"
 }
@@ -152,7 +152,7 @@ env_is_activated() {
   SHELL=zsh USER="$REAL_USER" NO_COLOR=1 run -0 expect "$TESTS_DIR/activate/hook.exp" "$PROJECT_DIR"
   assert_output --partial "Welcome to your flox environment!"
 
-  SHELL=zsh USER="$REAL_USER" NO_COLOR=1 run $FLOX_BIN activate --dir "$PROJECT_DIR" -- :
+  FLOX_SHELL=zsh USER="$REAL_USER" NO_COLOR=1 run $FLOX_BIN activate --dir "$PROJECT_DIR" -- :
   assert_success
   assert_output --partial "Welcome to your flox environment!"
 }
@@ -188,7 +188,7 @@ env_is_activated() {
   SHELL=bash NO_COLOR=1 run -0 expect "$TESTS_DIR/activate/envVar.exp" "$PROJECT_DIR"
   assert_output --partial "baz"
 
-  SHELL=bash NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- echo '$foo'
+  FLOX_SHELL=bash NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- echo '$foo'
   assert_success
   assert_output --partial "baz"
 }
@@ -205,7 +205,7 @@ env_is_activated() {
   SHELL=zsh USER="$REAL_USER" NO_COLOR=1 run -0 expect "$TESTS_DIR/activate/envVar.exp" "$PROJECT_DIR"
   assert_output --partial "baz"
 
-  SHELL=zsh NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- echo '$foo'
+  FLOX_SHELL=zsh NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- echo '$foo'
   assert_success
   assert_output --partial "baz"
 }
@@ -220,10 +220,10 @@ env_is_activated() {
   # TODO: flox will set HOME if it doesn't match the home of the user with
   # current euid. I'm not sure if we should change that, but for now just set
   # USER to REAL_USER.
-  SHELL=zsh NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- exit
+  FLOX_SHELL=zsh NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- exit
   assert_success
   assert_output --partial "baz"
-  SHELL=bash NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- exit
+  FLOX_SHELL=bash NO_COLOR=1 run "$FLOX_BIN" activate --dir "$PROJECT_DIR" -- exit
   assert_success
   assert_output --partial "baz"
 }
@@ -297,6 +297,99 @@ env_is_activated() {
 
 # ---------------------------------------------------------------------------- #
 
+@test "'flox activate' activates multiple environments listed first on top" {
+  OTHER_DIR="$BATS_TEST_TMPDIR/other"
+  mkdir -p "$OTHER_DIR"
+  "$FLOX_BIN" init -d "$OTHER_DIR" --name other
+
+  run "$FLOX_BIN" activate -d "$PROJECT_DIR" -d "$OTHER_DIR" -- \
+    bash -c 'echo "$FLOX_PROMPT_ENVIRONMENTS"; echo "$FLOX_ENV"'
+  assert_success
+  assert_line "$PROJECT_NAME other"
+  assert_line "$(realpath "$PROJECT_DIR")/.flox/run/$NIX_SYSTEM.$PROJECT_NAME"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox activate' runs the hooks of multiple environments in one shell" {
+  OTHER_DIR="$BATS_TEST_TMPDIR/other"
+  mkdir -p "$OTHER_DIR"
+  "$FLOX_BIN" init -d "$OTHER_DIR" --name other
+  MANIFEST_CONTENTS="$(cat << "EOF"
+    version = 1
+
+    [hook]
+    on-activate = """
+      echo "hook of $FLOX_ENV_PROJECT" >&2
+    """
+EOF
+  )"
+  echo "$MANIFEST_CONTENTS" | "$FLOX_BIN" edit -d "$PROJECT_DIR" -f -
+  echo "$MANIFEST_CONTENTS" | "$FLOX_BIN" edit -d "$OTHER_DIR" -f -
+
+  run --separate-stderr "$FLOX_BIN" activate -d "$PROJECT_DIR" -d "$OTHER_DIR" -- \
+    bash -c 'echo "$FLOX_ENV_DIRS"; echo "$PATH"'
+  assert_success
+  # hooks run in order, the environment listed last first
+  assert_equal "$(grep "hook of" <<< "$stderr")" \
+    "hook of $(realpath "$OTHER_DIR")"$'\n'"hook of $(realpath "$PROJECT_DIR")"
+
+  PROJECT_ENV="$(realpath "$PROJECT_DIR")/.flox/run/$NIX_SYSTEM.$PROJECT_NAME"
+  OTHER_ENV="$(realpath "$OTHER_DIR")/.flox/run/$NIX_SYSTEM.other"
+  assert_line --index 0 --regexp "^$PROJECT_ENV:$OTHER_ENV"
+  assert_line --index 1 --regexp "^$PROJECT_ENV/bin:$PROJECT_ENV/sbin:$OTHER_ENV/bin:$OTHER_ENV/sbin:"
+}
+
+# ---------------------------------------------------------------------------- #
+
+# bats test_tags=activate,activate:deactivate
+@test "'flox activate' activates multiple environments in place" {
+  export OTHER_DIR="$BATS_TEST_TMPDIR/other"
+  mkdir -p "$OTHER_DIR"
+  "$FLOX_BIN" init -d "$OTHER_DIR" --name other
+
+  run bash -c '
+    eval "$("$FLOX_BIN" activate -d "$PROJECT_DIR" -d "$OTHER_DIR")"
+    echo "$FLOX_PROMPT_ENVIRONMENTS"
+    eval "$("$FLOX_BIN" deactivate -d "$PROJECT_DIR")"
+    echo "$FLOX_PROMPT_ENVIRONMENTS"
+  '
+  assert_success
+  assert_line --index 0 "$PROJECT_NAME other"
+  assert_line --index 1 "other"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox activate' renders environments with 'prompt_format'" {
+  FLOX_PROMPT_FORMAT='<{name}>( by {owner})' run "$FLOX_BIN" activate -d "$PROJECT_DIR" -- \
+    bash -c 'echo "$FLOX_PROMPT_ENVIRONMENTS"'
+  assert_success
+  assert_line "<$PROJECT_NAME>"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox prompt' prints the active environments" {
+  run "$FLOX_BIN" prompt
+  assert_success
+  assert_output ""
+
+  run "$FLOX_BIN" activate -d "$PROJECT_DIR" -- "$FLOX_BIN" prompt --format '[{name}]'
+  assert_success
+  assert_line "[$PROJECT_NAME]"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox activate' refuses to activate the same environment twice" {
+  run "$FLOX_BIN" activate -d "$PROJECT_DIR" -d "$PROJECT_DIR" -- true
+  assert_failure
+  assert_output --partial "The same environment can not be activated twice."
+}
+
+# ---------------------------------------------------------------------------- #
+
 @test "a6: activate an environment by path" {
   skip FIXME
   # Steps
@@ -357,7 +450,7 @@ env_is_activated() {
   # TODO:
   # better with a flag like '--print-script'
   # this is confusing:
-  SHELL="bash" run "$FLOX_BIN" activate
+  FLOX_SHELL="bash" run "$FLOX_BIN" activate
   assert_success
   # check that env vars are set for compatibility with nix built software
   assert_line --partial "export NIX_SSL_CERT_FILE="
@@ -366,7 +459,7 @@ env_is_activated() {
 
 # bats test_tags=activate,activate:inplace-prints
 @test "'flox activate' prints script to modify current shell (zsh)" {
-  SHELL="zsh" run "$FLOX_BIN" activate
+  FLOX_SHELL="zsh" run "$FLOX_BIN" activate
   assert_success
   # check that env vars are set for compatibility with nix built software
   assert_line --partial "export NIX_SSL_CERT_FILE="
@@ -408,8 +501,193 @@ env_is_activated() {
   assert_line "baz"
 }
 
+# bats test_tags=activate,activate:inplace-prints
+@test "'flox activate' prints script for the parent shell rather than \$SHELL" {
+  SHELL="bash" run zsh -c '"$FLOX_BIN" activate'
+  assert_success
+  assert_output --regexp "source .*/activate/zsh"
+
+  SHELL="zsh" run bash -c '"$FLOX_BIN" activate'
+  assert_success
+  assert_output --regexp "source .*/activate/bash"
+}
+
+# bats test_tags=activate,activate:inplace-prints
+@test "'flox activate' prints environment record for the current shell (nu)" {
+  FLOX_SHELL="nu" run "$FLOX_BIN" activate
+  assert_success
+  # nushell can't source the activation script,
+  # so a record of the changes to the environment is printed instead
+  run jq -e '(.hide | type == "array") and .load.FLOX_ENV and .load.NIX_SSL_CERT_FILE and (.load.PATH | type == "array")' <<< "$output"
+  assert_success
+}
+
+# bats test_tags=activate,activate:inplace-modifies
+@test "'flox activate' modifies the current shell (nu)" {
+
+  # set a hook
+  sed -i -e "s/\[hook\]/${HELLO_HOOK//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+  # set vars
+  sed -i -e "s/\[vars\]/${VARS//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+  "$FLOX_BIN" install hello
+
+  run nu -c 'let activation = (^$env.FLOX_BIN activate | from json); hide-env --ignore-errors ...$activation.hide; load-env $activation.load; ^hello; print $env.foo'
+  assert_success
+  # hook output is redirected to stderr
+  assert_line "Welcome to your flox environment!"
+  # assert installed package
+  assert_line "Hello, world!"
+  # assert var
+  assert_line "baz"
+}
+
+# bats test_tags=activate,activate:nu
+@test "nu: 'flox activate' runs command in the environment" {
+  sed -i -e "s/\[vars\]/${VARS//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+
+  FLOX_SHELL="nu" run "$FLOX_BIN" activate -- print '$env.foo' '"quoted"'
+  assert_success
+  assert_line "baz"
+  assert_line '"quoted"'
+}
+
 # ---------------------------------------------------------------------------- #
 
+# bats test_tags=activate,activate:deactivate
+@test "'flox deactivate' restores the current shell (bash)" {
+  sed -i -e "s/\[vars\]/${VARS//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+  "$FLOX_BIN" install hello
+
+  run bash -c '
+    original_path="$PATH"
+    eval "$("$FLOX_BIN" activate)"
+    eval "$("$FLOX_BIN" deactivate)"
+    [ "$PATH" = "$original_path" ] && echo "path restored"
+    echo "foo=${foo-unset}"
+    echo "active=${_FLOX_ACTIVE_ENVIRONMENTS-unset}"
+    type hello
+  '
+  assert_line "path restored"
+  assert_line "foo=unset"
+  assert_line "active=unset"
+  assert_line --partial "hello: not found"
+}
+
+# bats test_tags=activate,activate:deactivate
+@test "'flox deactivate' restores the current shell (zsh)" {
+  sed -i -e "s/\[vars\]/${VARS//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+
+  run zsh -c '
+    original_path="$PATH"
+    eval "$("$FLOX_BIN" activate)"
+    eval "$("$FLOX_BIN" deactivate)"
+    [ "$PATH" = "$original_path" ] && echo "path restored"
+    echo "foo=${foo-unset}"
+  '
+  assert_success
+  assert_line "path restored"
+  assert_line "foo=unset"
+}
+
+# bats test_tags=activate,activate:deactivate
+@test "'flox deactivate' fails for environments activated in a subshell" {
+  FLOX_SHELL=bash run "$FLOX_BIN" activate -- "$FLOX_BIN" deactivate
+  assert_failure
+  assert_output --partial "was activated in a subshell"
+}
+
+# bats test_tags=activate,activate:pure
+@test "'flox activate --pure' only keeps the environment's paths" {
+  FLOX_SHELL=bash run "$FLOX_BIN" activate --pure -- echo '$PATH=$FLOX_ENV/bin:$FLOX_ENV/sbin'
+  assert_success
+  assert_equal "${output%%=*}" "${output#*=}"
+}
+
+# bats test_tags=activate,activate:pure
+@test "'flox activate --pure' does not inherit the current environment" {
+  FLOX_SHELL=bash FOO=bar LC_FOO=baz run "$FLOX_BIN" activate --pure -- \
+    echo 'foo=${FOO-unset}' 'lc=${LC_FOO-unset}' 'home=$HOME'
+  assert_success
+  assert_output "foo=unset lc=baz home=$HOME"
+}
+
+# bats test_tags=activate,activate:pure
+@test "'flox activate --pure' can't print an activation script" {
+  run "$FLOX_BIN" activate --pure --print-script
+  assert_failure
+  assert_output --partial "'--pure' can only be used"
+}
+
+# bats test_tags=activate,activate:status
+@test "'flox status' lists active environments" {
+  FLOX_SHELL=bash run "$FLOX_BIN" activate -- "$FLOX_BIN" status
+  assert_success
+  assert_output --partial "$PROJECT_NAME"
+  assert_output --partial "Type:       path"
+  assert_output --partial "Location:   $PROJECT_DIR"
+  assert_output --partial "Lockfile:   unchanged since activation"
+}
+
+# bats test_tags=activate,activate:status
+@test "'flox status' reports lockfile changes since activation" {
+  run bash -c '
+    eval "$("$FLOX_BIN" activate)"
+    "$FLOX_BIN" install hello > /dev/null
+    "$FLOX_BIN" status --json
+  '
+  assert_success
+  assert_output --partial '"lockfile_changed": true'
+}
+
+# bats test_tags=activate,activate:status
+@test "'flox status' without active environments" {
+  run "$FLOX_BIN" status --json
+  assert_success
+  assert_output "[]"
+}
+
+# bats test_tags=activate,activate:print-env
+@test "'flox activate --print-env --json' prints the activated environment" {
+  sed -i -e "s/\[vars\]/${VARS//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+  sed -i -e "s/\[hook\]/${HELLO_HOOK//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+
+  run --separate-stderr "$FLOX_BIN" activate --print-env --json
+  assert_success
+  assert_equal "$(jq -r '.foo' <<< "$output")" "baz"
+  flox_env="$(jq -r '.FLOX_ENV' <<< "$output")"
+  [[ "$(jq -r '.PATH' <<< "$output")" == "$flox_env/bin:"* ]]
+  # hook output is printed to stderr
+  [[ "$stderr" == *"Welcome to your flox environment!"* ]]
+}
+
+# bats test_tags=activate,activate:print-env
+@test "'flox activate --json' requires '--print-env'" {
+  run "$FLOX_BIN" activate --json
+  assert_failure
+  assert_output --partial "'--json' can only be used with '--print-env'"
+}
+
+# bats test_tags=activate,activate:direnv
+@test "'flox direnv' watches the files defining the environment" {
+  run "$FLOX_BIN" direnv
+  assert_success
+  assert_line --regexp "^watch_file .*/.flox/env/manifest.toml$"
+  assert_line --regexp "^watch_file .*/.flox/env/manifest.lock$"
+  assert_line --regexp "^watch_file .*/.flox/env.json$"
+}
+
+# bats test_tags=activate,activate:direnv
+@test "'flox direnv' activates the environment with direnv" {
+  sed -i -e "s/\[vars\]/${VARS//$'\n'/\\n}/" "$PROJECT_DIR/.flox/env/manifest.toml"
+
+  echo 'eval "$("$FLOX_BIN" direnv)"' > .envrc
+  direnv allow
+
+  run direnv exec . bash -c 'echo "foo=$foo"'
+  assert_success
+  assert_line "foo=baz"
+}
+
 # bats test_tags=activate,activate:inplace-reactivate
 @test "'flox activate' only patches PATH when already activated" {
   run bash -c 'eval "$("$FLOX_BIN" activate --print-script)"; "$FLOX_BIN" activate --print-script'
@@ -157,6 +157,106 @@ EOF
 
 }
 
+# bats test_tags=containerize:oci-archive
+@test "container can be written as an OCI archive with a name and tag" {
+  skip_if_not_linux
+
+  run "$FLOX_BIN" containerize --format oci-archive --name flox-test --tag v1
+  assert_success
+  assert_output --partial "Image:  flox-test:v1 (oci-archive)"
+  assert_output --regexp "Digest: sha256:[0-9a-f]{64}"
+
+  run podman load -i test-container.oci.tar
+  assert_success
+  assert_line --partial "flox-test:v1"
+}
+
+# bats test_tags=containerize:oci-dir
+@test "container can not be written to stdout as an OCI directory" {
+  run "$FLOX_BIN" containerize --format oci-dir -o -
+  assert_failure
+  assert_output --partial "Images in the 'oci-dir' format can not be written to stdout."
+}
+
+# bats test_tags=containerize:push
+@test "container can not be pushed with a different name" {
+  run "$FLOX_BIN" containerize --push localhost:5000/team/test:v1 --name other
+  assert_failure
+  assert_output --partial "'--name' can not be used with '--push'"
+}
+
+# bats test_tags=containerize:config
+@test "container uses the image config from '[containerize.config]'" {
+  skip_if_not_linux
+
+  cat "$TESTS_DIR/container/manifest.toml" - > "$BATS_TEST_TMPDIR/manifest.toml" << 'EOF'
+
+[containerize.config]
+exposed-ports = ["8080"]
+working-dir = "/tmp"
+labels = { "org.flox.test" = "containerize" }
+cmd = ["echo", "from cmd"]
+EOF
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/manifest.toml"
+
+  CONTAINER_ID="$("$FLOX_BIN" containerize -o - | podman load | sed -nr 's/^Loaded image: (.*)$/\1/p')"
+
+  run podman inspect --format '{{.Config.WorkingDir}} {{index .Config.Labels "org.flox.test"}} {{.Config.ExposedPorts}}' "$CONTAINER_ID"
+  assert_success
+  assert_output --partial "/tmp containerize"
+  assert_output --partial "8080/tcp"
+
+  run --separate-stderr podman run "$CONTAINER_ID"
+  assert_success
+  assert_line "bar"
+  assert_line "from cmd"
+}
+
+# bats test_tags=containerize:config
+@test "container passes every argument of 'cmd' to the command" {
+  skip_if_not_linux
+
+  cat "$TESTS_DIR/container/manifest.toml" - > "$BATS_TEST_TMPDIR/manifest.toml" << 'EOF'
+
+[containerize.config]
+cmd = ["printf", "<%s>\\n", "serve", "--port", "80", "with spaces"]
+EOF
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/manifest.toml"
+
+  CONTAINER_ID="$("$FLOX_BIN" containerize -o - | podman load | sed -nr 's/^Loaded image: (.*)$/\1/p')"
+
+  run --separate-stderr podman run "$CONTAINER_ID"
+  assert_success
+  assert_line "bar"
+  assert_line "<serve>"
+  assert_line "<--port>"
+  assert_line "<80>"
+  assert_line "<with spaces>"
+}
+
+# bats test_tags=containerize:layers
+@test "container has at most 'max-layers' layers" {
+  skip_if_not_linux
+
+  cat "$TESTS_DIR/container/manifest.toml" - > "$BATS_TEST_TMPDIR/manifest.toml" << 'EOF'
+
+[containerize]
+layering = "depth"
+max-layers = 3
+EOF
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/manifest.toml"
+
+  CONTAINER_ID="$("$FLOX_BIN" containerize -o - | podman load | sed -nr 's/^Loaded image: (.*)$/\1/p')"
+
+  run podman inspect --format '{{len .RootFS.Layers}}' "$CONTAINER_ID"
+  assert_success
+  assert_output "3"
+
+  run --separate-stderr podman run "$CONTAINER_ID" hello
+  assert_success
+  assert_line "Hello, world!"
+}
+
 # ---------------------------------------------------------------------------- #
 #
 #
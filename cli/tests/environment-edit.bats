@@ -224,16 +224,61 @@ EOF
   assert_equal "$AFTER" "after"
 }
 
+# bats test_tags=edit:rename-managed
+@test "'flox edit --name' renames a managed environment on FloxHub" {
+  floxhub_setup "owner"
+
+  "$FLOX_BIN" init --name name
+  "$FLOX_BIN" push --owner "owner"
+
+  run "$FLOX_BIN" edit --name "renamed"
+  assert_success
+  assert_output --partial "Renamed environment owner/name to owner/renamed"
+
+  AFTER="$(jq -r .name .flox/env.json)"
+  assert_equal "$AFTER" "renamed"
+
+  run "$FLOX_BIN" list
+  assert_success
+
+  # the environment is only available under its new name
+  run "$FLOX_BIN" pull --remote "owner/name" --dir "$BATS_TEST_TMPDIR/old"
+  assert_failure
+  run "$FLOX_BIN" pull --remote "owner/renamed" --dir "$BATS_TEST_TMPDIR/new"
+  assert_success
+}
+
 # bats test_tags=edit:rename-remote
-@test "'flox edit --name' fails with a remote environment" {
+@test "'flox edit --name' renames a remote environment and the directories linking to it" {
   floxhub_setup "owner"
 
   "$FLOX_BIN" init --name name
   "$FLOX_BIN" push --owner "owner"
 
   run "$FLOX_BIN" edit --remote "owner/name" --name "renamed"
+  assert_success
+
+  AFTER="$(jq -r .name .flox/env.json)"
+  assert_equal "$AFTER" "renamed"
+}
+
+# bats test_tags=edit:rename-existing
+@test "'flox edit --name' does not overwrite an existing environment on FloxHub" {
+  floxhub_setup "owner"
+
+  mkdir other
+  "$FLOX_BIN" init --name other --dir other
+  "$FLOX_BIN" push --owner "owner" --dir other
+
+  "$FLOX_BIN" init --name name
+  "$FLOX_BIN" push --owner "owner"
+
+  run "$FLOX_BIN" edit --name "other"
   assert_failure
-  assert_output --partial "Cannot rename environments on FloxHub"
+  assert_output --partial "An environment named 'owner/other' already exists."
+
+  AFTER="$(jq -r .name .flox/env.json)"
+  assert_equal "$AFTER" "name"
 }
 
 # ---------------------------------------------------------------------------- #
@@ -176,8 +176,43 @@ EOF
   popd > /dev/null || return
 }
 
+# bats test_tags=managed,update,managed:dry-run
+@test "m6: updates can be previewed without pulling them" {
+  mkdir a a_data
+  mkdir b b_data
+
+  # on machine a, create and push the (empty) environment
+  export FLOX_DATA_DIR="$(pwd)/a_data"
+  pushd a > /dev/null || return
+  "$FLOX_BIN" init
+  "$FLOX_BIN" push --owner "$OWNER"
+  popd > /dev/null || return
+
+  # on another b machine, pull the environment, install a package and push it
+  export FLOX_DATA_DIR="$(pwd)/b_data"
+  pushd b > /dev/null || return
+  "$FLOX_BIN" pull --remote "$OWNER/a"
+  "$FLOX_BIN" install hello
+  "$FLOX_BIN" push --owner "$OWNER"
+  popd > /dev/null || return
+
+  # on machine a, preview the update
+  export FLOX_DATA_DIR="$(pwd)/a_data"
+  pushd a > /dev/null || return
+  run "$FLOX_BIN" pull --dry-run
+  assert_success
+  assert_output --partial 'installed packages: ["hello"]'
+  assert_output --partial "Only installed in FloxHub: hello"
+  assert_output --partial "Pulling would add the generations in FloxHub listed above"
+
+  # assert that the package was not installed
+  run --separate-stderr "$FLOX_BIN" list --name
+  refute_output "hello"
+  popd > /dev/null || return
+}
+
 # bats test_tags=managed,diverged,managed:diverged
-@test "m7: remote can not be pulled into diverged environment" {
+@test "m7: remote is merged when pulling into diverged environment" {
   mkdir a a_data
   mkdir b b_data
 
@@ -199,24 +234,29 @@ EOF
   "$FLOX_BIN" push --owner "$OWNER"
   popd > /dev/null || return
 
-  # on machine a, pull the environment
-  # and check that the package is installed
+  # on machine a, install another package and pull the environment
   export FLOX_DATA_DIR="$(pwd)/a_data"
   pushd a > /dev/null || return
   run "$FLOX_BIN" install emacs
-  # assert that pulling fails
+  # assert that pulling merges the changes
   run "$FLOX_BIN" pull
-  assert_failure
-  # assert that the environment contains the installed package
-  assert_output --partial "diverged"
-
-  # assert that pulling with `--force` succeeds
-  run "$FLOX_BIN" pull --force
   assert_success
+  assert_output --partial "merging local and upstream changes"
+
+  # assert that the environment contains both packages
+  run --separate-stderr "$FLOX_BIN" list --name
+  assert_line "emacs"
+  assert_line "vim"
 
   popd > /dev/null || return
 }
 
+# set a variable in the manifest of the environment in the current directory
+function set_var() {
+  "$FLOX_BIN" list --config | tomlq -t ".vars.$1 = \"$2\"" > "$BATS_TEST_TMPDIR/manifest.toml"
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/manifest.toml"
+}
+
 # bats test_tags=managed,diverged,managed:diverged-upstream
 @test "m8: remote can be force pulled into diverged environment" {
   mkdir a
@@ -230,11 +270,11 @@ EOF
 
   pushd b > /dev/null || return
   FLOX_DATA_DIR="$(pwd)/b_data" "$FLOX_BIN" pull --remote "$OWNER/a"
-  FLOX_DATA_DIR="$(pwd)/b_data" "$FLOX_BIN" install vim
+  FLOX_DATA_DIR="$(pwd)/b_data" set_var foo b
   popd > /dev/null || return
 
   pushd a > /dev/null || return
-  FLOX_DATA_DIR="$(pwd)/a_data" "$FLOX_BIN" install emacs
+  FLOX_DATA_DIR="$(pwd)/a_data" set_var foo a
   FLOX_DATA_DIR="$(pwd)/a_data" "$FLOX_BIN" push
   popd > /dev/null || return
 
@@ -245,6 +285,8 @@ EOF
   pushd a > /dev/null || return
   FLOX_DATA_DIR="$(pwd)/a_data" run "$FLOX_BIN" pull
   assert_failure
+  assert_output --partial "conflict in:"
+  assert_output --partial "vars.foo"
   FLOX_DATA_DIR="$(pwd)/a_data" run "$FLOX_BIN" pull --force
   assert_success
   popd > /dev/null || return
@@ -324,6 +366,19 @@ EOF
   refute_output "vim"
 }
 
+# bats test_tags=managed,activate,managed:status
+@test "m12: status shows the generation that was activated" {
+  make_empty_remote_env
+
+  run bash -c '
+    eval "$("$FLOX_BIN" activate)"
+    "$FLOX_BIN" install hello > /dev/null
+    "$FLOX_BIN" status --json
+  '
+  assert_success
+  assert_output --partial '"generation": 1'
+}
+
 @test "sanity check upgrade works for managed environments" {
   _PKGDB_GA_REGISTRY_REF_OR_REV="${PKGDB_NIXPKGS_REV_OLD?}" \
   make_empty_remote_env
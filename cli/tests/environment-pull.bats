@@ -470,3 +470,39 @@ function add_insecure_package() {
   assert_success
   assert_line --partial "already up to date."
 }
+
+# ---------------------------------------------------------------------------- #
+
+# forks are owned by the logged in user ('test')
+function floxhub_setup_fork_owner() {
+  git -C "$FLOX_FLOXHUB_PATH" init --bare test/floxmeta
+}
+
+# bats test_tags=pull:fork
+@test "fork: copies the environment of another owner into the user's namespace" {
+  floxhub_setup_fork_owner
+
+  run "$FLOX_BIN" fork owner/name --as mine
+  assert_success
+  assert_output --partial "Forked owner/name to test/mine"
+  assert [ $(cat .flox/env.json | jq -r '.name') == "mine" ]
+  assert [ $(cat .flox/env.json | jq -r '.owner') == "test" ]
+
+  # the generation history is copied and the original environment is recorded
+  run git -C "$FLOX_FLOXHUB_PATH/test/floxmeta" show mine:metadata.json
+  assert_success
+  assert [ $(echo "$output" | jq -r '.forkedFrom.environment') == "owner/name" ]
+  assert [ $(echo "$output" | jq -r '.generations | length') == "1" ]
+}
+
+# bats test_tags=pull:fork
+@test "fork: fails if the user already owns an environment with that name" {
+  floxhub_setup_fork_owner
+
+  "$FLOX_BIN" fork owner/name --dir ./first
+
+  run "$FLOX_BIN" fork owner/name --dir ./second
+  assert_failure
+  assert_output --partial "An environment named 'test/name' already exists."
+  assert [ ! -e "second/.flox" ]
+}
@@ -109,6 +109,36 @@ function update_dummy_env() {
   popd > /dev/null || return
 }
 
+# bats test_tags=push:self-hosted
+@test "push: self-hosted: environments of owners with a floxmeta remote are pushed there without logging in to FloxHub" {
+  unset FLOX_FLOXHUB_TOKEN # logout, effectively
+
+  SELF_HOSTED="$BATS_TEST_TMPDIR/self-hosted"
+  mkdir -p "$SELF_HOSTED/myorg/floxmeta"
+  git -C "$SELF_HOSTED/myorg/floxmeta" init --bare
+  "$FLOX_BIN" config --set 'floxmeta_remotes.myorg' "file://$SELF_HOSTED"
+
+  mkdir -p "machine_a"
+  mkdir -p "machine_b"
+
+  pushd "machine_a" > /dev/null || return
+  "$FLOX_BIN" init --name "test"
+  "$FLOX_BIN" install hello
+  run "$FLOX_BIN" push --owner myorg
+  assert_success
+  assert_output --partial "successfully pushed to file://$SELF_HOSTED"
+  popd > /dev/null || return
+
+  pushd "machine_b" > /dev/null || return
+  run "$FLOX_BIN" pull --remote myorg/test
+  assert_success
+
+  run "$FLOX_BIN" list --name
+  assert_success
+  assert_line "hello"
+  popd > /dev/null || return
+}
+
 # bats test_tags=push:h5
 @test "h5: unique upstream environments: if you attempt to flox push an environment with the same name but different provenance from upstream then the push should fail with a message." {
   mkdir -p "machine_a"
@@ -163,6 +193,71 @@ function update_dummy_env() {
   popd > /dev/null || return
 }
 
+# bats test_tags=push:merge
+@test "push: merge: packages installed into a diverged environment on different machines are merged" {
+  mkdir -p "machine_a" "machine_b" "machine_c"
+
+  pushd "machine_a" > /dev/null || return
+  "$FLOX_BIN" init --name "test"
+  "$FLOX_BIN" push --owner owner
+  popd > /dev/null || return
+
+  pushd "machine_b" > /dev/null || return
+  "$FLOX_BIN" pull --remote owner/test
+  "$FLOX_BIN" install emacs
+  "$FLOX_BIN" push
+  popd > /dev/null || return
+
+  # push a change that diverged from the upstream environment
+  pushd "machine_a" > /dev/null || return
+  "$FLOX_BIN" install vim
+  run "$FLOX_BIN" push
+  assert_success
+  assert_output --partial "merging local and upstream changes"
+  popd > /dev/null || return
+
+  pushd "machine_c" > /dev/null || return
+  "$FLOX_BIN" pull --remote owner/test
+  run --separate-stderr "$FLOX_BIN" list --name
+  assert_success
+  assert_line "emacs"
+  assert_line "vim"
+  popd > /dev/null || return
+}
+
+# bats test_tags=push:dry-run
+@test "push: dry-run: shows the upstream changes a forced push would overwrite" {
+  mkdir -p "machine_a" "machine_b"
+
+  pushd "machine_a" > /dev/null || return
+  "$FLOX_BIN" init --name "test"
+  "$FLOX_BIN" push --owner owner
+  popd > /dev/null || return
+
+  pushd "machine_b" > /dev/null || return
+  "$FLOX_BIN" pull --remote owner/test
+  "$FLOX_BIN" install emacs
+  "$FLOX_BIN" push
+  popd > /dev/null || return
+
+  pushd "machine_a" > /dev/null || return
+  "$FLOX_BIN" install vim
+  run "$FLOX_BIN" push --force --dry-run
+  assert_success
+  assert_output --partial "Generations in FloxHub not in the local environment:"
+  assert_output --partial "Only installed locally: vim"
+  assert_output --partial "Only installed in FloxHub: emacs"
+  assert_output --partial "Pushing with '--force' would discard the generations in FloxHub listed above."
+  popd > /dev/null || return
+
+  # assert that nothing was pushed
+  pushd "machine_b" > /dev/null || return
+  run "$FLOX_BIN" pull --dry-run
+  assert_success
+  assert_output --partial "owner/test is up to date with FloxHub."
+  popd > /dev/null || return
+}
+
 # bats test_tags=push:broken
 @test "push: broken: if you attempt to flox push an environment that fails to build then the push should fail with a message." {
   run "$FLOX_BIN" init
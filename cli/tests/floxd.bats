@@ -0,0 +1,94 @@
+#! /usr/bin/env bats
+# -*- mode: bats; -*-
+# ============================================================================ #
+#
+# Test `floxd' tracking activations and cleaning up after them.
+#
+# ---------------------------------------------------------------------------- #
+
+load test_support.bash
+
+# bats file_tags=floxd
+
+# ---------------------------------------------------------------------------- #
+
+setup_file() {
+  common_file_setup
+}
+
+# ---------------------------------------------------------------------------- #
+
+project_setup() {
+  export PROJECT_DIR="${BATS_TEST_TMPDIR?}/project-${BATS_TEST_NUMBER?}"
+  export PROJECT_NAME="${PROJECT_DIR##*/}"
+  rm -rf "$PROJECT_DIR"
+  mkdir -p "$PROJECT_DIR"
+  pushd "$PROJECT_DIR" > /dev/null || return
+  "$FLOX_BIN" init
+}
+
+project_teardown() {
+  popd > /dev/null || return
+  rm -rf "${PROJECT_DIR?}"
+  unset PROJECT_DIR
+  unset PROJECT_NAME
+}
+
+# ---------------------------------------------------------------------------- #
+
+setup() {
+  common_test_setup
+  project_setup
+}
+teardown() {
+  project_teardown
+  common_test_teardown
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox status --all' lists environments active in any process" {
+  cat > "$BATS_TEST_TMPDIR/manifest.toml" << 'EOF'
+version = 1
+
+[hook]
+on-deactivate = 'true'
+EOF
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/manifest.toml"
+
+  run "$FLOX_BIN" activate -- "$FLOX_BIN" status --all --json
+  assert_success
+  assert_equal "$(jq -r '.[0].name' <<< "$output")" "$PROJECT_NAME"
+}
+
+@test "activations without cleanup don't start floxd" {
+  run "$FLOX_BIN" activate -- "$FLOX_BIN" status --all --json
+  assert_success
+  assert_output "[]"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "floxd runs 'hook.on-deactivate' after the last activation exited" {
+  cat > "$BATS_TEST_TMPDIR/manifest.toml" << 'EOF'
+version = 1
+
+[hook]
+on-deactivate = 'touch "$FLOX_ENV_PROJECT/deactivated"'
+EOF
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/manifest.toml"
+
+  run "$FLOX_BIN" activate -- true
+  assert_success
+
+  # floxd polls for exited processes
+  for _ in {1..50}; do
+    [[ -e "$PROJECT_DIR/deactivated" ]] && break
+    sleep 0.1
+  done
+  assert [ -e "$PROJECT_DIR/deactivated" ]
+
+  run "$FLOX_BIN" status --all --json
+  assert_success
+  assert_output "[]"
+}
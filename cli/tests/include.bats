@@ -0,0 +1,101 @@
+#! /usr/bin/env bats
+# -*- mode: bats; -*-
+# ============================================================================ #
+#
+# Test composing environments with `include' in the manifest.
+#
+# ---------------------------------------------------------------------------- #
+
+load test_support.bash
+
+# bats file_tags=include
+
+# ---------------------------------------------------------------------------- #
+
+setup_file() {
+  common_file_setup
+}
+
+# ---------------------------------------------------------------------------- #
+
+project_setup() {
+  export PROJECT_DIR="${BATS_TEST_TMPDIR?}/project-${BATS_TEST_NUMBER?}"
+  rm -rf "$PROJECT_DIR"
+  mkdir -p "$PROJECT_DIR/toolchain" "$PROJECT_DIR/app"
+  "$FLOX_BIN" init -d "$PROJECT_DIR/toolchain"
+  "$FLOX_BIN" init -d "$PROJECT_DIR/app"
+  pushd "$PROJECT_DIR/app" > /dev/null || return
+}
+
+project_teardown() {
+  popd > /dev/null || return
+  rm -rf "${PROJECT_DIR?}"
+  unset PROJECT_DIR
+}
+
+# ---------------------------------------------------------------------------- #
+
+setup() {
+  common_test_setup
+  project_setup
+}
+teardown() {
+  project_teardown
+  common_test_teardown
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "included environments are merged into the manifest" {
+  cat > "$BATS_TEST_TMPDIR/toolchain.toml" << 'EOF'
+version = 1
+
+[vars]
+CC = "gcc"
+LD = "ld"
+
+[hook]
+on-activate = 'echo "toolchain hook"'
+EOF
+  "$FLOX_BIN" edit -d "$PROJECT_DIR/toolchain" -f "$BATS_TEST_TMPDIR/toolchain.toml"
+
+  cat > "$BATS_TEST_TMPDIR/app.toml" << 'EOF'
+version = 1
+
+[include]
+environments = [{ dir = "../toolchain" }]
+
+[vars]
+CC = "clang"
+
+[hook]
+on-activate = 'echo "app hook"'
+EOF
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/app.toml"
+
+  run "$FLOX_BIN" activate -- bash -c 'echo "$CC $LD"'
+  assert_success
+  assert_line "clang ld"
+  assert_output --regexp "toolchain hook.*app hook"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "conflicting included environments are rejected" {
+  mkdir -p "$PROJECT_DIR/other"
+  "$FLOX_BIN" init -d "$PROJECT_DIR/other"
+  printf 'version = 1\n[vars]\nCC = "gcc"\n' > "$BATS_TEST_TMPDIR/toolchain.toml"
+  "$FLOX_BIN" edit -d "$PROJECT_DIR/toolchain" -f "$BATS_TEST_TMPDIR/toolchain.toml"
+  printf 'version = 1\n[vars]\nCC = "clang"\n' > "$BATS_TEST_TMPDIR/other.toml"
+  "$FLOX_BIN" edit -d "$PROJECT_DIR/other" -f "$BATS_TEST_TMPDIR/other.toml"
+
+  cat > "$BATS_TEST_TMPDIR/app.toml" << 'EOF'
+version = 1
+
+[include]
+environments = [{ dir = "../toolchain" }, { dir = "../other" }]
+EOF
+  run "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/app.toml"
+  assert_failure
+  assert_output --partial "'vars.CC' is defined differently"
+}
@@ -0,0 +1,101 @@
+#! /usr/bin/env bats
+# -*- mode: bats; -*-
+# ============================================================================ #
+#
+# Test the `flox services' subcommand.
+#
+# ---------------------------------------------------------------------------- #
+
+load test_support.bash
+
+# bats file_tags=services
+
+# ---------------------------------------------------------------------------- #
+
+setup_file() {
+  common_file_setup
+}
+
+# ---------------------------------------------------------------------------- #
+
+project_setup() {
+  export PROJECT_DIR="${BATS_TEST_TMPDIR?}/project-${BATS_TEST_NUMBER?}"
+  rm -rf "$PROJECT_DIR"
+  mkdir -p "$PROJECT_DIR"
+  pushd "$PROJECT_DIR" > /dev/null || return
+  "$FLOX_BIN" init
+
+  cat > "$BATS_TEST_TMPDIR/manifest.toml" << 'EOF'
+version = 1
+
+[vars]
+GREETING = "hello"
+
+[services.greeter]
+command = 'echo "$GREETING from $NAME"; touch "$FLOX_ENV_CACHE/greeter-ready"; sleep 100'
+vars = { NAME = "greeter" }
+is-ready = 'test -e "$FLOX_ENV_CACHE/greeter-ready"'
+EOF
+  "$FLOX_BIN" edit -f "$BATS_TEST_TMPDIR/manifest.toml"
+}
+
+project_teardown() {
+  "$FLOX_BIN" services stop || true
+  popd > /dev/null || return
+  rm -rf "${PROJECT_DIR?}"
+  unset PROJECT_DIR
+}
+
+# ---------------------------------------------------------------------------- #
+
+setup() {
+  common_test_setup
+  project_setup
+}
+teardown() {
+  project_teardown
+  common_test_teardown
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox services start' runs services in the environment" {
+  run "$FLOX_BIN" services start
+  assert_success
+  assert_output --partial "Service 'greeter' started"
+
+  run "$FLOX_BIN" services status
+  assert_success
+  assert_line --regexp "^greeter +ready +[0-9]+$"
+
+  run "$FLOX_BIN" services logs greeter
+  assert_success
+  assert_output "hello from greeter"
+
+  run "$FLOX_BIN" services stop
+  assert_success
+  assert_output --partial "Service 'greeter' stopped"
+
+  run "$FLOX_BIN" services status --json
+  assert_success
+  assert_equal "$(jq -r '.[0].status' <<< "$output")" "stopped"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox services start' rejects undeclared services" {
+  run "$FLOX_BIN" services start postgres
+  assert_failure
+  assert_output --partial "Service 'postgres' is not declared in the manifest."
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox activate --start-services' starts services" {
+  run "$FLOX_BIN" activate --start-services -- true
+  assert_success
+
+  run "$FLOX_BIN" services status greeter
+  assert_success
+  assert_line --regexp "^greeter +ready +[0-9]+$"
+}
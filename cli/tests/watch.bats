@@ -0,0 +1,93 @@
+#! /usr/bin/env bats
+# -*- mode: bats; -*-
+# ============================================================================ #
+#
+# Test the `flox watch' subcommand.
+#
+# ---------------------------------------------------------------------------- #
+
+load test_support.bash
+
+# bats file_tags=watch
+
+# ---------------------------------------------------------------------------- #
+
+setup_file() {
+  common_file_setup
+}
+
+# ---------------------------------------------------------------------------- #
+
+project_setup() {
+  export PROJECT_DIR="${BATS_TEST_TMPDIR?}/project-${BATS_TEST_NUMBER?}"
+  rm -rf "$PROJECT_DIR"
+  mkdir -p "$PROJECT_DIR"
+  pushd "$PROJECT_DIR" > /dev/null || return
+  "$FLOX_BIN" init
+}
+
+project_teardown() {
+  if [[ -n "${WATCH_PID:-}" ]]; then
+    kill "$WATCH_PID" || true
+    unset WATCH_PID
+  fi
+  popd > /dev/null || return
+  rm -rf "${PROJECT_DIR?}"
+  unset PROJECT_DIR
+}
+
+# ---------------------------------------------------------------------------- #
+
+setup() {
+  common_test_setup
+  project_setup
+}
+teardown() {
+  project_teardown
+  common_test_teardown
+}
+
+# ---------------------------------------------------------------------------- #
+
+# Wait for the latest prebuild to reach the given status
+wait_for_prebuild() {
+  for _ in {1..100}; do
+    if [[ "$(jq -r '.status' "$PROJECT_DIR"/.flox/cache/prebuild.*.json 2> /dev/null)" == "$1" ]]; then
+      return 0
+    fi
+    sleep 0.1
+  done
+  return 1
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox watch' rebuilds the environment when the manifest changes" {
+  "$FLOX_BIN" watch -d "$PROJECT_DIR" 3>&- &
+  export WATCH_PID="$!"
+
+  run wait_for_prebuild built
+  assert_success
+
+  cat > "$PROJECT_DIR/.flox/env/manifest.toml" << 'EOF'
+version = 1
+
+[install]
+hello-does-not-exist.pkg-path = "hello-does-not-exist"
+EOF
+
+  run wait_for_prebuild failed
+  assert_success
+
+  run jq -r '.error' "$PROJECT_DIR"/.flox/cache/prebuild.*.json
+  assert_success
+  refute_output "null"
+}
+
+# ---------------------------------------------------------------------------- #
+
+@test "'flox watch' fails for directories without an environment" {
+  run "$FLOX_BIN" watch -d "$BATS_TEST_TMPDIR/no-environment"
+  assert_failure
+  assert_output --partial "Did not find an environment"
+}
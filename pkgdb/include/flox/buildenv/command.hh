@@ -33,12 +33,15 @@ class BuildEnvCommand : NixState
 
 private:
 
-  command::VerboseParser     parser;
-  nlohmann::json             lockfileContent;
-  std::optional<std::string> outLink;
-  std::optional<System>      system;
-  std::optional<std::string> storePath;
-  bool                       buildContainer;
+  command::VerboseParser        parser;
+  nlohmann::json                lockfileContent;
+  std::optional<std::string>    outLink;
+  std::optional<System>         system;
+  std::optional<std::string>    storePath;
+  bool                          buildContainer;
+  std::optional<nlohmann::json> containerConfig;
+  std::optional<std::string>    containerLayering;
+  std::optional<unsigned>       containerMaxLayers;
 
 
 public:
@@ -16,6 +16,7 @@
 
 #include <nix/eval.hh>
 #include <nix/store-api.hh>
+#include <nlohmann/json.hpp>
 
 #include "flox/buildenv/buildenv.hh"
 #include "flox/core/exceptions.hh"
@@ -349,12 +350,20 @@ createEnvironmentStorePath(
  * @param state A `nix` evaluator.
  * @param environmentStorePath A storepath containing a realised environment.
  * @param system system to build the environment for.
+ * @param containerConfig OCI image config merged into the default config,
+ *                        e.g. `Cmd`, `User` or `ExposedPorts`.
+ * @param layering Order in which store paths get a layer of their own,
+ *                 `popularity` or `depth`.
+ * @param maxLayers Maximum number of layers of the container.
  * @return A @a nix::StorePath to a container builder.
  */
 nix::StorePath
-createContainerBuilder( nix::EvalState &       state,
-                        const nix::StorePath & environmentStorePath,
-                        const System &         system );
+createContainerBuilder( nix::EvalState &                   state,
+                        const nix::StorePath &             environmentStorePath,
+                        const System &                     system,
+                        const nlohmann::json &             containerConfig,
+                        const std::optional<std::string> & layering,
+                        std::optional<unsigned>            maxLayers );
 
 
 /* -------------------------------------------------------------------------- */
@@ -278,6 +278,10 @@ struct HookRaw
    * after the user's profile scripts have been sourced.*/
   std::optional<std::string> onActivate;
 
+  /** Defines an inline script to be run non-interactively from a bash subshell
+   * once the environment is no longer active in any process. */
+  std::optional<std::string> onDeactivate;
+
 
   /**
    * @brief Validate `Hook` fields, throwing an exception if its contents
@@ -314,6 +318,25 @@ struct ManifestRaw : public GlobalManifestRaw
 
   std::optional<HookRaw> hook;
 
+  /**
+   * Background services run by `flox services`.
+   * Their contents are interpreted and validated by the CLI.
+   */
+  std::optional<nlohmann::json> services;
+
+  /**
+   * Other environments this environment is composed with.
+   * The CLI merges them into the manifest before locking,
+   * and records the generations it included.
+   */
+  std::optional<nlohmann::json> include;
+
+  /**
+   * Options for `flox containerize`, e.g. the image config.
+   * These are validated by the CLI.
+   */
+  std::optional<nlohmann::json> containerize;
+
 
   ~ManifestRaw() override            = default;
   ManifestRaw()                      = default;
@@ -359,6 +382,9 @@ struct ManifestRaw : public GlobalManifestRaw
    * - @a registry does not contain indirect flake references.
    * - All members of @a install are valid.
    * - @a hook is valid.
+   * - @a services is an object.
+   * - @a include is an object.
+   * - @a containerize is an object.
    */
   void
   check() const override;
@@ -372,8 +398,11 @@ struct ManifestRaw : public GlobalManifestRaw
     /* From `ManifestRaw' */
     this->envBase = std::nullopt;
     this->install = std::nullopt;
-    this->vars    = std::nullopt;
-    this->hook    = std::nullopt;
+    this->vars         = std::nullopt;
+    this->hook         = std::nullopt;
+    this->services     = std::nullopt;
+    this->include      = std::nullopt;
+    this->containerize = std::nullopt;
   }
 
   /**
@@ -501,6 +530,25 @@ struct ManifestRawGA : public GlobalManifestRawGA
 
   std::optional<HookRaw> hook;
 
+  /**
+   * Background services run by `flox services`.
+   * Their contents are interpreted and validated by the CLI.
+   */
+  std::optional<nlohmann::json> services;
+
+  /**
+   * Other environments this environment is composed with.
+   * The CLI merges them into the manifest before locking,
+   * and records the generations it included.
+   */
+  std::optional<nlohmann::json> include;
+
+  /**
+   * Options for `flox containerize`, e.g. the image config.
+   * These are validated by the CLI.
+   */
+  std::optional<nlohmann::json> containerize;
+
 
   ~ManifestRawGA() override              = default;
   ManifestRawGA()                        = default;
@@ -544,6 +592,9 @@ struct ManifestRawGA : public GlobalManifestRawGA
    * This asserts:
    * - All members of @a install are valid.
    * - @a hook is valid.
+   * - @a services is an object.
+   * - @a include is an object.
+   * - @a containerize is an object.
    */
   void
   check() const override;
@@ -555,8 +606,11 @@ struct ManifestRawGA : public GlobalManifestRawGA
     this->options = std::nullopt;
     /* From `ManifestRawGA' */
     this->install = std::nullopt;
-    this->vars    = std::nullopt;
-    this->hook    = std::nullopt;
+    this->vars         = std::nullopt;
+    this->hook         = std::nullopt;
+    this->services     = std::nullopt;
+    this->include      = std::nullopt;
+    this->containerize = std::nullopt;
   }
 
   /**
@@ -570,11 +624,14 @@ struct ManifestRawGA : public GlobalManifestRawGA
   explicit operator ManifestRaw() const
   {
     ManifestRaw raw;
-    raw.registry = getGARegistry();
-    raw.options  = this->options;
-    raw.install  = this->install;
-    raw.vars     = this->vars;
-    raw.hook     = this->hook;
+    raw.registry     = getGARegistry();
+    raw.options      = this->options;
+    raw.install      = this->install;
+    raw.vars         = this->vars;
+    raw.hook         = this->hook;
+    raw.services     = this->services;
+    raw.include      = this->include;
+    raw.containerize = this->containerize;
     return raw;
   }
 
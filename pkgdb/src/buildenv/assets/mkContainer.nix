@@ -8,15 +8,82 @@
   # the system to build for
   system,
   containerSystem,
+  # OCI image config from the manifest's `[containerize.config]`,
+  # merged into the default config below
+  containerConfigJSON ? "{}",
+  # order in which store paths get a layer of their own,
+  # either "popularity" or "depth"
+  layering ? "popularity",
+  # maximum number of layers, the remaining store paths share the last layer
+  maxLayers ? 100,
 }: let
   environment = builtins.storePath environmentOutPath;
+  containerConfig = builtins.fromJSON containerConfigJSON;
   pkgs = nixpkgsFlake.legacyPackages.${system};
   containerPkgs = nixpkgsFlake.legacyPackages.${containerSystem};
   lib = pkgs.lib;
+  # Order store paths by their depth, the longest chain of references
+  # from the image contents to them,
+  # so that the packages of the environment get layers of their own
+  # and only deep (rarely changing) dependencies share the last layer.
+  # Paths at the same depth are ordered by size, largest first.
+  orderByDepth = pkgs.buildPackages.writeText "order-by-depth.py" ''
+    import json
+    import sys
+
+    with open(sys.argv[1]) as f:
+        attrs = json.load(f)
+
+    references = {
+        node["path"]: [ref for ref in node["references"] if ref != node["path"]]
+        for node in attrs["graph"]
+    }
+    sizes = {node["path"]: node["narSize"] for node in attrs["graph"]}
+
+    # visit paths in topological order, after all paths referencing them
+    referrers = {path: 0 for path in references}
+    for refs in references.values():
+        for ref in refs:
+            referrers[ref] += 1
+
+    depths = {attrs["root"]: 0}
+    queue = [attrs["root"]]
+    for path in queue:
+        for ref in references[path]:
+            depths[ref] = max(depths.get(ref, 0), depths[path] + 1)
+            referrers[ref] -= 1
+            if referrers[ref] == 0:
+                queue.append(ref)
+
+    for path in sorted(depths, key=lambda path: (depths[path], -sizes[path], path)):
+        print(path)
+  '';
+  # A drop-in replacement for `referencesByPopularity`,
+  # which `streamLayeredImage` uses to assign store paths to layers
+  referencesByDepth = path:
+    pkgs.buildPackages.runCommand "closure-paths" {
+      exportReferencesGraph.graph = path;
+      root = path;
+      __structuredAttrs = true;
+      preferLocalBuild = true;
+      nativeBuildInputs = [pkgs.buildPackages.python3];
+    } ''
+      python3 ${orderByDepth} .attrs.json > ''${outputs[out]}
+    '';
+  dockerTools =
+    if layering == "depth"
+    then
+      pkgs.dockerTools.override {
+        buildPackages = pkgs.buildPackages // {referencesByPopularity = referencesByDepth;};
+      }
+    else if layering == "popularity"
+    then pkgs.dockerTools
+    else throw "unknown layering '${layering}', expected 'popularity' or 'depth'";
   lowPriority = pkg: pkg.overrideAttrs (old: old // {meta = (old.meta or {}) // {priority = 10000;};});
 
   buildLayeredImageArgs = {
     name = "flox-env-container";
+    inherit maxLayers;
     # symlinkJoin fails when drv contains a symlinked bin directory, so wrap in an additional buildEnv
     contents = pkgs.buildEnv {
       name = "contents";
@@ -26,31 +93,46 @@
         (lowPriority containerPkgs.coreutils) # for just the basic utils
       ];
     };
-    config = {
-      # * run -it # [interactive, no args]
-      #   -> runs <Entrypoint> <Cmd>
-      #   -> bash -c -i bash --rcfile <activate>
-      #   (skip activation for the first bash and runs default rcfiles)
-      #
-      # * run cmd... # [non-interactive, with arguments]
-      #   -> BASH_ENV=<activate> bash -c cmd
-
-      # * follow convention of sh -c being container entrypoint
-      Entrypoint = ["${containerPkgs.bashInteractive}/bin/bash" "-c"];
-
-      Env = lib.mapAttrsToList (name: value: "${name}=${value}") {
-        "FLOX_ENV" = environment;
-        "FLOX_PROMPT_ENVIRONMENTS" = "floxenv";
-        "FLOX_PROMPT_COLOR_1" = "99";
-        "FLOX_PROMPT_COLOR_2" = "141";
-        "_FLOX_ACTIVE_ENVIRONMENTS" = "[]";
-        "FLOX_SOURCED_FROM_SHELL_RC" = "1"; # don't source from shell rc (again)
-        "BASH_ENV" = "${environment}/activate/bash";
+    config =
+      defaultConfig
+      // (builtins.removeAttrs containerConfig ["Env" "Entrypoint"])
+      // {
+        Env = defaultConfig.Env ++ (containerConfig.Env or []);
+      }
+      // lib.optionalAttrs (containerConfig ? Entrypoint || containerConfig ? Cmd) {
+        # run the entrypoint, or the command if there is none, through bash,
+        # which activates the environment with BASH_ENV,
+        # passing on every argument as is
+        Entrypoint = ["${containerPkgs.bashInteractive}/bin/bash" "-c" "exec \"$@\"" "--"] ++ (containerConfig.Entrypoint or []);
+        # as in a Dockerfile, setting the entrypoint drops the default Cmd
+        Cmd = containerConfig.Cmd or [];
       };
+  };
+
+  defaultConfig = {
+    # * run -it # [interactive, no args]
+    #   -> runs <Entrypoint> <Cmd>
+    #   -> bash -c -i bash --rcfile <activate>
+    #   (skip activation for the first bash and runs default rcfiles)
+    #
+    # * run cmd... # [non-interactive, with arguments]
+    #   -> BASH_ENV=<activate> bash -c cmd
 
-      # source original .bashrc, then start another shell that runs activation
-      Cmd = ["-i" "${containerPkgs.bashInteractive}/bin/bash --rcfile ${environment}/activate/bash"];
+    # * follow convention of sh -c being container entrypoint
+    Entrypoint = ["${containerPkgs.bashInteractive}/bin/bash" "-c"];
+
+    Env = lib.mapAttrsToList (name: value: "${name}=${value}") {
+      "FLOX_ENV" = environment;
+      "FLOX_PROMPT_ENVIRONMENTS" = "floxenv";
+      "FLOX_PROMPT_COLOR_1" = "99";
+      "FLOX_PROMPT_COLOR_2" = "141";
+      "_FLOX_ACTIVE_ENVIRONMENTS" = "[]";
+      "FLOX_SOURCED_FROM_SHELL_RC" = "1"; # don't source from shell rc (again)
+      "BASH_ENV" = "${environment}/activate/bash";
     };
+
+    # source original .bashrc, then start another shell that runs activation
+    Cmd = ["-i" "${containerPkgs.bashInteractive}/bin/bash --rcfile ${environment}/activate/bash"];
   };
 in
-  pkgs.dockerTools.streamLayeredImage buildLayeredImageArgs
+  dockerTools.streamLayeredImage buildLayeredImageArgs
@@ -1,4 +1,5 @@
 # Tweak the (already customized) prompt: add a flox indicator.
+# Setting FLOX_SET_PROMPT=false leaves the prompt alone.
 
 _esc="\x1b["
 colorReset="\[${_esc}0m\]"
@@ -21,7 +22,7 @@ fi
 
 unset _esc colorReset colorBold colorPrompt1 colorPrompt2 _floxPrompt1 _floxPrompt2
 
-if [ -n "$_flox" ] && [ -n "${PS1:-}" ]; then
+if [ -n "$_flox" ] && [ -n "${PS1:-}" ] && [ "${FLOX_SET_PROMPT:-true}" != false ]; then
   # Start by saving the original value of PS1.
   if [ -z "$FLOX_SAVE_PS1" ]; then
     export FLOX_SAVE_PS1="$PS1"
@@ -1,5 +1,6 @@
 
 # Tweak the (already customized) prompt: add a flox indicator.
+# Setting FLOX_SET_PROMPT=false leaves the prompt alone.
 
 _floxPrompt1="${FLOX_PROMPT-flox}"
 _floxPrompt2="[$FLOX_PROMPT_ENVIRONMENTS]"
@@ -11,7 +12,7 @@ fi
 
 _flox="${_floxPrompt1} ${_floxPrompt2} "
 
-if [ -n "$_flox" -a -n "${PS1:-}" ]
+if [ -n "$_flox" -a -n "${PS1:-}" -a "${FLOX_SET_PROMPT:-true}" != false ]
 then
     # Start by saving the original value of PS1.
     if [ -z "$FLOX_SAVE_PS1" ]; then
@@ -73,6 +73,33 @@ BuildEnvCommand::BuildEnvCommand() : parser( "buildenv" )
     .help( "build a container builder script" )
     .nargs( 0 )
     .action( [&]( const auto & ) { this->buildContainer = true; } );
+
+  this->parser.add_argument( "--container-config" )
+    .help( "inline JSON of the OCI image config to merge into the container" )
+    .metavar( "CONFIG" )
+    .action( [&]( const std::string & str )
+             { this->containerConfig = parseOrReadJSONObject( str ); } );
+
+  this->parser.add_argument( "--container-layering" )
+    .help( "order in which store paths get a layer of their own "
+           "(popularity|depth)" )
+    .metavar( "LAYERING" )
+    .action(
+      [&]( const std::string & str )
+      {
+        if ( ( str != "popularity" ) && ( str != "depth" ) )
+          {
+            throw command::InvalidArgException(
+              "'--container-layering' must be 'popularity' or 'depth'" );
+          }
+        this->containerLayering = str;
+      } );
+
+  this->parser.add_argument( "--container-max-layers" )
+    .help( "maximum number of layers of the container" )
+    .metavar( "LAYERS" )
+    .action( [&]( const std::string & str )
+             { this->containerMaxLayers = std::stoul( str ); } );
 }
 
 
@@ -123,7 +150,13 @@ BuildEnvCommand::run()
       debugLog( "container requested, building container build script" );
 
       auto containerBuilderStorePath
-        = createContainerBuilder( *state, storePath, system );
+        = createContainerBuilder( *state,
+                                  storePath,
+                                  system,
+                                  this->containerConfig.value_or(
+                                    nlohmann::json::object() ),
+                                  this->containerLayering,
+                                  this->containerMaxLayers );
 
       debugLog( "built container builder: "
                 + store->printStorePath( containerBuilderStorePath ) );
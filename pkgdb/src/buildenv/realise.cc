@@ -73,8 +73,8 @@ namespace flox::buildenv {
  */
 const char * const BASH_ACTIVATE_SCRIPT = R"(
 # We use --rcfile to activate using bash which skips sourcing ~/.bashrc,
-# so source that here.
-if [ -f ~/.bashrc -a "${FLOX_SOURCED_FROM_SHELL_RC:-}" != 1 ]
+# so source that here, unless this is a pure activation.
+if [ -f ~/.bashrc -a "${FLOX_SOURCED_FROM_SHELL_RC:-}" != 1 -a -z "${FLOX_PURE:-}" ]
 then
     source ~/.bashrc
 fi
@@ -89,6 +89,13 @@ if [ -d "$FLOX_ENV/etc/profile.d" ]; then
   unset _prof_scripts;
 fi
 
+# Pure activations only keep the paths of the environment itself.
+# This is done after the profile.d scripts,
+# which may still use utilities found on the calling PATH.
+if [ -n "${FLOX_PURE:-}" ]; then
+  PATH="$FLOX_ENV/bin:$FLOX_ENV/sbin"
+fi
+
 # Disable command hashing to allow for newly installed flox packages to be found
 # immediately.
 set +h
@@ -106,6 +113,13 @@ if [ -d "$FLOX_ENV/etc/profile.d" ]; then
   unset _prof_scripts;
 fi
 
+# Pure activations only keep the paths of the environment itself.
+# This is done after the profile.d scripts,
+# which may still use utilities found on the calling PATH.
+if [ -n "${FLOX_PURE:-}" ]; then
+  PATH="$FLOX_ENV/bin:$FLOX_ENV/sbin"
+fi
+
 # Disable command hashing to allow for newly installed flox packages to be found
 # immediately.
 setopt nohashcmds
@@ -748,6 +762,20 @@ makeActivationScripts( nix::EvalState & state, resolver::Lockfile & lockfile )
                                  commonActivate,
                                  false );
         }
+
+      /* The 'on-deactivate' script is run by `floxd' once the environment is
+       * no longer active in any process, so it is not referenced from the
+       * activation scripts. */
+      if ( hook->onDeactivate.has_value() )
+        {
+          debugLog( "adding 'hook.on-deactivate' to activation scripts" );
+          std::stringstream unreferenced;
+          addScriptToScriptsDir( hook->onDeactivate.value(),
+                                 tempDir,
+                                 "on-deactivate.sh",
+                                 unreferenced,
+                                 false );
+        }
     }
 
   /* Add bash activation script. */
@@ -865,9 +893,12 @@ createFloxEnv( nix::ref<nix::EvalState> & state,
 
 
 nix::StorePath
-createContainerBuilder( nix::EvalState &       state,
-                        const nix::StorePath & environmentStorePath,
-                        const System &         system )
+createContainerBuilder( nix::EvalState &                   state,
+                        const nix::StorePath &             environmentStorePath,
+                        const System &                     system,
+                        const nlohmann::json &             containerConfig,
+                        const std::optional<std::string> & layering,
+                        std::optional<unsigned>            maxLayers )
 {
   static const nix::FlakeRef nixpkgsRef
     = nix::parseFlakeRef( COMMON_NIXPKGS_URL );
@@ -896,8 +927,17 @@ createContainerBuilder( nix::EvalState &       state,
   nix::Value vContainerSystem {};
   vContainerSystem.mkString( system );
 
+  nix::Value vContainerConfig {};
+  vContainerConfig.mkString( containerConfig.dump() );
+
+  nix::Value vLayering {};
+  if ( layering.has_value() ) { vLayering.mkString( *layering ); }
+
+  nix::Value vMaxLayers {};
+  if ( maxLayers.has_value() ) { vMaxLayers.mkInt( *maxLayers ); }
+
   nix::Value vBindings {};
-  auto       bindings = state.buildBindings( 4 );
+  auto       bindings = state.buildBindings( 7 );
   bindings.push_back(
     { state.symbols.create( "nixpkgsFlake" ), &vNixpkgsFlake } );
   bindings.push_back(
@@ -905,6 +945,16 @@ createContainerBuilder( nix::EvalState &       state,
   bindings.push_back( { state.symbols.create( "system" ), &vSystem } );
   bindings.push_back(
     { state.symbols.create( "containerSystem" ), &vContainerSystem } );
+  bindings.push_back( { state.symbols.create( "containerConfigJSON" ),
+                       &vContainerConfig } );
+  if ( layering.has_value() )
+    {
+      bindings.push_back( { state.symbols.create( "layering" ), &vLayering } );
+    }
+  if ( maxLayers.has_value() )
+    {
+      bindings.push_back( { state.symbols.create( "maxLayers" ), &vMaxLayers } );
+    }
 
   vBindings.mkAttrs( bindings );
 
@@ -471,8 +471,9 @@ from_json( const nlohmann::json & jfrom, HookRaw & hook )
                                                     "manifest field 'hook'" );
 
   /* Clear fields. */
-  hook.script     = std::nullopt;
-  hook.onActivate = std::nullopt;
+  hook.script       = std::nullopt;
+  hook.onActivate   = std::nullopt;
+  hook.onDeactivate = std::nullopt;
 
   for ( const auto & [key, value] : jfrom.items() )
     {
@@ -502,6 +503,20 @@ from_json( const nlohmann::json & jfrom, HookRaw & hook )
                 + value.dump() );
             }
         }
+      else if ( key == "on-deactivate" )
+        {
+          try
+            {
+              value.get_to( hook.onDeactivate );
+            }
+          catch ( const nlohmann::json::exception & )
+            {
+              throw InvalidManifestFileException(
+                "failed to parse manifest field 'hook.on-deactivate' with "
+                "value: "
+                + value.dump() );
+            }
+        }
       else
         {
           throw InvalidManifestFileException(
@@ -517,12 +532,13 @@ static void
 to_json( nlohmann::json & jto, const HookRaw & hook )
 {
   hook.check();
-  if ( hook.script.has_value() ) { jto = { { "script", *hook.script } }; }
-  else if ( hook.onActivate.has_value() )
+  jto = nlohmann::json::object();
+  if ( hook.script.has_value() ) { jto["script"] = *hook.script; }
+  if ( hook.onActivate.has_value() ) { jto["on-activate"] = *hook.onActivate; }
+  if ( hook.onDeactivate.has_value() )
     {
-      jto = { { "on-activate", *hook.onActivate } };
+      jto["on-deactivate"] = *hook.onDeactivate;
     }
-  else { jto = nlohmann::json::object(); }
 }
 
 
@@ -601,9 +617,12 @@ ManifestRaw::operator ManifestRawGA() const
 {
   ManifestRawGA raw( static_cast<GlobalManifestRawGA>(
     static_cast<GlobalManifestRaw>( *this ) ) );
-  raw.install = this->install;
-  raw.vars    = this->vars;
-  raw.hook    = this->hook;
+  raw.install      = this->install;
+  raw.vars         = this->vars;
+  raw.hook         = this->hook;
+  raw.services     = this->services;
+  raw.include      = this->include;
+  raw.containerize = this->containerize;
   return raw;
 }
 
@@ -637,6 +656,33 @@ from_json( const nlohmann::json & jfrom, ManifestRaw & manifest )
           manifest.vars = varsFromJSON( value );
         }
       else if ( key == "hook" ) { value.get_to( manifest.hook ); }
+      else if ( key == "services" )
+        {
+          if ( value.is_null() )
+            {
+              manifest.services = std::nullopt;
+              continue;
+            }
+          manifest.services = value;
+        }
+      else if ( key == "include" )
+        {
+          if ( value.is_null() )
+            {
+              manifest.include = std::nullopt;
+              continue;
+            }
+          manifest.include = value;
+        }
+      else if ( key == "containerize" )
+        {
+          if ( value.is_null() )
+            {
+              manifest.containerize = std::nullopt;
+              continue;
+            }
+          manifest.containerize = value;
+        }
       else if ( key == "options" ) { value.get_to( manifest.options ); }
       else if ( key == "env-base" ) { value.get_to( manifest.envBase ); }
       else
@@ -666,6 +712,18 @@ to_json( nlohmann::json & jto, const ManifestRaw & manifest )
   if ( manifest.vars.has_value() ) { jto["vars"] = *manifest.vars; }
 
   if ( manifest.hook.has_value() ) { jto["hook"] = *manifest.hook; }
+
+  if ( manifest.services.has_value() )
+    {
+      jto["services"] = *manifest.services;
+    }
+
+  if ( manifest.include.has_value() ) { jto["include"] = *manifest.include; }
+
+  if ( manifest.containerize.has_value() )
+    {
+      jto["containerize"] = *manifest.containerize;
+    }
 }
 
 
@@ -684,6 +742,21 @@ ManifestRaw::check() const
         }
     }
   if ( this->hook.has_value() ) { this->hook->check(); }
+  if ( this->services.has_value() && ( ! this->services->is_object() ) )
+    {
+      throw InvalidManifestFileException(
+        "manifest field 'services' must be a table." );
+    }
+  if ( this->include.has_value() && ( ! this->include->is_object() ) )
+    {
+      throw InvalidManifestFileException(
+        "manifest field 'include' must be a table." );
+    }
+  if ( this->containerize.has_value() && ( ! this->containerize->is_object() ) )
+    {
+      throw InvalidManifestFileException(
+        "manifest field 'containerize' must be a table." );
+    }
   if ( this->registry.has_value() )
     {
       for ( const auto & [name, input] : this->registry->inputs )
@@ -766,6 +839,33 @@ from_json( const nlohmann::json & jfrom, ManifestRawGA & manifest )
           manifest.vars = varsFromJSON( value );
         }
       else if ( key == "hook" ) { value.get_to( manifest.hook ); }
+      else if ( key == "services" )
+        {
+          if ( value.is_null() )
+            {
+              manifest.services = std::nullopt;
+              continue;
+            }
+          manifest.services = value;
+        }
+      else if ( key == "include" )
+        {
+          if ( value.is_null() )
+            {
+              manifest.include = std::nullopt;
+              continue;
+            }
+          manifest.include = value;
+        }
+      else if ( key == "containerize" )
+        {
+          if ( value.is_null() )
+            {
+              manifest.containerize = std::nullopt;
+              continue;
+            }
+          manifest.containerize = value;
+        }
       else if ( key == "options" ) { value.get_to( manifest.options ); }
       else
         {
@@ -790,6 +890,18 @@ to_json( nlohmann::json & jto, const ManifestRawGA & manifest )
   if ( manifest.vars.has_value() ) { jto["vars"] = *manifest.vars; }
 
   if ( manifest.hook.has_value() ) { jto["hook"] = *manifest.hook; }
+
+  if ( manifest.services.has_value() )
+    {
+      jto["services"] = *manifest.services;
+    }
+
+  if ( manifest.include.has_value() ) { jto["include"] = *manifest.include; }
+
+  if ( manifest.containerize.has_value() )
+    {
+      jto["containerize"] = *manifest.containerize;
+    }
 }
 
 
@@ -816,6 +928,21 @@ ManifestRawGA::check() const
         }
     }
   if ( this->hook.has_value() ) { this->hook->check(); }
+  if ( this->services.has_value() && ( ! this->services->is_object() ) )
+    {
+      throw InvalidManifestFileException(
+        "manifest field 'services' must be a table." );
+    }
+  if ( this->include.has_value() && ( ! this->include->is_object() ) )
+    {
+      throw InvalidManifestFileException(
+        "manifest field 'include' must be a table." );
+    }
+  if ( this->containerize.has_value() && ( ! this->containerize->is_object() ) )
+    {
+      throw InvalidManifestFileException(
+        "manifest field 'containerize' must be a table." );
+    }
 }
 
 
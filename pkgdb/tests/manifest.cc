@@ -435,6 +435,70 @@ test_parseManifestRawWithOnActivateScript()
 }
 
 
+/* -------------------------------------------------------------------------- */
+
+/** @brief `hook.on-deactivate' may be combined with other hooks. */
+bool
+test_hookOnDeactivateRoundTrip()
+{
+  nlohmann::json hook = { { "on-activate", "foo" },
+                          { "on-deactivate", "bar" } };
+  flox::resolver::ManifestRaw manifest = nlohmann::json { { "hook", hook } };
+  EXPECT_EQ( *manifest.hook->onDeactivate, "bar" );
+  EXPECT_EQ( nlohmann::json( manifest ).at( "hook" ), hook );
+  return true;
+}
+
+
+/* -------------------------------------------------------------------------- */
+
+/** @brief `include' is passed through as is, but must be a table. */
+bool
+test_includeRoundTrip()
+{
+  nlohmann::json include
+    = { { "environments",
+          { { { "remote", "owner/name" }, { "generation", 1 } } } } };
+  flox::resolver::ManifestRaw manifest
+    = nlohmann::json { { "include", include } };
+  EXPECT_EQ( nlohmann::json( manifest ).at( "include" ), include );
+
+  try
+    {
+      flox::resolver::ManifestRaw invalid
+        = nlohmann::json { { "include", "owner/name" } };
+      return false;
+    }
+  catch ( const flox::resolver::InvalidManifestFileException & )
+    {}
+  return true;
+}
+
+
+/* -------------------------------------------------------------------------- */
+
+/** @brief `containerize' is passed through as is, but must be a table. */
+bool
+test_containerizeRoundTrip()
+{
+  nlohmann::json containerize
+    = { { "config", { { "user", "app" }, { "cmd", { "serve" } } } } };
+  flox::resolver::ManifestRaw manifest
+    = nlohmann::json { { "containerize", containerize } };
+  EXPECT_EQ( nlohmann::json( manifest ).at( "containerize" ), containerize );
+
+  try
+    {
+      flox::resolver::ManifestRaw invalid
+        = nlohmann::json { { "containerize", { "serve" } } };
+      return false;
+    }
+  catch ( const flox::resolver::InvalidManifestFileException & )
+    {}
+  return true;
+}
+
+
 /* -------------------------------------------------------------------------- */
 
 int
@@ -470,6 +534,9 @@ main()
 
   RUN_TEST( hookAllowsAtMostOneActivationHook );
   RUN_TEST( parseManifestRawWithOnActivateScript );
+  RUN_TEST( hookOnDeactivateRoundTrip );
+  RUN_TEST( includeRoundTrip );
+  RUN_TEST( containerizeRoundTrip );
 
   return exitCode;
 }
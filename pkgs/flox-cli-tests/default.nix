@@ -6,6 +6,8 @@
   bash,
   zsh,
   dash,
+  nushell,
+  direnv,
   bats,
   coreutils,
   entr,
@@ -47,6 +49,8 @@
       bash
       zsh
       dash
+      nushell
+      direnv
       batsWith
       coreutils
       entr
@@ -20,6 +20,7 @@
   installShellFiles,
   gnused,
   gitMinimal,
+  bash,
   nix,
   pkgsFor,
   flox-pkgdb,
@@ -53,6 +54,7 @@
       # we want to use our own binaries by absolute path
       # rather than relying on or modifying the user's `PATH` variable
       GIT_BIN = "${gitMinimal}/bin/git";
+      BASH_BIN = "${bash}/bin/bash";
       NIX_BIN = "${nix}/bin/nix";
       PKGDB_BIN =
         if flox-pkgdb == null
@@ -64,6 +66,9 @@
         else "${flox-pkgdb}/lib/ld-floxlib.so";
       FLOX_ZDOTDIR = ../../assets/flox.zdotdir;
 
+      # shell scripts recording the changes of in-place activations for `flox deactivate`
+      FLOX_RESTORE_RECORD_SCRIPTS = ../../assets/restore_records;
+
       # bundling of internally used nix scripts
       FLOX_RESOLVER_SRC = builtins.path {path = ../../resolver;};
 
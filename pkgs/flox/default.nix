@@ -42,6 +42,7 @@ in
         --set PKGDB_BIN       "${flox-pkgdb}/bin/pkgdb" \
         --set LD_FLOXLIB      "${flox-pkgdb}/lib/ld-floxlib.so" \
         --set FLOX_BIN        "${flox-cli}/bin/flox" \
+        --set FLOXD_BIN       "${flox-cli}/bin/floxd" \
         --set FLOX_VERSION    "${version}"
     '';
   }